@@ -293,7 +293,7 @@ pub async fn record_audio_loop(
     device: AudioDevice,
     channels_count: u16,
     mute: bool,
-    mut sender: StreamSender<()>,
+    sender: &mut StreamSender<()>,
 ) -> StrResult {
     let config = device
         .inner
@@ -442,7 +442,7 @@ pub fn get_next_frame_batch(
 // callback will gracefully handle an interruption, and the callback timing and sound wave
 // continuity will not be affected.
 pub async fn receive_samples_loop(
-    mut receiver: StreamReceiver<()>,
+    receiver: &mut StreamReceiver<()>,
     sample_buffer: Arc<Mutex<VecDeque<f32>>>,
     channels_count: usize,
     batch_frames_count: usize,
@@ -590,7 +590,7 @@ pub async fn play_audio_loop(
     channels_count: u16,
     sample_rate: u32,
     config: AudioBufferingConfig,
-    receiver: StreamReceiver<()>,
+    receiver: &mut StreamReceiver<()>,
 ) -> StrResult {
     // Size of a chunk of frames. It corresponds to the duration if a fade-in/out in frames.
     let batch_frames_count = sample_rate as usize * config.batch_ms as usize / 1000;
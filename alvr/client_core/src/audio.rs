@@ -120,7 +120,7 @@ pub async fn play_audio_loop(
     channels_count: u16,
     sample_rate: u32,
     config: AudioBufferingConfig,
-    receiver: StreamReceiver<()>,
+    mut receiver: StreamReceiver<()>,
 ) -> StrResult {
     // the client sends invalid sample rates sometimes, and we crash if we try and use one
     // (batch_frames_count ends up zero and the audio callback gets confused)
@@ -168,7 +168,7 @@ pub async fn play_audio_loop(
     });
 
     alvr_audio::receive_samples_loop(
-        receiver,
+        &mut receiver,
         sample_buffer,
         2,
         batch_frames_count,
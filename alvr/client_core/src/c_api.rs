@@ -63,6 +63,10 @@ pub enum AlvrEvent {
         codec: AlvrCodec,
     },
     FrameReady,
+    // Answer with alvr_confirm_pairing()
+    PairingRequested {
+        code: u32,
+    },
 }
 
 #[repr(C)]
@@ -263,6 +267,7 @@ pub extern "C" fn alvr_poll_event(out_event: *mut AlvrEvent) -> bool {
 
                 AlvrEvent::FrameReady
             }
+            ClientCoreEvent::PairingRequested { code } => AlvrEvent::PairingRequested { code },
         };
 
         unsafe { *out_event = event };
@@ -273,6 +278,11 @@ pub extern "C" fn alvr_poll_event(out_event: *mut AlvrEvent) -> bool {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn alvr_confirm_pairing(accepted: bool) {
+    crate::confirm_pairing(accepted);
+}
+
 /// Call only with external decoder
 /// Returns the number of bytes of the next nal, or 0 if there are no nals ready.
 /// If out_nal or out_timestamp_ns is null, no nal is dequeued. Use to get the nal allocation size.
@@ -5,17 +5,25 @@ use crate::{
     platform,
     sockets::AnnouncerSocket,
     statistics::StatisticsManager,
-    storage::Config,
-    ClientCoreEvent, CONTROL_CHANNEL_SENDER, DISCONNECT_NOTIFIER, EVENT_QUEUE, IS_ALIVE,
-    IS_RESUMED, IS_STREAMING, STATISTICS_MANAGER, STATISTICS_SENDER, TRACKING_SENDER,
+    storage::{self, Config},
+    ClientCoreEvent, CONTROL_CHANNEL_SENDER, DISCONNECT_NOTIFIER, EVENT_QUEUE, EXTENSION_REGISTRY,
+    IS_ALIVE, IS_RESUMED, IS_STREAMING, LOCAL_IPC, PAIRING_CONFIRMATION_SENDER, STATISTICS_MANAGER,
+    STATISTICS_SENDER, TRACKING_SENDER,
 };
 use alvr_audio::AudioDevice;
 use alvr_common::{glam::UVec2, prelude::*, ALVR_VERSION, HEAD_ID};
-use alvr_session::{settings_schema::Switch, SessionDesc};
+use alvr_session::{
+    settings_schema::Switch, BandwidthProbeResult, CodecType, ConnectionTransport, SessionDesc,
+};
 use alvr_sockets::{
-    spawn_cancelable, BatteryPacket, ClientConnectionResult, ClientControlPacket, Haptics,
-    PeerType, ProtoControlSocket, ReceiverBuffer, ServerControlPacket, StreamConfigPacket,
-    StreamSocketBuilder, VideoStreamingCapabilities, AUDIO, HAPTICS, STATISTICS, TRACKING, VIDEO,
+    capabilities, capabilities_to_string, spawn_cancelable, BandwidthProbeHeader,
+    BandwidthProbeMeter, BatteryPacket, ChannelKeys, ClientConnectionResult, ClientControlPacket,
+    ConnectionEnd, HandshakePacket, Haptics, MdnsAnnouncer, PairingKey, PeerType,
+    ProtoControlSocket, ReceiverBuffer, ResumeAction, ResumeStateMachine, ServerControlPacket,
+    SessionToken, StreamConfigPacket, StreamReceiver, StreamSocketBuilder,
+    VideoStreamingCapabilities, AUDIO, AUDIO_QUEUE, BANDWIDTH_PROBE, BANDWIDTH_PROBE_QUEUE,
+    HAPTICS, HAPTICS_QUEUE, RESUME_RETRY_INTERVAL, RESUME_TIMEOUT, STATISTICS, TRACKING, VIDEO,
+    VIDEO_QUEUE,
 };
 use futures::future::BoxFuture;
 use serde_json as json;
@@ -28,7 +36,7 @@ use std::{
 };
 use tokio::{
     runtime::Runtime,
-    sync::{mpsc as tmpsc, Mutex},
+    sync::{mpsc as tmpsc, oneshot, Mutex},
     time,
 };
 
@@ -52,12 +60,57 @@ const NETWORK_UNREACHABLE_MESSAGE: &str = "Cannot connect to the internet";
 const STREAM_STARTING_MESSAGE: &str = "The stream will begin soon\nPlease wait...";
 const SERVER_RESTART_MESSAGE: &str = "The streamer is restarting\nPlease wait...";
 const SERVER_DISCONNECTED_MESSAGE: &str = "The streamer has disconnected.";
+const RESUMING_MESSAGE: &str = "Connection lost\nResuming the stream...";
 
 const DISCOVERY_RETRY_PAUSE: Duration = Duration::from_millis(500);
 const RETRY_CONNECT_MIN_INTERVAL: Duration = Duration::from_secs(1);
 const NETWORK_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
 const CONNECTION_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+// Bursts are sent every frame interval, a longer pause means that the last ones were lost
+const BANDWIDTH_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const PAIRING_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn client_capabilities() -> Vec<&'static str> {
+    let mut client_capabilities = vec![
+        capabilities::FORWARD_ERROR_CORRECTION,
+        capabilities::PACKET_RETRANSMISSION,
+        capabilities::QUIC,
+    ];
+    if decoder::is_codec_supported(CodecType::H264) {
+        client_capabilities.push(capabilities::H264);
+    }
+    if decoder::is_codec_supported(CodecType::Hevc) {
+        client_capabilities.push(capabilities::HEVC);
+    }
+
+    client_capabilities
+}
+
+// Shows the pairing code, to be compared with the code shown by the streamer
+async fn wait_pairing_confirmation(code: u32) -> bool {
+    let (sender, receiver) = oneshot::channel();
+    *PAIRING_CONFIRMATION_SENDER.lock() = Some(sender);
+
+    set_hud_message(&format!(
+        "Pairing code: {code:06}\nConfirm only if the streamer\nshows the same code\nA: confirm, B: reject"
+    ));
+    EVENT_QUEUE
+        .lock()
+        .push_back(ClientCoreEvent::PairingRequested { code });
+
+    let res = time::timeout(PAIRING_CONFIRMATION_TIMEOUT, receiver).await;
+    PAIRING_CONFIRMATION_SENDER.lock().take();
+
+    matches!(res, Ok(Ok(true)))
+}
+
+// Kept across connection attempts after the connection is lost, to resume the stream
+struct ResumableStream {
+    session_token: SessionToken,
+    stream_config: StreamConfigPacket,
+    state: ResumeStateMachine,
+}
 
 fn set_hud_message(message: &str) {
     let message = format!(
@@ -72,6 +125,40 @@ fn set_hud_message(message: &str) {
         .push_back(ClientCoreEvent::UpdateHudMessage(message));
 }
 
+// The streamer finds the Unix domain socket of the client by its hostname, no announcement is
+// needed
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn wait_for_local_server(runtime: &Runtime) -> IntResult<(ProtoControlSocket, IpAddr)> {
+    #[cfg(unix)]
+    {
+        let listener = runtime
+            .block_on(alvr_sockets::get_local_server_listener(
+                &Config::load().hostname,
+            ))
+            .map_err(to_int_e!())?;
+
+        loop {
+            check_interrupt!(IS_ALIVE.value());
+
+            let maybe_pair = runtime.block_on(async {
+                tokio::select! {
+                    maybe_pair = ProtoControlSocket::connect_to(PeerType::LocalServer(&listener)) => {
+                        maybe_pair.map_err(to_int_e!())
+                    },
+                    _ = time::sleep(DISCOVERY_RETRY_PAUSE) => Err(InterruptibleError::Interrupted)
+                }
+            });
+
+            if let Ok(pair) = maybe_pair {
+                return Ok(pair);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    int_fmt_e!("Local IPC is supported only on Unix platforms")
+}
+
 pub fn connection_lifecycle_loop(
     recommended_view_resolution: UVec2,
     supported_refresh_rates: Vec<f32>,
@@ -79,6 +166,7 @@ pub fn connection_lifecycle_loop(
     set_hud_message(INITIAL_MESSAGE);
 
     let decoder_guard = Arc::new(Mutex::new(()));
+    let mut resumable_stream = None;
 
     loop {
         check_interrupt!(IS_ALIVE.value());
@@ -88,7 +176,13 @@ pub fn connection_lifecycle_loop(
                 recommended_view_resolution,
                 supported_refresh_rates.clone(),
                 Arc::clone(&decoder_guard),
+                &mut resumable_stream,
             ) {
+                // The stream is still resumable until the resume timeout
+                if let Some(stream) = &mut resumable_stream {
+                    stream.state.attempt_failed(Instant::now());
+                }
+
                 match e {
                     InterruptibleError::Interrupted => return Ok(()),
                     InterruptibleError::Other(_) => {
@@ -109,12 +203,30 @@ fn connection_pipeline(
     recommended_view_resolution: UVec2,
     supported_refresh_rates: Vec<f32>,
     decoder_guard: Arc<Mutex<()>>,
+    resumable_stream: &mut Option<ResumableStream>,
 ) -> IntResult {
     let runtime = Runtime::new().map_err(to_int_e!())?;
 
-    let (mut proto_control_socket, server_ip) = {
+    // The server tries to connect again until the resume timeout
+    if let Some(stream) = resumable_stream {
+        if stream.state.poll(Instant::now()) == ResumeAction::GiveUp {
+            info!("Could not resume the stream");
+            set_hud_message(INITIAL_MESSAGE);
+
+            *resumable_stream = None;
+        }
+    }
+
+    let (mut proto_control_socket, server_ip) = if LOCAL_IPC.value() {
+        wait_for_local_server(&runtime)?
+    } else {
         let config = Config::load();
         let announcer_socket = AnnouncerSocket::new(&config.hostname).map_err(to_int_e!())?;
+        // The advertisement stops when this is dropped, once connected. mDNS is optional, since
+        // the broadcast works on most networks
+        let _mdns_announcer = MdnsAnnouncer::new(&config.hostname)
+            .map_err(|e| warn!("mDNS advertisement error: {e}"))
+            .ok();
         let listener_socket = runtime
             .block_on(alvr_sockets::get_server_listener())
             .map_err(to_int_e!())?;
@@ -149,66 +261,175 @@ fn connection_pipeline(
         }
     };
 
-    let microphone_sample_rate = AudioDevice::new_input(None)
-        .unwrap()
-        .input_sample_rate()
-        .unwrap();
-
-    runtime
-        .block_on(
-            proto_control_socket.send(&ClientConnectionResult::ConnectionAccepted {
-                display_name: platform::device_model(),
-                server_ip,
-                streaming_capabilities: Some(VideoStreamingCapabilities {
-                    default_view_resolution: recommended_view_resolution,
-                    supported_refresh_rates,
-                    microphone_sample_rate,
-                }),
-            }),
-        )
-        .map_err(to_int_e!())?;
-    let config_packet = runtime
-        .block_on(proto_control_socket.recv::<StreamConfigPacket>())
+    let handshake = HandshakePacket::new(&client_capabilities(), &[]);
+    let server_handshake = runtime
+        .block_on(proto_control_socket.exchange_handshake(&handshake, false))
         .map_err(to_int_e!())?;
+    match handshake.negotiate(&server_handshake) {
+        Ok(capabilities) => info!(
+            "Streamer v{} capabilities: {}",
+            server_handshake.version,
+            capabilities_to_string(&capabilities)
+        ),
+        Err(incompatibility) => {
+            return int_fmt_e!("{}", incompatibility.describe("client", "streamer"));
+        }
+    }
+
+    let stream_keys = {
+        let mut config = Config::load();
+        let known_pairing_keys = config
+            .pairing_keys
+            .iter()
+            .filter_map(|key| PairingKey::from_hex_string(key).ok())
+            .collect::<Vec<_>>();
+
+        let (pairing_key, stream_keys) = runtime
+            .block_on(
+                proto_control_socket
+                    .authenticate_server(&known_pairing_keys, wait_pairing_confirmation),
+            )
+            .map_err(to_int_e!())?;
+
+        if !known_pairing_keys.contains(&pairing_key) {
+            info!("Paired with streamer at {server_ip}");
+
+            config.pairing_keys.push(pairing_key.to_hex_string());
+            config.store();
+        }
+
+        stream_keys
+    };
+
+    // If the server does not accept to resume, it closes the connection. The resume state is kept
+    // until the server starts the stream, so the next attempts can still resume until the timeout
+    let config_packet = if let Some(stream) = resumable_stream {
+        info!("Resuming the stream");
+
+        runtime
+            .block_on(
+                proto_control_socket.send(&ClientConnectionResult::ResumeRequested {
+                    session_token: stream.session_token,
+                }),
+            )
+            .map_err(to_int_e!())?;
 
-    runtime
+        stream.stream_config.clone()
+    } else {
+        let microphone_sample_rate = AudioDevice::new_input(None)
+            .unwrap()
+            .input_sample_rate()
+            .unwrap();
+
+        runtime
+            .block_on(
+                proto_control_socket.send(&ClientConnectionResult::ConnectionAccepted {
+                    display_name: platform::device_model(),
+                    server_ip,
+                    streaming_capabilities: Some(VideoStreamingCapabilities {
+                        default_view_resolution: recommended_view_resolution,
+                        supported_refresh_rates,
+                        microphone_sample_rate,
+                    }),
+                }),
+            )
+            .map_err(to_int_e!())?;
+
+        runtime
+            .block_on(proto_control_socket.recv::<StreamConfigPacket>())
+            .map_err(to_int_e!())?
+    };
+
+    let connection_end = runtime
         .block_on(stream_pipeline(
             proto_control_socket,
-            config_packet,
+            config_packet.clone(),
             server_ip,
+            stream_keys,
             decoder_guard,
+            resumable_stream,
         ))
-        .map_err(to_int_e!())
+        .map_err(to_int_e!())?;
+
+    // The server did not start the stream
+    if let Some(stream) = resumable_stream {
+        stream.state.attempt_failed(Instant::now());
+    }
+
+    if let ConnectionEnd::Lost = connection_end {
+        set_hud_message(RESUMING_MESSAGE);
+
+        let mut state = ResumeStateMachine::new(RESUME_TIMEOUT, RESUME_RETRY_INTERVAL);
+        state.connected();
+        state.connection_lost(Instant::now());
+
+        *resumable_stream = Some(ResumableStream {
+            session_token: config_packet.session_token,
+            stream_config: config_packet,
+            state,
+        });
+    }
+
+    Ok(())
+}
+
+// Returns None if not enough bursts were received
+async fn measure_bandwidth(
+    receiver: &mut StreamReceiver<BandwidthProbeHeader>,
+) -> StrResult<Option<BandwidthProbeResult>> {
+    let mut meter = BandwidthProbeMeter::new();
+    let mut receiver_buffer = ReceiverBuffer::new();
+    while !meter.is_complete() {
+        tokio::select! {
+            res = receiver.recv_buffer(&mut receiver_buffer) => {
+                res?;
+                let (header, padding) = receiver_buffer.get()?;
+                meter.report_burst(header, padding.len(), Instant::now());
+            }
+            _ = time::sleep(BANDWIDTH_PROBE_TIMEOUT), if meter.is_started() => break,
+        }
+    }
+
+    Ok(meter.result())
 }
 
 async fn stream_pipeline(
     proto_socket: ProtoControlSocket,
     stream_config: StreamConfigPacket,
     server_ip: IpAddr,
+    stream_keys: ChannelKeys,
     decoder_guard: Arc<Mutex<()>>,
-) -> StrResult {
-    let (control_sender, mut control_receiver) = proto_socket.split();
+    resumable_stream: &mut Option<ResumableStream>,
+) -> StrResult<ConnectionEnd> {
+    let (control_sender, mut control_receiver) = proto_socket.split()?;
     let control_sender = Arc::new(Mutex::new(control_sender));
 
     match control_receiver.recv().await {
         Ok(ServerControlPacket::StartStream) => {
             info!("Stream starting");
             set_hud_message(STREAM_STARTING_MESSAGE);
+
+            // If resuming, the server accepted the session token
+            *resumable_stream = None;
         }
         Ok(ServerControlPacket::Restarting) => {
             info!("Server restarting");
             set_hud_message(SERVER_RESTART_MESSAGE);
-            return Ok(());
+
+            // The streaming session does not survive a restart
+            *resumable_stream = None;
+
+            return Ok(ConnectionEnd::Closed);
         }
         Err(e) => {
             info!("Server disconnected. Cause: {e}");
             set_hud_message(SERVER_DISCONNECTED_MESSAGE);
-            return Ok(());
+            return Ok(ConnectionEnd::Closed);
         }
         _ => {
             info!("Unexpected packet");
             set_hud_message("Unexpected packet");
-            return Ok(());
+            return Ok(ConnectionEnd::Closed);
         }
     }
 
@@ -229,13 +450,20 @@ async fn stream_pipeline(
         },
     ));
 
-    let stream_socket_builder = StreamSocketBuilder::listen_for_server(
-        settings.connection.stream_port,
-        settings.connection.stream_protocol,
-        settings.connection.client_send_buffer_bytes,
-        settings.connection.client_recv_buffer_bytes,
-    )
-    .await?;
+    let stream_socket_builder = match settings.connection.transport {
+        ConnectionTransport::Network => {
+            StreamSocketBuilder::listen_for_server(
+                settings.connection.stream_port,
+                settings.connection.stream_protocol,
+                settings.connection.client_send_buffer_bytes,
+                settings.connection.client_recv_buffer_bytes,
+            )
+            .await?
+        }
+        ConnectionTransport::LocalIpc { .. } => {
+            StreamSocketBuilder::listen_for_local_server(&Config::load().hostname).await?
+        }
+    };
 
     if let Err(e) = control_sender
         .lock()
@@ -245,19 +473,38 @@ async fn stream_pipeline(
     {
         info!("Server disconnected. Cause: {e}");
         set_hud_message(SERVER_DISCONNECTED_MESSAGE);
-        return Ok(());
+        return Ok(ConnectionEnd::Closed);
     }
 
+    let fec_redundancy_ratio =
+        if let Switch::Enabled(config) = &settings.connection.forward_error_correction {
+            Some(config.redundancy_ratio)
+        } else {
+            None
+        };
+
     let stream_socket = tokio::select! {
         res = stream_socket_builder.accept_from_server(
             server_ip,
-            settings.connection.stream_port,
-            settings.connection.packet_size as _
+            stream_config.server_stream_port,
+            settings.connection.packet_size as _,
+            fec_redundancy_ratio,
+            settings.connection.packet_retransmission.clone().into_option(),
+            settings.connection.reorder_window_size as _,
+            stream_keys,
         ) => res?,
         _ = time::sleep(Duration::from_secs(5)) => {
             return fmt_e!("Timeout while setting up streams");
         }
     };
+    if settings.extra.capture_stream {
+        if let Err(e) = stream_socket
+            .capture_to_file(&storage::stream_capture_path())
+            .await
+        {
+            warn!("Failed to capture stream: {e}");
+        }
+    }
     let stream_socket = Arc::new(stream_socket);
 
     info!("Connected to server");
@@ -284,16 +531,12 @@ async fn stream_pipeline(
             *TRACKING_SENDER.lock() = Some(data_sender);
 
             while let Some(tracking) = data_receiver.recv().await {
-                socket_sender.send(&tracking, vec![]).await.ok();
-
-                // Note: this is not the best place to report the acquired input. Instead it should
-                // be done as soon as possible (or even just before polling the input). Instead this
-                // is reported late to partially compensate for lack of network latency measurement,
-                // so the server can just use total_pipeline_latency as the postTimeoffset.
-                // This hack will be removed once poseTimeOffset can be calculated more accurately.
+                // The server measures the tracking network latency from this timestamp
                 if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
                     stats.report_input_acquired(tracking.target_timestamp);
                 }
+
+                socket_sender.send(&tracking, vec![]).await.ok();
             }
 
             Ok(())
@@ -325,7 +568,9 @@ async fn stream_pipeline(
     IS_STREAMING.set(true);
 
     let video_receive_loop = {
-        let mut receiver = stream_socket.subscribe_to_stream::<Duration>(VIDEO).await?;
+        let mut receiver = stream_socket
+            .subscribe_to_stream::<Duration>(VIDEO, VIDEO_QUEUE)
+            .await?;
         let disconnection_critera = settings.connection.disconnection_criteria;
         async move {
             let _decoder_guard = decoder_guard.lock().await;
@@ -364,7 +609,12 @@ async fn stream_pipeline(
                 }
 
                 if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
-                    stats.report_video_packet_received(timestamp);
+                    stats.report_video_packet_received(
+                        timestamp,
+                        receiver_buffer.reorder_depth(),
+                        receiver_buffer.late_shards_count(),
+                        receiver_buffer.dropped_shards_count(),
+                    );
                 }
 
                 decoder::push_nal(timestamp, nal);
@@ -400,7 +650,7 @@ async fn stream_pipeline(
 
     let haptics_receive_loop = {
         let mut receiver = stream_socket
-            .subscribe_to_stream::<Haptics>(HAPTICS)
+            .subscribe_to_stream_with_retransmission::<Haptics>(HAPTICS, HAPTICS_QUEUE)
             .await?;
         async move {
             loop {
@@ -416,10 +666,37 @@ async fn stream_pipeline(
         }
     };
 
+    let bandwidth_probe_loop: BoxFuture<_> =
+        if matches!(settings.connection.bandwidth_probing, Switch::Enabled(_)) {
+            let mut receiver = stream_socket
+                .subscribe_to_stream::<BandwidthProbeHeader>(BANDWIDTH_PROBE, BANDWIDTH_PROBE_QUEUE)
+                .await?;
+            Box::pin(async move {
+                // The stream does not need the probe, a failed one is only discarded
+                match measure_bandwidth(&mut receiver).await {
+                    Ok(Some(result)) => {
+                        if let Some(sender) = &*CONTROL_CHANNEL_SENDER.lock() {
+                            sender
+                                .send(ClientControlPacket::BandwidthProbeResult(result))
+                                .ok();
+                        }
+                    }
+                    Ok(None) => warn!("Bandwidth probe failed: not enough packets received"),
+                    Err(e) => warn!("Bandwidth probe failed: {e}"),
+                }
+
+                future::pending().await
+            })
+        } else {
+            Box::pin(future::pending())
+        };
+
     let game_audio_loop: BoxFuture<_> = if let Switch::Enabled(config) = settings.audio.game_audio {
         let device = AudioDevice::new_output(None, None).map_err(err!())?;
 
-        let game_audio_receiver = stream_socket.subscribe_to_stream(AUDIO).await?;
+        let game_audio_receiver = stream_socket
+            .subscribe_to_stream_with_retransmission(AUDIO, AUDIO_QUEUE)
+            .await?;
         Box::pin(audio::play_audio_loop(
             device,
             2,
@@ -492,7 +769,20 @@ async fn stream_pipeline(
                 if let Err(e) = res {
                     info!("Server disconnected. Cause: {e}");
                     set_hud_message(SERVER_DISCONNECTED_MESSAGE);
-                    break Ok(());
+                    break Ok(ConnectionEnd::Lost);
+                }
+
+                let ping_timestamp = STATISTICS_MANAGER
+                    .lock()
+                    .as_ref()
+                    .map(|stats| stats.ping_timestamp());
+                if let Some(timestamp) = ping_timestamp {
+                    control_sender
+                        .lock()
+                        .await
+                        .send(&ClientControlPacket::Ping(timestamp))
+                        .await
+                        .ok();
                 }
 
                 time::sleep(NETWORK_KEEPALIVE_INTERVAL).await;
@@ -510,20 +800,40 @@ async fn stream_pipeline(
 
     let control_receive_loop = async move {
         loop {
-            match control_receiver.recv().await {
+            let maybe_packet = control_receiver.recv().await;
+            let receive_instant = Instant::now();
+
+            match maybe_packet {
                 Ok(ServerControlPacket::InitializeDecoder(config)) => {
                     decoder::create_decoder(config);
                 }
+                Ok(ServerControlPacket::Ping(origin_timestamp)) => {
+                    let pong = STATISTICS_MANAGER
+                        .lock()
+                        .as_ref()
+                        .map(|stats| stats.answer_ping(origin_timestamp, receive_instant));
+                    if let (Some(pong), Some(sender)) = (pong, &*CONTROL_CHANNEL_SENDER.lock()) {
+                        sender.send(ClientControlPacket::Pong(pong)).ok();
+                    }
+                }
+                Ok(ServerControlPacket::Pong(pong)) => {
+                    if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+                        stats.report_pong(pong, receive_instant);
+                    }
+                }
                 Ok(ServerControlPacket::Restarting) => {
                     info!("{SERVER_RESTART_MESSAGE}");
                     set_hud_message(SERVER_RESTART_MESSAGE);
-                    break Ok(());
+                    break Ok(ConnectionEnd::Closed);
+                }
+                Ok(ServerControlPacket::Extension(packet)) => {
+                    EXTENSION_REGISTRY.lock().dispatch(&packet);
                 }
                 Ok(_) => (),
                 Err(e) => {
                     info!("{SERVER_DISCONNECTED_MESSAGE} Cause: {e}");
                     set_hud_message(SERVER_DISCONNECTED_MESSAGE);
-                    break Ok(());
+                    break Ok(ConnectionEnd::Lost);
                 }
             }
         }
@@ -541,20 +851,22 @@ async fn stream_pipeline(
                 SERVER_DISCONNECTED_MESSAGE
             );
 
-            Ok(())
+            Ok(ConnectionEnd::Lost)
         },
-        res = spawn_cancelable(game_audio_loop) => res,
-        res = spawn_cancelable(microphone_loop) => res,
-        res = spawn_cancelable(tracking_send_loop) => res,
-        res = spawn_cancelable(statistics_send_loop) => res,
-        res = spawn_cancelable(video_receive_loop) => res,
-        res = spawn_cancelable(haptics_receive_loop) => res,
-        res = spawn_cancelable(control_send_loop) => res,
+        res = spawn_cancelable(game_audio_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(microphone_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(tracking_send_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(statistics_send_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(video_receive_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(haptics_receive_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(bandwidth_probe_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(control_send_loop) => res.map(|_| ConnectionEnd::Closed),
 
         // keep these loops on the current task
         res = keepalive_sender_loop => res,
         res = control_receive_loop => res,
 
-        _ = DISCONNECT_NOTIFIER.notified() => Ok(()),
+        // The user or the app disconnected on purpose
+        _ = DISCONNECT_NOTIFIER.notified() => Ok(ConnectionEnd::Closed),
     }
 }
@@ -32,6 +32,17 @@ pub static DECODER_DEQUEUER: Lazy<Mutex<Option<crate::platform::VideoDecoderDequ
 
 pub static EXTERNAL_DECODER: RelaxedAtomic = RelaxedAtomic::new(false);
 
+// External decoders are managed by the app, which must support all codecs
+#[cfg(target_os = "android")]
+pub fn is_codec_supported(codec: CodecType) -> bool {
+    EXTERNAL_DECODER.value() || crate::platform::is_decoder_available(codec)
+}
+
+#[cfg(not(target_os = "android"))]
+pub fn is_codec_supported(_: CodecType) -> bool {
+    true
+}
+
 pub fn create_decoder(lazy_config: DecoderInitializationConfig) {
     let mut config = DECODER_INIT_CONFIG.lock();
     config.codec = lazy_config.codec;
@@ -31,7 +31,10 @@ use alvr_common::{
 };
 use alvr_events::ButtonValue;
 use alvr_session::{CodecType, FoveatedRenderingDesc, OculusFovetionLevel};
-use alvr_sockets::{BatteryPacket, ClientControlPacket, ClientStatistics, Tracking, ViewsConfig};
+use alvr_sockets::{
+    BatteryPacket, ClientControlPacket, ClientStatistics, ControlExtension, ExtensionHandler,
+    ExtensionPacket, ExtensionRegistry, Tracking, ViewsConfig,
+};
 use decoder::EXTERNAL_DECODER;
 use serde::{Deserialize, Serialize};
 use statistics::StatisticsManager;
@@ -41,7 +44,7 @@ use std::{
     time::Duration,
 };
 use storage::Config;
-use tokio::{sync::mpsc, sync::Notify};
+use tokio::sync::{mpsc, oneshot, Notify};
 
 static STATISTICS_MANAGER: Lazy<Mutex<Option<StatisticsManager>>> = Lazy::new(|| Mutex::new(None));
 
@@ -52,6 +55,11 @@ static STATISTICS_SENDER: Lazy<Mutex<Option<mpsc::UnboundedSender<ClientStatisti
 static CONTROL_CHANNEL_SENDER: Lazy<Mutex<Option<mpsc::UnboundedSender<ClientControlPacket>>>> =
     Lazy::new(|| Mutex::new(None));
 static DISCONNECT_NOTIFIER: Lazy<Notify> = Lazy::new(Notify::new);
+// Set while a pairing waits for the user to confirm the code
+static PAIRING_CONFIRMATION_SENDER: Lazy<Mutex<Option<oneshot::Sender<bool>>>> =
+    Lazy::new(|| Mutex::new(None));
+static EXTENSION_REGISTRY: Lazy<Mutex<ExtensionRegistry>> =
+    Lazy::new(|| Mutex::new(ExtensionRegistry::new()));
 
 static EVENT_QUEUE: Lazy<Mutex<VecDeque<ClientCoreEvent>>> =
     Lazy::new(|| Mutex::new(VecDeque::new()));
@@ -59,12 +67,18 @@ static EVENT_QUEUE: Lazy<Mutex<VecDeque<ClientCoreEvent>>> =
 static IS_ALIVE: RelaxedAtomic = RelaxedAtomic::new(true);
 static IS_RESUMED: RelaxedAtomic = RelaxedAtomic::new(false);
 static IS_STREAMING: RelaxedAtomic = RelaxedAtomic::new(false);
+static LOCAL_IPC: RelaxedAtomic = RelaxedAtomic::new(false);
 
 static CONNECTION_THREAD: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientCoreEvent {
     UpdateHudMessage(String),
+    // A new streamer wants to pair. The user must check that the streamer shows the same code, then
+    // call confirm_pairing(). The code is also shown in the HUD message.
+    PairingRequested {
+        code: u32,
+    },
     StreamingStarted {
         view_resolution: UVec2,
         fps: f32,
@@ -93,6 +107,13 @@ pub fn manufacturer_name() -> String {
     platform::manufacturer_name()
 }
 
+// Wait for a streamer on the same machine through Unix domain sockets, instead of announcing the
+// client on the network. The streamer must use the local IPC transport too. Call before
+// initialize().
+pub fn set_local_ipc(enabled: bool) {
+    LOCAL_IPC.set(enabled);
+}
+
 pub fn initialize(
     recommended_view_resolution: UVec2,
     supported_refresh_rates: Vec<f32>,
@@ -142,6 +163,12 @@ pub fn poll_event() -> Option<ClientCoreEvent> {
     EVENT_QUEUE.lock().pop_front()
 }
 
+pub fn confirm_pairing(accepted: bool) {
+    if let Some(sender) = PAIRING_CONFIRMATION_SENDER.lock().take() {
+        sender.send(accepted).ok();
+    }
+}
+
 pub fn send_views_config(fov: [Fov; 2], ipd_m: f32) {
     if let Some(sender) = &*CONTROL_CHANNEL_SENDER.lock() {
         sender
@@ -176,6 +203,22 @@ pub fn send_button(path_id: u64, value: ButtonValue) {
     }
 }
 
+// The handler is called from the connection thread for each extension message sent by the server
+pub fn register_extension_handler(handler: impl ExtensionHandler + 'static) {
+    EXTENSION_REGISTRY.lock().register(handler);
+}
+
+pub fn send_extension<E: ControlExtension>(message: &E) {
+    if let Some(sender) = &*CONTROL_CHANNEL_SENDER.lock() {
+        match ExtensionPacket::new(message) {
+            Ok(packet) => {
+                sender.send(ClientControlPacket::Extension(packet)).ok();
+            }
+            Err(e) => warn!("Failed to encode extension {}: {e}", E::ID),
+        }
+    }
+}
+
 pub fn send_tracking(tracking: Tracking) {
     if let Some(sender) = &*TRACKING_SENDER.lock() {
         sender.send(tracking).ok();
@@ -31,6 +31,7 @@ use std::{
 };
 
 static WIFI_LOCK: Lazy<Mutex<Option<GlobalRef>>> = Lazy::new(|| Mutex::new(None));
+static MULTICAST_LOCK: Lazy<Mutex<Option<GlobalRef>>> = Lazy::new(|| Mutex::new(None));
 
 struct FakeThreadSafe<T>(T);
 unsafe impl<T> Send for FakeThreadSafe<T> {}
@@ -199,6 +200,31 @@ pub fn acquire_wifi_lock() {
 
         *maybe_wifi_lock = Some(env.new_global_ref(wifi_lock).unwrap());
     }
+
+    // Multicast packets are filtered out without this lock, which breaks mDNS discovery
+    let mut maybe_multicast_lock = MULTICAST_LOCK.lock();
+
+    if maybe_multicast_lock.is_none() {
+        let vm = vm();
+        let mut env = vm.attach_current_thread().unwrap();
+
+        let wifi_manager = get_system_service(&mut env, "wifi");
+        let multicast_lock_jstring = env.new_string("alvr_multicast_lock").unwrap();
+        let multicast_lock = env
+            .call_method(
+                wifi_manager,
+                "createMulticastLock",
+                "(Ljava/lang/String;)Landroid/net/wifi/WifiManager$MulticastLock;",
+                &[(&multicast_lock_jstring).into()],
+            )
+            .unwrap()
+            .l()
+            .unwrap();
+        env.call_method(&multicast_lock, "acquire", "()V", &[])
+            .unwrap();
+
+        *maybe_multicast_lock = Some(env.new_global_ref(multicast_lock).unwrap());
+    }
 }
 
 pub fn release_wifi_lock() {
@@ -211,6 +237,14 @@ pub fn release_wifi_lock() {
 
         // wifi_lock is dropped here
     }
+
+    if let Some(multicast_lock) = MULTICAST_LOCK.lock().take() {
+        let vm = vm();
+        let mut env = vm.attach_current_thread().unwrap();
+
+        env.call_method(multicast_lock.as_obj(), "release", "()V", &[])
+            .unwrap();
+    }
 }
 
 pub fn battery_status() -> (f32, bool) {
@@ -345,6 +379,18 @@ impl Drop for VideoDecoderDequeuer {
     }
 }
 
+fn codec_mime(codec: CodecType) -> &'static str {
+    match codec {
+        CodecType::H264 => "video/avc",
+        CodecType::Hevc => "video/hevc",
+    }
+}
+
+// Some devices have no hardware decoder for HEVC. The decoder is created and released immediately
+pub fn is_decoder_available(codec: CodecType) -> bool {
+    MediaCodec::from_decoder_type(codec_mime(codec)).is_some()
+}
+
 // Create a enqueuer/dequeuer pair. To preserve the state of internal variables, use
 // `enqueuer.recreate_decoder()` instead of dropping the pair and calling this function again.
 pub fn video_decoder_split(
@@ -369,10 +415,7 @@ pub fn video_decoder_split(
             // 2x: keep the target buffering in the middle of the max amount of queuable frames
             let available_buffering_frames = (2. * config.max_buffering_frames).ceil() as usize;
 
-            let mime = match config.codec {
-                CodecType::H264 => "video/avc",
-                CodecType::Hevc => "video/hevc",
-            };
+            let mime = codec_mime(config.codec);
 
             let format = MediaFormat::new();
             format.set_str("mime", mime);
@@ -3,8 +3,8 @@ pub mod android;
 
 #[cfg(target_os = "android")]
 pub use android::{
-    acquire_wifi_lock, battery_status, context, device_model, local_ip, manufacturer_name,
-    release_wifi_lock, try_get_microphone_permission, video_decoder_split, vm,
+    acquire_wifi_lock, battery_status, context, device_model, is_decoder_available, local_ip,
+    manufacturer_name, release_wifi_lock, try_get_microphone_permission, video_decoder_split, vm,
     VideoDecoderDequeuer, VideoDecoderEnqueuer,
 };
 
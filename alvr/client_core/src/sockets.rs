@@ -1,6 +1,10 @@
 use alvr_common::{StrResult, *};
-use alvr_sockets::{CONTROL_PORT, LOCAL_IP};
-use std::net::{Ipv4Addr, UdpSocket};
+use alvr_sockets::{CONTROL_PORT, IPV6_ALL_NODES};
+use if_addrs::IfAddr;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddrV6, UdpSocket},
+};
 
 pub struct AnnouncerSocket {
     socket: UdpSocket,
@@ -9,21 +13,48 @@ pub struct AnnouncerSocket {
 
 impl AnnouncerSocket {
     pub fn new(hostname: &str) -> StrResult<Self> {
-        let socket = UdpSocket::bind((LOCAL_IP, CONTROL_PORT)).map_err(err!())?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, CONTROL_PORT)).map_err(err!())?;
         socket.set_broadcast(true).map_err(err!())?;
 
         let mut packet = [0; 56];
         packet[0..ALVR_NAME.len()].copy_from_slice(ALVR_NAME.as_bytes());
-        packet[16..24].copy_from_slice(&alvr_common::protocol_id().to_le_bytes());
+        packet[16..24].copy_from_slice(&DISCOVERY_PROTOCOL_ID.to_le_bytes());
         packet[24..24 + hostname.len()].copy_from_slice(hostname.as_bytes());
 
         Ok(Self { socket, packet })
     }
 
+    // IPv6 has no broadcast. The packet is sent to the all-nodes multicast group of each interface,
+    // from a routable address: the server could not connect back to a link-local address.
+    fn multicast_ipv6(&self) -> io::Result<bool> {
+        let mut sent = false;
+        for interface in if_addrs::get_if_addrs()? {
+            if let (IfAddr::V6(address), Some(index)) = (&interface.addr, interface.index) {
+                if interface.is_loopback()
+                    || alvr_sockets::is_ipv6_link_local(IpAddr::V6(address.ip))
+                {
+                    continue;
+                }
+
+                let destination = SocketAddrV6::new(IPV6_ALL_NODES, CONTROL_PORT, 0, index);
+                sent |= UdpSocket::bind((address.ip, 0))
+                    .and_then(|socket| socket.send_to(&self.packet, destination))
+                    .is_ok();
+            }
+        }
+
+        Ok(sent)
+    }
+
     pub fn broadcast(&self) -> StrResult {
-        self.socket
-            .send_to(&self.packet, (Ipv4Addr::BROADCAST, CONTROL_PORT))
-            .map_err(err!())?;
-        Ok(())
+        let ipv4_res = self
+            .socket
+            .send_to(&self.packet, (Ipv4Addr::BROADCAST, CONTROL_PORT));
+
+        // On IPv6-only networks the IPv4 broadcast fails
+        match (ipv4_res, self.multicast_ipv6()) {
+            (Ok(_), _) | (_, Ok(true)) => Ok(()),
+            (Err(e), _) => fmt_e!("{e}"),
+        }
     }
 }
@@ -1,5 +1,5 @@
 use alvr_common::SlidingWindowAverage;
-use alvr_sockets::ClientStatistics;
+use alvr_sockets::{ClientStatistics, ClockSync, PongPacket};
 use std::{
     collections::VecDeque,
     time::{Duration, Instant},
@@ -17,6 +17,7 @@ pub struct StatisticsManager {
     prev_vsync: Instant,
     total_pipeline_latency_average: SlidingWindowAverage<Duration>,
     steamvr_pipeline_latency: Duration,
+    clock_sync: ClockSync,
 }
 
 impl StatisticsManager {
@@ -36,6 +37,7 @@ impl StatisticsManager {
             steamvr_pipeline_latency: Duration::from_secs_f32(
                 steamvr_pipeline_frames * nominal_server_frame_interval.as_secs_f32(),
             ),
+            clock_sync: ClockSync::new(),
         }
     }
 
@@ -45,12 +47,14 @@ impl StatisticsManager {
             .iter()
             .any(|frame| frame.client_stats.target_timestamp == target_timestamp)
         {
+            let now = Instant::now();
             self.history_buffer.push_front(HistoryFrame {
-                input_acquired: Instant::now(),
+                input_acquired: now,
                 // this is just a placeholder because Instant does not have a default value
-                video_packet_received: Instant::now(),
+                video_packet_received: now,
                 client_stats: ClientStatistics {
                     target_timestamp,
+                    input_acquired_timestamp: self.clock_sync.timestamp(now),
                     ..Default::default()
                 },
             });
@@ -61,13 +65,24 @@ impl StatisticsManager {
         }
     }
 
-    pub fn report_video_packet_received(&mut self, target_timestamp: Duration) {
+    pub fn report_video_packet_received(
+        &mut self,
+        target_timestamp: Duration,
+        reorder_depth: u32,
+        late_shards_count: usize,
+        dropped_shards_count: usize,
+    ) {
         if let Some(frame) = self
             .history_buffer
             .iter_mut()
             .find(|frame| frame.client_stats.target_timestamp == target_timestamp)
         {
-            frame.video_packet_received = Instant::now();
+            let now = Instant::now();
+            frame.video_packet_received = now;
+            frame.client_stats.video_packet_received_timestamp = self.clock_sync.timestamp(now);
+            frame.client_stats.video_reorder_depth = reorder_depth;
+            frame.client_stats.video_late_shards = late_shards_count;
+            frame.client_stats.video_dropped_shards = dropped_shards_count;
         }
     }
 
@@ -121,6 +136,22 @@ impl StatisticsManager {
         }
     }
 
+    pub fn ping_timestamp(&self) -> Duration {
+        self.clock_sync.now()
+    }
+
+    // The receive instant must be taken as soon as the packet is received, the time spent waiting
+    // for locks before answering must not count as round trip time
+    pub fn answer_ping(&self, origin_timestamp: Duration, receive_instant: Instant) -> PongPacket {
+        self.clock_sync
+            .answer_ping(origin_timestamp, self.clock_sync.timestamp(receive_instant))
+    }
+
+    pub fn report_pong(&mut self, pong: PongPacket, receive_instant: Instant) {
+        let receive_timestamp = self.clock_sync.timestamp(receive_instant);
+        self.clock_sync.report_pong(pong, receive_timestamp);
+    }
+
     pub fn summary(&self, target_timestamp: Duration) -> Option<ClientStatistics> {
         self.history_buffer
             .iter()
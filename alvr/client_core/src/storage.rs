@@ -4,7 +4,7 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::{fs, path::PathBuf};
 
-fn config_path() -> PathBuf {
+fn config_dir() -> PathBuf {
     app_dirs2::app_root(
         AppDataType::UserConfig,
         &AppInfo {
@@ -13,13 +13,23 @@ fn config_path() -> PathBuf {
         },
     )
     .unwrap()
-    .join("session.json")
+}
+
+fn config_path() -> PathBuf {
+    config_dir().join("session.json")
+}
+
+pub fn stream_capture_path() -> PathBuf {
+    config_dir().join("stream_capture.bin")
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub protocol_id: u64,
     pub hostname: String,
+    // One for each streamer this client is paired with
+    #[serde(default)]
+    pub pairing_keys: Vec<String>,
 }
 
 impl Default for Config {
@@ -35,6 +45,7 @@ impl Default for Config {
                 rng.gen_range(0..10),
                 rng.gen_range(0..10),
             ),
+            pairing_keys: vec![],
         }
     }
 }
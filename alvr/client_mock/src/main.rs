@@ -11,6 +11,7 @@ use eframe::{
     Frame, NativeOptions,
 };
 use std::{
+    env,
     f32::consts::{FRAC_PI_2, PI},
     sync::{
         mpsc::{self, TryRecvError},
@@ -58,6 +59,7 @@ struct WindowOutput {
     foveated_rendering: bool,
     decoder_codec: Option<CodecType>,
     current_frame_timestamp: Duration,
+    pairing_code: Option<u32>,
 }
 
 impl Default for WindowOutput {
@@ -70,6 +72,7 @@ impl Default for WindowOutput {
             foveated_rendering: false,
             decoder_codec: None,
             current_frame_timestamp: Duration::ZERO,
+            pairing_code: None,
         }
     }
 }
@@ -106,6 +109,20 @@ impl eframe::App for Window {
         CentralPanel::default().show(context, |ui| {
             ui.vertical_centered(|ui| {
                 ui.heading(RichText::new(&self.output.hud_message));
+
+                if let Some(code) = self.output.pairing_code {
+                    ui.label(format!("Pairing code: {code:06}"));
+                    ui.horizontal(|ui| {
+                        if ui.button("Confirm pairing").clicked() {
+                            alvr_client_core::confirm_pairing(true);
+                            self.output.pairing_code = None;
+                        }
+                        if ui.button("Reject pairing").clicked() {
+                            alvr_client_core::confirm_pairing(false);
+                            self.output.pairing_code = None;
+                        }
+                    });
+                }
             });
             ui.label(format!("FPS: {}", self.output.fps));
             ui.label(format!("Connected: {}", self.output.connected));
@@ -212,6 +229,11 @@ fn client_thread(
     output_sender: mpsc::Sender<WindowOutput>,
     input_receiver: mpsc::Receiver<WindowInput>,
 ) {
+    // Connect to a streamer on the same machine without opening network ports, like in CI
+    if env::args().any(|arg| arg == "--local-ipc") {
+        alvr_client_core::set_local_ipc(true);
+    }
+
     alvr_client_core::initialize(
         UVec2::new(1920, 1832),
         vec![60.0, 72.0, 80.0, 90.0, 120.0],
@@ -233,6 +255,11 @@ fn client_thread(
             match event {
                 ClientCoreEvent::UpdateHudMessage(message) => {
                     window_output.hud_message = message;
+                    // The message changes once the pairing is answered
+                    window_output.pairing_code = None;
+                }
+                ClientCoreEvent::PairingRequested { code } => {
+                    window_output.pairing_code = Some(code);
                 }
                 ClientCoreEvent::StreamingStarted {
                     view_resolution,
@@ -326,3 +353,128 @@ fn main() {
 
     client_thread.join().unwrap();
 }
+
+// The mock client resumes a stream with a mock streamer, over the loopback interface
+#[cfg(test)]
+mod tests {
+    use alvr_common::prelude::*;
+    use alvr_sockets::{
+        ClientConnectionResult, PairingKey, PeerType, ProtoControlSocket, ResumeAction,
+        ResumeStateMachine, SessionToken,
+    };
+    use std::{
+        future,
+        net::Ipv4Addr,
+        time::{Duration, Instant},
+    };
+
+    const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+    const RESUME_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+    // Returns the client and streamer sockets. Without a pairing key, the peers are paired
+    async fn connect(
+        pairing_key: Option<PairingKey>,
+    ) -> StrResult<(ProtoControlSocket, ProtoControlSocket, PairingKey)> {
+        let listener = alvr_sockets::get_server_listener().await?;
+        let (client, server) = tokio::join!(
+            ProtoControlSocket::connect_to(PeerType::Server(&listener)),
+            ProtoControlSocket::connect_to(PeerType::AnyClient(vec![Ipv4Addr::LOCALHOST.into()])),
+        );
+        let (mut client, _) = client?;
+        let (mut server, _) = server?;
+
+        let known_pairing_keys = pairing_key.into_iter().collect::<Vec<_>>();
+        let (client_res, server_res) = tokio::join!(
+            client.authenticate_server(&known_pairing_keys, |_| future::ready(true)),
+            server.authenticate_client(pairing_key, |_| future::ready(true)),
+        );
+        let (pairing_key, _) = client_res?;
+        server_res?;
+
+        Ok((client, server, pairing_key))
+    }
+
+    async fn try_resume(
+        pairing_key: PairingKey,
+        client_token: SessionToken,
+        server_token: SessionToken,
+        server_state: &ResumeStateMachine,
+        now: Instant,
+    ) -> StrResult {
+        let (mut client, mut server, _) = connect(Some(pairing_key)).await?;
+
+        client
+            .send(&ClientConnectionResult::ResumeRequested {
+                session_token: client_token,
+            })
+            .await?;
+
+        match server.recv().await? {
+            ClientConnectionResult::ResumeRequested { session_token } => {
+                server_state.validate_token(server_token, session_token, now)
+            }
+            _ => fmt_e!("Unexpected packet"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let (_, _, pairing_key) = connect(None).await.unwrap();
+        let session_token = SessionToken::new_random().unwrap();
+
+        let mut client_state = ResumeStateMachine::new(RESUME_TIMEOUT, RESUME_RETRY_INTERVAL);
+        let mut server_state = ResumeStateMachine::new(RESUME_TIMEOUT, RESUME_RETRY_INTERVAL);
+        client_state.connected();
+        server_state.connected();
+
+        // Disconnection
+        let start = Instant::now();
+        client_state.connection_lost(start);
+        server_state.connection_lost(start);
+        assert_eq!(client_state.poll(start), ResumeAction::TryResume);
+        assert_eq!(server_state.poll(start), ResumeAction::TryResume);
+
+        let wrong_token = SessionToken::new_random().unwrap();
+        assert!(try_resume(
+            pairing_key,
+            wrong_token,
+            session_token,
+            &server_state,
+            start
+        )
+        .await
+        .is_err());
+        server_state.attempt_failed(start);
+
+        // Resume with the session token
+        let now = start + RESUME_RETRY_INTERVAL;
+        assert_eq!(server_state.poll(now), ResumeAction::TryResume);
+        try_resume(
+            pairing_key,
+            session_token,
+            session_token,
+            &server_state,
+            now,
+        )
+        .await
+        .unwrap();
+        client_state.connected();
+        server_state.connected();
+
+        // Expired session token
+        client_state.connection_lost(now);
+        server_state.connection_lost(now);
+        let expired = now + RESUME_TIMEOUT;
+        assert_eq!(client_state.poll(expired), ResumeAction::GiveUp);
+        assert!(try_resume(
+            pairing_key,
+            session_token,
+            session_token,
+            &server_state,
+            expired
+        )
+        .await
+        .is_err());
+        assert_eq!(server_state.poll(expired), ResumeAction::GiveUp);
+    }
+}
@@ -456,6 +456,36 @@ pub fn initialize_streaming_interaction(
     }
 }
 
+// Pairing is confirmed with the A button and rejected with the B button. Returns None until one of
+// them is pressed.
+pub fn poll_pairing_confirmation(
+    xr_session: &xr::Session<xr::AnyGraphics>,
+    context: &StreamingInteractionContext,
+) -> Option<bool> {
+    xr_session
+        .sync_actions(&[(&context.action_set).into()])
+        .ok()?;
+
+    let is_pressed = |id: u64| {
+        if let Some(ButtonAction::Binary(action)) = context.button_actions.get(&id) {
+            action
+                .state(xr_session, xr::Path::NULL)
+                .map(|state| state.current_state && state.changed_since_last_sync)
+                .unwrap_or(false)
+        } else {
+            false
+        }
+    };
+
+    if is_pressed(*A_CLICK_ID) {
+        Some(true)
+    } else if is_pressed(*B_CLICK_ID) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
 pub fn get_hand_motion(
     session: &xr::Session<xr::AnyGraphics>,
     reference_space: &xr::Space,
@@ -427,6 +427,7 @@ pub fn entry_point() {
         let mut stream_swapchains = None;
         let mut stream_view_resolution = UVec2::ZERO;
         let mut streaming_input_thread = None::<thread::JoinHandle<_>>;
+        let mut pairing_requested = false;
         let views_history = Arc::new(Mutex::new(VecDeque::new()));
 
         let default_view = xr::View {
@@ -581,6 +582,7 @@ pub fn entry_point() {
                     ClientCoreEvent::UpdateHudMessage(message) => {
                         alvr_client_core::opengl::update_hud_message(&message);
                     }
+                    ClientCoreEvent::PairingRequested { .. } => pairing_requested = true,
                     ClientCoreEvent::StreamingStarted {
                         view_resolution,
                         fps,
@@ -692,6 +694,17 @@ pub fn entry_point() {
                 }
             }
 
+            // The pairing code is shown in the HUD message
+            if pairing_requested {
+                if let Some(accepted) = interaction::poll_pairing_confirmation(
+                    &xr_session.clone().into_any_graphics(),
+                    &streaming_interaction_context,
+                ) {
+                    alvr_client_core::confirm_pairing(accepted);
+                    pairing_requested = false;
+                }
+            }
+
             let frame_state = match xr_frame_waiter.wait() {
                 Ok(state) => state,
                 Err(e) => {
@@ -36,6 +36,37 @@ pub fn protocol_id() -> u64 {
     hash_string(&protocol_string)
 }
 
+// Identifies the format of the discovery packets and of the handshake that follows the connection
+// of the control socket. It changes only when these formats change, unlike protocol_id(), so that
+// peers with incompatible versions can still find each other and report why they can't connect.
+pub const DISCOVERY_PROTOCOL_ID: u64 = 1;
+
+// Peers from before DISCOVERY_PROTOCOL_ID announced themselves with their protocol_id(). Returns
+// the version it was derived from, like "20" or "20-dev10", if it is a known one
+pub fn legacy_protocol_version(protocol_id: u64) -> Option<String> {
+    (0..=ALVR_VERSION.major)
+        .flat_map(|major| {
+            [major.to_string()]
+                .into_iter()
+                .chain((0..100).map(move |n| format!("{major}-dev{n:02}")))
+        })
+        .chain([format!("{}-{}", ALVR_VERSION.major, ALVR_VERSION.pre)])
+        .find(|version| hash_string(version) == protocol_id)
+}
+
+// Message for a client discovered with a different protocol ID, which cannot be connected to
+pub fn incompatible_client_message(hostname: &str, protocol_id: Option<u64>) -> String {
+    let client_version = protocol_id
+        .and_then(legacy_protocol_version)
+        .map(|version| format!("v{version}"))
+        .unwrap_or_else(|| "unknown version".into());
+
+    format!(
+        "Found client {hostname} ({client_version}), which is incompatible with the streamer (v{}). Upgrade the client",
+        *ALVR_VERSION
+    )
+}
+
 // deprecated
 pub fn is_version_compatible(other_version: &Version) -> bool {
     let protocol_string = if other_version.pre.is_empty() {
@@ -46,3 +77,23 @@ pub fn is_version_compatible(other_version: &Version) -> bool {
 
     protocol_id() == hash_string(&protocol_string)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_protocol_version() {
+        let version = if ALVR_VERSION.pre.is_empty() {
+            ALVR_VERSION.major.to_string()
+        } else {
+            format!("{}-{}", ALVR_VERSION.major, ALVR_VERSION.pre)
+        };
+        assert_eq!(legacy_protocol_version(protocol_id()), Some(version));
+        assert_eq!(
+            legacy_protocol_version(hash_string("19")),
+            Some("19".into())
+        );
+        assert_eq!(legacy_protocol_version(DISCOVERY_PROTOCOL_ID), None);
+    }
+}
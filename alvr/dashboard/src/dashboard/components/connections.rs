@@ -3,14 +3,32 @@ use crate::{
     steamvr_launcher::LAUNCHER,
     theme::{self, log_colors},
 };
-use alvr_session::SessionDesc;
+use alvr_events::PairingEvent;
+use alvr_session::{SessionDesc, StreamingRole};
 use alvr_sockets::ClientListAction;
 use eframe::{
-    egui::{Frame, Grid, Layout, RichText, TextEdit, Ui, Window},
+    egui::{Button, Context, Frame, Grid, Layout, RichText, TextEdit, Ui, Window},
     emath::{Align, Align2},
     epaint::Color32,
 };
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr},
+};
+
+// Accepts IPv4 and IPv6 addresses, also in the bracketed form used in URLs. Link-local IPv6
+// addresses are rejected since they need the interface to be specified.
+fn parse_manual_ip(text: &str) -> Option<IpAddr> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .unwrap_or(text);
+
+    text.parse()
+        .ok()
+        .filter(|ip| !alvr_sockets::is_ipv6_link_local(*ip))
+}
 
 struct EditPopupState {
     new_client: bool,
@@ -20,13 +38,64 @@ struct EditPopupState {
 
 pub struct ConnectionsTab {
     edit_popup_state: Option<EditPopupState>,
+    // Pairing codes waiting for confirmation, by client hostname
+    pending_pairings: BTreeMap<String, u32>,
 }
 
 impl ConnectionsTab {
     pub fn new() -> Self {
         Self {
             edit_popup_state: None,
+            pending_pairings: BTreeMap::new(),
+        }
+    }
+
+    pub fn update_pairing(&mut self, event: PairingEvent) {
+        if let Some(code) = event.code {
+            self.pending_pairings.insert(event.hostname, code);
+        } else {
+            self.pending_pairings.remove(&event.hostname);
+        }
+    }
+
+    // Shown on top of any tab
+    pub fn pairing_ui(&mut self, context: &Context) -> Option<DashboardRequest> {
+        let mut response = None;
+
+        for (hostname, code) in &self.pending_pairings {
+            Window::new(format!("Pairing with {hostname}"))
+                .anchor(Align2::CENTER_CENTER, (0.0, 0.0))
+                .resizable(false)
+                .collapsible(false)
+                .show(context, |ui| {
+                    ui.label("Confirm only if the client shows the same code:");
+                    ui.vertical_centered(|ui| {
+                        ui.heading(RichText::new(format!("{code:06}")).size(30.0).strong());
+                    });
+                    ui.columns(2, |ui| {
+                        let accepted = if ui[0].button("Confirm").clicked() {
+                            Some(true)
+                        } else if ui[1].button("Reject").clicked() {
+                            Some(false)
+                        } else {
+                            None
+                        };
+
+                        if let Some(accepted) = accepted {
+                            response = Some(DashboardRequest::ConfirmPairing {
+                                hostname: hostname.clone(),
+                                accepted,
+                            });
+                        }
+                    });
+                });
         }
+
+        if let Some(DashboardRequest::ConfirmPairing { hostname, .. }) = &response {
+            self.pending_pairings.remove(hostname);
+        }
+
+        response
     }
 
     pub fn ui(
@@ -113,6 +182,22 @@ impl ConnectionsTab {
                                     data.current_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
                                     data.display_name
                                 ));
+                                match data.streaming_role {
+                                    Some(StreamingRole::Primary) => {
+                                        ui.label(RichText::new("Primary").strong());
+                                    }
+                                    Some(StreamingRole::Spectator) => {
+                                        ui.label("Spectator");
+                                    }
+                                    None => (),
+                                }
+                                if let Some(probe) = data.bandwidth_probe {
+                                    ui.label(format!(
+                                        "Link: {:.0} Mbps, {:.1}% loss",
+                                        probe.throughput_bps / 1e6,
+                                        probe.packet_loss * 100.0
+                                    ));
+                                }
                             });
                             ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                                 if ui.button("Remove").clicked() {
@@ -121,6 +206,13 @@ impl ConnectionsTab {
                                         action: ClientListAction::RemoveEntry,
                                     });
                                 }
+                                // The stream is not restarted
+                                if data.streaming_role == Some(StreamingRole::Spectator)
+                                    && ui.button("Make primary").clicked()
+                                {
+                                    response =
+                                        Some(DashboardRequest::SetPrimaryClient(hostname.clone()));
+                                }
                                 if ui.button("Edit").clicked() {
                                     self.edit_popup_state = Some(EditPopupState {
                                         new_client: false,
@@ -161,17 +253,29 @@ impl ConnectionsTab {
                         );
                         ui[0].label("IP Addresses:");
                         for address in &mut state.ips {
-                            ui[1].text_edit_singleline(address);
+                            let mut text_edit = TextEdit::singleline(address);
+                            if !address.trim().is_empty() && parse_manual_ip(address).is_none() {
+                                text_edit = text_edit.text_color(log_colors::ERROR_LIGHT);
+                            }
+                            ui[1].add(text_edit);
                         }
                         if ui[1].button("Add new").clicked() {
                             state.ips.push("192.168.1.2".to_string());
                         }
                     });
                     ui.columns(2, |ui| {
-                        if ui[0].button("Ok").clicked() {
-                            let manual_ips =
-                                state.ips.iter().filter_map(|s| s.parse().ok()).collect();
+                        let manual_ips = state
+                            .ips
+                            .iter()
+                            .filter(|s| !s.trim().is_empty())
+                            .map(|s| parse_manual_ip(s))
+                            .collect::<Option<Vec<_>>>();
 
+                        // Invalid addresses are highlighted and must be fixed before confirming
+                        let ok_clicked = ui[0]
+                            .add_enabled(manual_ips.is_some(), Button::new("Ok"))
+                            .clicked();
+                        if let Some(manual_ips) = manual_ips.filter(|_| ok_clicked) {
                             if state.new_client {
                                 response = Some(DashboardRequest::UpdateClientList {
                                     hostname: state.hostname.clone(),
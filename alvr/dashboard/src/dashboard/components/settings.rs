@@ -5,8 +5,8 @@ use super::{
 };
 use crate::dashboard::{get_id, DashboardRequest};
 use alvr_session::{SessionSettings, Settings};
-use alvr_sockets::AudioDevicesList;
-use eframe::egui::{Grid, ScrollArea, Ui};
+use alvr_sockets::{AudioDevicesList, SettingsChange, SettingsHistory};
+use eframe::egui::{Button, Grid, ScrollArea, Ui};
 use serde_json as json;
 
 pub struct SettingsTab {
@@ -19,6 +19,34 @@ pub struct SettingsTab {
     advanced_grid_id: usize,
     session_settings_json: json::Value,
     root_control: SettingControl,
+    session_backups: Vec<String>,
+    settings_history: SettingsHistory,
+}
+
+fn format_history_value(value: &json::Value) -> String {
+    const MAX_LEN: usize = 60;
+
+    let string = value.to_string();
+    if string.chars().count() > MAX_LEN {
+        format!("{}…", string.chars().take(MAX_LEN).collect::<String>())
+    } else {
+        string
+    }
+}
+
+// Undone changes are grayed out
+fn settings_change_ui(ui: &mut Ui, change: &SettingsChange, undone: bool) {
+    ui.add_enabled_ui(!undone, |ui| {
+        ui.label(format!("{} ({:?})", change.timestamp, change.origin));
+        for value in &change.values {
+            ui.label(format!(
+                "    {}: {} → {}",
+                alvr_sockets::format_path(&value.path),
+                format_history_value(&value.old_value),
+                format_history_value(&value.new_value)
+            ));
+        }
+    });
 }
 
 impl SettingsTab {
@@ -41,6 +69,8 @@ impl SettingsTab {
             advanced_grid_id: get_id(),
             session_settings_json: json::to_value(session_settings).unwrap(),
             root_control: SettingControl::new(nesting_info, schema),
+            session_backups: vec![],
+            settings_history: SettingsHistory::default(),
         }
     }
 
@@ -72,7 +102,15 @@ impl SettingsTab {
             .update_session_settings(&self.session_settings_json);
     }
 
-    pub fn ui(&mut self, ui: &mut Ui) -> Option<DashboardRequest> {
+    pub fn update_session_backups(&mut self, ids: Vec<String>) {
+        self.session_backups = ids;
+    }
+
+    pub fn update_settings_history(&mut self, history: SettingsHistory) {
+        self.settings_history = history;
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) -> Vec<DashboardRequest> {
         let mut requests = vec![];
 
         ui.heading("Presets");
@@ -102,6 +140,56 @@ impl SettingsTab {
 
         ui.add_space(15.0);
 
+        let mut dashboard_requests = vec![];
+        ui.collapsing("Session backups", |ui| {
+            if ui.button("Refresh").clicked() {
+                dashboard_requests.push(DashboardRequest::GetSessionBackups);
+            }
+            if self.session_backups.is_empty() {
+                ui.label("No backups yet");
+            }
+            for id in &self.session_backups {
+                ui.horizontal(|ui| {
+                    ui.label(id);
+                    if ui.small_button("Restore").clicked() {
+                        dashboard_requests.push(DashboardRequest::RestoreSessionBackup(id.clone()));
+                        // The current session has been backed up too
+                        dashboard_requests.push(DashboardRequest::GetSessionBackups);
+                    }
+                });
+            }
+        });
+
+        ui.collapsing("History", |ui| {
+            let history = &self.settings_history;
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!history.undo.is_empty(), Button::new("Undo"))
+                    .clicked()
+                {
+                    dashboard_requests.push(DashboardRequest::UndoSettingsChange);
+                }
+                if ui
+                    .add_enabled(!history.redo.is_empty(), Button::new("Redo"))
+                    .clicked()
+                {
+                    dashboard_requests.push(DashboardRequest::RedoSettingsChange);
+                }
+            });
+            if history.undo.is_empty() && history.redo.is_empty() {
+                ui.label("No changes yet");
+            }
+            // From the newest
+            for change in history.redo.iter().rev() {
+                settings_change_ui(ui, change, true);
+            }
+            for change in history.undo.iter().rev() {
+                settings_change_ui(ui, change, false);
+            }
+        });
+
+        ui.add_space(15.0);
+
         ui.horizontal(|ui| {
             ui.heading("All Settings (Advanced)");
             notice::notice(ui, "Changing some advanced settings may break ALVR");
@@ -125,9 +213,9 @@ impl SettingsTab {
             });
 
         if !requests.is_empty() {
-            Some(DashboardRequest::SetValues(requests))
-        } else {
-            None
+            dashboard_requests.push(DashboardRequest::SetValues(requests));
         }
+
+        dashboard_requests
     }
 }
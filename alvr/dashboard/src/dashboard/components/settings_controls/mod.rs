@@ -62,6 +62,7 @@ pub enum SettingControl {
     Text(text::Control),
     Numeric(number::Control),
     Array(array::Control),
+    Vector(vector::Control),
     None,
 }
 
@@ -101,7 +102,9 @@ impl SettingControl {
             SchemaNode::Array(schema_array) => {
                 Self::Array(array::Control::new(nesting_info, schema_array))
             }
-            // SchemaNode::Vector { default_element, default } => todo!(),
+            SchemaNode::Vector {
+                default_element, ..
+            } => Self::Vector(vector::Control::new(nesting_info, *default_element)),
             // SchemaNode::Dictionary { default_key, default_value, default } => todo!(),
             _ => Self::None,
         }
@@ -123,6 +126,7 @@ impl SettingControl {
             Self::Text(control) => control.ui(ui, session_fragment, allow_inline),
             Self::Numeric(control) => control.ui(ui, session_fragment, allow_inline),
             Self::Array(control) => control.ui(ui, session_fragment, allow_inline),
+            Self::Vector(control) => control.ui(ui, session_fragment, allow_inline),
             Self::None => {
                 grid_flow_inline(ui, allow_inline);
                 ui.add_enabled_ui(false, |ui| ui.label("Unimplemented UI"));
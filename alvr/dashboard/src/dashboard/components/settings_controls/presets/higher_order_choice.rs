@@ -30,7 +30,8 @@ impl Control {
                         .iter()
                         .map(|modifier| match &modifier.operation {
                             PresetModifierOperation::Assign(value) => PathValuePair {
-                                path: alvr_sockets::parse_path(&modifier.target_path),
+                                // Builtin presets only contain valid paths
+                                path: alvr_sockets::parse_path(&modifier.target_path).unwrap(),
                                 value: value.clone(),
                             },
                         })
@@ -1,18 +1,95 @@
-// use super::{SettingContainer, SettingsContext, SettingsResponse};
-// use crate::dashboard::DashboardResponse;
-// use egui::Ui;
-// use serde_json as json;
-// use settings_schema::VectorDefault;
-
-// pub struct Vector {}
-
-// impl SettingContainer for Vector {
-//     fn ui(
-//         &mut self,
-//         ui: &mut Ui,
-//         session_fragment: json::Value,
-//         context: &SettingsContext,
-//     ) -> Option<SettingsResponse> {
-//         None
-//     }
-// }
+use super::{NestingInfo, SettingControl, INDENTATION_STEP};
+use alvr_session::settings_schema::SchemaNode;
+use alvr_sockets::PathValuePair;
+use eframe::egui::Ui;
+use serde_json as json;
+
+// Each entry is edited through its own path, like "extra_openvr_props.content[2]", so that editing
+// an entry does not overwrite changes made to the others. Adding or removing entries replaces the
+// whole list.
+pub struct Control {
+    nesting_info: NestingInfo,
+    default_element: SchemaNode,
+    controls: Vec<SettingControl>,
+}
+
+impl Control {
+    pub fn new(mut nesting_info: NestingInfo, default_element: SchemaNode) -> Self {
+        nesting_info.indentation_level += 1;
+
+        Self {
+            nesting_info,
+            default_element,
+            controls: vec![],
+        }
+    }
+
+    fn element_control(&self, index: usize) -> SettingControl {
+        let mut nesting_info = self.nesting_info.clone();
+        nesting_info.path.push("content".into());
+        nesting_info.path.push(index.into());
+
+        SettingControl::new(nesting_info, self.default_element.clone())
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        session_fragment: &mut json::Value,
+        allow_inline: bool,
+    ) -> Option<PathValuePair> {
+        super::grid_flow_inline(ui, allow_inline);
+
+        let session_vector_mut = session_fragment.as_object_mut().unwrap();
+
+        let mut request = None;
+
+        let mut content = session_vector_mut["content"].as_array().unwrap().clone();
+        if ui.button("Add entry").clicked() {
+            content.push(session_vector_mut["element"].clone());
+            request = super::set_single_value(
+                &self.nesting_info,
+                "content".into(),
+                json::Value::Array(content.clone()),
+            );
+        }
+
+        // The list can also change because of other dashboard instances
+        let content_mut = session_vector_mut["content"].as_array_mut().unwrap();
+        while self.controls.len() < content_mut.len() {
+            let control = self.element_control(self.controls.len());
+            self.controls.push(control);
+        }
+        self.controls.truncate(content_mut.len());
+
+        let mut removed_index = None;
+        for (index, (control, element_mut)) in self
+            .controls
+            .iter_mut()
+            .zip(content_mut.iter_mut())
+            .enumerate()
+        {
+            ui.end_row();
+
+            ui.horizontal(|ui| {
+                ui.add_space(INDENTATION_STEP * self.nesting_info.indentation_level as f32);
+                ui.label(format!("Entry {}", index + 1));
+                if ui.small_button("Remove").clicked() {
+                    removed_index = Some(index);
+                }
+            });
+            request = control.ui(ui, element_mut, true).or(request);
+        }
+
+        if let Some(index) = removed_index {
+            content.remove(index);
+            request = super::set_single_value(
+                &self.nesting_info,
+                "content".into(),
+                json::Value::Array(content),
+            );
+        }
+
+        request
+    }
+}
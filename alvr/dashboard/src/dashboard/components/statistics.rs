@@ -326,6 +326,9 @@ impl StatisticsTab {
             ui[0].label("Transport latency:");
             ui[1].label(&format!("{:.2} ms", statistics.network_latency_ms));
 
+            ui[0].label("Round trip time:");
+            ui[1].label(&format!("{:.2} ms", statistics.round_trip_time_ms));
+
             ui[0].label("Decoder latency:");
             ui[1].label(&format!("{:.2} ms", statistics.decode_latency_ms));
 
@@ -335,6 +338,36 @@ impl StatisticsTab {
                 statistics.packets_lost_total, statistics.packets_lost_per_sec
             ));
 
+            ui[0].label("Max reorder depth:");
+            ui[1].label(&format!("{} packets", statistics.video_reorder_depth_max));
+
+            ui[0].label("Total late shards:");
+            ui[1].label(&format!(
+                "{} shards ({} shards/s)",
+                statistics.video_late_shards_total, statistics.video_late_shards_per_sec
+            ));
+
+            ui[0].label("Dropped video frames:");
+            ui[1].label(&format!(
+                "{} frames (server), {} shards (client)",
+                statistics.video_frames_dropped_total, statistics.video_shards_dropped_total
+            ));
+
+            ui[0].label("Dropped haptics:");
+            ui[1].label(&format!("{} events", statistics.haptics_dropped_total));
+
+            ui[0].label("Dropped tracking shards:");
+            ui[1].label(&format!(
+                "{} shards",
+                statistics.tracking_shards_dropped_total
+            ));
+
+            ui[0].label("Video pacing rate:");
+            ui[1].label(&format!("{:.1} Mbps", statistics.video_pacing_rate_mbps));
+
+            ui[0].label("Max pacing queue delay:");
+            ui[1].label(&format!("{:.2} ms", statistics.video_pacing_queue_delay_ms));
+
             ui[0].label("Client FPS:");
             ui[1].label(&format!("{} FPS", statistics.client_fps));
 
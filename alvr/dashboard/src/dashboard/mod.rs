@@ -6,8 +6,8 @@ use self::components::{
     SetupWizard, SetupWizardRequest,
 };
 use crate::{dashboard::components::StatisticsTab, steamvr_launcher::LAUNCHER, theme, ServerEvent};
-use alvr_common::RelaxedAtomic;
-use alvr_events::EventType;
+use alvr_common::{LogSeverity, RelaxedAtomic};
+use alvr_events::{EventType, LogEvent};
 use alvr_session::SessionDesc;
 use alvr_sockets::{DashboardRequest, PathValuePair};
 use eframe::{
@@ -92,6 +92,9 @@ impl Dashboard {
         dashboard_requests_sender
             .send(DashboardRequest::GetAudioDevices)
             .unwrap();
+        dashboard_requests_sender
+            .send(DashboardRequest::GetSessionBackups)
+            .unwrap();
 
         theme::set_theme(&creation_context.egui_ctx);
 
@@ -155,6 +158,11 @@ impl eframe::App for Dashboard {
                             }
 
                             self.session = *session;
+
+                            // Each session change can add an entry to the history
+                            self.dashboard_requests_sender
+                                .send(DashboardRequest::GetSettingsHistory)
+                                .ok();
                         }
                         EventType::ServerRequestsSelfRestart => {
                             if !self.server_restarting.value() {
@@ -168,6 +176,7 @@ impl eframe::App for Dashboard {
                                 });
                             }
                         }
+                        EventType::Pairing(event) => self.connections_tab.update_pairing(event),
                         EventType::Log(event) => {
                             self.notification_bar.push_notification(event);
                         }
@@ -185,6 +194,28 @@ impl eframe::App for Dashboard {
                 ServerEvent::AudioDevicesUpdated(list) => {
                     self.settings_tab.update_audio_devices(list);
                 }
+                ServerEvent::SessionBackupsUpdated(ids) => {
+                    self.settings_tab.update_session_backups(ids);
+                }
+                ServerEvent::SettingsHistoryUpdated(history) => {
+                    self.settings_tab.update_settings_history(history);
+                }
+                ServerEvent::SettingsViolations(violations) => {
+                    let violations = violations
+                        .iter()
+                        .map(|violation| violation.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    self.notification_bar.push_notification(LogEvent {
+                        severity: LogSeverity::Warning,
+                        content: format!("Settings not applied:\n{violations}"),
+                    });
+
+                    // The controls still show the rejected values
+                    self.dashboard_requests_sender
+                        .send(DashboardRequest::GetSession)
+                        .ok();
+                }
                 _ => (),
             }
         }
@@ -205,6 +236,10 @@ impl eframe::App for Dashboard {
 
         let mut requests = vec![];
 
+        if let Some(request) = self.connections_tab.pairing_ui(context) {
+            requests.push(request);
+        }
+
         if self.setup_wizard_open {
             CentralPanel::default().show(context, |ui| {
                 if let Some(SetupWizardRequest::Close { finished }) = self.setup_wizard.ui(ui) {
@@ -212,7 +247,8 @@ impl eframe::App for Dashboard {
                         requests.push(DashboardRequest::SetValues(vec![PathValuePair {
                             path: alvr_sockets::parse_path(
                                 "session_settings.extra.open_setup_wizard",
-                            ),
+                            )
+                            .unwrap(),
                             value: serde_json::Value::Bool(false),
                         }]))
                     }
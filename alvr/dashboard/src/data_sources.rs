@@ -1,7 +1,8 @@
 use alvr_common::{parking_lot::Mutex, prelude::*, StrResult};
 use alvr_events::{Event, EventType};
 use alvr_server_data::ServerDataManager;
-use alvr_sockets::{AudioDevicesList, DashboardRequest, ServerResponse};
+use alvr_session::SettingsViolation;
+use alvr_sockets::{AudioDevicesList, DashboardRequest, ServerResponse, SettingsHistory};
 use eframe::egui;
 use std::{
     env,
@@ -21,6 +22,9 @@ pub enum ServerEvent {
     PingResponseDisconnected,
     Event(alvr_events::Event),
     AudioDevicesUpdated(AudioDevicesList),
+    SettingsViolations(Vec<SettingsViolation>),
+    SessionBackupsUpdated(Vec<String>),
+    SettingsHistoryUpdated(SettingsHistory),
     ChannelTest,
 }
 
@@ -201,13 +205,31 @@ pub fn data_interop_thread(
             .get(&dashboard_request_uri)
             .send_json(&request)
         {
-            Ok(response) => {
-                if let Ok(ServerResponse::AudioDevices(list)) =
-                    response.into_json::<ServerResponse>()
-                {
+            Ok(response) => match response.into_json::<ServerResponse>() {
+                Ok(ServerResponse::AudioDevices(list)) => {
                     report_event(&context, &sender, ServerEvent::AudioDevicesUpdated(list)).ok();
                 }
-            }
+                Ok(ServerResponse::SettingsViolations(violations)) => {
+                    report_event(
+                        &context,
+                        &sender,
+                        ServerEvent::SettingsViolations(violations),
+                    )
+                    .ok();
+                }
+                Ok(ServerResponse::SessionBackups(ids)) => {
+                    report_event(&context, &sender, ServerEvent::SessionBackupsUpdated(ids)).ok();
+                }
+                Ok(ServerResponse::SettingsHistory(history)) => {
+                    report_event(
+                        &context,
+                        &sender,
+                        ServerEvent::SettingsHistoryUpdated(history),
+                    )
+                    .ok();
+                }
+                Err(_) => (),
+            },
             Err(_) => {
                 if let DataSource::Local(data_manager) = &mut *data_source.lock() {
                     match request {
@@ -215,13 +237,64 @@ pub fn data_interop_thread(
                             report_session(&context, &sender, data_manager);
                         }
                         DashboardRequest::UpdateSession(session) => {
-                            *data_manager.session_mut() = *session;
+                            if let Err(violations) = data_manager.update_session(*session) {
+                                report_event(
+                                    &context,
+                                    &sender,
+                                    ServerEvent::SettingsViolations(violations),
+                                )
+                                .ok();
+                            }
 
                             report_session(&context, &sender, data_manager);
                         }
                         DashboardRequest::SetValues(descs) => {
-                            if let Err(e) = data_manager.set_values(descs) {
-                                error!("Failed to set session value: {e}")
+                            if let Err(violations) = data_manager.set_values(descs) {
+                                report_event(
+                                    &context,
+                                    &sender,
+                                    ServerEvent::SettingsViolations(violations),
+                                )
+                                .ok();
+                            }
+
+                            report_session(&context, &sender, data_manager);
+                        }
+                        DashboardRequest::GetSessionBackups => {
+                            report_event(
+                                &context,
+                                &sender,
+                                ServerEvent::SessionBackupsUpdated(data_manager.session_backups()),
+                            )
+                            .ok();
+                        }
+                        DashboardRequest::RestoreSessionBackup(id) => {
+                            if let Err(e) = data_manager.restore_session_backup(&id) {
+                                error!("Failed to restore the session backup: {e}");
+                            }
+
+                            report_session(&context, &sender, data_manager);
+                        }
+                        DashboardRequest::GetSettingsHistory => {
+                            report_event(
+                                &context,
+                                &sender,
+                                ServerEvent::SettingsHistoryUpdated(
+                                    data_manager.settings_history(),
+                                ),
+                            )
+                            .ok();
+                        }
+                        DashboardRequest::UndoSettingsChange => {
+                            if let Err(e) = data_manager.undo_settings_change() {
+                                error!("Failed to undo the settings change: {e}");
+                            }
+
+                            report_session(&context, &sender, data_manager);
+                        }
+                        DashboardRequest::RedoSettingsChange => {
+                            if let Err(e) = data_manager.redo_settings_change() {
+                                error!("Failed to redo the settings change: {e}");
                             }
 
                             report_session(&context, &sender, data_manager);
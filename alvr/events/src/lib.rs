@@ -11,10 +11,20 @@ pub struct Statistics {
     pub video_mbits_per_sec: f32,
     pub total_latency_ms: f32,
     pub network_latency_ms: f32,
+    pub round_trip_time_ms: f32,
     pub encode_latency_ms: f32,
     pub decode_latency_ms: f32,
     pub packets_lost_total: usize,
     pub packets_lost_per_sec: usize,
+    pub video_reorder_depth_max: u32,
+    pub video_late_shards_total: usize,
+    pub video_late_shards_per_sec: usize,
+    pub video_shards_dropped_total: usize,
+    pub video_frames_dropped_total: usize,
+    pub haptics_dropped_total: usize,
+    pub tracking_shards_dropped_total: usize,
+    pub video_pacing_rate_mbps: f32,
+    pub video_pacing_queue_delay_ms: f32,
     pub client_fps: u32,
     pub server_fps: u32,
     pub battery_hmd: u32,
@@ -63,6 +73,20 @@ pub struct HapticsEvent {
     pub amplitude: f32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BandwidthProbeEvent {
+    pub hostname: String,
+    pub throughput_bps: f32,
+    pub packet_loss: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PairingEvent {
+    pub hostname: String,
+    // None once the pairing does not wait for confirmation anymore
+    pub code: Option<u32>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(tag = "id", content = "data")]
 pub enum EventType {
@@ -71,6 +95,8 @@ pub enum EventType {
     GraphStatistics(GraphStatistics),
     Button(ButtonEvent),
     Haptics(HapticsEvent),
+    BandwidthProbe(BandwidthProbeEvent),
+    Pairing(PairingEvent),
     ServerRequestsSelfRestart,
     Log(LogEvent),
 }
@@ -0,0 +1,216 @@
+// ALVR uses the same ports on server and client and binds them on all interfaces, so the server,
+// the client and the proxy must run in separate network namespaces (or devices), for example
+// connected with veth pairs on Linux. The proxy accepts connections from the server on the server
+// side IP and connects to the client from the client side IP. In the dashboard, the client must be
+// added manually with the server side IP of the proxy. Example:
+//
+// RUST_LOG=info cargo run -p alvr_network_proxy -- --client-ip 10.0.2.2 \
+//     --server-side-ip 10.0.1.2 --client-side-ip 10.0.2.1 --latency-ms 20 --frame-drop-rate 0.01
+
+mod tcp;
+mod udp;
+
+use alvr_common::prelude::*;
+use alvr_sockets::{ImpairmentConfig, RandomImpairment, CONTROL_PORT};
+use pico_args::Arguments;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+const HELP_STR: &str = r#"
+alvr_network_proxy
+Forward the control and stream sockets between server and client, injecting network impairments.
+
+USAGE:
+    alvr_network_proxy --client-ip <IP> --server-side-ip <IP> --client-side-ip <IP> [FLAG] [ARGS]
+
+FLAGS:
+    --help                  Print this text
+    --tcp                   Forward the stream socket using TCP. By default UDP is used. QUIC is
+                            not supported
+
+ARGS:
+    --client-ip <IP>        IP of the client
+    --server-side-ip <IP>   IP on which the proxy accepts connections from the server
+    --client-side-ip <IP>   IP from which the proxy connects to the client
+    --stream-port <PORT>    Stream port, as set in the connection settings. Default: 9944
+    --latency-ms <MS>       One way latency added to each packet. Default: 0
+    --jitter-ms <MS>        Maximum random variation of the latency. Default: 0
+    --loss-rate <RATE>      Probability that a loss burst starts at a stream packet. Default: 0
+    --loss-burst <COUNT>    Number of consecutive stream packets dropped by a loss burst. Default: 1
+    --bandwidth-mbps <MBPS> Bandwidth limit of each direction. Default: unlimited
+    --frame-drop-rate <RATE> Probability of dropping all shards of a stream packet. Default: 0
+    --shard-drop-rate <RATE> Probability of dropping a single shard. Default: 0
+    --stream-id <ID>        Apply frame and shard drops only to this stream. Can be repeated.
+                            Default: all streams
+    --seed <SEED>           Seed of the random impairments. Default: 0
+"#;
+
+struct Args {
+    client_ip: IpAddr,
+    server_side_ip: IpAddr,
+    client_side_ip: IpAddr,
+    stream_port: u16,
+    tcp: bool,
+    config: ImpairmentConfig,
+}
+
+fn parse_args() -> StrResult<Option<Args>> {
+    let mut args = Arguments::from_env();
+
+    if args.contains("--help") {
+        return Ok(None);
+    }
+
+    let tcp = args.contains("--tcp");
+    let client_ip = args.value_from_str("--client-ip").map_err(err!())?;
+    let server_side_ip = args.value_from_str("--server-side-ip").map_err(err!())?;
+    let client_side_ip = args.value_from_str("--client-side-ip").map_err(err!())?;
+    let stream_port = args
+        .opt_value_from_str("--stream-port")
+        .map_err(err!())?
+        .unwrap_or(9944);
+    let latency_ms = args
+        .opt_value_from_str("--latency-ms")
+        .map_err(err!())?
+        .unwrap_or(0);
+    let jitter_ms = args
+        .opt_value_from_str("--jitter-ms")
+        .map_err(err!())?
+        .unwrap_or(0);
+    let loss_rate = args
+        .opt_value_from_str("--loss-rate")
+        .map_err(err!())?
+        .unwrap_or(0.0);
+    let loss_burst_length = args
+        .opt_value_from_str("--loss-burst")
+        .map_err(err!())?
+        .unwrap_or(1);
+    let bandwidth_mbps: Option<f32> = args
+        .opt_value_from_str("--bandwidth-mbps")
+        .map_err(err!())?;
+    let frame_drop_rate = args
+        .opt_value_from_str("--frame-drop-rate")
+        .map_err(err!())?
+        .unwrap_or(0.0);
+    let shard_drop_rate = args
+        .opt_value_from_str("--shard-drop-rate")
+        .map_err(err!())?
+        .unwrap_or(0.0);
+    let stream_ids = args.values_from_str("--stream-id").map_err(err!())?;
+    let seed = args
+        .opt_value_from_str("--seed")
+        .map_err(err!())?
+        .unwrap_or(0);
+
+    let remaining = args.finish();
+    if !remaining.is_empty() {
+        return fmt_e!("Unexpected arguments: {remaining:?}");
+    }
+
+    Ok(Some(Args {
+        client_ip,
+        server_side_ip,
+        client_side_ip,
+        stream_port,
+        tcp,
+        config: ImpairmentConfig {
+            drop_rate: loss_rate,
+            drop_burst_length: loss_burst_length,
+            delay: Duration::from_millis(latency_ms),
+            jitter: Duration::from_millis(jitter_ms),
+            bandwidth_bytes_per_sec: bandwidth_mbps.map(|mbps| mbps * 1e6 / 8.0),
+            frame_drop_rate,
+            shard_drop_rate,
+            stream_ids,
+            seed,
+            ..Default::default()
+        },
+    }))
+}
+
+// Each direction uses a different seed, so that the impairments are not correlated
+fn impairment(config: &ImpairmentConfig, seed_offset: u64) -> RandomImpairment {
+    RandomImpairment::new(ImpairmentConfig {
+        seed: config.seed + seed_offset,
+        ..config.clone()
+    })
+}
+
+async fn run(args: Args) -> StrResult {
+    let Args {
+        client_ip,
+        server_side_ip,
+        client_side_ip,
+        stream_port,
+        tcp,
+        config,
+    } = args;
+
+    // Control packets are never dropped
+    let control_config = ImpairmentConfig {
+        drop_rate: 0.0,
+        frame_drop_rate: 0.0,
+        shard_drop_rate: 0.0,
+        ..config.clone()
+    };
+    let control_proxy = tcp::proxy_loop(
+        (server_side_ip, CONTROL_PORT).into(),
+        client_side_ip,
+        (client_ip, CONTROL_PORT).into(),
+        move || {
+            (
+                impairment(&control_config, 0),
+                impairment(&control_config, 1),
+            )
+        },
+    );
+
+    let server_side_address = SocketAddr::from((server_side_ip, stream_port));
+    let client_address = SocketAddr::from((client_ip, stream_port));
+    let stream_proxy = async {
+        if tcp {
+            tcp::proxy_loop(server_side_address, client_side_ip, client_address, || {
+                (impairment(&config, 2), impairment(&config, 3))
+            })
+            .await
+        } else {
+            udp::proxy_loop(
+                server_side_address,
+                (client_side_ip, stream_port).into(),
+                client_address,
+                impairment(&config, 2),
+                impairment(&config, 3),
+            )
+            .await
+        }
+    };
+
+    tokio::select! {
+        res = control_proxy => res,
+        res = stream_proxy => res,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{HELP_STR}");
+            return;
+        }
+        Err(e) => {
+            error!("{e}");
+            println!("{HELP_STR}");
+            return;
+        }
+    };
+
+    if let Err(e) = run(args).await {
+        error!("{e}");
+    }
+}
@@ -0,0 +1,114 @@
+use alvr_common::prelude::*;
+use alvr_sockets::{ImpairmentModel, RandomImpairment};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use std::net::{IpAddr, SocketAddr};
+use tokio::{
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpSocket,
+    },
+    sync::mpsc,
+    time::{self, Instant},
+};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+// Frames are forwarded in order. Dropping should be enabled only for the stream socket, where a
+// frame is a single shard and the receiver handles the missing ones like on UDP.
+async fn forward(
+    source: OwnedReadHalf,
+    destination: OwnedWriteHalf,
+    mut impairment: RandomImpairment,
+) -> StrResult {
+    let mut source = FramedRead::new(source, LengthDelimitedCodec::new());
+    let mut destination = FramedWrite::new(destination, LengthDelimitedCodec::new());
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(Instant, Bytes)>();
+
+    let receive_loop = async {
+        let mut last_instant = Instant::now();
+        while let Some(maybe_packet) = source.next().await {
+            let packet = maybe_packet.map_err(err!())?.freeze();
+
+            for instant in impairment.schedule(&packet, Instant::now()) {
+                // The jitter cannot reorder a TCP stream
+                last_instant = Instant::max(instant, last_instant);
+                sender
+                    .send((last_instant, packet.clone()))
+                    .map_err(err!())?;
+            }
+        }
+
+        StrResult::Ok(())
+    };
+
+    let send_loop = async {
+        while let Some((instant, packet)) = receiver.recv().await {
+            time::sleep_until(instant).await;
+            destination.send(packet).await.map_err(err!())?;
+        }
+
+        Ok(())
+    };
+
+    tokio::select! {
+        res = receive_loop => res,
+        res = send_loop => res,
+    }
+}
+
+// Accept connections from the server on `listen_address` and forward each one to the client.
+// `make_impairments` creates the impairments for the server to client and client to server
+// directions of each connection.
+pub async fn proxy_loop(
+    listen_address: SocketAddr,
+    client_side_ip: IpAddr,
+    client_address: SocketAddr,
+    make_impairments: impl Fn() -> (RandomImpairment, RandomImpairment),
+) -> StrResult {
+    let listener = TcpListener::bind(listen_address).await.map_err(err!())?;
+
+    loop {
+        let (server_socket, server_address) = listener.accept().await.map_err(err!())?;
+
+        let client_socket = if client_side_ip.is_ipv4() {
+            TcpSocket::new_v4()
+        } else {
+            TcpSocket::new_v6()
+        }
+        .map_err(err!())?;
+        client_socket
+            .bind((client_side_ip, 0).into())
+            .map_err(err!())?;
+        let client_socket = match client_socket.connect(client_address).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                // The server retries periodically
+                debug!("Failed to connect to client {client_address}: {e}");
+                continue;
+            }
+        };
+
+        server_socket.set_nodelay(true).map_err(err!())?;
+        client_socket.set_nodelay(true).map_err(err!())?;
+
+        info!("Forwarding {server_address} -> {listen_address} to {client_address}");
+
+        let (server_to_client, client_to_server) = make_impairments();
+        let (server_read, server_write) = server_socket.into_split();
+        let (client_read, client_write) = client_socket.into_split();
+
+        tokio::spawn(async move {
+            let res = tokio::select! {
+                res = forward(server_read, client_write, server_to_client) => res,
+                res = forward(client_read, server_write, client_to_server) => res,
+            };
+
+            if let Err(e) = res {
+                info!("Connection to {client_address} closed: {e}");
+            } else {
+                info!("Connection to {client_address} closed");
+            }
+        });
+    }
+}
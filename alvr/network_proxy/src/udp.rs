@@ -0,0 +1,84 @@
+use alvr_common::{parking_lot::Mutex, prelude::*};
+use alvr_sockets::{ImpairmentModel, RandomImpairment};
+use bytes::Bytes;
+use futures::StreamExt;
+use std::net::SocketAddr;
+use tokio::{net::UdpSocket, time::Instant};
+use tokio_util::time::DelayQueue;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+// Stream socket datagrams start with the length prefix of the length delimited codec
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+// `route` returns the destination of a datagram received from the given address, or None to ignore
+// the datagram. Datagrams can be reordered by the impairment jitter.
+async fn forward(
+    source: &UdpSocket,
+    destination: &UdpSocket,
+    mut impairment: RandomImpairment,
+    route: impl Fn(SocketAddr) -> Option<SocketAddr>,
+) -> StrResult {
+    let mut queue = DelayQueue::new();
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        tokio::select! {
+            res = source.recv_from(&mut buffer) => {
+                let (size, address) = res.map_err(err!())?;
+
+                let destination_address = if let Some(address) = route(address) {
+                    address
+                } else {
+                    continue;
+                };
+
+                let datagram = &buffer[..size];
+                let packet = datagram.get(LENGTH_PREFIX_SIZE..).unwrap_or_default();
+                for instant in impairment.schedule(packet, Instant::now()) {
+                    queue.insert_at(
+                        (Bytes::copy_from_slice(datagram), destination_address),
+                        instant,
+                    );
+                }
+            }
+            Some(expired) = queue.next() => {
+                let (datagram, address) = expired.into_inner();
+                destination.send_to(&datagram, address).await.map_err(err!())?;
+            }
+        }
+    }
+}
+
+// The server sends to `server_side_address`, the client receives from `client_side_address`.
+// Both use the same port.
+pub async fn proxy_loop(
+    server_side_address: SocketAddr,
+    client_side_address: SocketAddr,
+    client_address: SocketAddr,
+    server_to_client: RandomImpairment,
+    client_to_server: RandomImpairment,
+) -> StrResult {
+    let server_side_socket = UdpSocket::bind(server_side_address).await.map_err(err!())?;
+    let client_side_socket = UdpSocket::bind(client_side_address).await.map_err(err!())?;
+
+    // The server address is known only after it sends the first packet
+    let server_address = Mutex::new(None);
+
+    tokio::select! {
+        res = forward(&server_side_socket, &client_side_socket, server_to_client, |address| {
+            if server_address.lock().replace(address) != Some(address) {
+                info!("Forwarding {address} -> {server_side_address} to {client_address}");
+            }
+
+            Some(client_address)
+        }) => res,
+        res = forward(&client_side_socket, &server_side_socket, client_to_server, |address| {
+            if address == client_address {
+                *server_address.lock()
+            } else {
+                None
+            }
+        }) => res,
+    }
+}
@@ -7,6 +7,9 @@ use std::{
 };
 
 const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// Same range as the setting slider. The pacing rate is divided by the fraction
+const MIN_FRAME_INTERVAL_FRACTION: f32 = 0.1;
+const MAX_FRAME_INTERVAL_FRACTION: f32 = 1.0;
 
 pub struct BitrateManager {
     config: BitrateConfig,
@@ -19,6 +22,7 @@ pub struct BitrateManager {
     last_frame_instant: Instant,
     last_update_instant: Instant,
     dynamic_max_bitrate: f32,
+    last_bitrate_bps: f32,
     update_needed: bool,
 }
 
@@ -41,6 +45,7 @@ impl BitrateManager {
             last_frame_instant: Instant::now(),
             last_update_instant: Instant::now(),
             dynamic_max_bitrate: f32::MAX,
+            last_bitrate_bps: 30_000_000.0,
             update_needed: true,
         }
     }
@@ -117,6 +122,25 @@ impl BitrateManager {
         }
     }
 
+    // The measured link capacity replaces the initial guess, so the adaptive bitrate does not have
+    // to converge from it
+    pub fn report_link_capacity(&mut self, throughput_bps: f32) {
+        self.bitrate_average = SlidingWindowAverage::new(throughput_bps, self.max_history_size);
+        self.update_needed = true;
+    }
+
+    // A frame of average size contains bitrate * frame_interval bits. To send it in
+    // frame_interval_fraction * frame_interval, the rate must be bitrate / frame_interval_fraction
+    pub fn get_pacing_rate_bps(&self, frame_interval_fraction: f32) -> f32 {
+        let frame_interval_fraction = if frame_interval_fraction.is_nan() {
+            MAX_FRAME_INTERVAL_FRACTION
+        } else {
+            frame_interval_fraction.clamp(MIN_FRAME_INTERVAL_FRACTION, MAX_FRAME_INTERVAL_FRACTION)
+        };
+
+        self.last_bitrate_bps / frame_interval_fraction
+    }
+
     pub fn get_encoder_params(&mut self) -> FfiDynamicEncoderParams {
         let now = Instant::now();
         if self.update_needed || now > self.last_update_instant + UPDATE_INTERVAL {
@@ -156,6 +180,7 @@ impl BitrateManager {
         }
 
         bitrate_bps = f32::min(bitrate_bps, self.dynamic_max_bitrate);
+        self.last_bitrate_bps = bitrate_bps;
 
         let framerate = 1.0
             / self
@@ -1,9 +1,10 @@
 use crate::{
     bitrate::BitrateManager, buttons::BUTTON_PATH_FROM_ID, haptics::HapticsManager,
     sockets::WelcomeSocket, statistics::StatisticsManager, tracking::TrackingManager,
-    FfiButtonValue, FfiFov, FfiViewsConfig, VideoPacket, BITRATE_MANAGER, CONTROL_CHANNEL_SENDER,
-    DECODER_CONFIG, DISCONNECT_CLIENT_NOTIFIER, HAPTICS_SENDER, IS_ALIVE, RESTART_NOTIFIER,
-    SERVER_DATA_MANAGER, STATISTICS_MANAGER, VIDEO_RECORDING_FILE, VIDEO_SENDER,
+    FfiButtonValue, FfiFov, FfiViewsConfig, VideoPacket, BITRATE_MANAGER, DECODER_CONFIG,
+    DISCONNECT_CLIENT_NOTIFIER, FILESYSTEM_LAYOUT, HAPTICS_SENDERS, HAPTICS_SEND_QUEUE, IS_ALIVE,
+    RESTART_NOTIFIER, SERVER_DATA_MANAGER, SPECTATOR_IDR_REQUESTS, STATISTICS_MANAGER,
+    VIDEO_RECORDING_FILE, VIDEO_SENDERS, VIDEO_SEND_QUEUE,
 };
 use alvr_audio::AudioDevice;
 use alvr_common::{
@@ -14,13 +15,25 @@ use alvr_common::{
     settings_schema::Switch,
     RelaxedAtomic, DEVICE_ID_TO_PATH, LEFT_HAND_ID, RIGHT_HAND_ID,
 };
-use alvr_events::{ButtonEvent, ButtonValue, EventType, HapticsEvent};
-use alvr_session::{CodecType, ControllersEmulationMode, FrameSize, OpenvrConfig};
+use alvr_events::{
+    BandwidthProbeEvent, ButtonEvent, ButtonValue, EventType, HapticsEvent, PairingEvent,
+};
+use alvr_session::{
+    BandwidthProbingConfig, CodecType, ConnectionTransport, ControllersEmulationMode,
+    DiscoveryProtocol, FrameSize, OpenvrConfig, SessionDesc, Settings, SocketProtocol,
+    StreamingRole,
+};
 use alvr_sockets::{
-    spawn_cancelable, ClientConnectionResult, ClientControlPacket, ClientListAction,
-    ClientStatistics, ControlSocketReceiver, ControlSocketSender, PeerType, ProtoControlSocket,
-    ServerControlPacket, StreamConfigPacket, StreamSocketBuilder, Tracking, AUDIO, HAPTICS,
-    KEEPALIVE_INTERVAL, STATISTICS, TRACKING, VIDEO,
+    bandwidth_probe_bursts, bounded_queue, capabilities, capabilities_to_string,
+    is_probe_saturated, spawn_cancelable, BandwidthProbeHeader, ChannelKeys,
+    ClientConnectionResult, ClientControlPacket, ClientListAction, ClientStatistics, ConnectionEnd,
+    ConnectionState, ControlExtension, ControlSocketReceiver, ControlSocketSender,
+    ExtensionHandler, ExtensionPacket, ExtensionRegistry, HandshakePacket, MdnsBrowser, PairingKey,
+    PeerType, ProtoControlSocket, ReceiverBuffer, ResumeAction, ResumeStateMachine,
+    ServerControlPacket, SessionToken, StreamConfigPacket, StreamReceiver, StreamSender,
+    StreamSocketBuilder, Tracking, AUDIO, AUDIO_QUEUE, BANDWIDTH_PROBE, HAPTICS,
+    KEEPALIVE_INTERVAL, RESUME_RETRY_INTERVAL, RESUME_TIMEOUT, STATISTICS, STATISTICS_QUEUE,
+    TRACKING, TRACKING_QUEUE, VIDEO,
 };
 use futures::future::BoxFuture;
 use std::{
@@ -29,39 +42,344 @@ use std::{
     net::IpAddr,
     process::Command,
     ptr,
-    sync::{mpsc as smpsc, Arc},
+    sync::{mpsc as smpsc, Arc, Weak},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{
     runtime::Runtime,
-    sync::{mpsc as tmpsc, Mutex},
+    sync::{mpsc as tmpsc, oneshot, Mutex, Notify},
     time,
 };
 
 const RETRY_CONNECT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+const PAIRING_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+const SERVER_CAPABILITIES: &[&str] = &[
+    capabilities::H264,
+    capabilities::HEVC,
+    capabilities::FORWARD_ERROR_CORRECTION,
+    capabilities::PACKET_RETRANSMISSION,
+    capabilities::QUIC,
+];
 
 static CONNECTED_CLIENT_HOSTNAMES: Lazy<parking_lot::Mutex<HashSet<String>>> =
     Lazy::new(|| parking_lot::Mutex::new(HashSet::new()));
-static STREAMING_CLIENT_HOSTNAME: Lazy<parking_lot::Mutex<Option<String>>> =
-    Lazy::new(|| parking_lot::Mutex::new(None));
+// Clients that failed the version handshake. They are not connected to again until they are removed
+// from the client list or the streamer restarts
+static INCOMPATIBLE_CLIENT_HOSTNAMES: Lazy<parking_lot::Mutex<HashSet<String>>> =
+    Lazy::new(|| parking_lot::Mutex::new(HashSet::new()));
+static STREAMING_CLIENTS: Lazy<parking_lot::Mutex<StreamingClients>> =
+    Lazy::new(|| parking_lot::Mutex::new(StreamingClients::default()));
+// Clients whose link was probed since the streamer started
+static PROBED_CLIENT_HOSTNAMES: Lazy<parking_lot::Mutex<HashSet<String>>> =
+    Lazy::new(|| parking_lot::Mutex::new(HashSet::new()));
+// Notified when the primary client changes
+static PRIMARY_CLIENT_NOTIFIER: Lazy<Notify> = Lazy::new(Notify::new);
+static STREAMING_SESSION: Lazy<parking_lot::Mutex<Weak<StreamingSession>>> =
+    Lazy::new(|| parking_lot::Mutex::new(Weak::new()));
+// Clients waiting for the user to confirm the pairing code. They are not connected again meanwhile
+static PAIRING_CLIENT_HOSTNAMES: Lazy<parking_lot::Mutex<HashSet<String>>> =
+    Lazy::new(|| parking_lot::Mutex::new(HashSet::new()));
+static STREAM_SETUP_LOCK: Lazy<parking_lot::Mutex<()>> = Lazy::new(|| parking_lot::Mutex::new(()));
+// Pairings waiting for the user to confirm the code in the dashboard, by client hostname
+static PAIRING_CONFIRMATIONS: Lazy<parking_lot::Mutex<HashMap<String, oneshot::Sender<bool>>>> =
+    Lazy::new(|| parking_lot::Mutex::new(HashMap::new()));
+// Handlers of the extension messages sent by the clients
+static EXTENSION_REGISTRY: Lazy<parking_lot::Mutex<ExtensionRegistry>> =
+    Lazy::new(|| parking_lot::Mutex::new(ExtensionRegistry::new()));
+// Senders of the control packets of the streaming clients, by hostname
+static CONTROL_CHANNEL_SENDERS: Lazy<
+    parking_lot::Mutex<HashMap<String, tmpsc::UnboundedSender<ServerControlPacket>>>,
+> = Lazy::new(|| parking_lot::Mutex::new(HashMap::new()));
+
+// Clients receiving the stream. The primary client drives tracking, input and bitrate adaptation.
+#[derive(Default)]
+struct StreamingClients {
+    primary: Option<String>,
+    // The hashmap key is the hostname, the value is the server stream port used by the client
+    ports: HashMap<String, u16>,
+}
+
+impl StreamingClients {
+    fn spectators_count(&self) -> usize {
+        self.ports.len() - self.primary.is_some() as usize
+    }
+
+    // UDP sockets cannot share the port, so each client uses the first port available starting
+    // from the stream port
+    fn free_port(&self, stream_port: u16) -> Option<u16> {
+        (stream_port..=u16::MAX).find(|port| !self.ports.values().any(|p| p == port))
+    }
+
+    fn add(&mut self, hostname: String, port: u16, role: StreamingRole) {
+        if role == StreamingRole::Primary {
+            self.primary = Some(hostname.clone());
+        }
+        self.ports.insert(hostname, port);
+    }
+
+    // Returns true if the client was the primary client
+    fn remove(&mut self, hostname: &str) -> bool {
+        self.ports.remove(hostname);
+
+        if self.primary.as_deref() == Some(hostname) {
+            self.primary = None;
+
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns the previous primary client, if different
+    fn set_primary(&mut self, hostname: &str) -> StrResult<Option<String>> {
+        if !self.ports.contains_key(hostname) {
+            return fmt_e!("Client {hostname} is not streaming");
+        }
+
+        Ok(self
+            .primary
+            .replace(hostname.to_owned())
+            .filter(|previous| previous != hostname))
+    }
+}
+
+pub fn primary_client() -> Option<String> {
+    STREAMING_CLIENTS.lock().primary.clone()
+}
+
+fn is_primary_client(hostname: &str) -> bool {
+    STREAMING_CLIENTS.lock().primary.as_deref() == Some(hostname)
+}
+
+fn set_streaming_role(hostname: &str, role: Option<StreamingRole>) {
+    SERVER_DATA_MANAGER.write().update_client_list(
+        hostname.to_owned(),
+        ClientListAction::SetStreamingRole(role),
+    );
+}
+
+// Waits until the client gains or loses the primary role
+async fn wait_primary_role(hostname: &str, primary: bool) {
+    loop {
+        // Created before checking the role, so a change cannot be missed
+        let primary_changed = PRIMARY_CLIENT_NOTIFIER.notified();
+        if is_primary_client(hostname) == primary {
+            return;
+        }
+
+        primary_changed.await;
+    }
+}
+
+async fn discard_stream(receiver: &mut StreamReceiver<()>) -> StrResult {
+    loop {
+        receiver.recv_header_only().await?;
+    }
+}
+
+// Hand over the primary role to a streaming client. The stream is not restarted: the previous
+// primary client becomes a spectator, and the microphone moves to the new primary client.
+pub fn set_primary_client(hostname: &str) -> StrResult {
+    let previous_primary = STREAMING_CLIENTS.lock().set_primary(hostname)?;
+    PRIMARY_CLIENT_NOTIFIER.notify_waiters();
+
+    // The new primary client may have skipped frames as a spectator
+    unsafe { crate::RequestIDR() };
+
+    if let Some(previous_hostname) = previous_primary {
+        set_streaming_role(&previous_hostname, Some(StreamingRole::Spectator));
+    }
+    set_streaming_role(hostname, Some(StreamingRole::Primary));
+
+    info!("Client {hostname} is now the primary client");
+
+    Ok(())
+}
+
+// The handler is called from the connection tasks for each extension message sent by the clients
+pub fn register_extension_handler(handler: impl ExtensionHandler + 'static) {
+    EXTENSION_REGISTRY.lock().register(handler);
+}
+
+// The message is dropped if the client is not streaming
+pub fn send_extension<E: ControlExtension>(client_hostname: &str, message: &E) {
+    if let Some(sender) = CONTROL_CHANNEL_SENDERS.lock().get(client_hostname) {
+        match ExtensionPacket::new(message) {
+            Ok(packet) => {
+                sender.send(ServerControlPacket::Extension(packet)).ok();
+            }
+            Err(e) => warn!("Failed to encode extension {}: {e}", E::ID),
+        }
+    }
+}
+
+pub fn confirm_pairing(hostname: &str, accepted: bool) {
+    if let Some(sender) = PAIRING_CONFIRMATIONS.lock().remove(hostname) {
+        sender.send(accepted).ok();
+    }
+}
+
+// Shows the pairing code in the dashboard, to be compared with the code shown by the client
+async fn wait_pairing_confirmation(hostname: String, code: u32) -> bool {
+    let (sender, receiver) = oneshot::channel();
+    PAIRING_CONFIRMATIONS
+        .lock()
+        .insert(hostname.clone(), sender);
+
+    info!("Pairing with client {hostname}. Confirm if the client shows the code {code:06}");
+    alvr_events::send_event(EventType::Pairing(PairingEvent {
+        hostname: hostname.clone(),
+        code: Some(code),
+    }));
+
+    let res = time::timeout(PAIRING_CONFIRMATION_TIMEOUT, receiver).await;
+
+    PAIRING_CONFIRMATIONS.lock().remove(&hostname);
+    alvr_events::send_event(EventType::Pairing(PairingEvent {
+        hostname: hostname.clone(),
+        code: None,
+    }));
+
+    match res {
+        Ok(Ok(true)) => true,
+        Ok(Ok(false)) => {
+            // Otherwise the pairing would be attempted again at the next connection attempt
+            warn!("Pairing with client {hostname} rejected. The client is no longer trusted");
+            SERVER_DATA_MANAGER
+                .write()
+                .update_client_list(hostname, ClientListAction::RemoveEntry);
+
+            false
+        }
+        _ => false,
+    }
+}
 
 fn align32(value: f32) -> u32 {
     ((value / 32.).floor() * 32.) as u32
 }
 
-// Alternate connection trials with manual IPs and clients discovered on the local network
+// The client must support the features enabled in the settings
+fn handshake_packet(settings: &Settings) -> HandshakePacket {
+    let mut required_capabilities = vec![match settings.video.codec {
+        CodecType::H264 => capabilities::H264,
+        CodecType::Hevc => capabilities::HEVC,
+    }];
+    if matches!(settings.connection.stream_protocol, SocketProtocol::Quic) {
+        required_capabilities.push(capabilities::QUIC);
+    }
+    if matches!(
+        settings.connection.forward_error_correction,
+        Switch::Enabled(_)
+    ) {
+        required_capabilities.push(capabilities::FORWARD_ERROR_CORRECTION);
+    }
+    if matches!(
+        settings.connection.packet_retransmission,
+        Switch::Enabled(_)
+    ) {
+        required_capabilities.push(capabilities::PACKET_RETRANSMISSION);
+    }
+
+    HandshakePacket::new(SERVER_CAPABILITIES, &required_capabilities)
+}
+
+// Clients joining a running stream must support its settings
+fn stream_settings(client_hostname: &str) -> Settings {
+    let running_session = STREAMING_SESSION.lock().upgrade();
+    if let Some(session) = running_session {
+        session.settings.clone()
+    } else {
+        SERVER_DATA_MANAGER.read().client_settings(client_hostname)
+    }
+}
+
+// Clients are reached through the network, or through Unix domain sockets when running on the
+// same machine
+enum ClientPeers {
+    Network(HashMap<IpAddr, String>),
+    Local(String),
+}
+
+// Add the client to the list if missing. Returns whether the client is trusted.
+fn register_client(client_hostname: &str, auto_trust: bool) -> bool {
+    let mut data_manager = SERVER_DATA_MANAGER.write();
+
+    data_manager.update_client_list(
+        client_hostname.to_owned(),
+        ClientListAction::AddIfMissing {
+            trusted: false,
+            manual_ips: vec![],
+        },
+    );
+
+    if auto_trust {
+        data_manager.update_client_list(client_hostname.to_owned(), ClientListAction::Trust);
+    }
+
+    data_manager
+        .client_list()
+        .get(client_hostname)
+        .unwrap()
+        .trusted
+}
+
+// Connected clients, clients that are pairing and incompatible clients are not tried again
+fn is_connection_candidate(hostname: &str) -> bool {
+    !CONNECTED_CLIENT_HOSTNAMES.lock().contains(hostname)
+        && !PAIRING_CLIENT_HOSTNAMES.lock().contains(hostname)
+        && !INCOMPATIBLE_CLIENT_HOSTNAMES.lock().contains(hostname)
+}
+
+// Alternate connection trials with manual IPs and clients discovered on the local network. With
+// local IPC, only clients on this machine are tried.
 pub fn handshake_loop(frame_interval_sender: smpsc::Sender<Duration>) -> IntResult {
-    let mut welcome_socket = WelcomeSocket::new().map_err(to_int_e!())?;
+    // Created when broadcast discovery is first enabled
+    let mut welcome_socket = None;
+    // Created when mDNS discovery is first enabled
+    let mut mdns_browser = None;
 
     loop {
         check_interrupt!(IS_ALIVE.value());
 
+        INCOMPATIBLE_CLIENT_HOSTNAMES.lock().retain(|hostname| {
+            SERVER_DATA_MANAGER
+                .read()
+                .client_list()
+                .contains_key(hostname)
+        });
+
+        let transport = SERVER_DATA_MANAGER
+            .read()
+            .settings()
+            .connection
+            .transport
+            .clone();
+        if let ConnectionTransport::LocalIpc { auto_trust_clients } = transport {
+            for client_hostname in alvr_sockets::local_ipc_clients() {
+                if register_client(&client_hostname, auto_trust_clients)
+                    && is_connection_candidate(&client_hostname)
+                {
+                    // Fails also for the socket files left by clients that exited
+                    if let Err(InterruptibleError::Other(e)) = try_connect(
+                        ClientPeers::Local(client_hostname.clone()),
+                        frame_interval_sender.clone(),
+                    ) {
+                        debug!("Local connection error for {client_hostname}: {e}");
+                    }
+                }
+            }
+
+            thread::sleep(RETRY_CONNECT_MIN_INTERVAL);
+            continue;
+        }
+
         let manual_client_ips = {
-            let connected_hostnames_lock = CONNECTED_CLIENT_HOSTNAMES.lock();
             let mut manual_client_ips = HashMap::new();
             for (hostname, connection_info) in SERVER_DATA_MANAGER.read().client_list() {
-                if !connected_hostnames_lock.contains(hostname) {
+                if is_connection_candidate(hostname) {
                     for ip in &connection_info.manual_ips {
                         manual_client_ips.insert(*ip, hostname.clone());
                     }
@@ -71,7 +389,11 @@ pub fn handshake_loop(frame_interval_sender: smpsc::Sender<Duration>) -> IntResu
         };
 
         if !manual_client_ips.is_empty()
-            && try_connect(manual_client_ips, frame_interval_sender.clone()).is_ok()
+            && try_connect(
+                ClientPeers::Network(manual_client_ips),
+                frame_interval_sender.clone(),
+            )
+            .is_ok()
         {
             thread::sleep(RETRY_CONNECT_MIN_INTERVAL);
             continue;
@@ -84,45 +406,51 @@ pub fn handshake_loop(frame_interval_sender: smpsc::Sender<Duration>) -> IntResu
             .client_discovery
             .clone();
         if let Switch::Enabled(config) = discovery_config {
-            let (client_hostname, client_ip) = match welcome_socket.recv_non_blocking() {
-                Ok(pair) => pair,
-                Err(e) => {
-                    if let InterruptibleError::Other(e) = e {
-                        warn!("UDP handshake listening error: {e}");
-                    }
+            let mut discovered_client = None;
 
-                    thread::sleep(RETRY_CONNECT_MIN_INTERVAL);
-                    continue;
+            if config.protocol != DiscoveryProtocol::Mdns {
+                if welcome_socket.is_none() {
+                    welcome_socket = Some(WelcomeSocket::new().map_err(to_int_e!())?);
                 }
-            };
 
-            let trusted = {
-                let mut data_manager = SERVER_DATA_MANAGER.write();
+                if let Some(socket) = &mut welcome_socket {
+                    match socket.recv_non_blocking() {
+                        Ok(pair) => discovered_client = Some(pair),
+                        Err(InterruptibleError::Other(e)) => {
+                            warn!("UDP handshake listening error: {e}")
+                        }
+                        Err(_) => (),
+                    }
+                }
+            }
 
-                data_manager.update_client_list(
-                    client_hostname.clone(),
-                    ClientListAction::AddIfMissing {
-                        trusted: false,
-                        manual_ips: vec![],
-                    },
-                );
+            if discovered_client.is_none() && config.protocol != DiscoveryProtocol::Broadcast {
+                if mdns_browser.is_none() {
+                    mdns_browser = MdnsBrowser::new()
+                        .map_err(|e| warn!("mDNS browser error: {e}"))
+                        .ok();
+                }
 
-                if config.auto_trust_clients {
-                    data_manager
-                        .update_client_list(client_hostname.clone(), ClientListAction::Trust);
+                if let Some(browser) = &mut mdns_browser {
+                    discovered_client = browser.next_client();
                 }
+            }
 
-                data_manager
-                    .client_list()
-                    .get(&client_hostname)
-                    .unwrap()
-                    .trusted
+            let (client_hostname, client_ip) = if let Some(pair) = discovered_client {
+                pair
+            } else {
+                thread::sleep(RETRY_CONNECT_MIN_INTERVAL);
+                continue;
             };
 
+            let trusted = register_client(&client_hostname, config.auto_trust_clients);
+
             // do not attempt connection if the client is already connected
-            if trusted && !CONNECTED_CLIENT_HOSTNAMES.lock().contains(&client_hostname) {
-                if let Err(e) = try_connect(
-                    [(client_ip, client_hostname.clone())].into_iter().collect(),
+            if trusted && is_connection_candidate(&client_hostname) {
+                if let Err(InterruptibleError::Other(e)) = try_connect(
+                    ClientPeers::Network(
+                        [(client_ip, client_hostname.clone())].into_iter().collect(),
+                    ),
                     frame_interval_sender.clone(),
                 ) {
                     error!("Handshake error for {client_hostname}: {e}");
@@ -134,19 +462,19 @@ pub fn handshake_loop(frame_interval_sender: smpsc::Sender<Duration>) -> IntResu
     }
 }
 
-fn try_connect(
-    mut client_ips: HashMap<IpAddr, String>,
-    frame_interval_sender: smpsc::Sender<Duration>,
-) -> IntResult {
+fn try_connect(peers: ClientPeers, frame_interval_sender: smpsc::Sender<Duration>) -> IntResult {
     let runtime = Runtime::new().map_err(to_int_e!())?;
 
+    let peer_type = match &peers {
+        ClientPeers::Network(client_ips) => {
+            PeerType::AnyClient(client_ips.keys().cloned().collect())
+        }
+        ClientPeers::Local(client_hostname) => PeerType::LocalClient(client_hostname),
+    };
     let (mut proto_socket, client_ip) = runtime
         .block_on(async {
-            let get_proto_socket = ProtoControlSocket::connect_to(PeerType::AnyClient(
-                client_ips.keys().cloned().collect(),
-            ));
             tokio::select! {
-                proto_socket = get_proto_socket => proto_socket,
+                proto_socket = ProtoControlSocket::connect_to(peer_type) => proto_socket,
                 _ = time::sleep(Duration::from_secs(1)) => {
                     fmt_e!("Control socket failed to connect")
                 }
@@ -154,42 +482,155 @@ fn try_connect(
         })
         .map_err(to_int_e!())?;
 
-    // Safety: this never panics because client_ip is picked from client_ips keys
-    let client_hostname = client_ips.remove(&client_ip).unwrap();
+    let client_hostname = match peers {
+        // Safety: this never panics because client_ip is picked from client_ips keys
+        ClientPeers::Network(mut client_ips) => client_ips.remove(&client_ip).unwrap(),
+        ClientPeers::Local(client_hostname) => client_hostname,
+    };
 
     SERVER_DATA_MANAGER.write().update_client_list(
         client_hostname.clone(),
         ClientListAction::UpdateCurrentIp(Some(client_ip)),
     );
 
-    let maybe_streaming_caps = if let ClientConnectionResult::ConnectionAccepted {
-        display_name,
-        streaming_capabilities,
-        ..
-    } = runtime.block_on(proto_socket.recv()).map_err(to_int_e!())?
-    {
+    let handshake = handshake_packet(&stream_settings(&client_hostname));
+    let client_handshake = runtime
+        .block_on(proto_socket.exchange_handshake(&handshake, true))
+        .map_err(to_int_e!())?;
+    match handshake.negotiate(&client_handshake) {
+        Ok(capabilities) => info!(
+            "Client {client_hostname} v{} capabilities: {}",
+            client_handshake.version,
+            capabilities_to_string(&capabilities)
+        ),
+        Err(incompatibility) => {
+            // Logged here to be shown in the dashboard also for clients with manual IPs
+            error!(
+                "{}",
+                incompatibility.describe("streamer", &format!("client {client_hostname}"))
+            );
+            INCOMPATIBLE_CLIENT_HOSTNAMES.lock().insert(client_hostname);
+
+            return interrupt();
+        }
+    }
+
+    let pairing_key = SERVER_DATA_MANAGER
+        .read()
+        .pairing_key(&client_hostname)
+        .map(PairingKey::from_hex_string)
+        .transpose()
+        .map_err(to_int_e!())?;
+
+    if pairing_key.is_none() {
+        // The user may take a while to confirm the pairing code. Meanwhile, other clients can
+        // still be discovered and connected
+        PAIRING_CLIENT_HOSTNAMES
+            .lock()
+            .insert(client_hostname.clone());
+        thread::spawn(move || {
+            if let Err(InterruptibleError::Other(e)) = accept_client(
+                runtime,
+                proto_socket,
+                client_hostname.clone(),
+                client_ip,
+                None,
+                frame_interval_sender,
+            ) {
+                error!("Connection error for {client_hostname}: {e}");
+            }
+
+            PAIRING_CLIENT_HOSTNAMES.lock().remove(&client_hostname);
+        });
+
+        return Ok(());
+    }
+
+    accept_client(
+        runtime,
+        proto_socket,
+        client_hostname,
+        client_ip,
+        pairing_key,
+        frame_interval_sender,
+    )
+}
+
+// Authenticates the client, then starts or joins the stream
+fn accept_client(
+    runtime: Runtime,
+    mut proto_socket: ProtoControlSocket,
+    client_hostname: String,
+    client_ip: IpAddr,
+    pairing_key: Option<PairingKey>,
+    frame_interval_sender: smpsc::Sender<Duration>,
+) -> IntResult {
+    let (new_pairing_key, stream_keys) =
+        match runtime.block_on(proto_socket.authenticate_client(pairing_key, |code| {
+            wait_pairing_confirmation(client_hostname.clone(), code)
+        })) {
+            Ok(pair) => pair,
+            Err(e) => return int_fmt_e!("Failed to authenticate client {client_hostname}: {e}"),
+        };
+
+    if pairing_key != Some(new_pairing_key) {
+        info!("Paired with client {client_hostname}");
+
         SERVER_DATA_MANAGER.write().update_client_list(
             client_hostname.clone(),
-            ClientListAction::SetDisplayName(display_name),
+            ClientListAction::SetPairingKey(Some(new_pairing_key.to_hex_string())),
         );
+    }
 
-        streaming_capabilities
-    } else {
-        debug!("Found client in standby. Retrying");
-        return Ok(());
+    let maybe_streaming_caps = match runtime.block_on(proto_socket.recv()).map_err(to_int_e!())? {
+        ClientConnectionResult::ConnectionAccepted {
+            display_name,
+            streaming_capabilities,
+            ..
+        } => {
+            SERVER_DATA_MANAGER.write().update_client_list(
+                client_hostname.clone(),
+                ClientListAction::SetDisplayName(display_name),
+            );
+
+            streaming_capabilities
+        }
+        ClientConnectionResult::ClientStandby => {
+            debug!("Found client in standby. Retrying");
+            return Ok(());
+        }
+        ClientConnectionResult::ResumeRequested { .. } => {
+            // Closing the connection makes the client start a new session at the next attempt
+            debug!("Client {client_hostname} tried to resume an expired stream");
+            return Ok(());
+        }
     };
 
     let streaming_caps = if let Some(streaming_caps) = maybe_streaming_caps {
-        if let Some(hostname) = &*STREAMING_CLIENT_HOSTNAME.lock() {
-            return int_fmt_e!("Streaming client {hostname} is already connected!");
-        } else {
-            streaming_caps
-        }
+        streaming_caps
     } else {
         return int_fmt_e!("Only streaming clients are supported for now");
     };
 
-    let settings = SERVER_DATA_MANAGER.read().settings().clone();
+    let settings = SERVER_DATA_MANAGER.read().client_settings(&client_hostname);
+
+    // Clients that finished pairing are accepted on another thread. Only one at a time can start
+    // or join the stream
+    let _stream_setup_lock = STREAM_SETUP_LOCK.lock();
+
+    // Clients connecting while the stream is running join it with the same configuration
+    let running_session = STREAMING_SESSION.lock().upgrade();
+    if let Some(session) = running_session {
+        return join_streaming_session(
+            runtime,
+            proto_socket,
+            client_hostname,
+            client_ip,
+            stream_keys,
+            streaming_caps.microphone_sample_rate,
+            session,
+        );
+    }
 
     fn get_view_res(config: FrameSize, default_res: UVec2) -> UVec2 {
         let res = match config {
@@ -267,20 +708,32 @@ fn try_connect(
         0
     };
 
+    let session_token = SessionToken::new_random().map_err(to_int_e!())?;
+    let server_stream_port = STREAMING_CLIENTS
+        .lock()
+        .free_port(settings.connection.stream_port)
+        .ok_or_else(enone!())
+        .map_err(to_int_e!())?;
+
     let client_config = StreamConfigPacket {
         session_desc: {
-            let session = SERVER_DATA_MANAGER.read().session().clone();
+            let session = SERVER_DATA_MANAGER
+                .read()
+                .session()
+                .with_client_overrides(&client_hostname);
             serde_json::to_string(&session).map_err(to_int_e!())?
         },
         view_resolution: stream_view_resolution,
         fps,
         game_audio_sample_rate,
+        session_token,
+        server_stream_port,
     };
     runtime
         .block_on(proto_socket.send(&client_config))
         .map_err(to_int_e!())?;
 
-    let (mut control_sender, control_receiver) = proto_socket.split();
+    let (mut control_sender, control_receiver) = proto_socket.split().map_err(to_int_e!())?;
 
     let mut controllers_mode_idx = 0;
     let mut override_trigger_threshold = false;
@@ -425,11 +878,129 @@ fn try_connect(
         crate::notify_restart_driver();
     }
 
+    let session = Arc::new(StreamingSession::new(
+        stream_view_resolution,
+        fps,
+        game_audio_sample_rate,
+        SERVER_DATA_MANAGER
+            .read()
+            .session()
+            .with_client_overrides(&client_hostname),
+        &frame_interval_sender,
+    ));
+    *STREAMING_SESSION.lock() = Arc::downgrade(&session);
+
+    spawn_connection(
+        runtime,
+        client_hostname,
+        client_ip,
+        control_sender,
+        control_receiver,
+        stream_keys,
+        streaming_caps.microphone_sample_rate,
+        session,
+        session_token,
+        server_stream_port,
+        StreamingRole::Primary,
+    );
+
+    Ok(())
+}
+
+// The client becomes a spectator, or the primary client if the previous one disconnected
+#[allow(clippy::too_many_arguments)]
+fn join_streaming_session(
+    runtime: Runtime,
+    mut proto_socket: ProtoControlSocket,
+    client_hostname: String,
+    client_ip: IpAddr,
+    stream_keys: ChannelKeys,
+    microphone_sample_rate: u32,
+    session: Arc<StreamingSession>,
+) -> IntResult {
+    let settings = &session.settings;
+    let (role, server_stream_port) = {
+        let clients_lock = STREAMING_CLIENTS.lock();
+
+        let role = if clients_lock.primary.is_none() {
+            StreamingRole::Primary
+        } else if let Switch::Enabled(config) = &settings.connection.spectators {
+            if clients_lock.spectators_count() as u64 >= config.max_count {
+                return int_fmt_e!(
+                    "Client {client_hostname} cannot join the stream: too many spectators"
+                );
+            }
+
+            StreamingRole::Spectator
+        } else {
+            return int_fmt_e!(
+                "Client {client_hostname} cannot join the stream: spectators are disabled"
+            );
+        };
+
+        let server_stream_port = clients_lock
+            .free_port(settings.connection.stream_port)
+            .ok_or_else(enone!())
+            .map_err(to_int_e!())?;
+
+        (role, server_stream_port)
+    };
+
+    let session_token = SessionToken::new_random().map_err(to_int_e!())?;
+
+    let client_config = StreamConfigPacket {
+        session_desc: serde_json::to_string(&session.session_desc).map_err(to_int_e!())?,
+        view_resolution: session.view_resolution,
+        fps: session.fps,
+        game_audio_sample_rate: session.game_audio_sample_rate,
+        session_token,
+        server_stream_port,
+    };
+    runtime
+        .block_on(proto_socket.send(&client_config))
+        .map_err(to_int_e!())?;
+
+    let (control_sender, control_receiver) = proto_socket.split().map_err(to_int_e!())?;
+
+    spawn_connection(
+        runtime,
+        client_hostname,
+        client_ip,
+        control_sender,
+        control_receiver,
+        stream_keys,
+        microphone_sample_rate,
+        session,
+        session_token,
+        server_stream_port,
+        role,
+    );
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_connection(
+    runtime: Runtime,
+    client_hostname: String,
+    client_ip: IpAddr,
+    control_sender: ControlSocketSender<ServerControlPacket>,
+    control_receiver: ControlSocketReceiver<ClientControlPacket>,
+    stream_keys: ChannelKeys,
+    microphone_sample_rate: u32,
+    session: Arc<StreamingSession>,
+    session_token: SessionToken,
+    server_stream_port: u16,
+    role: StreamingRole,
+) {
     CONNECTED_CLIENT_HOSTNAMES
         .lock()
         .insert(client_hostname.clone());
 
-    *STREAMING_CLIENT_HOSTNAME.lock() = Some(client_hostname.clone());
+    STREAMING_CLIENTS
+        .lock()
+        .add(client_hostname.clone(), server_stream_port, role);
+    set_streaming_role(&client_hostname, Some(role));
 
     thread::spawn(move || {
         runtime.block_on({
@@ -448,9 +1019,11 @@ fn try_connect(
                         client_ip,
                         control_sender,
                         control_receiver,
-                        streaming_caps.microphone_sample_rate,
-                        fps,
-                        frame_interval_sender
+                        stream_keys,
+                        microphone_sample_rate,
+                        session,
+                        session_token,
+                        server_stream_port,
                     ) => {
                         show_warn(res);
                     },
@@ -461,18 +1034,19 @@ fn try_connect(
         });
 
         {
-            let mut streaming_hostname_lock = STREAMING_CLIENT_HOSTNAME.lock();
-            if let Some(hostname) = streaming_hostname_lock.clone() {
-                if hostname == client_hostname {
-                    *streaming_hostname_lock = None
+            let mut clients_lock = STREAMING_CLIENTS.lock();
+            if clients_lock.remove(&client_hostname) {
+                PRIMARY_CLIENT_NOTIFIER.notify_waiters();
+
+                if clients_lock.spectators_count() > 0 {
+                    info!("The primary client disconnected. Choose a new one in the dashboard");
                 }
             }
         }
+        set_streaming_role(&client_hostname, None);
 
         CONNECTED_CLIENT_HOSTNAMES.lock().remove(&client_hostname);
     });
-
-    Ok(())
 }
 
 // close stream on Drop (manual disconnection or execution canceling)
@@ -504,15 +1078,276 @@ impl Drop for StreamCloseGuard {
     }
 }
 
+// Shared by the clients receiving the stream. The stream is closed when the last client
+// disconnects.
+struct StreamingSession {
+    // Session with the overrides of the client which started the stream. The clients joining the
+    // stream receive it, since the encoder is already configured.
+    session_desc: SessionDesc,
+    settings: Settings,
+    view_resolution: UVec2,
+    fps: f32,
+    game_audio_sample_rate: u32,
+    tracking_manager: Arc<Mutex<TrackingManager>>,
+    playspace_sync_sender: parking_lot::Mutex<smpsc::Sender<Option<Vec2>>>,
+    _stream_guard: StreamCloseGuard,
+}
+
+impl StreamingSession {
+    fn new(
+        view_resolution: UVec2,
+        fps: f32,
+        game_audio_sample_rate: u32,
+        session_desc: SessionDesc,
+        frame_interval_sender: &smpsc::Sender<Duration>,
+    ) -> Self {
+        let settings = session_desc.to_settings();
+
+        *STATISTICS_MANAGER.lock() = Some(StatisticsManager::new(
+            settings.connection.statistics_history_size as _,
+            Duration::from_secs_f32(1.0 / fps),
+            if let Switch::Enabled(config) = &settings.headset.controllers {
+                config.steamvr_pipeline_frames
+            } else {
+                0.0
+            },
+        ));
+
+        *BITRATE_MANAGER.lock() = BitrateManager::new(
+            settings.video.bitrate.clone(),
+            settings.connection.statistics_history_size as _,
+        );
+
+        // todo: dynamic framerate
+        frame_interval_sender
+            .send(Duration::from_secs_f32(1.0 / fps))
+            .ok();
+
+        {
+            let on_connect_script = &settings.connection.on_connect_script;
+
+            if !on_connect_script.is_empty() {
+                info!("Running on connect script (connect): {on_connect_script}");
+                if let Err(e) = Command::new(on_connect_script)
+                    .env("ACTION", "connect")
+                    .spawn()
+                {
+                    warn!("Failed to run connect script: {e}");
+                }
+            }
+        }
+
+        if settings.extra.save_video_stream {
+            crate::create_recording_file();
+        }
+
+        unsafe { crate::InitializeStreaming() };
+
+        let is_streaming = Arc::new(RelaxedAtomic::new(true));
+        let stream_guard = StreamCloseGuard(Arc::clone(&is_streaming));
+
+        let (playspace_sync_sender, playspace_sync_receiver) = smpsc::channel::<Option<Vec2>>();
+
+        if !settings.headset.tracking_ref_only {
+            // use a separate thread because SetChaperone() is blocking
+            thread::spawn(move || {
+                while let Ok(packet) = playspace_sync_receiver.recv() {
+                    if let Some(area) = packet {
+                        unsafe { crate::SetChaperone(area.x, area.y) };
+                    } else {
+                        unsafe { crate::SetChaperone(2.0, 2.0) };
+                    }
+                }
+            });
+        }
+
+        Self {
+            view_resolution,
+            fps,
+            game_audio_sample_rate,
+            tracking_manager: Arc::new(Mutex::new(TrackingManager::new(&settings.headset))),
+            session_desc,
+            settings,
+            playspace_sync_sender: parking_lot::Mutex::new(playspace_sync_sender),
+            _stream_guard: stream_guard,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn connection_pipeline(
     client_hostname: String,
     client_ip: IpAddr,
     control_sender: ControlSocketSender<ServerControlPacket>,
-    mut control_receiver: ControlSocketReceiver<ClientControlPacket>,
+    control_receiver: ControlSocketReceiver<ClientControlPacket>,
+    stream_keys: ChannelKeys,
     microphone_sample_rate: u32,
-    refresh_rate: f32,
-    frame_interval_sender: smpsc::Sender<Duration>,
+    session: Arc<StreamingSession>,
+    session_token: SessionToken,
+    server_stream_port: u16,
 ) -> StrResult {
+    let settings = session.settings.clone();
+
+    // The streaming session outlives the connection, which can be resumed if lost
+    let mut connection = Some((control_sender, control_receiver, stream_keys));
+    let mut resume_state = ResumeStateMachine::new(RESUME_TIMEOUT, RESUME_RETRY_INTERVAL);
+    loop {
+        if let Some((control_sender, control_receiver, stream_keys)) = connection.take() {
+            let resumed = matches!(resume_state.state(), ConnectionState::Resuming { .. });
+            resume_state.connected();
+
+            let connection_end = stream_connection(
+                client_hostname.clone(),
+                client_ip,
+                server_stream_port,
+                control_sender,
+                control_receiver,
+                stream_keys,
+                microphone_sample_rate,
+                settings.clone(),
+                Arc::clone(&session),
+                resumed,
+            )
+            .await?;
+
+            match connection_end {
+                ConnectionEnd::Closed => return Ok(()),
+                ConnectionEnd::Lost => {
+                    info!("Connection with client {client_hostname} lost. Resuming the stream");
+                    resume_state.connection_lost(Instant::now());
+                }
+            }
+        }
+
+        match resume_state.poll(Instant::now()) {
+            ResumeAction::TryResume => {
+                let resume = resume_connection(
+                    &client_hostname,
+                    client_ip,
+                    &settings,
+                    session_token,
+                    &resume_state,
+                );
+                match resume.await {
+                    Ok(new_connection) => {
+                        info!("Resumed stream with client {client_hostname}");
+                        connection = Some(new_connection);
+                    }
+                    Err(e) => {
+                        debug!("Failed to resume stream with client {client_hostname}: {e}");
+                        resume_state.attempt_failed(Instant::now());
+                    }
+                }
+            }
+            ResumeAction::Wait(duration) => time::sleep(duration).await,
+            ResumeAction::GiveUp => {
+                info!("Could not resume stream with client {client_hostname}");
+                return Ok(());
+            }
+        }
+    }
+}
+
+type ControlConnection = (
+    ControlSocketSender<ServerControlPacket>,
+    ControlSocketReceiver<ClientControlPacket>,
+    ChannelKeys,
+);
+
+// Connects to the client again after a brief disconnection. The handshake and authentication are
+// repeated, since the client can tell a resume apart from a new connection only once
+// authenticated, but the stream configuration is not negotiated again.
+async fn resume_connection(
+    client_hostname: &str,
+    client_ip: IpAddr,
+    settings: &Settings,
+    session_token: SessionToken,
+    resume_state: &ResumeStateMachine,
+) -> StrResult<ControlConnection> {
+    let peer_type = match settings.connection.transport {
+        ConnectionTransport::Network => PeerType::AnyClient(vec![client_ip]),
+        ConnectionTransport::LocalIpc { .. } => PeerType::LocalClient(client_hostname),
+    };
+    let (mut proto_socket, _) = tokio::select! {
+        res = ProtoControlSocket::connect_to(peer_type) => res?,
+        _ = time::sleep(Duration::from_secs(1)) => {
+            return fmt_e!("Control socket failed to connect");
+        }
+    };
+
+    let handshake = handshake_packet(settings);
+    let client_handshake = proto_socket.exchange_handshake(&handshake, true).await?;
+    if let Err(incompatibility) = handshake.negotiate(&client_handshake) {
+        return fmt_e!(
+            "{}",
+            incompatibility.describe("streamer", &format!("client {client_hostname}"))
+        );
+    }
+
+    // The client was paired when the stream started
+    let pairing_key = SERVER_DATA_MANAGER
+        .read()
+        .pairing_key(client_hostname)
+        .map(PairingKey::from_hex_string)
+        .transpose()?
+        .ok_or_else(enone!())?;
+    let (_, stream_keys) = proto_socket
+        .authenticate_client(Some(pairing_key), |_| future::ready(false))
+        .await?;
+
+    match proto_socket.recv().await? {
+        ClientConnectionResult::ResumeRequested {
+            session_token: requested_token,
+        } => resume_state.validate_token(session_token, requested_token, Instant::now())?,
+        _ => return fmt_e!("The client did not request to resume the stream"),
+    }
+
+    let (control_sender, control_receiver) = proto_socket.split()?;
+
+    Ok((control_sender, control_receiver, stream_keys))
+}
+
+// Padded bursts sent one per frame interval, like video frames. The client measures the throughput
+async fn send_bandwidth_probe(
+    mut sender: StreamSender<BandwidthProbeHeader>,
+    config: &BandwidthProbingConfig,
+    frame_interval: Duration,
+) {
+    let (bursts_count, burst_bytes) = bandwidth_probe_bursts(
+        Duration::from_millis(config.duration_ms),
+        frame_interval,
+        config.max_bitrate_mbps as f32 * 1e6,
+    );
+
+    let mut deadline = time::Instant::now();
+    for index in 0..bursts_count {
+        let header = BandwidthProbeHeader {
+            index,
+            bursts_count,
+        };
+        if let Err(e) = sender.send(&header, vec![0; burst_bytes]).await {
+            warn!("Bandwidth probe error: {e}");
+            return;
+        }
+
+        deadline += frame_interval;
+        time::sleep_until(deadline).await;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stream_connection(
+    client_hostname: String,
+    client_ip: IpAddr,
+    server_stream_port: u16,
+    control_sender: ControlSocketSender<ServerControlPacket>,
+    mut control_receiver: ControlSocketReceiver<ClientControlPacket>,
+    stream_keys: ChannelKeys,
+    microphone_sample_rate: u32,
+    settings: Settings,
+    session: Arc<StreamingSession>,
+    resumed: bool,
+) -> StrResult<ConnectionEnd> {
     let control_sender = Arc::new(Mutex::new(control_sender));
 
     control_sender
@@ -531,68 +1366,59 @@ async fn connection_pipeline(
         }
     }
 
-    let settings = SERVER_DATA_MANAGER.read().settings().clone();
+    let fec_redundancy_ratio =
+        if let Switch::Enabled(config) = &settings.connection.forward_error_correction {
+            Some(config.redundancy_ratio)
+        } else {
+            None
+        };
 
-    let stream_socket = tokio::select! {
-        res = StreamSocketBuilder::connect_to_client(
+    let retransmission = settings
+        .connection
+        .packet_retransmission
+        .clone()
+        .into_option();
+    let connect_stream_socket: BoxFuture<_> = match &settings.connection.transport {
+        ConnectionTransport::Network => Box::pin(StreamSocketBuilder::connect_to_client(
             client_ip,
             settings.connection.stream_port,
+            server_stream_port,
             settings.connection.stream_protocol,
             settings.connection.server_send_buffer_bytes,
             settings.connection.server_recv_buffer_bytes,
             settings.connection.packet_size as _,
-        ) => res?,
+            fec_redundancy_ratio,
+            retransmission,
+            settings.connection.reorder_window_size as _,
+            stream_keys,
+        )),
+        ConnectionTransport::LocalIpc { .. } => {
+            Box::pin(StreamSocketBuilder::connect_to_local_client(
+                &client_hostname,
+                settings.connection.packet_size as _,
+                fec_redundancy_ratio,
+                retransmission,
+                settings.connection.reorder_window_size as _,
+                stream_keys,
+            ))
+        }
+    };
+    let stream_socket = tokio::select! {
+        res = connect_stream_socket => res?,
         _ = time::sleep(Duration::from_secs(5)) => {
             return fmt_e!("Timeout while setting up streams");
         }
     };
-    let stream_socket = Arc::new(stream_socket);
-
-    *STATISTICS_MANAGER.lock() = Some(StatisticsManager::new(
-        settings.connection.statistics_history_size as _,
-        Duration::from_secs_f32(1.0 / refresh_rate),
-        if let Switch::Enabled(config) = &settings.headset.controllers {
-            config.steamvr_pipeline_frames
-        } else {
-            0.0
-        },
-    ));
-
-    *BITRATE_MANAGER.lock() = BitrateManager::new(
-        settings.video.bitrate,
-        settings.connection.statistics_history_size as _,
-    );
-
-    // todo: dynamic framerate
-    frame_interval_sender
-        .send(Duration::from_secs_f32(1.0 / refresh_rate))
-        .ok();
-
-    {
-        let on_connect_script = settings.connection.on_connect_script;
-
-        if !on_connect_script.is_empty() {
-            info!("Running on connect script (connect): {on_connect_script}");
-            if let Err(e) = Command::new(&on_connect_script)
-                .env("ACTION", "connect")
-                .spawn()
-            {
-                warn!("Failed to run connect script: {e}");
-            }
+    if settings.extra.capture_stream {
+        let path = FILESYSTEM_LAYOUT.log_dir.join("stream_capture.bin");
+        if let Err(e) = stream_socket.capture_to_file(&path).await {
+            warn!("Failed to capture stream: {e}");
         }
     }
-
-    if settings.extra.save_video_stream {
-        crate::create_recording_file();
-    }
-
-    unsafe { crate::InitializeStreaming() };
-
-    let is_streaming = Arc::new(RelaxedAtomic::new(true));
-    let _stream_guard = StreamCloseGuard(Arc::clone(&is_streaming));
+    let stream_socket = Arc::new(stream_socket);
 
     let game_audio_loop: BoxFuture<_> = if let Switch::Enabled(config) = settings.audio.game_audio {
-        let sender = stream_socket.request_stream(AUDIO).await?;
+        let mut sender = stream_socket.request_stream(AUDIO).await?;
         Box::pin(async move {
             loop {
                 let device = match AudioDevice::new_output(
@@ -623,9 +1449,9 @@ async fn connection_pipeline(
                     continue;
                 };
 
-                let new_sender = sender.clone();
+                // The same sender is reused, so that packet indices and nonces never repeat
                 if let Err(e) =
-                    alvr_audio::record_audio_loop(device, 2, mute_when_streaming, new_sender).await
+                    alvr_audio::record_audio_loop(device, 2, mute_when_streaming, &mut sender).await
                 {
                     warn!("Audio task exit with error : {e}")
                 }
@@ -649,46 +1475,110 @@ async fn connection_pipeline(
     } else {
         Box::pin(future::pending())
     };
-    let microphone_loop: BoxFuture<_> = if let Switch::Enabled(config) = settings.audio.microphone {
-        #[allow(unused_variables)]
-        let (sink, source) = AudioDevice::new_virtual_microphone_pair(
-            Some(settings.audio.linux_backend),
-            config.devices,
-        )?;
-        let receiver = stream_socket.subscribe_to_stream(AUDIO).await?;
-
-        #[cfg(windows)]
-        if let Ok(id) = alvr_audio::get_windows_device_id(&source) {
-            unsafe {
-                crate::SetOpenvrProperty(
-                    *alvr_common::HEAD_ID,
-                    crate::openvr_props::to_ffi_openvr_prop(
-                        alvr_session::OpenvrPropertyKey::AudioDefaultRecordingDeviceId,
-                        alvr_session::OpenvrPropValue::String(id),
-                    ),
-                )
-            }
-        }
+    // Only one client can use the virtual microphone: the primary client. The audio of the
+    // spectators is discarded, and the microphone follows the primary role when handed over.
+    let microphone_loop: BoxFuture<_> =
+        if let Switch::Enabled(config) = settings.audio.microphone.clone() {
+            let mut receiver = stream_socket
+                .subscribe_to_stream_with_retransmission(AUDIO, AUDIO_QUEUE)
+                .await?;
+            let linux_backend = settings.audio.linux_backend;
+            let client_hostname = client_hostname.clone();
 
-        Box::pin(alvr_audio::play_audio_loop(
-            sink,
-            1,
-            microphone_sample_rate,
-            config.buffering,
-            receiver,
-        ))
-    } else {
-        Box::pin(future::pending())
-    };
+            Box::pin(async move {
+                loop {
+                    tokio::select! {
+                        res = discard_stream(&mut receiver) => return res,
+                        _ = wait_primary_role(&client_hostname, true) => (),
+                    }
+
+                    #[allow(unused_variables)]
+                    let (sink, source) = AudioDevice::new_virtual_microphone_pair(
+                        Some(linux_backend),
+                        config.devices.clone(),
+                    )?;
+
+                    #[cfg(windows)]
+                    if let Ok(id) = alvr_audio::get_windows_device_id(&source) {
+                        unsafe {
+                            crate::SetOpenvrProperty(
+                                *alvr_common::HEAD_ID,
+                                crate::openvr_props::to_ffi_openvr_prop(
+                                    alvr_session::OpenvrPropertyKey::AudioDefaultRecordingDeviceId,
+                                    alvr_session::OpenvrPropValue::String(id),
+                                ),
+                            )
+                        }
+                    }
+
+                    tokio::select! {
+                        res = alvr_audio::play_audio_loop(
+                            sink,
+                            1,
+                            microphone_sample_rate,
+                            config.buffering.clone(),
+                            &mut receiver,
+                        ) => return res,
+                        _ = wait_primary_role(&client_hostname, false) => {
+                            info!("Client {client_hostname} released the microphone");
+                        }
+                    }
+                }
+            })
+        } else {
+            Box::pin(future::pending())
+        };
 
     let video_send_loop = {
         let mut socket_sender = stream_socket.request_stream(VIDEO).await?;
+        let probe_sender = match &settings.connection.bandwidth_probing {
+            Switch::Enabled(config)
+                if PROBED_CLIENT_HOSTNAMES
+                    .lock()
+                    .insert(client_hostname.clone()) =>
+            {
+                Some((
+                    stream_socket.request_stream(BANDWIDTH_PROBE).await?,
+                    config.clone(),
+                ))
+            }
+            _ => None,
+        };
+        let frame_interval = Duration::from_secs_f32(1.0 / session.fps);
+        let pacing_config = settings.connection.video_pacing.clone().into_option();
+        let client_hostname = client_hostname.clone();
         async move {
-            let (data_sender, mut data_receiver) = tmpsc::unbounded_channel();
-            *VIDEO_SENDER.lock() = Some(data_sender);
+            // The video is sent to this client only after the probe, not to disturb the measurement
+            if let Some((sender, config)) = probe_sender {
+                send_bandwidth_probe(sender, &config, frame_interval).await;
+
+                // The keyframes sent in the meantime were skipped
+                unsafe { crate::RequestIDR() };
+            }
+
+            let (data_sender, mut data_receiver) = bounded_queue(VIDEO_SEND_QUEUE);
+            VIDEO_SENDERS
+                .lock()
+                .insert(client_hostname.clone(), data_sender);
 
             while let Some(VideoPacket { timestamp, payload }) = data_receiver.recv().await {
+                if let Some(config) = &pacing_config {
+                    let rate_bps = BITRATE_MANAGER
+                        .lock()
+                        .get_pacing_rate_bps(config.frame_interval_fraction);
+                    socket_sender.set_pacing_rate(rate_bps / 8.0);
+                }
+
                 socket_sender.send(&timestamp, payload).await.ok();
+
+                if pacing_config.is_some() && is_primary_client(&client_hostname) {
+                    if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+                        stats.report_video_pacing(
+                            socket_sender.pacing_rate() * 8.0,
+                            socket_sender.pacing_queue_delay(),
+                        );
+                    }
+                }
             }
 
             Ok(())
@@ -698,9 +1588,12 @@ async fn connection_pipeline(
     let haptics_send_loop = {
         let mut socket_sender = stream_socket.request_stream(HAPTICS).await?;
         let controllers_desc = settings.headset.controllers.clone();
+        let client_hostname = client_hostname.clone();
         async move {
-            let (data_sender, mut data_receiver) = tmpsc::unbounded_channel();
-            *HAPTICS_SENDER.lock() = Some(data_sender);
+            let (data_sender, mut data_receiver) = bounded_queue(HAPTICS_SEND_QUEUE);
+            HAPTICS_SENDERS
+                .lock()
+                .insert(client_hostname.clone(), data_sender);
 
             let haptics_manager = controllers_desc
                 .into_option()
@@ -708,6 +1601,10 @@ async fn connection_pipeline(
                 .map(HapticsManager::new);
 
             while let Some(haptics) = data_receiver.recv().await {
+                if !is_primary_client(&client_hostname) {
+                    continue;
+                }
+
                 if settings.extra.log_haptics {
                     alvr_events::send_event(EventType::Haptics(HapticsEvent {
                         path: DEVICE_ID_TO_PATH
@@ -729,34 +1626,27 @@ async fn connection_pipeline(
         }
     };
 
-    let (playspace_sync_sender, playspace_sync_receiver) = smpsc::channel::<Option<Vec2>>();
-
     let is_tracking_ref_only = settings.headset.tracking_ref_only;
-    if !is_tracking_ref_only {
-        // use a separate thread because SetChaperone() is blocking
-        thread::spawn(move || {
-            while let Ok(packet) = playspace_sync_receiver.recv() {
-                if let Some(area) = packet {
-                    unsafe { crate::SetChaperone(area.x, area.y) };
-                } else {
-                    unsafe { crate::SetChaperone(2.0, 2.0) };
-                }
-            }
-        });
-    }
-
-    let tracking_manager = Arc::new(Mutex::new(TrackingManager::new(&settings.headset)));
 
     let tracking_receive_loop = {
         let mut receiver = stream_socket
-            .subscribe_to_stream::<Tracking>(TRACKING)
+            .subscribe_to_stream::<Tracking>(TRACKING, TRACKING_QUEUE)
             .await?;
-        let tracking_manager = Arc::clone(&tracking_manager);
+        let session = Arc::clone(&session);
+        let client_hostname = client_hostname.clone();
         async move {
+            let mut receiver_buffer = ReceiverBuffer::new();
             loop {
-                let tracking = receiver.recv_header_only().await?;
+                receiver.recv_buffer(&mut receiver_buffer).await?;
+
+                // The tracking of spectators is ignored
+                if !is_primary_client(&client_hostname) {
+                    continue;
+                }
 
-                let mut tracking_manager_lock = tracking_manager.lock().await;
+                let (tracking, _) = receiver_buffer.get()?;
+
+                let mut tracking_manager_lock = session.tracking_manager.lock().await;
 
                 let ffi_motions = tracking_manager_lock.transform_motions(
                     &tracking.device_motions,
@@ -775,6 +1665,7 @@ async fn connection_pipeline(
 
                 if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
                     stats.report_tracking_received(tracking.target_timestamp);
+                    stats.report_tracking_shards_dropped(receiver_buffer.dropped_shards_count());
 
                     unsafe {
                         crate::SetTracking(
@@ -801,12 +1692,21 @@ async fn connection_pipeline(
 
     let statistics_receive_loop = {
         let mut receiver = stream_socket
-            .subscribe_to_stream::<ClientStatistics>(STATISTICS)
+            .subscribe_to_stream_with_retransmission::<ClientStatistics>(
+                STATISTICS,
+                STATISTICS_QUEUE,
+            )
             .await?;
+        let client_hostname = client_hostname.clone();
         async move {
             loop {
                 let client_stats = receiver.recv_header_only().await?;
 
+                // The bitrate adapts to the network of the primary client only
+                if !is_primary_client(&client_hostname) {
+                    continue;
+                }
+
                 if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
                     let timestamp = client_stats.target_timestamp;
                     let decoder_latency = client_stats.video_decode;
@@ -833,137 +1733,223 @@ async fn connection_pipeline(
                     .await;
                 if let Err(e) = res {
                     info!("Client disconnected. Cause: {e}");
-                    break Ok(());
+                    break Ok(ConnectionEnd::Lost);
+                }
+
+                let ping_timestamp = STATISTICS_MANAGER
+                    .lock()
+                    .as_ref()
+                    .map(|stats| stats.ping_timestamp());
+                if let Some(timestamp) = ping_timestamp {
+                    control_sender
+                        .lock()
+                        .await
+                        .send(&ServerControlPacket::Ping(timestamp))
+                        .await
+                        .ok();
                 }
                 time::sleep(KEEPALIVE_INTERVAL).await;
             }
         }
     };
 
+    // Replies are sent only to this client
     let (control_channel_sender, mut control_channel_receiver) = tmpsc::unbounded_channel();
-    *CONTROL_CHANNEL_SENDER.lock() = Some(control_channel_sender);
+    CONTROL_CHANNEL_SENDERS
+        .lock()
+        .insert(client_hostname.clone(), control_channel_sender.clone());
+
+    // The encoder kept running: the new decoder needs the configuration and an IDR frame
+    if resumed {
+        if let Some(config) = &*DECODER_CONFIG.lock() {
+            control_channel_sender
+                .send(ServerControlPacket::InitializeDecoder(config.clone()))
+                .ok();
+        }
+        unsafe { crate::RequestIDR() }
+    }
 
     let control_send_loop = {
         let control_sender = Arc::clone(&control_sender);
         async move {
             while let Some(packet) = control_channel_receiver.recv().await {
-                control_sender.lock().await.send(&packet).await?;
+                if let Err(e) = control_sender.lock().await.send(&packet).await {
+                    info!("Client disconnected. Cause: {e}");
+                    return Ok(ConnectionEnd::Lost);
+                }
             }
 
-            Ok(())
+            Ok(ConnectionEnd::Closed)
         }
     };
 
-    let control_loop = async move {
-        loop {
-            match control_receiver.recv().await {
-                Ok(ClientControlPacket::PlayspaceSync(packet)) => {
-                    if !is_tracking_ref_only {
-                        playspace_sync_sender.send(packet).ok();
+    let control_loop = {
+        let client_hostname = client_hostname.clone();
+        async move {
+            loop {
+                let maybe_packet = control_receiver.recv().await;
+                let receive_instant = Instant::now();
+
+                // Spectators do not control the stream, except for requesting keyframes
+                let is_primary = is_primary_client(&client_hostname);
+
+                match maybe_packet {
+                    Ok(ClientControlPacket::PlayspaceSync(packet)) if is_primary => {
+                        if !is_tracking_ref_only {
+                            session.playspace_sync_sender.lock().send(packet).ok();
 
-                        tracking_manager.lock().await.recenter();
+                            session.tracking_manager.lock().await.recenter();
+                        }
                     }
-                }
-                Ok(ClientControlPacket::RequestIdr) => {
-                    if let Some(sender) = &*CONTROL_CHANNEL_SENDER.lock() {
+                    Ok(ClientControlPacket::RequestIdr) => {
                         if let Some(config) = &*DECODER_CONFIG.lock() {
-                            sender
+                            control_channel_sender
                                 .send(ServerControlPacket::InitializeDecoder(config.clone()))
                                 .ok();
                         }
+                        unsafe { crate::RequestIDR() }
                     }
-                    unsafe { crate::RequestIDR() }
-                }
-                Ok(ClientControlPacket::VideoErrorReport) => {
-                    if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
-                        stats.report_packet_loss();
+                    Ok(ClientControlPacket::VideoErrorReport) => {
+                        if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+                            stats.report_packet_loss();
+                        }
+                        unsafe { crate::VideoErrorReportReceive() };
                     }
-                    unsafe { crate::VideoErrorReportReceive() };
-                }
-                Ok(ClientControlPacket::ViewsConfig(config)) => unsafe {
-                    crate::SetViewsConfig(FfiViewsConfig {
-                        fov: [
-                            FfiFov {
-                                left: config.fov[0].left,
-                                right: config.fov[0].right,
-                                up: config.fov[0].up,
-                                down: config.fov[0].down,
+                    Ok(ClientControlPacket::ViewsConfig(config)) if is_primary => unsafe {
+                        crate::SetViewsConfig(FfiViewsConfig {
+                            fov: [
+                                FfiFov {
+                                    left: config.fov[0].left,
+                                    right: config.fov[0].right,
+                                    up: config.fov[0].up,
+                                    down: config.fov[0].down,
+                                },
+                                FfiFov {
+                                    left: config.fov[1].left,
+                                    right: config.fov[1].right,
+                                    up: config.fov[1].up,
+                                    down: config.fov[1].down,
+                                },
+                            ],
+                            ipd_m: config.ipd_m,
+                        });
+                    },
+                    Ok(ClientControlPacket::Battery(packet)) if is_primary => unsafe {
+                        crate::SetBattery(packet.device_id, packet.gauge_value, packet.is_plugged);
+
+                        if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+                            stats.report_battery(packet.device_id, packet.gauge_value);
+                        }
+                    },
+                    Ok(ClientControlPacket::Button { path_id, value }) if is_primary => {
+                        if settings.extra.log_button_presses {
+                            alvr_events::send_event(EventType::Button(ButtonEvent {
+                                path: BUTTON_PATH_FROM_ID
+                                    .get(&path_id)
+                                    .cloned()
+                                    .unwrap_or_else(|| format!("Unknown (ID: {path_id:#16x})")),
+                                value,
+                            }));
+                        }
+
+                        let value = match value {
+                            ButtonValue::Binary(value) => FfiButtonValue {
+                                type_: crate::FfiButtonType_BUTTON_TYPE_BINARY,
+                                __bindgen_anon_1: crate::FfiButtonValue__bindgen_ty_1 {
+                                    binary: value.into(),
+                                },
                             },
-                            FfiFov {
-                                left: config.fov[1].left,
-                                right: config.fov[1].right,
-                                up: config.fov[1].up,
-                                down: config.fov[1].down,
+
+                            ButtonValue::Scalar(value) => FfiButtonValue {
+                                type_: crate::FfiButtonType_BUTTON_TYPE_SCALAR,
+                                __bindgen_anon_1: crate::FfiButtonValue__bindgen_ty_1 {
+                                    scalar: value,
+                                },
                             },
-                        ],
-                        ipd_m: config.ipd_m,
-                    });
-                },
-                Ok(ClientControlPacket::Battery(packet)) => unsafe {
-                    crate::SetBattery(packet.device_id, packet.gauge_value, packet.is_plugged);
+                        };
 
-                    if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
-                        stats.report_battery(packet.device_id, packet.gauge_value);
+                        unsafe { crate::SetButton(path_id, value) };
                     }
-                },
-                Ok(ClientControlPacket::Button { path_id, value }) => {
-                    if settings.extra.log_button_presses {
-                        alvr_events::send_event(EventType::Button(ButtonEvent {
-                            path: BUTTON_PATH_FROM_ID
-                                .get(&path_id)
-                                .cloned()
-                                .unwrap_or_else(|| format!("Unknown (ID: {path_id:#16x})")),
-                            value,
+                    Ok(ClientControlPacket::BandwidthProbeResult(result)) => {
+                        info!(
+                            "Client {client_hostname} link capacity: {:.1} Mbps, {:.1}% loss",
+                            result.throughput_bps / 1e6,
+                            result.packet_loss * 100.0
+                        );
+
+                        alvr_events::send_event(EventType::BandwidthProbe(BandwidthProbeEvent {
+                            hostname: client_hostname.clone(),
+                            throughput_bps: result.throughput_bps,
+                            packet_loss: result.packet_loss,
                         }));
+                        SERVER_DATA_MANAGER.write().update_client_list(
+                            client_hostname.clone(),
+                            ClientListAction::SetBandwidthProbe(result),
+                        );
+
+                        let saturated = match &settings.connection.bandwidth_probing {
+                            Switch::Enabled(config) => {
+                                is_probe_saturated(&result, config.max_bitrate_mbps as f32 * 1e6)
+                            }
+                            Switch::Disabled => false,
+                        };
+                        if saturated {
+                            info!("The link capacity is higher than the probe rate");
+                        } else if is_primary {
+                            BITRATE_MANAGER
+                                .lock()
+                                .report_link_capacity(result.throughput_bps);
+                        }
+                    }
+                    Ok(ClientControlPacket::Log { level, message }) => {
+                        info!("Client {client_hostname}: [{level:?}] {message}")
+                    }
+                    Ok(ClientControlPacket::Ping(origin_timestamp)) => {
+                        let pong = STATISTICS_MANAGER
+                            .lock()
+                            .as_ref()
+                            .map(|stats| stats.answer_ping(origin_timestamp, receive_instant));
+                        if let Some(pong) = pong {
+                            control_channel_sender
+                                .send(ServerControlPacket::Pong(pong))
+                                .ok();
+                        }
+                    }
+                    Ok(ClientControlPacket::Pong(pong)) if is_primary => {
+                        if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+                            stats.report_pong(pong, receive_instant);
+                        }
+                    }
+                    Ok(ClientControlPacket::Extension(packet)) => {
+                        EXTENSION_REGISTRY.lock().dispatch(&packet);
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        info!("Client disconnected. Cause: {e}");
+                        break Ok(ConnectionEnd::Lost);
                     }
-
-                    let value = match value {
-                        ButtonValue::Binary(value) => FfiButtonValue {
-                            type_: crate::FfiButtonType_BUTTON_TYPE_BINARY,
-                            __bindgen_anon_1: crate::FfiButtonValue__bindgen_ty_1 {
-                                binary: value.into(),
-                            },
-                        },
-
-                        ButtonValue::Scalar(value) => FfiButtonValue {
-                            type_: crate::FfiButtonType_BUTTON_TYPE_SCALAR,
-                            __bindgen_anon_1: crate::FfiButtonValue__bindgen_ty_1 { scalar: value },
-                        },
-                    };
-
-                    unsafe { crate::SetButton(path_id, value) };
-                }
-                Ok(ClientControlPacket::Log { level, message }) => {
-                    info!("Client {client_hostname}: [{level:?}] {message}")
-                }
-                Ok(_) => (),
-                Err(e) => {
-                    info!("Client disconnected. Cause: {e}");
-                    break;
                 }
             }
         }
-
-        Ok(())
     };
 
     let receive_loop = async move { stream_socket.receive_loop().await };
 
-    tokio::select! {
+    let connection_end = tokio::select! {
         // Spawn new tasks and let the runtime manage threading
         res = spawn_cancelable(receive_loop) => {
             if let Err(e) = res {
                 info!("Client disconnected. Cause: {e}" );
             }
 
-            Ok(())
+            Ok(ConnectionEnd::Lost)
         },
-        res = spawn_cancelable(game_audio_loop) => res,
-        res = spawn_cancelable(microphone_loop) => res,
-        res = spawn_cancelable(video_send_loop) => res,
-        res = spawn_cancelable(statistics_receive_loop) => res,
-        res = spawn_cancelable(haptics_send_loop) => res,
-        res = spawn_cancelable(tracking_receive_loop) => res,
+        res = spawn_cancelable(game_audio_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(microphone_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(video_send_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(statistics_receive_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(haptics_send_loop) => res.map(|_| ConnectionEnd::Closed),
+        res = spawn_cancelable(tracking_receive_loop) => res.map(|_| ConnectionEnd::Closed),
 
         // Leave these loops on the current task
         res = keepalive_loop => res,
@@ -978,7 +1964,89 @@ async fn connection_pipeline(
                 .await
                 .ok();
 
+            Ok(ConnectionEnd::Closed)
+        }
+    };
+
+    CONTROL_CHANNEL_SENDERS.lock().remove(&client_hostname);
+    VIDEO_SENDERS.lock().remove(&client_hostname);
+    SPECTATOR_IDR_REQUESTS.lock().remove(&client_hostname);
+    HAPTICS_SENDERS.lock().remove(&client_hostname);
+
+    connection_end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn test_streaming_clients() {
+        let mut clients = StreamingClients::default();
+        clients.add("a".into(), 9944, StreamingRole::Primary);
+        clients.add("b".into(), 9945, StreamingRole::Spectator);
+        clients.add("c".into(), 9947, StreamingRole::Spectator);
+        assert_eq!(clients.primary.as_deref(), Some("a"));
+        assert_eq!(clients.spectators_count(), 2);
+        assert_eq!(clients.free_port(9944), Some(9946));
+
+        // Hand-over
+        assert!(clients.set_primary("d").is_err());
+        assert_eq!(clients.set_primary("b"), Ok(Some("a".into())));
+        assert_eq!(clients.set_primary("b"), Ok(None));
+        assert_eq!(clients.primary.as_deref(), Some("b"));
+        assert_eq!(clients.spectators_count(), 2);
+
+        assert!(!clients.remove("a"));
+        assert!(clients.remove("b"));
+        assert_eq!(clients.primary, None);
+        assert_eq!(clients.spectators_count(), 1);
+        assert_eq!(clients.free_port(9944), Some(9944));
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Recenter(f32);
+
+    impl ControlExtension for Recenter {
+        const ID: &'static str = "test.recenter";
+    }
+
+    struct RecenterHandler(Arc<parking_lot::Mutex<Vec<f32>>>);
+
+    impl ExtensionHandler for RecenterHandler {
+        type Message = Recenter;
+
+        fn handle(&mut self, message: Recenter) -> StrResult {
+            self.0.lock().push(message.0);
+
             Ok(())
         }
     }
+
+    #[test]
+    fn test_extension_round_trip() {
+        let received = Arc::new(parking_lot::Mutex::new(vec![]));
+        register_extension_handler(RecenterHandler(Arc::clone(&received)));
+
+        let (sender, mut receiver) = tmpsc::unbounded_channel();
+        CONTROL_CHANNEL_SENDERS
+            .lock()
+            .insert("test.client".into(), sender);
+
+        // Not streaming
+        send_extension("other.client", &Recenter(1.0));
+        send_extension("test.client", &Recenter(2.0));
+        CONTROL_CHANNEL_SENDERS.lock().remove("test.client");
+
+        // Echo the packet back, as a client would
+        let packet = match receiver.try_recv() {
+            Ok(ServerControlPacket::Extension(packet)) => packet,
+            _ => panic!("Expected an extension packet"),
+        };
+        assert!(receiver.try_recv().is_err());
+        EXTENSION_REGISTRY.lock().dispatch(&packet);
+
+        assert_eq!(*received.lock(), vec![2.0]);
+    }
 }
@@ -33,11 +33,13 @@ use alvr_events::EventType;
 use alvr_filesystem::{self as afs, Layout};
 use alvr_server_data::ServerDataManager;
 use alvr_session::CodecType;
-use alvr_sockets::{ClientListAction, DecoderInitializationConfig, Haptics, ServerControlPacket};
+use alvr_sockets::{
+    ClientListAction, DecoderInitializationConfig, DropPolicy, Haptics, QueueConfig, QueueSender,
+};
 use bitrate::BitrateManager;
 use statistics::StatisticsManager;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::{c_char, c_void, CStr, CString},
     fs::File,
     io::Write,
@@ -52,7 +54,7 @@ use std::{
 };
 use tokio::{
     runtime::Runtime,
-    sync::{broadcast, mpsc, Notify},
+    sync::{broadcast, Notify},
 };
 
 static FILESYSTEM_LAYOUT: Lazy<Layout> = Lazy::new(|| {
@@ -77,16 +79,30 @@ pub struct VideoPacket {
     pub payload: Vec<u8>,
 }
 
-static CONTROL_CHANNEL_SENDER: Lazy<Mutex<Option<mpsc::UnboundedSender<ServerControlPacket>>>> =
-    Lazy::new(|| Mutex::new(None));
-static VIDEO_SENDER: Lazy<Mutex<Option<mpsc::UnboundedSender<VideoPacket>>>> =
-    Lazy::new(|| Mutex::new(None));
-static HAPTICS_SENDER: Lazy<Mutex<Option<mpsc::UnboundedSender<Haptics>>>> =
-    Lazy::new(|| Mutex::new(None));
+// One sender for each streaming client. The hashmap key is the hostname
+static VIDEO_SENDERS: Lazy<Mutex<HashMap<String, QueueSender<VideoPacket>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static SPECTATOR_IDR_REQUESTS: Lazy<Mutex<SpectatorIdrRequests>> =
+    Lazy::new(|| Mutex::new(SpectatorIdrRequests::default()));
+static HAPTICS_SENDERS: Lazy<Mutex<HashMap<String, QueueSender<Haptics>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 static VIDEO_MIRROR_SENDER: Lazy<Mutex<Option<broadcast::Sender<Vec<u8>>>>> =
     Lazy::new(|| Mutex::new(None));
 static VIDEO_RECORDING_FILE: Lazy<Mutex<Option<File>>> = Lazy::new(|| Mutex::new(None));
 
+const SPECTATOR_IDR_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+
+// Encoded frames waiting to be sent. If the queue is full, the new frame is dropped and an IDR is
+// requested, since the next frames would reference the dropped one.
+const VIDEO_SEND_QUEUE: QueueConfig = QueueConfig {
+    capacity: 8,
+    policy: DropPolicy::DropNewest,
+};
+const HAPTICS_SEND_QUEUE: QueueConfig = QueueConfig {
+    capacity: 32,
+    policy: DropPolicy::DropOldest,
+};
+
 static DISCONNECT_CLIENT_NOTIFIER: Lazy<Notify> = Lazy::new(Notify::new);
 static RESTART_NOTIFIER: Lazy<Notify> = Lazy::new(Notify::new);
 static SHUTDOWN_NOTIFIER: Lazy<Notify> = Lazy::new(Notify::new);
@@ -124,9 +140,97 @@ fn to_ffi_quat(quat: Quat) -> FfiQuat {
     }
 }
 
+// Check the type of the first picture NAL unit, which can follow other NAL units like SEI or
+// access unit delimiters. The parameter sets preceding an IDR frame are already stripped from the
+// payload and sent separately with InitializeDecoder().
+fn is_idr_frame(codec: CodecType, payload: &[u8]) -> bool {
+    // A 4 byte start code ends with a 3 byte one. Start codes cannot appear inside NAL units
+    let nal_headers = payload
+        .windows(3)
+        .enumerate()
+        .filter(|(_, window)| *window == [0, 0, 1])
+        .filter_map(|(index, _)| payload.get(index + 3));
+
+    for header in nal_headers {
+        match codec {
+            CodecType::H264 => {
+                let nal_type = header & 0x1F;
+                if (1..=5).contains(&nal_type) {
+                    return nal_type == 5;
+                }
+            }
+            CodecType::Hevc => {
+                let nal_type = (header >> 1) & 0x3F;
+                if nal_type < 32 {
+                    // IRAP pictures: BLA, IDR and CRA
+                    return (16..=21).contains(&nal_type);
+                }
+            }
+        }
+    }
+
+    false
+}
+
+// A spectator which dropped a frame cannot decode the next frames, which are skipped until an IDR.
+// The IDRs requested for the spectators are rate limited, so that a spectator with a poor connection
+// does not degrade the stream of the other clients.
+#[derive(Default)]
+struct SpectatorIdrRequests {
+    waiting: HashSet<String>,
+    last_requests: HashMap<String, Instant>,
+}
+
+impl SpectatorIdrRequests {
+    fn is_waiting(&self, hostname: &str) -> bool {
+        self.waiting.contains(hostname)
+    }
+
+    // Returns true if an IDR should be requested
+    fn frame_dropped(&mut self, hostname: &str, now: Instant) -> bool {
+        self.waiting.insert(hostname.to_owned());
+
+        self.try_request(hostname, now)
+    }
+
+    fn try_request(&mut self, hostname: &str, now: Instant) -> bool {
+        match self.last_requests.get(hostname) {
+            Some(last_request)
+                if now.saturating_duration_since(*last_request)
+                    < SPECTATOR_IDR_REQUEST_INTERVAL =>
+            {
+                false
+            }
+            _ => {
+                self.last_requests.insert(hostname.to_owned(), now);
+
+                true
+            }
+        }
+    }
+
+    fn idr_sent(&mut self) {
+        self.waiting.clear();
+    }
+
+    fn remove(&mut self, hostname: &str) {
+        self.waiting.remove(hostname);
+        self.last_requests.remove(hostname);
+    }
+}
+
+// The codec used by the encoder, which can differ from the settings because of the overrides of
+// the connected client
+fn stream_codec() -> CodecType {
+    if SERVER_DATA_MANAGER.read().session().openvr_config.codec == CodecType::Hevc as u32 {
+        CodecType::Hevc
+    } else {
+        CodecType::H264
+    }
+}
+
 pub fn create_recording_file() {
-    let codec = SERVER_DATA_MANAGER.read().settings().video.codec;
-    let ext = if matches!(codec, CodecType::H264) {
+    let ext = if matches!(stream_codec(), CodecType::H264) {
         "h264"
     } else {
         "h265"
@@ -219,6 +323,7 @@ fn init() {
             .values_mut()
         {
             conn.current_ip = None;
+            conn.streaming_role = None;
         }
     }
 
@@ -331,7 +436,13 @@ pub unsafe extern "C" fn HmdDriverFactory(
     }
 
     extern "C" fn video_send(timestamp_ns: u64, buffer_ptr: *mut u8, len: i32) {
-        if let Some(sender) = &*VIDEO_SENDER.lock() {
+        // The senders are cloned, not to hold the lock while sending
+        let senders = VIDEO_SENDERS
+            .lock()
+            .iter()
+            .map(|(hostname, sender)| (hostname.clone(), sender.clone()))
+            .collect::<Vec<_>>();
+        if !senders.is_empty() {
             let timestamp = Duration::from_nanos(timestamp_ns);
 
             let mut payload = vec![0; len as _];
@@ -349,10 +460,54 @@ pub unsafe extern "C" fn HmdDriverFactory(
                 file.write_all(&payload).ok();
             }
 
-            sender.send(VideoPacket { timestamp, payload }).ok();
+            let mut dropped_frames_count = 0;
+
+            let drop_stale_frames_on_idr = SERVER_DATA_MANAGER
+                .read()
+                .settings()
+                .video
+                .drop_stale_frames_on_idr;
+            let is_idr = is_idr_frame(stream_codec(), &payload);
+            let primary_hostname = connection::primary_client();
+
+            let now = Instant::now();
+            let mut request_idr = false;
+            let mut idr_requests_lock = SPECTATOR_IDR_REQUESTS.lock();
+            if is_idr {
+                idr_requests_lock.idr_sent();
+            }
+            for (hostname, sender) in &senders {
+                if drop_stale_frames_on_idr && is_idr {
+                    dropped_frames_count += sender.clear();
+                }
+
+                let is_primary = primary_hostname.as_deref() == Some(hostname.as_str());
+                if !is_primary && idr_requests_lock.is_waiting(hostname) {
+                    request_idr |= idr_requests_lock.try_request(hostname, now);
+
+                    continue;
+                }
+
+                let packet = VideoPacket {
+                    timestamp,
+                    payload: payload.clone(),
+                };
+                if let Ok(count) = sender.try_send(packet) {
+                    if count > 0 {
+                        dropped_frames_count += count;
+                        request_idr |= is_primary || idr_requests_lock.frame_dropped(hostname, now);
+                    }
+                }
+            }
+            drop(idr_requests_lock);
+
+            if request_idr {
+                unsafe { RequestIDR() };
+            }
 
             if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
                 stats.report_video_packet(len as _);
+                stats.report_video_frames_dropped(dropped_frames_count);
             }
             BITRATE_MANAGER
                 .lock()
@@ -361,15 +516,21 @@ pub unsafe extern "C" fn HmdDriverFactory(
     }
 
     extern "C" fn haptics_send(device_id: u64, duration_s: f32, frequency: f32, amplitude: f32) {
-        if let Some(sender) = &*HAPTICS_SENDER.lock() {
-            let haptics = Haptics {
-                device_id,
-                duration: Duration::from_secs_f32(f32::max(duration_s, 0.0)),
-                frequency,
-                amplitude,
-            };
-
-            sender.send(haptics).ok();
+        let haptics = Haptics {
+            device_id,
+            duration: Duration::from_secs_f32(f32::max(duration_s, 0.0)),
+            frequency,
+            amplitude,
+        };
+
+        // Spectator clients discard the haptics
+        let mut dropped_count = 0;
+        for sender in HAPTICS_SENDERS.lock().values() {
+            dropped_count += sender.try_send(haptics.clone()).unwrap_or(0);
+        }
+
+        if let Some(stats) = &mut *STATISTICS_MANAGER.lock() {
+            stats.report_haptics_dropped(dropped_count);
         }
     }
 
@@ -492,3 +653,63 @@ pub unsafe extern "C" fn HmdDriverFactory(
 
     PTR_USIZE.get().unwrap().load(Ordering::Relaxed) as _
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_idr_frame() {
+        assert!(is_idr_frame(CodecType::H264, &[0, 0, 0, 1, 0x65, 0x88]));
+        assert!(is_idr_frame(CodecType::H264, &[0, 0, 1, 0x65, 0x88]));
+        assert!(!is_idr_frame(CodecType::H264, &[0, 0, 0, 1, 0x41, 0x9a]));
+
+        // IDR_W_RADL, CRA and TRAIL_R
+        assert!(is_idr_frame(CodecType::Hevc, &[0, 0, 0, 1, 0x26, 0x01]));
+        assert!(is_idr_frame(CodecType::Hevc, &[0, 0, 1, 0x2a, 0x01]));
+        assert!(!is_idr_frame(CodecType::Hevc, &[0, 0, 0, 1, 0x02, 0x01]));
+
+        // SEI before the picture
+        assert!(is_idr_frame(
+            CodecType::H264,
+            &[0, 0, 0, 1, 0x06, 0x05, 0x01, 0x80, 0, 0, 1, 0x65, 0x88]
+        ));
+        assert!(!is_idr_frame(
+            CodecType::H264,
+            &[0, 0, 0, 1, 0x06, 0x05, 0x01, 0x80, 0, 0, 1, 0x41, 0x9a]
+        ));
+        assert!(is_idr_frame(
+            CodecType::Hevc,
+            &[0, 0, 0, 1, 0x4e, 0x01, 0x05, 0x80, 0, 0, 0, 1, 0x26, 0x01]
+        ));
+
+        // Missing start code or NAL header
+        assert!(!is_idr_frame(CodecType::H264, &[0x65, 0x88]));
+        assert!(!is_idr_frame(CodecType::H264, &[0, 0, 0, 1]));
+        assert!(!is_idr_frame(CodecType::Hevc, &[]));
+    }
+
+    #[test]
+    fn test_spectator_idr_requests() {
+        let mut requests = SpectatorIdrRequests::default();
+        let start = Instant::now();
+
+        assert!(requests.frame_dropped("a", start));
+        assert!(requests.is_waiting("a"));
+        assert!(!requests.is_waiting("b"));
+
+        // Rate limited for each spectator
+        assert!(!requests.try_request("a", start + Duration::from_secs(1)));
+        assert!(requests.frame_dropped("b", start + Duration::from_secs(1)));
+        assert!(requests.try_request("a", start + SPECTATOR_IDR_REQUEST_INTERVAL));
+
+        requests.idr_sent();
+        assert!(!requests.is_waiting("a"));
+        assert!(!requests.frame_dropped("a", start + SPECTATOR_IDR_REQUEST_INTERVAL));
+        assert!(requests.is_waiting("a"));
+
+        requests.remove("a");
+        assert!(!requests.is_waiting("a"));
+        assert!(requests.frame_dropped("a", start + SPECTATOR_IDR_REQUEST_INTERVAL));
+    }
+}
@@ -1,5 +1,5 @@
 use alvr_common::{prelude::*, StrResult, *};
-use alvr_sockets::{CONTROL_PORT, HANDSHAKE_PACKET_SIZE_BYTES, LOCAL_IP};
+use alvr_sockets::{CONTROL_PORT, HANDSHAKE_PACKET_SIZE_BYTES};
 use std::{
     io::ErrorKind,
     net::{IpAddr, UdpSocket},
@@ -12,7 +12,8 @@ pub struct WelcomeSocket {
 
 impl WelcomeSocket {
     pub fn new() -> StrResult<Self> {
-        let socket = UdpSocket::bind((LOCAL_IP, CONTROL_PORT)).map_err(err!())?;
+        // Dual-stack: receives both the IPv4 broadcast and the IPv6 multicast announcements
+        let socket = alvr_sockets::bind_udp_socket(CONTROL_PORT)?;
         socket.set_nonblocking(true).map_err(err!())?;
 
         Ok(Self {
@@ -42,13 +43,6 @@ impl WelcomeSocket {
             protocol_id_bytes.copy_from_slice(&self.buffer[16..24]);
             let received_protocol_id = u64::from_le_bytes(protocol_id_bytes);
 
-            if received_protocol_id != alvr_common::protocol_id() {
-                warn!("Found incompatible client! Upgrade or downgrade\nExpected protocol ID {}, Found {received_protocol_id}",
-                alvr_common::protocol_id());
-
-                return interrupt();
-            }
-
             let mut hostname_bytes = [0; 32];
             hostname_bytes.copy_from_slice(&self.buffer[24..56]);
             let hostname = std::str::from_utf8(&hostname_bytes)
@@ -56,7 +50,25 @@ impl WelcomeSocket {
                 .trim_end_matches('\x00')
                 .to_owned();
 
-            Ok((hostname, address.ip()))
+            // Version incompatibilities are reported after connecting, during the handshake. Only
+            // clients from before the handshake are rejected here
+            if received_protocol_id != DISCOVERY_PROTOCOL_ID {
+                warn!(
+                    "{}",
+                    incompatible_client_message(&hostname, Some(received_protocol_id))
+                );
+
+                return interrupt();
+            }
+
+            let ip = alvr_sockets::canonical_ip(address.ip());
+            if alvr_sockets::is_ipv6_link_local(ip) {
+                warn!("Found client {hostname} with link-local IPv6 address {ip}, which is not supported");
+
+                return interrupt();
+            }
+
+            Ok((hostname, ip))
         } else if &self.buffer[..16] == b"\x00\x00\x00\x00\x04\x00\x00\x00\x00\x00\x00\x00ALVR"
             || &self.buffer[..5] == b"\x01ALVR"
         {
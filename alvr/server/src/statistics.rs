@@ -1,6 +1,6 @@
 use alvr_common::{SlidingWindowAverage, HEAD_ID, LEFT_HAND_ID, RIGHT_HAND_ID};
 use alvr_events::{EventType, GraphStatistics, Statistics};
-use alvr_sockets::ClientStatistics;
+use alvr_sockets::{ClientStatistics, ClockSync, PongPacket};
 use std::{
     collections::{HashMap, VecDeque},
     time::{Duration, Instant},
@@ -43,9 +43,19 @@ pub struct StatisticsManager {
     video_bytes_partial_sum: usize,
     packets_lost_total: usize,
     packets_lost_partial_sum: usize,
+    video_reorder_depth_partial_max: u32,
+    video_late_shards_total: usize,
+    video_late_shards_partial_sum: usize,
+    video_shards_dropped_total: usize,
+    video_frames_dropped_total: usize,
+    haptics_dropped_total: usize,
+    tracking_shards_dropped_total: usize,
+    video_pacing_rate_bps: f32,
+    video_pacing_queue_delay_partial_max: Duration,
     battery_gauges: HashMap<u64, f32>,
     steamvr_pipeline_latency: Duration,
     total_pipeline_latency_average: SlidingWindowAverage<Duration>,
+    clock_sync: ClockSync,
 }
 
 impl StatisticsManager {
@@ -67,6 +77,15 @@ impl StatisticsManager {
             video_bytes_partial_sum: 0,
             packets_lost_total: 0,
             packets_lost_partial_sum: 0,
+            video_reorder_depth_partial_max: 0,
+            video_late_shards_total: 0,
+            video_late_shards_partial_sum: 0,
+            video_shards_dropped_total: 0,
+            video_frames_dropped_total: 0,
+            haptics_dropped_total: 0,
+            tracking_shards_dropped_total: 0,
+            video_pacing_rate_bps: 0.0,
+            video_pacing_queue_delay_partial_max: Duration::ZERO,
             battery_gauges: HashMap::new(),
             steamvr_pipeline_latency: Duration::from_secs_f32(
                 steamvr_pipeline_frames * nominal_server_frame_interval.as_secs_f32(),
@@ -75,6 +94,7 @@ impl StatisticsManager {
                 Duration::ZERO,
                 max_history_size,
             ),
+            clock_sync: ClockSync::new(),
         }
     }
 
@@ -139,6 +159,25 @@ impl StatisticsManager {
         self.video_bytes_partial_sum += bytes_count;
     }
 
+    pub fn report_video_pacing(&mut self, rate_bps: f32, queue_delay: Duration) {
+        self.video_pacing_rate_bps = rate_bps;
+        self.video_pacing_queue_delay_partial_max =
+            Duration::max(self.video_pacing_queue_delay_partial_max, queue_delay);
+    }
+
+    // Encoded frames dropped from the send queue, because it was full or they were stale
+    pub fn report_video_frames_dropped(&mut self, count: usize) {
+        self.video_frames_dropped_total += count;
+    }
+
+    pub fn report_haptics_dropped(&mut self, count: usize) {
+        self.haptics_dropped_total += count;
+    }
+
+    pub fn report_tracking_shards_dropped(&mut self, count: usize) {
+        self.tracking_shards_dropped_total += count;
+    }
+
     pub fn report_packet_loss(&mut self) {
         self.packets_lost_total += 1;
         self.packets_lost_partial_sum += 1;
@@ -148,8 +187,24 @@ impl StatisticsManager {
         *self.battery_gauges.entry(device_id).or_default() = gauge_value;
     }
 
+    pub fn ping_timestamp(&self) -> Duration {
+        self.clock_sync.now()
+    }
+
+    // The receive instant must be taken as soon as the packet is received, the time spent waiting
+    // for locks before answering must not count as round trip time
+    pub fn answer_ping(&self, origin_timestamp: Duration, receive_instant: Instant) -> PongPacket {
+        self.clock_sync
+            .answer_ping(origin_timestamp, self.clock_sync.timestamp(receive_instant))
+    }
+
+    pub fn report_pong(&mut self, pong: PongPacket, receive_instant: Instant) {
+        let receive_timestamp = self.clock_sync.timestamp(receive_instant);
+        self.clock_sync.report_pong(pong, receive_timestamp);
+    }
+
     // Called every frame. Some statistics are reported once every frame
-    // Returns network latency
+    // Returns the video network latency
     pub fn report_statistics(&mut self, client_stats: ClientStatistics) -> Duration {
         if let Some(frame) = self
             .history_buffer
@@ -158,6 +213,13 @@ impl StatisticsManager {
         {
             frame.total_pipeline_latency = client_stats.total_pipeline_latency;
 
+            self.video_reorder_depth_partial_max = self
+                .video_reorder_depth_partial_max
+                .max(client_stats.video_reorder_depth);
+            self.video_late_shards_total += client_stats.video_late_shards;
+            self.video_late_shards_partial_sum += client_stats.video_late_shards;
+            self.video_shards_dropped_total += client_stats.video_dropped_shards;
+
             let game_time_latency = frame
                 .frame_present
                 .saturating_duration_since(frame.tracking_received);
@@ -170,21 +232,45 @@ impl StatisticsManager {
                 .frame_encoded
                 .saturating_duration_since(frame.frame_composed);
 
-            // The network latency cannot be estiamed directly. It is what's left of the total
-            // latency after subtracting all other latency intervals. In particular it contains the
-            // transport latency of the tracking packet and the interval between the first video
-            // packet is sent and the last video packet is received for a specific frame.
+            // The network latency is the transport latency of the tracking packet plus the interval
+            // between the first video packet is sent and the last video packet is received for a
+            // specific frame. Once the clock offset is known, the client timestamps are converted
+            // to the server clock and the two parts are measured directly.
+            // Until then, the network latency is what's left of the total latency after
+            // subtracting all other latency intervals, and is attributed to the video.
             // For safety, use saturating_sub to avoid a crash if for some reason the network
             // latency is miscalculated as negative.
-            let network_latency = frame.total_pipeline_latency.saturating_sub(
-                game_time_latency
-                    + server_compositor_latency
-                    + encoder_latency
-                    + client_stats.video_decode
-                    + client_stats.video_decoder_queue
-                    + client_stats.rendering
-                    + client_stats.vsync_queue,
-            );
+            let (network_latency, video_network_latency) =
+                if let (Some(input_acquired), Some(video_packet_received)) = (
+                    self.clock_sync
+                        .peer_to_local(client_stats.input_acquired_timestamp),
+                    self.clock_sync
+                        .peer_to_local(client_stats.video_packet_received_timestamp),
+                ) {
+                    let tracking_network_latency = self
+                        .clock_sync
+                        .timestamp(frame.tracking_received)
+                        .saturating_sub(input_acquired);
+                    let video_network_latency = video_packet_received
+                        .saturating_sub(self.clock_sync.timestamp(frame.frame_encoded));
+
+                    (
+                        tracking_network_latency + video_network_latency,
+                        video_network_latency,
+                    )
+                } else {
+                    let network_latency = frame.total_pipeline_latency.saturating_sub(
+                        game_time_latency
+                            + server_compositor_latency
+                            + encoder_latency
+                            + client_stats.video_decode
+                            + client_stats.video_decoder_queue
+                            + client_stats.rendering
+                            + client_stats.vsync_queue,
+                    );
+
+                    (network_latency, network_latency)
+                };
 
             let client_fps = 1.0
                 / client_stats
@@ -211,11 +297,30 @@ impl StatisticsManager {
                         / 1e6,
                     total_latency_ms: client_stats.total_pipeline_latency.as_secs_f32() * 1000.,
                     network_latency_ms: network_latency.as_secs_f32() * 1000.,
+                    round_trip_time_ms: self
+                        .clock_sync
+                        .round_trip_time()
+                        .unwrap_or_default()
+                        .as_secs_f32()
+                        * 1000.,
                     encode_latency_ms: encoder_latency.as_secs_f32() * 1000.,
                     decode_latency_ms: client_stats.video_decode.as_secs_f32() * 1000.,
                     packets_lost_total: self.packets_lost_total,
                     packets_lost_per_sec: (self.packets_lost_partial_sum as f32 / interval_secs)
                         as _,
+                    video_reorder_depth_max: self.video_reorder_depth_partial_max,
+                    video_late_shards_total: self.video_late_shards_total,
+                    video_late_shards_per_sec: (self.video_late_shards_partial_sum as f32
+                        / interval_secs) as _,
+                    video_shards_dropped_total: self.video_shards_dropped_total,
+                    video_frames_dropped_total: self.video_frames_dropped_total,
+                    haptics_dropped_total: self.haptics_dropped_total,
+                    tracking_shards_dropped_total: self.tracking_shards_dropped_total,
+                    video_pacing_rate_mbps: self.video_pacing_rate_bps / 1e6,
+                    video_pacing_queue_delay_ms: self
+                        .video_pacing_queue_delay_partial_max
+                        .as_secs_f32()
+                        * 1000.,
                     client_fps: client_fps as _,
                     server_fps: server_fps as _,
                     battery_hmd: (self
@@ -241,6 +346,9 @@ impl StatisticsManager {
                 self.video_packets_partial_sum = 0;
                 self.video_bytes_partial_sum = 0;
                 self.packets_lost_partial_sum = 0;
+                self.video_reorder_depth_partial_max = 0;
+                self.video_late_shards_partial_sum = 0;
+                self.video_pacing_queue_delay_partial_max = Duration::ZERO;
             }
 
             // todo: use target timestamp in nanoseconds. the dashboard needs to use the first
@@ -259,7 +367,7 @@ impl StatisticsManager {
                 server_fps,
             }));
 
-            network_latency
+            video_network_latency
         } else {
             Duration::ZERO
         }
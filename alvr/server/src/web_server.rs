@@ -119,14 +119,53 @@ async fn http_api(
                         )));
                     }
                     DashboardRequest::UpdateSession(session) => {
-                        *SERVER_DATA_MANAGER.write().session_mut() = *session
+                        if let Err(violations) =
+                            SERVER_DATA_MANAGER.write().update_session(*session)
+                        {
+                            return reply_json(&ServerResponse::SettingsViolations(violations));
+                        }
                     }
                     DashboardRequest::SetValues(descs) => {
-                        SERVER_DATA_MANAGER.write().set_values(descs).ok();
+                        if let Err(violations) = SERVER_DATA_MANAGER.write().set_values(descs) {
+                            return reply_json(&ServerResponse::SettingsViolations(violations));
+                        }
+                    }
+                    DashboardRequest::GetSessionBackups => {
+                        return reply_json(&ServerResponse::SessionBackups(
+                            SERVER_DATA_MANAGER.read().session_backups(),
+                        ));
+                    }
+                    DashboardRequest::RestoreSessionBackup(id) => {
+                        if let Err(e) = SERVER_DATA_MANAGER.write().restore_session_backup(&id) {
+                            warn!("Failed to restore the session backup: {e}");
+                        }
+                    }
+                    DashboardRequest::GetSettingsHistory => {
+                        return reply_json(&ServerResponse::SettingsHistory(
+                            SERVER_DATA_MANAGER.read().settings_history(),
+                        ));
+                    }
+                    DashboardRequest::UndoSettingsChange => {
+                        if let Err(e) = SERVER_DATA_MANAGER.write().undo_settings_change() {
+                            warn!("Failed to undo the settings change: {e}");
+                        }
+                    }
+                    DashboardRequest::RedoSettingsChange => {
+                        if let Err(e) = SERVER_DATA_MANAGER.write().redo_settings_change() {
+                            warn!("Failed to redo the settings change: {e}");
+                        }
                     }
                     DashboardRequest::UpdateClientList { hostname, action } => SERVER_DATA_MANAGER
                         .write()
                         .update_client_list(hostname, action),
+                    DashboardRequest::SetPrimaryClient(hostname) => {
+                        if let Err(e) = crate::connection::set_primary_client(&hostname) {
+                            warn!("Failed to set the primary client: {e}");
+                        }
+                    }
+                    DashboardRequest::ConfirmPairing { hostname, accepted } => {
+                        crate::connection::confirm_pairing(&hostname, accepted)
+                    }
                     DashboardRequest::GetAudioDevices => {
                         if let Ok(list) = SERVER_DATA_MANAGER.read().get_audio_devices_list() {
                             return reply_json(&ServerResponse::AudioDevices(list));
@@ -0,0 +1,165 @@
+// Snapshots of session.json, stored in the session_backups folder next to it. A snapshot of the
+// previous session is taken before it is overwritten, at most once every few minutes so that
+// dragging a slider does not push out the older snapshots.
+
+use alvr_common::prelude::*;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde_json as json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const BACKUPS_DIR: &str = "session_backups";
+const BACKUP_PREFIX: &str = "session_";
+const MAX_BACKUPS: usize = 10;
+const BACKUP_INTERVAL_MINUTES: i64 = 10;
+// Also used as id, in UTC. Valid as file name on every platform
+const ID_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+fn backups_dir(session_path: &Path) -> PathBuf {
+    session_path.with_file_name(BACKUPS_DIR)
+}
+
+fn backup_path(session_path: &Path, id: &str) -> PathBuf {
+    backups_dir(session_path).join(format!("{BACKUP_PREFIX}{id}.json"))
+}
+
+// Snapshots taken in the same second get a counter after the timestamp, like "<timestamp>_2"
+fn split_id(id: &str) -> (&str, u32) {
+    match id.match_indices('_').nth(1) {
+        Some((position, _)) => (&id[..position], id[position + 1..].parse().unwrap_or(0)),
+        None => (id, 0),
+    }
+}
+
+// The ids are the timestamps of the snapshots, sorted from the newest
+pub fn list_backups(session_path: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(backups_dir(session_path)) else {
+        return vec![];
+    };
+
+    let mut ids = entries
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name().into_string().ok()?;
+            let id = file_name
+                .strip_prefix(BACKUP_PREFIX)?
+                .strip_suffix(".json")?;
+
+            Some(id.to_owned())
+        })
+        .collect::<Vec<_>>();
+    ids.sort_unstable_by(|a, b| split_id(b).cmp(&split_id(a)));
+
+    ids
+}
+
+// Copies the current session.json to a new snapshot and deletes the oldest ones. Unless forced, the
+// snapshot is skipped if the last one is recent.
+pub fn backup_session(session_path: &Path, force: bool) -> StrResult {
+    if !session_path.exists() {
+        return Ok(());
+    }
+
+    let ids = list_backups(session_path);
+    if !force {
+        let last_time = ids
+            .first()
+            .and_then(|id| NaiveDateTime::parse_from_str(split_id(id).0, ID_FORMAT).ok());
+        if let Some(last_time) = last_time {
+            if Utc::now().naive_utc() - last_time < Duration::minutes(BACKUP_INTERVAL_MINUTES) {
+                return Ok(());
+            }
+        }
+    }
+
+    fs::create_dir_all(backups_dir(session_path)).map_err(err!())?;
+    let timestamp = Utc::now().format(ID_FORMAT).to_string();
+    // Counters are not reused, the snapshot with the first counter may have been deleted already
+    let last_counter = ids
+        .iter()
+        .map(|id| split_id(id))
+        .filter(|(id_timestamp, _)| *id_timestamp == timestamp)
+        .map(|(_, counter)| counter)
+        .max();
+    let id = if let Some(counter) = last_counter {
+        format!("{timestamp}_{}", counter.max(1) + 1)
+    } else {
+        timestamp
+    };
+    fs::copy(session_path, backup_path(session_path, &id)).map_err(err!())?;
+
+    for old_id in ids.iter().skip(MAX_BACKUPS - 1) {
+        fs::remove_file(backup_path(session_path, old_id)).ok();
+    }
+
+    Ok(())
+}
+
+pub fn read_backup(session_path: &Path, id: &str) -> StrResult<json::Value> {
+    // Only ids returned by list_backups() are accepted, the id must not escape the backups folder
+    if !list_backups(session_path)
+        .iter()
+        .any(|backup_id| backup_id == id)
+    {
+        return fmt_e!("Session backup {id} not found");
+    }
+
+    json::from_str(&fs::read_to_string(backup_path(session_path, id)).map_err(err!())?)
+        .map_err(err!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let session_path = dir.path().join("session.json");
+
+        backup_session(&session_path, true).unwrap();
+        assert!(list_backups(&session_path).is_empty());
+
+        fs::write(&session_path, "{}").unwrap();
+        backup_session(&session_path, false).unwrap();
+        backup_session(&session_path, false).unwrap();
+        assert_eq!(list_backups(&session_path).len(), 1);
+
+        // Forced snapshots in the same second do not overwrite each other
+        for index in 0..MAX_BACKUPS + 2 {
+            fs::write(&session_path, format!("{{ \"index\": {index} }}")).unwrap();
+            backup_session(&session_path, true).unwrap();
+        }
+
+        let ids = list_backups(&session_path);
+        assert_eq!(ids.len(), MAX_BACKUPS);
+        let indices = ids
+            .iter()
+            .map(|id| read_backup(&session_path, id).unwrap()["index"].clone())
+            .collect::<Vec<_>>();
+        let expected = (2..MAX_BACKUPS + 2)
+            .rev()
+            .map(|index| json::json!(index))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, expected);
+    }
+
+    #[test]
+    fn test_read_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let session_path = dir.path().join("session.json");
+
+        fs::write(&session_path, r#"{ "a": 1 }"#).unwrap();
+        backup_session(&session_path, true).unwrap();
+        let id = list_backups(&session_path)[0].clone();
+        assert_eq!(
+            read_backup(&session_path, &id).unwrap(),
+            json::json!({ "a": 1 })
+        );
+
+        // Only listed ids are accepted
+        assert!(read_backup(&session_path, "2000-01-01_00-00-00").is_err());
+        assert!(read_backup(&session_path, "../session").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+// In-memory journal of the settings changes, used to undo and redo them. Each entry contains the
+// values changed by one request, compared leaf by leaf. Only the session settings are recorded, the
+// client list is not reverted. The journal is lost when the server closes.
+
+use alvr_sockets::{
+    PathSegment, SettingsChange, SettingsChangeOrigin, SettingsHistory, ValueChange,
+};
+use chrono::Local;
+use serde_json as json;
+use std::collections::VecDeque;
+
+const MAX_HISTORY_LEN: usize = 100;
+
+// Objects with different fields and arrays with different lengths, like the client list or vector
+// settings, are recorded as a whole
+fn diff_values(
+    path: &mut Vec<PathSegment>,
+    old_value: &json::Value,
+    new_value: &json::Value,
+    changes: &mut Vec<ValueChange>,
+) {
+    match (old_value, new_value) {
+        (json::Value::Object(old_map), json::Value::Object(new_map))
+            if old_map.len() == new_map.len()
+                && old_map.keys().all(|key| new_map.contains_key(key)) =>
+        {
+            for (key, old_entry) in old_map {
+                path.push(key.clone().into());
+                diff_values(path, old_entry, &new_map[key], changes);
+                path.pop();
+            }
+        }
+        (json::Value::Array(old_array), json::Value::Array(new_array))
+            if old_array.len() == new_array.len() =>
+        {
+            for (index, (old_entry, new_entry)) in old_array.iter().zip(new_array).enumerate() {
+                path.push(index.into());
+                diff_values(path, old_entry, new_entry, changes);
+                path.pop();
+            }
+        }
+        _ => {
+            if old_value != new_value {
+                changes.push(ValueChange {
+                    path: path.clone(),
+                    old_value: old_value.clone(),
+                    new_value: new_value.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingsJournal {
+    undo: VecDeque<SettingsChange>,
+    redo: Vec<SettingsChange>,
+}
+
+impl SettingsJournal {
+    // A new change discards the changes that have been undone. Paths start from the session root
+    pub fn record(
+        &mut self,
+        origin: SettingsChangeOrigin,
+        old_session_json: &json::Value,
+        new_session_json: &json::Value,
+    ) {
+        const SESSION_SETTINGS_STR: &str = "session_settings";
+
+        let mut values = vec![];
+        diff_values(
+            &mut vec![SESSION_SETTINGS_STR.into()],
+            &old_session_json[SESSION_SETTINGS_STR],
+            &new_session_json[SESSION_SETTINGS_STR],
+            &mut values,
+        );
+        if values.is_empty() {
+            return;
+        }
+
+        self.push_undo(SettingsChange {
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            origin,
+            values,
+        });
+        self.redo.clear();
+    }
+
+    fn push_undo(&mut self, change: SettingsChange) {
+        if self.undo.len() == MAX_HISTORY_LEN {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(change);
+    }
+
+    pub fn last_undo(&self) -> Option<&SettingsChange> {
+        self.undo.back()
+    }
+
+    pub fn last_redo(&self) -> Option<&SettingsChange> {
+        self.redo.last()
+    }
+
+    // Call once the last change has been undone
+    pub fn mark_undone(&mut self) {
+        if let Some(change) = self.undo.pop_back() {
+            self.redo.push(change);
+        }
+    }
+
+    // Call once the last undone change has been redone
+    pub fn mark_redone(&mut self) {
+        if let Some(change) = self.redo.pop() {
+            self.push_undo(change);
+        }
+    }
+
+    pub fn history(&self) -> SettingsHistory {
+        SettingsHistory {
+            undo: self.undo.iter().cloned().collect(),
+            redo: self.redo.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let old_session_json = json::json!({
+            "client_connections": { "a": {} },
+            "session_settings": {
+                "props": [1, 2],
+                "video": { "codec": { "variant": "H264" }, "preferred_fps": 72.0 }
+            }
+        });
+        let new_session_json = json::json!({
+            "client_connections": { "a": {}, "b": {} },
+            "session_settings": {
+                "props": [1, 3],
+                "video": { "codec": { "variant": "H264" }, "preferred_fps": 90.0 }
+            }
+        });
+
+        let mut journal = SettingsJournal::default();
+        journal.record(
+            SettingsChangeOrigin::SetValues,
+            &old_session_json,
+            &new_session_json,
+        );
+        journal.record(
+            SettingsChangeOrigin::SetValues,
+            &new_session_json,
+            &new_session_json,
+        );
+
+        // The client list is not recorded
+        assert_eq!(journal.history().undo.len(), 1);
+        assert_eq!(
+            journal.last_undo().unwrap().values,
+            vec![
+                ValueChange {
+                    path: vec!["session_settings".into(), "props".into(), 1.into()],
+                    old_value: json::json!(2),
+                    new_value: json::json!(3),
+                },
+                ValueChange {
+                    path: vec![
+                        "session_settings".into(),
+                        "video".into(),
+                        "preferred_fps".into()
+                    ],
+                    old_value: json::json!(72.0),
+                    new_value: json::json!(90.0),
+                },
+            ]
+        );
+
+        // Changes only to the client list are not recorded
+        journal.record(
+            SettingsChangeOrigin::SetValues,
+            &new_session_json,
+            &json::json!({
+                "client_connections": {},
+                "session_settings": new_session_json["session_settings"]
+            }),
+        );
+        assert_eq!(journal.history().undo.len(), 1);
+
+        journal.mark_undone();
+        assert!(journal.last_undo().is_none());
+        assert_eq!(journal.history().redo.len(), 1);
+        journal.mark_redone();
+        assert!(journal.last_redo().is_none());
+        journal.mark_undone();
+
+        // A new change discards the undone ones
+        journal.record(
+            SettingsChangeOrigin::UpdateSession,
+            &new_session_json,
+            &old_session_json,
+        );
+        let history = journal.history();
+        assert_eq!(history.undo.len(), 1);
+        assert!(history.redo.is_empty());
+    }
+}
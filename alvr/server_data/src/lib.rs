@@ -1,19 +1,107 @@
+mod backups;
+mod history;
+
 use alvr_common::prelude::*;
 use alvr_events::EventType;
-use alvr_session::{ClientConnectionDesc, SessionDesc, Settings};
-use alvr_sockets::{AudioDevicesList, ClientListAction, GpuVendor, PathSegment, PathValuePair};
+use alvr_session::{ClientConnectionDesc, SessionDesc, Settings, SettingsViolation};
+use alvr_sockets::{
+    canonical_ip, format_path, AudioDevicesList, ClientListAction, GpuVendor, PathSegment,
+    PathValuePair, SettingsChangeOrigin, SettingsHistory,
+};
 use cpal::traits::{DeviceTrait, HostTrait};
+use history::SettingsJournal;
 use serde_json as json;
 use std::{
     collections::{hash_map::Entry, HashMap},
     fs,
+    io::Write,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
 };
 use wgpu::AdapterInfo;
 
+// Files are written to a temporary file which then replaces the original, so that a crash while
+// writing cannot leave a truncated file.
+fn write_file_atomically(path: &Path, contents: &[u8]) -> StrResult {
+    let temp_path = path.with_extension("json.tmp");
+    let mut file = fs::File::create(&temp_path).map_err(err!())?;
+    file.write_all(contents).map_err(err!())?;
+    file.sync_all().map_err(err!())?;
+
+    fs::rename(temp_path, path).map_err(err!())?;
+
+    // The rename is durable only once the folder is synced. Folders cannot be opened on Windows
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(err!())?;
+    }
+
+    Ok(())
+}
+
 fn save_session(session: &SessionDesc, path: &Path) -> StrResult {
-    fs::write(path, json::to_string_pretty(session).map_err(err!())?).map_err(err!())
+    if let Err(e) = backups::backup_session(path, false) {
+        warn!("Failed to backup the session: {e}");
+    }
+
+    write_file_atomically(
+        path,
+        json::to_string_pretty(session).map_err(err!())?.as_bytes(),
+    )
+}
+
+// Pairing keys are kept out of session.json, so that they are never sent to the dashboard and are
+// not restored together with old backups
+fn save_pairing_keys(pairing_keys: &HashMap<String, String>, path: &Path) {
+    let res = json::to_string_pretty(pairing_keys)
+        .map_err(err!())
+        .and_then(|keys_string| write_file_atomically(path, keys_string.as_bytes()));
+    if let Err(e) = res {
+        error!("Failed to save the pairing keys: {e}");
+    }
+}
+
+// Fails if a path is not found. Values are not validated
+fn set_json_values(
+    session_json: &mut json::Value,
+    descs: &[PathValuePair],
+) -> Result<(), Vec<SettingsViolation>> {
+    for desc in descs {
+        let mut session_ref = &mut *session_json;
+        for (position, segment) in desc.path.iter().enumerate() {
+            let maybe_child = match segment {
+                PathSegment::Name(name) => session_ref.get_mut(name),
+                PathSegment::Index(index) => session_ref.get_mut(index),
+            };
+            session_ref = if let Some(child) = maybe_child {
+                child
+            } else {
+                return Err(vec![SettingsViolation {
+                    path: format_path(&desc.path),
+                    message: format!("{} not found", format_path(&desc.path[..=position])),
+                }]);
+            };
+        }
+        *session_ref = desc.value.clone();
+    }
+
+    Ok(())
+}
+
+fn violations_to_string(violations: &[SettingsViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn save_session_or_log(session: &SessionDesc, path: &Path) {
+    if let Err(e) = save_session(session, path) {
+        error!("Failed to save the session: {e}");
+    }
 }
 
 // SessionDesc wrapper that saves settings.json and session.json on destruction.
@@ -38,7 +126,7 @@ impl DerefMut for SessionLock<'_> {
 
 impl Drop for SessionLock<'_> {
     fn drop(&mut self) {
-        save_session(self.session_desc, self.session_path).unwrap();
+        save_session_or_log(self.session_desc, self.session_path);
         *self.settings = self.session_desc.to_settings();
         alvr_events::send_event(EventType::Session(Box::new(self.session_desc.clone())));
     }
@@ -53,7 +141,11 @@ pub struct ServerDataManager {
     session: SessionDesc,
     settings: Settings,
     session_path: PathBuf,
+    // Client hostname to hex pairing key
+    pairing_keys: HashMap<String, String>,
+    pairing_keys_path: PathBuf,
     gpu_infos: Vec<AdapterInfo>,
+    journal: SettingsJournal,
 }
 
 impl ServerDataManager {
@@ -62,6 +154,16 @@ impl ServerDataManager {
         fs::create_dir_all(config_dir).ok();
         let session_desc = Self::load_session(session_path, config_dir);
 
+        let pairing_keys_path = config_dir.join("pairing_keys.json");
+        let pairing_keys = fs::read_to_string(&pairing_keys_path)
+            .ok()
+            .and_then(|keys_string| {
+                json::from_str(&keys_string)
+                    .map_err(|e| error!("Failed to load the pairing keys: {e}"))
+                    .ok()
+            })
+            .unwrap_or_default();
+
         let vk_adapters: Vec<wgpu::Adapter> = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::VULKAN,
             dx12_shader_compiler: Default::default(),
@@ -78,7 +180,10 @@ impl ServerDataManager {
             session: session_desc.clone(),
             settings: session_desc.to_settings(),
             session_path: session_path.to_owned(),
+            pairing_keys,
+            pairing_keys_path,
             gpu_infos,
+            journal: SettingsJournal::default(),
         }
     }
 
@@ -89,44 +194,100 @@ impl ServerDataManager {
             return SessionDesc::default();
         }
 
-        let session_json = json::from_str::<json::Value>(&session_string)
+        let mut session_json = json::from_str::<json::Value>(&session_string)
             .unwrap_or_else(|e| {
                 error!(
                     "{} {} {}\n{}",
                     "Failed to load session.json.",
-                    "Its contents will be reset and the original file content stored as session_invalid.json.",
+                    "It will be restored from the last backup if any, and the original file content stored as session_invalid.json.",
                     "See error message below for details:",
                     e
                 );
                 json::Value::Null
             });
 
+        // Content of the file the session is loaded from, which is a backup if session.json is not
+        // valid
+        let mut loaded_string = session_string.clone();
+
         if session_json.is_null() {
             fs::write(config_dir.join("session_invalid.json"), &session_string).ok();
-            return SessionDesc::default();
+
+            let maybe_backup_json = backups::list_backups(session_path)
+                .first()
+                .and_then(|id| backups::read_backup(session_path, id).ok());
+            if let Some(backup_json) = maybe_backup_json {
+                loaded_string = json::to_string_pretty(&backup_json).unwrap();
+                session_json = backup_json;
+            } else {
+                return SessionDesc::default();
+            }
         }
 
-        json::from_value(session_json.clone()).unwrap_or_else(|_| {
-            fs::write(config_dir.join("session_old.json"), &session_string).ok();
-            let mut session_desc = SessionDesc::default();
-            match session_desc.merge_from_json(&session_json) {
-                Ok(_) => info!(
-                    "{} {}",
-                    "Session extrapolated successfully.",
-                    "Old session.json is stored as session_old.json"
-                ),
-                Err(e) => error!(
-                    "{} {} {}",
-                    "Error while extrapolating session.",
-                    "Old session.json is stored as session_old.json.",
-                    e
-                ),
+        let migrated = alvr_session::migrate_session_json(&mut session_json);
+
+        let mut keep_original = false;
+        let (mut session_desc, mut changed) = match json::from_value(session_json.clone()) {
+            Ok(session_desc) if !migrated => (session_desc, false),
+            Ok(session_desc) => {
+                fs::write(config_dir.join("session_old.json"), &loaded_string).ok();
+
+                (session_desc, true)
+            }
+            Err(e) => {
+                fs::write(config_dir.join("session_old.json"), &loaded_string).ok();
+
+                // Fields added since the session was saved are filled with defaults. Any other
+                // value that is not kept is reported
+                let mut session_desc = SessionDesc::default();
+                if let Err(e) = session_desc.merge_from_json(&session_json) {
+                    error!("Failed to extrapolate the session: {e}");
+                }
+
+                let lost_values = alvr_session::lost_session_values(
+                    &session_json,
+                    &json::to_value(&session_desc).unwrap(),
+                );
+                for path in &lost_values {
+                    warn!("Session value not loaded: {path}");
+                }
+
+                if !lost_values.is_empty() {
+                    error!(
+                        "{} {} {}\n{}",
+                        "Some session values could not be loaded and were reset to their defaults.",
+                        "The original file is kept until the settings are changed, and is stored as session_old.json.",
+                        "See error message below for details:",
+                        e
+                    );
+                    keep_original = true;
+                }
+
+                (session_desc, true)
+            }
+        };
+
+        // Values that are not valid anymore, or that were edited by hand, would reject any change
+        // of the settings
+        let mut session_settings_json = json::to_value(&session_desc.session_settings).unwrap();
+        let violations = alvr_session::repair_session_settings_json(&mut session_settings_json);
+        if !violations.is_empty() {
+            warn!(
+                "Invalid settings were reset to their defaults: {}",
+                violations_to_string(&violations)
+            );
+            if let Ok(session_settings) = json::from_value(session_settings_json) {
+                session_desc.session_settings = session_settings;
+                changed = true;
             }
+        }
+
+        if changed && !keep_original {
             // not essential, but useful to avoid duplicated errors
             save_session(&session_desc, session_path).ok();
+        }
 
-            session_desc
-        })
+        session_desc
     }
 
     // prefer settings()
@@ -146,55 +307,153 @@ impl ServerDataManager {
         &self.settings
     }
 
+    // Settings with the overrides of the client applied
+    pub fn client_settings(&self, hostname: &str) -> Settings {
+        self.session.with_client_overrides(hostname).to_settings()
+    }
+
     pub fn client_list(&self) -> &HashMap<String, ClientConnectionDesc> {
         &self.session.client_connections
     }
 
     // Note: "value" can be any session subtree, in json format.
-    pub fn set_values(&mut self, descs: Vec<PathValuePair>) -> StrResult {
+    // Values are applied all at once, or not at all. Settings are validated against the schema,
+    // every violation is reported.
+    pub fn set_values(&mut self, descs: Vec<PathValuePair>) -> Result<(), Vec<SettingsViolation>> {
         let mut session_json = serde_json::to_value(self.session.clone()).unwrap();
+        set_json_values(&mut session_json, &descs)?;
 
-        for desc in descs {
-            let mut session_ref = &mut session_json;
-            for segment in &desc.path {
-                session_ref = match segment {
-                    PathSegment::Name(name) => {
-                        if let Some(name) = session_ref.get_mut(name) {
-                            name
-                        } else {
-                            return fmt_e!(
-                                "From path {:?}: segment \"{}\" not found",
-                                desc.path,
-                                name
-                            );
-                        }
-                    }
-                    PathSegment::Index(index) => {
-                        if let Some(index) = session_ref.get_mut(index) {
-                            index
-                        } else {
-                            return fmt_e!(
-                                "From path {:?}: segment [{}] not found",
-                                desc.path,
-                                index
-                            );
-                        }
-                    }
-                };
-            }
-            *session_ref = desc.value.clone();
+        // session_json has been updated
+        self.apply_session_json(session_json, Some(SettingsChangeOrigin::SetValues))
+    }
+
+    // Replaces the whole session, if valid
+    pub fn update_session(&mut self, session: SessionDesc) -> Result<(), Vec<SettingsViolation>> {
+        self.apply_session_json(
+            serde_json::to_value(session).unwrap(),
+            Some(SettingsChangeOrigin::UpdateSession),
+        )
+    }
+
+    // The change is recorded in the journal if it has an origin
+    fn apply_session_json(
+        &mut self,
+        session_json: json::Value,
+        maybe_origin: Option<SettingsChangeOrigin>,
+    ) -> Result<(), Vec<SettingsViolation>> {
+        let violations =
+            alvr_session::validate_session_settings_json(&session_json["session_settings"]);
+        if !violations.is_empty() {
+            return Err(violations);
         }
 
-        // session_json has been updated
-        self.session = serde_json::from_value(session_json).map_err(err!())?;
+        let session = serde_json::from_value(session_json).map_err(|e| {
+            vec![SettingsViolation {
+                path: "".into(),
+                message: e.to_string(),
+            }]
+        })?;
+        if let Some(origin) = maybe_origin {
+            // Compare the serialized sessions, the values may have been rounded
+            self.journal.record(
+                origin,
+                &json::to_value(&self.session).unwrap(),
+                &json::to_value(&session).unwrap(),
+            );
+        }
+        self.session = session;
         self.settings = self.session.to_settings();
 
-        save_session(&self.session, &self.session_path).unwrap();
+        save_session_or_log(&self.session, &self.session_path);
         alvr_events::send_event(EventType::Session(Box::new(self.session.clone())));
 
         Ok(())
     }
 
+    // Snapshot ids, from the newest
+    pub fn session_backups(&self) -> Vec<String> {
+        backups::list_backups(&self.session_path)
+    }
+
+    // The current session is backed up first, so that the restore can be reverted
+    pub fn restore_session_backup(&mut self, id: &str) -> StrResult {
+        let mut session_json = backups::read_backup(&self.session_path, id)?;
+        alvr_session::migrate_session_json(&mut session_json);
+
+        // Fields added since the backup was taken are filled with defaults
+        let mut session_desc = SessionDesc::default();
+        session_desc.merge_from_json(&session_json)?;
+
+        // Restoring settings must not bring back clients that were removed or untrusted since
+        session_desc.client_connections = self.session.client_connections.clone();
+
+        backups::backup_session(&self.session_path, true)?;
+
+        self.apply_session_json(
+            json::to_value(session_desc).map_err(err!())?,
+            Some(SettingsChangeOrigin::RestoreBackup),
+        )
+        .map_err(|violations| {
+            format!(
+                "Session backup {id} is not valid: {}",
+                violations_to_string(&violations)
+            )
+        })
+    }
+
+    pub fn settings_history(&self) -> SettingsHistory {
+        self.journal.history()
+    }
+
+    // Restores the old values of the last change. Undoing or redoing does not discard other changes
+    pub fn undo_settings_change(&mut self) -> StrResult {
+        let Some(change) = self.journal.last_undo() else {
+            return fmt_e!("No settings change to undo");
+        };
+
+        let descs = change
+            .values
+            .iter()
+            .map(|value| PathValuePair {
+                path: value.path.clone(),
+                value: value.old_value.clone(),
+            })
+            .collect::<Vec<_>>();
+        // The change stays in the journal if it cannot be applied
+        self.apply_change_values(&descs)?;
+
+        self.journal.mark_undone();
+
+        Ok(())
+    }
+
+    pub fn redo_settings_change(&mut self) -> StrResult {
+        let Some(change) = self.journal.last_redo() else {
+            return fmt_e!("No settings change to redo");
+        };
+
+        let descs = change
+            .values
+            .iter()
+            .map(|value| PathValuePair {
+                path: value.path.clone(),
+                value: value.new_value.clone(),
+            })
+            .collect::<Vec<_>>();
+        self.apply_change_values(&descs)?;
+
+        self.journal.mark_redone();
+
+        Ok(())
+    }
+
+    fn apply_change_values(&mut self, descs: &[PathValuePair]) -> StrResult {
+        let mut session_json = serde_json::to_value(self.session.clone()).unwrap();
+        set_json_values(&mut session_json, descs)
+            .and_then(|_| self.apply_session_json(session_json, None))
+            .map_err(|violations| violations_to_string(&violations))
+    }
+
     pub fn get_gpu_vendors(&self) -> Vec<GpuVendor> {
         return self
             .gpu_infos
@@ -240,6 +499,16 @@ impl ServerDataManager {
         Ok(AudioDevicesList { output, input })
     }
 
+    pub fn pairing_key(&self, hostname: &str) -> Option<&str> {
+        self.pairing_keys.get(hostname).map(String::as_str)
+    }
+
+    fn remove_pairing_key(&mut self, hostname: &str) {
+        if self.pairing_keys.remove(hostname).is_some() {
+            save_pairing_keys(&self.pairing_keys, &self.pairing_keys_path);
+        }
+    }
+
     pub fn update_client_list(&mut self, hostname: String, action: ClientListAction) {
         let mut client_connections = self.session.client_connections.clone();
 
@@ -255,8 +524,11 @@ impl ServerDataManager {
                     let client_connection_desc = ClientConnectionDesc {
                         trusted,
                         current_ip: None,
-                        manual_ips: manual_ips.into_iter().collect(),
+                        streaming_role: None,
+                        bandwidth_probe: None,
+                        manual_ips: manual_ips.into_iter().map(canonical_ip).collect(),
                         display_name: "Unknown".into(),
+                        settings_overrides: Default::default(),
                     };
                     new_entry.insert(client_connection_desc);
 
@@ -272,25 +544,42 @@ impl ServerDataManager {
             }
             ClientListAction::Trust => {
                 if let Entry::Occupied(mut entry) = maybe_client_entry {
-                    entry.get_mut().trusted = true;
+                    // A new pairing key is agreed at the next connection
+                    if !entry.get().trusted {
+                        entry.get_mut().trusted = true;
+                        self.remove_pairing_key(entry.key());
 
-                    updated = true;
+                        updated = true;
+                    }
                 }
             }
             ClientListAction::SetManualIps(ips) => {
                 if let Entry::Occupied(mut entry) = maybe_client_entry {
-                    entry.get_mut().manual_ips = ips.into_iter().collect();
+                    // IPv4-mapped addresses would never match the IP of a connected client
+                    entry.get_mut().manual_ips = ips.into_iter().map(canonical_ip).collect();
 
                     updated = true;
                 }
             }
             ClientListAction::RemoveEntry => {
                 if let Entry::Occupied(entry) = maybe_client_entry {
-                    entry.remove_entry();
+                    let (hostname, _) = entry.remove_entry();
+                    self.remove_pairing_key(&hostname);
 
                     updated = true;
                 }
             }
+            ClientListAction::SetPairingKey(pairing_key) => {
+                // Not part of the session, so no session event is sent
+                if let Entry::Occupied(entry) = maybe_client_entry {
+                    if let Some(pairing_key) = pairing_key {
+                        self.pairing_keys.insert(entry.key().clone(), pairing_key);
+                        save_pairing_keys(&self.pairing_keys, &self.pairing_keys_path);
+                    } else {
+                        self.remove_pairing_key(entry.key());
+                    }
+                }
+            }
             ClientListAction::UpdateCurrentIp(current_ip) => {
                 if let Entry::Occupied(mut entry) = maybe_client_entry {
                     if entry.get().current_ip != current_ip {
@@ -300,12 +589,44 @@ impl ServerDataManager {
                     }
                 }
             }
+            ClientListAction::SetStreamingRole(role) => {
+                if let Entry::Occupied(mut entry) = maybe_client_entry {
+                    if entry.get().streaming_role != role {
+                        entry.get_mut().streaming_role = role;
+
+                        updated = true;
+                    }
+                }
+            }
+            ClientListAction::SetBandwidthProbe(result) => {
+                if let Entry::Occupied(mut entry) = maybe_client_entry {
+                    entry.get_mut().bandwidth_probe = Some(result);
+
+                    updated = true;
+                }
+            }
+            ClientListAction::SetSettingsOverrides(overrides) => {
+                if let Entry::Occupied(mut entry) = maybe_client_entry {
+                    let violations = overrides.validate(&self.session.session_settings);
+                    if violations.is_empty() {
+                        entry.get_mut().settings_overrides = overrides;
+
+                        updated = true;
+                    } else {
+                        warn!(
+                            "Invalid settings overrides for client {}: {}",
+                            entry.key(),
+                            violations_to_string(&violations)
+                        );
+                    }
+                }
+            }
         }
 
         if updated {
             self.session.client_connections = client_connections;
 
-            save_session(&self.session, &self.session_path).unwrap();
+            save_session_or_log(&self.session, &self.session_path);
             alvr_events::send_event(EventType::Session(Box::new(self.session.clone())));
         }
     }
@@ -1,12 +1,16 @@
+mod migrations;
 mod settings;
+mod validation;
 
+pub use migrations::{lost_session_values, migrate_session_json};
 pub use settings::*;
 pub use settings_schema;
+pub use validation::*;
 
 use alvr_common::{prelude::*, semver::Version, ALVR_VERSION};
 use serde::{Deserialize, Serialize};
 use serde_json as json;
-use settings_schema::{NumberType, SchemaNode};
+use settings_schema::{NumberType, SchemaNode, SwitchDefault};
 use std::{
     collections::{HashMap, HashSet},
     net::IpAddr,
@@ -95,12 +99,97 @@ pub struct OpenvrConfig {
     pub capture_frame_dir: String,
 }
 
+// The primary client drives tracking and input. Spectators only receive video and audio
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamingRole {
+    Primary,
+    Spectator,
+}
+
+// Link capacity measured at the start of the stream
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct BandwidthProbeResult {
+    pub throughput_bps: f32,
+    // Fraction of the probe packets that were lost, in the range [0, 1]
+    pub packet_loss: f32,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ClientConnectionDesc {
     pub display_name: String,
     pub current_ip: Option<IpAddr>,
+    // Set while the client is streaming
+    #[serde(default)]
+    pub streaming_role: Option<StreamingRole>,
+    // Result of the last bandwidth probe
+    #[serde(default)]
+    pub bandwidth_probe: Option<BandwidthProbeResult>,
     pub manual_ips: HashSet<IpAddr>,
     pub trusted: bool,
+    #[serde(default)]
+    pub settings_overrides: ClientSettingsOverrides,
+}
+
+// Settings that replace the global ones for a single client, for example to stream at a lower
+// bitrate to a headset connected to a weaker access point. Values use the same representation as
+// SessionSettings.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ClientSettingsOverrides {
+    pub bitrate: Option<BitrateConfigDefault>,
+    pub transcoding_view_resolution: Option<FrameSizeDefault>,
+    pub codec: Option<CodecTypeDefault>,
+    pub foveated_rendering: Option<SwitchDefault<FoveatedRenderingDescDefault>>,
+    pub max_buffering_frames: Option<f32>,
+    pub server_send_buffer_bytes: Option<SocketBufferSizeDefault>,
+    pub server_recv_buffer_bytes: Option<SocketBufferSizeDefault>,
+    pub client_send_buffer_bytes: Option<SocketBufferSizeDefault>,
+    pub client_recv_buffer_bytes: Option<SocketBufferSizeDefault>,
+}
+
+impl ClientSettingsOverrides {
+    pub fn apply(&self, session_settings: &mut SessionSettings) {
+        fn set<T: Clone>(value: &mut T, maybe_override: &Option<T>) {
+            if let Some(override_value) = maybe_override {
+                *value = override_value.clone();
+            }
+        }
+
+        let video = &mut session_settings.video;
+        set(&mut video.bitrate, &self.bitrate);
+        set(
+            &mut video.transcoding_view_resolution,
+            &self.transcoding_view_resolution,
+        );
+        set(&mut video.codec, &self.codec);
+        set(&mut video.foveated_rendering, &self.foveated_rendering);
+        set(&mut video.max_buffering_frames, &self.max_buffering_frames);
+
+        let connection = &mut session_settings.connection;
+        set(
+            &mut connection.server_send_buffer_bytes,
+            &self.server_send_buffer_bytes,
+        );
+        set(
+            &mut connection.server_recv_buffer_bytes,
+            &self.server_recv_buffer_bytes,
+        );
+        set(
+            &mut connection.client_send_buffer_bytes,
+            &self.client_send_buffer_bytes,
+        );
+        set(
+            &mut connection.client_recv_buffer_bytes,
+            &self.client_recv_buffer_bytes,
+        );
+    }
+
+    // Returns an empty list if the settings are valid once the overrides are applied
+    pub fn validate(&self, session_settings: &SessionSettings) -> Vec<SettingsViolation> {
+        let mut session_settings = session_settings.clone();
+        self.apply(&mut session_settings);
+
+        validate_session_settings_json(&json::to_value(session_settings).unwrap())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -200,6 +289,19 @@ impl SessionDesc {
         }
     }
 
+    // Copy of the session with the settings overrides of a client applied. This is the session sent
+    // to the client when it connects.
+    pub fn with_client_overrides(&self, hostname: &str) -> SessionDesc {
+        let mut session = self.clone();
+        if let Some(connection) = self.client_connections.get(hostname) {
+            connection
+                .settings_overrides
+                .apply(&mut session.session_settings);
+        }
+
+        session
+    }
+
     pub fn to_settings(&self) -> Settings {
         let session_settings_json = json::to_value(&self.session_settings).unwrap();
         let schema = Settings::schema(settings::session_settings_default());
@@ -584,4 +686,53 @@ mod tests {
             .merge_from_json(&json::from_str(input_json_string).unwrap())
             .unwrap();
     }
+
+    #[test]
+    fn test_client_settings_overrides() {
+        let mut session = SessionDesc::default();
+
+        session.client_connections.insert(
+            "client.alvr".into(),
+            ClientConnectionDesc {
+                display_name: "Client".into(),
+                current_ip: None,
+                streaming_role: None,
+                bandwidth_probe: None,
+                manual_ips: HashSet::new(),
+                trusted: true,
+                settings_overrides: ClientSettingsOverrides {
+                    codec: Some(CodecTypeDefault {
+                        variant: CodecTypeDefaultVariant::Hevc,
+                    }),
+                    max_buffering_frames: Some(4.0),
+                    ..Default::default()
+                },
+            },
+        );
+
+        let settings = session.with_client_overrides("client.alvr").to_settings();
+        assert!(matches!(settings.video.codec, CodecType::Hevc));
+        assert_eq!(settings.video.max_buffering_frames, 4.0);
+
+        // Other clients and the global settings are not affected
+        let settings = session
+            .with_client_overrides("other.client.alvr")
+            .to_settings();
+        assert!(matches!(settings.video.codec, CodecType::H264));
+        assert!(matches!(session.to_settings().video.codec, CodecType::H264));
+
+        let overrides = &session.client_connections["client.alvr"].settings_overrides;
+        assert_eq!(overrides.validate(&session.session_settings), vec![]);
+
+        let overrides = ClientSettingsOverrides {
+            max_buffering_frames: Some(0.0),
+            ..Default::default()
+        };
+        let paths = overrides
+            .validate(&session.session_settings)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["session_settings.video.max_buffering_frames"]);
+    }
 }
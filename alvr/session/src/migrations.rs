@@ -0,0 +1,357 @@
+// Migrations of session.json files saved by older versions. They run on the json representation
+// before deserialization, so that values whose path or format changed are not lost. Fields that
+// were added are filled with their default when loading. Values that still do not fit the session
+// are reported by lost_session_values(), and the original session.json is kept.
+//
+// When a setting is renamed, moved or a variant is replaced, add a step to the migration of the
+// version that introduces the change, and a fixture saved by the previous release.
+
+use alvr_common::{prelude::*, semver::Version};
+use serde_json as json;
+
+pub enum MigrationStep {
+    // Renames the last segment of the path, the value stays in the same object
+    RenamePath {
+        path: &'static str,
+        new_name: &'static str,
+    },
+    // Moves the value to another object, which is created if missing
+    MoveSubtree {
+        from: &'static str,
+        to: &'static str,
+    },
+    // Moves the value to the content of a variant of the choice at `path`, and selects the variant
+    MoveToVariant {
+        from: &'static str,
+        path: &'static str,
+        variant: &'static str,
+    },
+    // Selects another variant of a choice if `from` is selected. The content of `from` is dropped
+    ConvertVariant {
+        path: &'static str,
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+pub struct Migration {
+    // First version that saves sessions in the new format
+    pub version: &'static str,
+    pub steps: &'static [MigrationStep],
+}
+
+// Must be sorted by version
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: "20.0.0-dev00",
+    steps: &[
+        MigrationStep::ConvertVariant {
+            path: "session_settings.connection.stream_protocol",
+            from: "ThrottledUdp",
+            to: "Udp",
+        },
+        MigrationStep::MoveToVariant {
+            from: "session_settings.video.encode_bitrate_mbps",
+            path: "session_settings.video.bitrate.mode",
+            variant: "ConstantMbps",
+        },
+    ],
+}];
+
+fn split_path(path: &str) -> (Vec<&str>, &str) {
+    match path.rsplit_once('.') {
+        Some((parent, name)) => (parent.split('.').collect(), name),
+        None => (vec![], path),
+    }
+}
+
+fn get_object_mut<'a>(
+    mut value: &'a mut json::Value,
+    segments: &[&str],
+    create: bool,
+) -> Option<&'a mut json::Map<String, json::Value>> {
+    for segment in segments {
+        let object = value.as_object_mut()?;
+        if create && !object.contains_key(*segment) {
+            object.insert((*segment).into(), json::Value::Object(json::Map::new()));
+        }
+        value = object.get_mut(*segment)?;
+    }
+
+    value.as_object_mut()
+}
+
+// Returns false if the value is missing or an object cannot be created at the destination
+fn move_value(
+    session_json: &mut json::Value,
+    from: &str,
+    to_parent: &[&str],
+    to_name: &str,
+) -> bool {
+    let (from_parent, from_name) = split_path(from);
+    let value = match get_object_mut(session_json, &from_parent, false)
+        .and_then(|object| object.remove(from_name))
+    {
+        Some(value) => value,
+        None => return false,
+    };
+
+    if let Some(object) = get_object_mut(session_json, to_parent, true) {
+        object.insert(to_name.into(), value);
+
+        true
+    } else {
+        // A value that is not an object is in the way. Leave the session unchanged
+        if let Some(object) = get_object_mut(session_json, &from_parent, false) {
+            object.insert(from_name.into(), value);
+        }
+
+        false
+    }
+}
+
+impl MigrationStep {
+    // Returns the description of the change, or None if the session does not need it
+    fn apply(&self, session_json: &mut json::Value) -> Option<String> {
+        match self {
+            MigrationStep::RenamePath { path, new_name } => {
+                let (parent, name) = split_path(path);
+                let object = get_object_mut(session_json, &parent, false)?;
+                let value = object.remove(name)?;
+                object.insert((*new_name).into(), value);
+
+                Some(format!("renamed {path} to {new_name}"))
+            }
+            MigrationStep::MoveSubtree { from, to } => {
+                let (to_parent, to_name) = split_path(to);
+                move_value(session_json, from, &to_parent, to_name)
+                    .then(|| format!("moved {from} to {to}"))
+            }
+            MigrationStep::MoveToVariant {
+                from,
+                path,
+                variant,
+            } => {
+                let segments = path.split('.').collect::<Vec<_>>();
+                if !move_value(session_json, from, &segments, variant) {
+                    return None;
+                }
+                get_object_mut(session_json, &segments, false)?
+                    .insert("variant".into(), json::Value::String((*variant).into()));
+
+                Some(format!("moved {from} to {path} as {variant}"))
+            }
+            MigrationStep::ConvertVariant { path, from, to } => {
+                let segments = path.split('.').collect::<Vec<_>>();
+                let object = get_object_mut(session_json, &segments, false)?;
+                if object.get("variant")?.as_str()? != *from {
+                    return None;
+                }
+                object.insert("variant".into(), json::Value::String((*to).into()));
+                object.remove(*from);
+
+                Some(format!("converted {path} from {from} to {to}"))
+            }
+        }
+    }
+}
+
+// Applies in order the migrations introduced after the version that saved the session. Sessions
+// without a valid version get all migrations. Returns true if the session was changed.
+pub fn migrate_session_json(session_json: &mut json::Value) -> bool {
+    let session_version = session_json
+        .get("server_version")
+        .and_then(|version| version.as_str())
+        .and_then(|version| Version::parse(version).ok());
+
+    let mut changed = false;
+    for migration in MIGRATIONS {
+        let version = Version::parse(migration.version).unwrap();
+        if session_version.as_ref().map_or(false, |v| *v >= version) {
+            continue;
+        }
+
+        for step in migration.steps {
+            if let Some(change) = step.apply(session_json) {
+                info!("Session migration to v{version}: {change}");
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+fn find_lost_values(
+    old_json: &json::Value,
+    new_json: &json::Value,
+    path: &str,
+    lost_paths: &mut Vec<String>,
+) {
+    match (old_json, new_json) {
+        (json::Value::Object(old_fields), json::Value::Object(new_fields)) => {
+            for (name, old_value) in old_fields {
+                let field_path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{path}.{name}")
+                };
+
+                if let Some(new_value) = new_fields.get(name) {
+                    find_lost_values(old_value, new_value, &field_path, lost_paths);
+                } else {
+                    lost_paths.push(format!("{field_path} (dropped)"));
+                }
+            }
+        }
+        // Integers are saved as floats once they go through the settings
+        (json::Value::Number(old_number), json::Value::Number(new_number)) => {
+            if old_number.as_f64() != new_number.as_f64() {
+                lost_paths.push(format!("{path} (reset)"));
+            }
+        }
+        _ => {
+            if old_json != new_json {
+                lost_paths.push(format!("{path} (reset)"));
+            }
+        }
+    }
+}
+
+// Returns the paths of the values of `old_json` that are missing or different in `new_json`, for
+// example after a session that does not match SessionDesc anymore is extrapolated
+pub fn lost_session_values(old_json: &json::Value, new_json: &json::Value) -> Vec<String> {
+    let mut lost_paths = vec![];
+    find_lost_values(old_json, new_json, "", &mut lost_paths);
+
+    lost_paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_sorted() {
+        let versions = MIGRATIONS
+            .iter()
+            .map(|migration| Version::parse(migration.version).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut session_json = json::json!({
+            "a": { "b": 1, "choice": { "variant": "Old", "Old": 2, "New": 3 } },
+            "c": 4
+        });
+
+        let rename = MigrationStep::RenamePath {
+            path: "a.b",
+            new_name: "d",
+        };
+        assert!(rename.apply(&mut session_json).is_some());
+        assert!(rename.apply(&mut session_json).is_none());
+
+        let convert = MigrationStep::ConvertVariant {
+            path: "a.choice",
+            from: "Old",
+            to: "New",
+        };
+        assert!(convert.apply(&mut session_json).is_some());
+        assert!(convert.apply(&mut session_json).is_none());
+
+        let moved = MigrationStep::MoveSubtree {
+            from: "a.d",
+            to: "e.f.g",
+        };
+        assert!(moved.apply(&mut session_json).is_some());
+
+        // "c" is not an object, the value stays where it is
+        let blocked = MigrationStep::MoveSubtree {
+            from: "e.f.g",
+            to: "c.h",
+        };
+        assert!(blocked.apply(&mut session_json).is_none());
+
+        let to_variant = MigrationStep::MoveToVariant {
+            from: "e.f.g",
+            path: "a.choice",
+            variant: "Other",
+        };
+        assert!(to_variant.apply(&mut session_json).is_some());
+        assert!(to_variant.apply(&mut session_json).is_none());
+
+        assert_eq!(
+            session_json,
+            json::json!({
+                "a": { "choice": { "variant": "Other", "New": 3, "Other": 1 } },
+                "c": 4,
+                "e": { "f": {} }
+            })
+        );
+    }
+
+    #[test]
+    fn test_lost_values() {
+        let old_json = json::json!({
+            "a": { "b": 1, "c": "text", "d": [1, 2] },
+            "e": true
+        });
+        let new_json = json::json!({
+            "a": { "b": 1.0, "c": "default", "d": [1, 2], "f": 3 }
+        });
+
+        assert_eq!(
+            lost_session_values(&old_json, &new_json),
+            ["a.c (reset)", "e (dropped)"]
+        );
+        assert!(lost_session_values(&new_json, &new_json).is_empty());
+    }
+
+    // The fixture is a session.json saved by v19.1.0, with a few settings changed by the user
+    #[test]
+    fn test_v19_session() {
+        let mut session_json =
+            json::from_str(include_str!("../fixtures/session_v19.1.0.json")).unwrap();
+        assert!(migrate_session_json(&mut session_json));
+
+        let settings_json = &session_json["session_settings"];
+        assert_eq!(
+            settings_json["connection"]["stream_protocol"],
+            json::json!({ "variant": "Udp" })
+        );
+        assert_eq!(
+            settings_json["video"]["bitrate"]["mode"],
+            json::json!({ "variant": "ConstantMbps", "ConstantMbps": 60 })
+        );
+        assert!(settings_json["video"].get("encode_bitrate_mbps").is_none());
+
+        let mut session = crate::SessionDesc::default();
+        session.merge_from_json(&session_json).unwrap();
+        assert!(session.client_connections["1234.client.alvr"].trusted);
+
+        let settings = session.to_settings();
+        assert!(matches!(
+            settings.video.bitrate.mode,
+            crate::BitrateMode::ConstantMbps(60)
+        ));
+        assert!(matches!(
+            settings.connection.stream_protocol,
+            crate::SocketProtocol::Udp
+        ));
+        assert!(matches!(settings.video.codec, crate::CodecType::Hevc));
+        assert_eq!(settings.video.preferred_fps, 90.0);
+
+        // Migrations already applied are not run again
+        session_json["server_version"] = json::json!("20.0.0-dev10");
+        assert!(!migrate_session_json(&mut session_json));
+    }
+
+    #[test]
+    fn test_current_session() {
+        let mut session_json = json::to_value(crate::SessionDesc::default()).unwrap();
+        assert!(!migrate_session_json(&mut session_json));
+    }
+}
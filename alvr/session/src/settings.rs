@@ -292,7 +292,7 @@ pub struct ColorCorrectionDesc {
     #[schema(flag = "steamvr-restart")]
     pub saturation: f32,
 
-    #[schema(gui(slider(min = 0.0, max = 5.0, step = 0.01)))]
+    #[schema(gui(slider(min = 0.01, max = 5.0, step = 0.01)))]
     #[schema(flag = "steamvr-restart")]
     pub gamma: f32,
 
@@ -389,6 +389,11 @@ CABAC produces better compression but it's significantly slower and may lead to
 
     pub bitrate: BitrateConfig,
 
+    #[schema(strings(
+        help = "Drop the encoded frames still waiting to be sent when a new IDR frame is ready. The IDR frame does not depend on them, so sending it first reduces latency"
+    ))]
+    pub drop_stale_frames_on_idr: bool,
+
     #[schema(flag = "steamvr-restart")]
     pub advanced_codec_options: AdvancedCodecOptions,
 
@@ -647,6 +652,30 @@ pub enum SocketProtocol {
     Udp,
     #[schema(strings(display_name = "TCP"))]
     Tcp,
+    #[schema(strings(display_name = "QUIC"))]
+    Quic,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub enum ConnectionTransport {
+    Network,
+    #[schema(strings(display_name = "Local IPC"))]
+    LocalIpc {
+        #[schema(strings(
+            help = "Trust local clients without confirmation. Any user of this machine can start a local client."
+        ))]
+        auto_trust_clients: bool,
+    },
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[schema(gui = "button_group")]
+pub enum DiscoveryProtocol {
+    #[schema(strings(display_name = "UDP broadcast"))]
+    Broadcast,
+    #[schema(strings(display_name = "mDNS"))]
+    Mdns,
+    Both,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -655,6 +684,11 @@ pub struct DiscoveryConfig {
         help = "Allow untrusted clients to connect without confirmation. This is not recommended for security reasons."
     ))]
     pub auto_trust_clients: bool,
+
+    #[schema(strings(
+        help = "Clients announce themselves with both methods. mDNS works on networks which block broadcast packets, like some enterprise and mesh networks."
+    ))]
+    pub protocol: DiscoveryProtocol,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -674,15 +708,74 @@ pub struct DisconnectionCriteria {
     pub sustain_duration_s: u64,
 }
 
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct SpectatorsConfig {
+    #[schema(strings(
+        help = "Maximum number of clients connected at the same time, in addition to the primary client"
+    ))]
+    #[schema(gui(slider(min = 1, max = 8)))]
+    pub max_count: u64,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct ForwardErrorCorrectionConfig {
+    #[schema(strings(
+        help = "Number of parity shards sent for each data shard. Higher values can recover from more packet loss, at the cost of more bandwidth"
+    ))]
+    #[schema(gui(slider(min = 0.01, max = 1.0, step = 0.01)))]
+    pub redundancy_ratio: f32,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct RetransmissionConfig {
+    #[schema(strings(
+        help = "Maximum time spent waiting for the requested shards before signaling packet loss"
+    ))]
+    #[schema(gui(slider(min = 1, max = 50)), suffix = "ms")]
+    pub deadline_ms: u64,
+
+    #[schema(strings(
+        help = "Number of sent shards kept for each stream to serve retransmissions"
+    ))]
+    #[schema(suffix = " shards")]
+    pub history_size: u64,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct PacingConfig {
+    #[schema(strings(
+        help = "Fraction of the frame interval over which the video shards of a frame are spread. Lower values reduce latency but produce bigger bursts"
+    ))]
+    #[schema(gui(slider(min = 0.1, max = 1.0, step = 0.05)))]
+    pub frame_interval_fraction: f32,
+}
+
+#[derive(SettingsSchema, Serialize, Deserialize, Clone)]
+pub struct BandwidthProbingConfig {
+    #[schema(gui(slider(min = 100, max = 2000, step = 100)), suffix = "ms")]
+    pub duration_ms: u64,
+
+    #[schema(strings(
+        help = "Rate of the probe packets. The measured throughput cannot be higher than this"
+    ))]
+    #[schema(gui(slider(min = 10, max = 1000, logarithmic)), suffix = "Mbps")]
+    pub max_bitrate_mbps: u64,
+}
+
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
 pub struct ConnectionDesc {
+    #[schema(strings(help = r#"Network: clients connect through the network.
+Local IPC: only clients running on this machine can connect, like desktop preview clients. Unix domain sockets are used instead of network ports, so client discovery and manual IPs are ignored. Not supported on Windows."#))]
+    pub transport: ConnectionTransport,
+
     pub client_discovery: Switch<DiscoveryConfig>,
 
     pub web_server_port: u16,
 
     #[schema(strings(
         help = r#"UDP: Faster, but less stable than TCP. Try this if your network is well optimized and free of interference.
-TCP: Slower than UDP, but more stable. Pick this if you experience video or audio stutters with UDP."#
+TCP: Slower than UDP, but more stable. Pick this if you experience video or audio stutters with UDP.
+QUIC: Video and audio are sent like UDP, other streams like TCP. Adds congestion control and keeps the connection alive when the network changes."#
     ))]
     pub stream_protocol: SocketProtocol,
 
@@ -718,10 +811,42 @@ TCP: Slower than UDP, but more stable. Pick this if you experience video or audi
     #[schema(gui(slider(min = 1024, max = 65507, logarithmic)), suffix = "B")]
     pub packet_size: i32,
 
+    #[schema(strings(
+        display_name = "Forward error correction",
+        help = "Send redundant data with each packet so that a few lost UDP packets can be rebuilt by the receiver instead of causing a keyframe request"
+    ))]
+    pub forward_error_correction: Switch<ForwardErrorCorrectionConfig>,
+
+    #[schema(strings(
+        help = "Request lost shards again to the sender. Applies only to audio, haptics and statistics streams"
+    ))]
+    pub packet_retransmission: Switch<RetransmissionConfig>,
+
+    #[schema(strings(
+        help = "Number of consecutive packets that can be reassembled at the same time. Higher values tolerate more packet reordering, but delay the detection of packet loss"
+    ))]
+    #[schema(gui(slider(min = 1, max = 16)), suffix = " packets")]
+    pub reorder_window_size: u64,
+
+    #[schema(strings(
+        help = "Send video shards at a steady rate, based on the current bitrate, instead of all at once. Helps with access points that drop packets on bursts"
+    ))]
+    pub video_pacing: Switch<PacingConfig>,
+
+    #[schema(strings(
+        help = "Measure the link capacity the first time a client starts streaming, by sending padded packets before the video. The adaptive bitrate starts from the measured throughput instead of 30 Mbps, unless the link is faster than the probe rate"
+    ))]
+    pub bandwidth_probing: Switch<BandwidthProbingConfig>,
+
     #[schema(suffix = " frames")]
     pub statistics_history_size: u64,
 
     pub disconnection_criteria: Switch<DisconnectionCriteria>,
+
+    #[schema(strings(
+        help = "Let clients connect while another client is streaming. Spectators receive the same video and audio, but their tracking and input are ignored. The primary role can be handed over from the Connections tab. Spectator streams use the ports following the stream port"
+    ))]
+    pub spectators: Switch<SpectatorsConfig>,
 }
 
 #[derive(SettingsSchema, Serialize, Deserialize, Clone)]
@@ -747,6 +872,11 @@ pub struct ExtraDesc {
     pub log_haptics: bool,
     pub save_video_stream: bool,
 
+    #[schema(strings(
+        help = "Record the stream packets received by server and client into stream_capture.bin, in the log folder of the server and in the configuration folder of the client. Captures can be replayed with alvr_stream_replay."
+    ))]
+    pub capture_stream: bool,
+
     #[schema(strings(
         help = r#"This controls the driver registration operations while launching SteamVR.
 Unregister other drivers at startup: This is the recommended option and will handle most interferences from other installed drivers.
@@ -869,6 +999,7 @@ pub fn session_settings_default() -> SettingsDefault {
                     },
                 },
             },
+            drop_stale_frames_on_idr: true,
             advanced_codec_options: AdvancedCodecOptionsDefault {
                 nvenc_overrides: NvencOverridesDefault {
                     nvenc_quality_preset: EncoderQualityPresetNvidiaDefault {
@@ -1051,10 +1182,19 @@ pub fn session_settings_default() -> SettingsDefault {
             },
         },
         connection: ConnectionDescDefault {
+            transport: ConnectionTransportDefault {
+                LocalIpc: ConnectionTransportLocalIpcDefault {
+                    auto_trust_clients: false,
+                },
+                variant: ConnectionTransportDefaultVariant::Network,
+            },
             client_discovery: SwitchDefault {
                 enabled: true,
                 content: DiscoveryConfigDefault {
                     auto_trust_clients: cfg!(debug_assertions),
+                    protocol: DiscoveryProtocolDefault {
+                        variant: DiscoveryProtocolDefaultVariant::Both,
+                    },
                 },
             },
             web_server_port: 8082,
@@ -1070,6 +1210,33 @@ pub fn session_settings_default() -> SettingsDefault {
             on_connect_script: "".into(),
             on_disconnect_script: "".into(),
             packet_size: 1400,
+            forward_error_correction: SwitchDefault {
+                enabled: false,
+                content: ForwardErrorCorrectionConfigDefault {
+                    redundancy_ratio: 0.1,
+                },
+            },
+            packet_retransmission: SwitchDefault {
+                enabled: false,
+                content: RetransmissionConfigDefault {
+                    deadline_ms: 10,
+                    history_size: 256,
+                },
+            },
+            reorder_window_size: 4,
+            video_pacing: SwitchDefault {
+                enabled: false,
+                content: PacingConfigDefault {
+                    frame_interval_fraction: 0.5,
+                },
+            },
+            bandwidth_probing: SwitchDefault {
+                enabled: false,
+                content: BandwidthProbingConfigDefault {
+                    duration_ms: 500,
+                    max_bitrate_mbps: 200,
+                },
+            },
             statistics_history_size: 256,
             disconnection_criteria: SwitchDefault {
                 enabled: false,
@@ -1078,12 +1245,17 @@ pub fn session_settings_default() -> SettingsDefault {
                     sustain_duration_s: 3,
                 },
             },
+            spectators: SwitchDefault {
+                enabled: false,
+                content: SpectatorsConfigDefault { max_count: 2 },
+            },
         },
         extra: ExtraDescDefault {
             log_to_disk: cfg!(debug_assertions),
             log_button_presses: false,
             log_haptics: false,
             save_video_stream: false,
+            capture_stream: false,
             driver_launch_action: DriverLaunchActionDefault {
                 variant: DriverLaunchActionDefaultVariant::UnregisterOtherDriversAtStartup,
             },
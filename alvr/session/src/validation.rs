@@ -0,0 +1,305 @@
+// Validation of the session settings against the constraints declared in the schema. Numbers must
+// match their type and stay in the range of their slider, choices must select one of their
+// variants. Only the branches in use are checked: unselected variants and disabled switches can
+// contain anything, they are checked again when selected.
+
+use crate::{settings, Settings};
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+use settings_schema::{NumberType, NumericGuiType, SchemaNode};
+use std::fmt::{self, Display};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SettingsViolation {
+    // Path from the root of the session, like "session_settings.video.preferred_fps"
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for SettingsViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn push_violation(violations: &mut Vec<SettingsViolation>, path: &str, message: String) {
+    violations.push(SettingsViolation {
+        path: path.to_owned(),
+        message,
+    })
+}
+
+fn validate_node(
+    schema: &SchemaNode,
+    value: &json::Value,
+    path: &str,
+    violations: &mut Vec<SettingsViolation>,
+) {
+    match schema {
+        SchemaNode::Section(entries) => {
+            for entry in entries {
+                if let Some(entry_value) = value.get(&entry.name) {
+                    validate_node(
+                        &entry.content,
+                        entry_value,
+                        &format!("{path}.{}", entry.name),
+                        violations,
+                    );
+                } else {
+                    push_violation(
+                        violations,
+                        path,
+                        format!("missing field \"{}\"", entry.name),
+                    );
+                }
+            }
+        }
+        SchemaNode::Choice { variants, .. } => {
+            let Some(variant) = value.get("variant").and_then(|v| v.as_str()) else {
+                push_violation(violations, path, "missing selected variant".into());
+                return;
+            };
+
+            if let Some(entry) = variants.iter().find(|entry| entry.name == variant) {
+                if let Some(content) = &entry.content {
+                    if let Some(content_value) = value.get(variant) {
+                        validate_node(
+                            content,
+                            content_value,
+                            &format!("{path}.{variant}"),
+                            violations,
+                        );
+                    } else {
+                        push_violation(
+                            violations,
+                            path,
+                            format!("missing content of variant \"{variant}\""),
+                        );
+                    }
+                }
+            } else {
+                let names = variants
+                    .iter()
+                    .map(|entry| entry.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                push_violation(
+                    violations,
+                    path,
+                    format!("unknown variant \"{variant}\", expected one of: {names}"),
+                );
+            }
+        }
+        SchemaNode::Optional { content, .. } => {
+            if value.get("set").and_then(|v| v.as_bool()) == Some(true) {
+                validate_node(
+                    content,
+                    &value["content"],
+                    &format!("{path}.content"),
+                    violations,
+                );
+            }
+        }
+        SchemaNode::Switch { content, .. } => {
+            if value.get("enabled").and_then(|v| v.as_bool()) == Some(true) {
+                validate_node(
+                    content,
+                    &value["content"],
+                    &format!("{path}.content"),
+                    violations,
+                );
+            }
+        }
+        SchemaNode::Number { ty, gui, .. } => {
+            let number = match ty {
+                NumberType::UnsignedInteger => value.as_u64().map(|n| n as f64),
+                NumberType::SignedInteger => value.as_i64().map(|n| n as f64),
+                NumberType::Float => value.as_f64(),
+            };
+            let Some(number) = number else {
+                let expected = match ty {
+                    NumberType::UnsignedInteger => "a non negative integer",
+                    NumberType::SignedInteger => "an integer",
+                    NumberType::Float => "a number",
+                };
+                push_violation(
+                    violations,
+                    path,
+                    format!("expected {expected}, found {value}"),
+                );
+                return;
+            };
+
+            if let NumericGuiType::Slider { range, .. } = gui {
+                if !range.contains(&number) {
+                    push_violation(
+                        violations,
+                        path,
+                        format!(
+                            "{value} is out of range [{}, {}]",
+                            range.start(),
+                            range.end()
+                        ),
+                    );
+                }
+            }
+        }
+        SchemaNode::Array(schema_array) => {
+            for (index, element_schema) in schema_array.iter().enumerate() {
+                if let Some(element) = value.get(index) {
+                    validate_node(
+                        element_schema,
+                        element,
+                        &format!("{path}[{index}]"),
+                        violations,
+                    );
+                } else {
+                    push_violation(violations, path, format!("missing element [{index}]"));
+                }
+            }
+        }
+        SchemaNode::Vector {
+            default_element, ..
+        } => {
+            if let Some(content) = value.get("content").and_then(|v| v.as_array()) {
+                for (index, element) in content.iter().enumerate() {
+                    validate_node(
+                        default_element,
+                        element,
+                        &format!("{path}.content[{index}]"),
+                        violations,
+                    );
+                }
+            }
+        }
+        // Booleans and strings are checked by deserialization
+        _ => (),
+    }
+}
+
+// Returns an empty list if the session settings, in json format, are valid
+pub fn validate_session_settings_json(
+    session_settings_json: &json::Value,
+) -> Vec<SettingsViolation> {
+    let schema = Settings::schema(settings::session_settings_default());
+
+    let mut violations = vec![];
+    validate_node(
+        &schema,
+        session_settings_json,
+        "session_settings",
+        &mut violations,
+    );
+
+    violations
+}
+
+// Converts a violation path to a json pointer relative to the session settings
+fn violation_pointer(path: &str) -> String {
+    path.trim_start_matches("session_settings")
+        .replace('.', "/")
+        .replace('[', "/")
+        .replace(']', "")
+}
+
+// Resets the values that violate the schema to their defaults. Elements of vectors have no default,
+// so the whole vector is reset. Returns the violations that were repaired
+pub fn repair_session_settings_json(
+    session_settings_json: &mut json::Value,
+) -> Vec<SettingsViolation> {
+    let violations = validate_session_settings_json(session_settings_json);
+    if violations.is_empty() {
+        return violations;
+    }
+
+    let default_json = json::to_value(settings::session_settings_default()).unwrap();
+    for violation in &violations {
+        let mut pointer = violation_pointer(&violation.path);
+        loop {
+            if let (Some(value), Some(default)) = (
+                session_settings_json.pointer_mut(&pointer),
+                default_json.pointer(&pointer),
+            ) {
+                *value = default.clone();
+                break;
+            }
+
+            // The empty pointer is the root, which always exists
+            pointer = pointer
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_owned())
+                .unwrap_or_default();
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_session_is_valid() {
+        let session_settings_json = json::to_value(settings::session_settings_default()).unwrap();
+
+        assert_eq!(
+            validate_session_settings_json(&session_settings_json),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut session_settings_json =
+            json::to_value(settings::session_settings_default()).unwrap();
+        session_settings_json["video"]["preferred_fps"] = json::json!(0.0);
+        session_settings_json["video"]["color_correction"]["content"]["gamma"] = json::json!(0.0);
+        session_settings_json["connection"]["stream_protocol"]["variant"] = json::json!("Serial");
+        session_settings_json["connection"]["server_send_buffer_bytes"] = json::json!({
+            "variant": "Custom",
+            "Custom": -1
+        });
+
+        let paths = validate_session_settings_json(&session_settings_json)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            paths,
+            vec![
+                "session_settings.video.preferred_fps",
+                "session_settings.video.color_correction.content.gamma",
+                "session_settings.connection.stream_protocol",
+                "session_settings.connection.server_send_buffer_bytes.Custom",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        let default_json = json::to_value(settings::session_settings_default()).unwrap();
+
+        let mut session_settings_json = default_json.clone();
+        session_settings_json["video"]["preferred_fps"] = json::json!(0.0);
+        session_settings_json["video"]["max_buffering_frames"] = json::json!(4.0);
+        session_settings_json["connection"]["stream_protocol"]["variant"] = json::json!("Serial");
+        session_settings_json["headset"]["extra_openvr_props"]["content"] = json::json!([{
+            "key": { "variant": "NotAProperty" },
+            "value": { "variant": "String", "String": "" }
+        }]);
+
+        let violations = repair_session_settings_json(&mut session_settings_json);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            validate_session_settings_json(&session_settings_json),
+            vec![]
+        );
+
+        // Valid values are kept
+        let mut expected_json = default_json;
+        expected_json["video"]["max_buffering_frames"] = json::json!(4.0);
+        assert_eq!(session_settings_json, expected_json);
+    }
+}
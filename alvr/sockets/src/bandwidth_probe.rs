@@ -0,0 +1,173 @@
+// Measurement of the link capacity at the start of the stream. Before the video, the server sends
+// padded bursts on the BANDWIDTH_PROBE stream, one per frame interval like video frames. The client
+// measures the achieved throughput and the packet loss and reports them to the server, which seeds
+// the adaptive bitrate.
+//
+// If the link is faster than the probe rate, the bursts arrive one frame interval apart and the
+// throughput is the probe rate. Otherwise the bursts queue up in the network and arrive at the link
+// capacity.
+//
+// The link is probed once per client, the stream resumes and reconnections are not probed.
+
+use alvr_session::BandwidthProbeResult;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Tolerance for the jitter of the arrival times
+const SATURATION_FRACTION: f32 = 0.9;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct BandwidthProbeHeader {
+    pub index: u32,
+    pub bursts_count: u32,
+}
+
+// Returns the number of bursts and the size in bytes of each burst needed to send at
+// `bitrate_bps` for `duration`. At least two bursts are needed to measure the throughput.
+pub fn bandwidth_probe_bursts(
+    duration: Duration,
+    frame_interval: Duration,
+    bitrate_bps: f32,
+) -> (u32, usize) {
+    let bursts_count = f32::max(
+        (duration.as_secs_f32() / frame_interval.as_secs_f32()).ceil(),
+        2.0,
+    );
+    let burst_bytes = bitrate_bps / 8.0 * frame_interval.as_secs_f32();
+
+    (bursts_count as u32, burst_bytes as usize)
+}
+
+// A throughput close to the probe rate means that the link capacity is higher than the probe rate,
+// so it is unknown
+pub fn is_probe_saturated(result: &BandwidthProbeResult, probe_bitrate_bps: f32) -> bool {
+    result.throughput_bps >= probe_bitrate_bps * SATURATION_FRACTION
+}
+
+#[derive(Default)]
+pub struct BandwidthProbeMeter {
+    bursts_count: u32,
+    received_count: u32,
+    max_index: Option<u32>,
+    first_receive_instant: Option<Instant>,
+    last_receive_instant: Option<Instant>,
+    // The first burst marks the start of the measurement, so its bytes are not counted
+    measured_bytes: usize,
+}
+
+impl BandwidthProbeMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report_burst(&mut self, header: BandwidthProbeHeader, size_bytes: usize, now: Instant) {
+        self.bursts_count = header.bursts_count;
+        self.received_count += 1;
+        self.max_index = Some(u32::max(self.max_index.unwrap_or(0), header.index));
+
+        if self.first_receive_instant.is_none() {
+            self.first_receive_instant = Some(now);
+        } else {
+            self.measured_bytes += size_bytes;
+        }
+        self.last_receive_instant = Some(now);
+    }
+
+    // The probe timeout should start only after the first burst, which is not sent if the link
+    // was already probed
+    pub fn is_started(&self) -> bool {
+        self.first_receive_instant.is_some()
+    }
+
+    // True once the last burst is received. If it is lost, the caller should stop waiting after a
+    // timeout.
+    pub fn is_complete(&self) -> bool {
+        self.max_index
+            .map(|index| index + 1 >= self.bursts_count)
+            .unwrap_or(false)
+    }
+
+    // None if less than two bursts were received
+    pub fn result(&self) -> Option<BandwidthProbeResult> {
+        let duration = self.last_receive_instant? - self.first_receive_instant?;
+        if duration.is_zero() {
+            return None;
+        }
+
+        // The burst count comes from the peer, it cannot be trusted to be consistent
+        let bursts_count = u32::max(self.bursts_count, self.received_count);
+
+        Some(BandwidthProbeResult {
+            throughput_bps: self.measured_bytes as f32 * 8.0 / duration.as_secs_f32(),
+            packet_loss: 1.0 - self.received_count as f32 / bursts_count as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_bursts() {
+        let (count, bytes) =
+            bandwidth_probe_bursts(Duration::from_millis(500), FRAME_INTERVAL, 100e6);
+        assert_eq!(count, 50);
+        assert_eq!(bytes, 125_000);
+
+        let (count, _) = bandwidth_probe_bursts(Duration::ZERO, FRAME_INTERVAL, 100e6);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_meter() {
+        let start = Instant::now();
+        let mut meter = BandwidthProbeMeter::new();
+        assert!(meter.result().is_none());
+        assert!(!meter.is_started());
+
+        // Burst 2 is lost, the others arrive 20ms apart: the link is slower than the probe rate
+        for (index, arrival_ms) in [(0, 0), (1, 20), (3, 40)] {
+            meter.report_burst(
+                BandwidthProbeHeader {
+                    index,
+                    bursts_count: 4,
+                },
+                100_000,
+                start + Duration::from_millis(arrival_ms),
+            );
+        }
+        assert!(meter.is_started());
+        assert!(meter.is_complete());
+
+        let result = meter.result().unwrap();
+        assert!((result.throughput_bps - 40e6).abs() < 1e3);
+        assert!((result.packet_loss - 0.25).abs() < 1e-6);
+
+        // The probe rate was 80 Mbps
+        assert!(!is_probe_saturated(&result, 80e6));
+        assert!(is_probe_saturated(&result, 40e6));
+        assert!(is_probe_saturated(&result, 42e6));
+    }
+
+    #[test]
+    fn test_invalid_bursts_count() {
+        let start = Instant::now();
+        let mut meter = BandwidthProbeMeter::new();
+
+        for (index, arrival_ms) in [(0, 0), (1, 10)] {
+            meter.report_burst(
+                BandwidthProbeHeader {
+                    index,
+                    bursts_count: 0,
+                },
+                100_000,
+                start + Duration::from_millis(arrival_ms),
+            );
+        }
+
+        assert_eq!(meter.result().unwrap().packet_loss, 0.0);
+    }
+}
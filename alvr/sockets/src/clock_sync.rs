@@ -0,0 +1,155 @@
+// Round trip time and clock offset measurement, NTP-style.
+// Each peer timestamps packets with its own monotonic clock, which starts when its ClockSync is
+// created. A ping carries the timestamp of the sender (t0). The peer answers with a pong containing
+// t0, the time it received the ping (t1) and the time it sent the pong (t2). When the pong is
+// received (t3):
+// round trip time = (t3 - t0) - (t2 - t1)
+// clock offset = ((t1 - t0) + (t2 - t3)) / 2
+// The offset is exact only if the network latency is the same in both directions. Samples with a
+// higher round trip time were likely delayed in one direction, so the offset of the sample with
+// the lowest round trip time in the history is used.
+
+use crate::PongPacket;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const MAX_HISTORY_SIZE: usize = 16;
+
+struct Sample {
+    round_trip_time: Duration,
+    // peer clock minus local clock, in nanoseconds
+    clock_offset_ns: i128,
+}
+
+pub struct ClockSync {
+    epoch: Instant,
+    samples: VecDeque<Sample>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    // Timestamp of `instant` in the local clock
+    pub fn timestamp(&self, instant: Instant) -> Duration {
+        instant.saturating_duration_since(self.epoch)
+    }
+
+    pub fn now(&self) -> Duration {
+        self.timestamp(Instant::now())
+    }
+
+    pub fn answer_ping(
+        &self,
+        origin_timestamp: Duration,
+        receive_timestamp: Duration,
+    ) -> PongPacket {
+        PongPacket {
+            origin_timestamp,
+            receive_timestamp,
+            transmit_timestamp: self.now(),
+        }
+    }
+
+    pub fn report_pong(&mut self, pong: PongPacket, receive_timestamp: Duration) {
+        let t0 = pong.origin_timestamp.as_nanos() as i128;
+        let t1 = pong.receive_timestamp.as_nanos() as i128;
+        let t2 = pong.transmit_timestamp.as_nanos() as i128;
+        let t3 = receive_timestamp.as_nanos() as i128;
+
+        let round_trip_time_ns = (t3 - t0) - (t2 - t1);
+
+        if self.samples.len() >= MAX_HISTORY_SIZE {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(Sample {
+            round_trip_time: Duration::from_nanos(round_trip_time_ns.max(0) as u64),
+            clock_offset_ns: ((t1 - t0) + (t2 - t3)) / 2,
+        });
+    }
+
+    // Average round trip time. None if no pong has been received yet
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        (!self.samples.is_empty()).then(|| {
+            self.samples
+                .iter()
+                .map(|sample| sample.round_trip_time)
+                .sum::<Duration>()
+                / self.samples.len() as u32
+        })
+    }
+
+    // Peer clock minus local clock, in nanoseconds
+    fn clock_offset_ns(&self) -> Option<i128> {
+        self.samples
+            .iter()
+            .min_by_key(|sample| sample.round_trip_time)
+            .map(|sample| sample.clock_offset_ns)
+    }
+
+    // Convert a timestamp of the peer clock to the local clock. None if no pong has been received
+    // yet
+    pub fn peer_to_local(&self, peer_timestamp: Duration) -> Option<Duration> {
+        self.clock_offset_ns().map(|offset_ns| {
+            let local_ns = peer_timestamp.as_nanos() as i128 - offset_ns;
+
+            Duration::from_nanos(local_ns.max(0) as u64)
+        })
+    }
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_clock_sync() {
+        let mut clock_sync = ClockSync::new();
+        assert!(clock_sync.round_trip_time().is_none());
+        assert!(clock_sync.peer_to_local(ms(100)).is_none());
+
+        // The peer clock is 1000ms ahead. 5ms latency in each direction, 2ms to answer
+        clock_sync.report_pong(
+            PongPacket {
+                origin_timestamp: ms(10),
+                receive_timestamp: ms(1015),
+                transmit_timestamp: ms(1017),
+            },
+            ms(22),
+        );
+        assert_eq!(clock_sync.round_trip_time(), Some(ms(10)));
+        assert_eq!(clock_sync.peer_to_local(ms(1100)), Some(ms(100)));
+
+        // The pong was delayed by 20ms: the offset of the first sample is kept
+        clock_sync.report_pong(
+            PongPacket {
+                origin_timestamp: ms(30),
+                receive_timestamp: ms(1035),
+                transmit_timestamp: ms(1035),
+            },
+            ms(60),
+        );
+        assert_eq!(clock_sync.round_trip_time(), Some(ms(20)));
+        assert_eq!(clock_sync.peer_to_local(ms(1100)), Some(ms(100)));
+
+        // Timestamps before the local epoch saturate
+        assert_eq!(clock_sync.peer_to_local(ms(500)), Some(Duration::ZERO));
+    }
+}
@@ -1,29 +1,85 @@
-use super::{Ldc, CONTROL_PORT, LOCAL_IP};
+use super::{Ldc, CONTROL_PORT};
+use crate::crypto::{
+    self, ChannelKeys, ClientHandshakePacket, PacketCipher, PairingKey, ServerHandshakePacket,
+    SessionKeys,
+};
 use alvr_common::prelude::*;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use std::{marker::PhantomData, net::IpAddr};
-use tokio::net::{TcpListener, TcpStream};
+use std::{future::Future, marker::PhantomData, net::IpAddr};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
 use tokio_util::codec::Framed;
 
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+// TCP stream, or Unix domain socket stream for local IPC
+trait ControlStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> ControlStream for T {}
+
+type ControlFramed = Framed<Box<dyn ControlStream>, Ldc>;
+
+// Control packets are delivered in order, so the nonce is just a counter
+struct ControlCipher {
+    cipher: PacketCipher,
+    next_packet_index: u64,
+}
+
+impl ControlCipher {
+    fn new(cipher: PacketCipher) -> Self {
+        Self {
+            cipher,
+            next_packet_index: 0,
+        }
+    }
+
+    fn nonce(&mut self) -> ring::aead::Nonce {
+        let nonce = crypto::packet_nonce(0, self.next_packet_index);
+        self.next_packet_index += 1;
+
+        nonce
+    }
+
+    fn seal(&mut self, packet_bytes: Vec<u8>) -> StrResult<Bytes> {
+        let mut buffer = BytesMut::from(packet_bytes.as_slice());
+        let nonce = self.nonce();
+        self.cipher.seal(nonce, &[], &mut buffer)?;
+
+        Ok(buffer.freeze())
+    }
+
+    fn open(&mut self, mut buffer: BytesMut) -> StrResult<BytesMut> {
+        let nonce = self.nonce();
+        self.cipher.open(nonce, &[], &mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
 pub struct ControlSocketSender<T> {
-    inner: SplitSink<Framed<TcpStream, Ldc>, Bytes>,
+    inner: SplitSink<ControlFramed, Bytes>,
+    cipher: ControlCipher,
     _phantom: PhantomData<T>,
 }
 
 impl<S: Serialize> ControlSocketSender<S> {
     pub async fn send(&mut self, packet: &S) -> StrResult {
         let packet_bytes = bincode::serialize(packet).map_err(err!())?;
-        self.inner.send(packet_bytes.into()).await.map_err(err!())
+        let packet_bytes = self.cipher.seal(packet_bytes)?;
+        self.inner.send(packet_bytes).await.map_err(err!())
     }
 }
 
 pub struct ControlSocketReceiver<T> {
-    inner: SplitStream<Framed<TcpStream, Ldc>>,
+    inner: SplitStream<ControlFramed>,
+    cipher: ControlCipher,
     _phantom: PhantomData<T>,
 }
 
@@ -35,55 +91,108 @@ impl<R: DeserializeOwned> ControlSocketReceiver<R> {
             .await
             .ok_or_else(enone!())?
             .map_err(err!())?;
+        let packet_bytes = self.cipher.open(packet_bytes)?;
         bincode::deserialize(&packet_bytes).map_err(err!())
     }
 }
 
 pub async fn get_server_listener() -> StrResult<TcpListener> {
-    TcpListener::bind((LOCAL_IP, CONTROL_PORT))
-        .await
-        .map_err(err!())
+    let socket = crate::bind_all_interfaces(socket2::Type::STREAM, CONTROL_PORT)?;
+    socket.listen(1024).map_err(err!())?;
+    socket.set_nonblocking(true).map_err(err!())?;
+
+    TcpListener::from_std(socket.into()).map_err(err!())
+}
+
+// Used by clients on the same machine as the streamer, in place of get_server_listener()
+#[cfg(unix)]
+pub async fn get_local_server_listener(hostname: &str) -> StrResult<UnixListener> {
+    crate::bind_local_socket(&crate::local_control_socket_path(hostname))
+}
+
+fn tcp_control_stream(socket: TcpStream) -> StrResult<(Box<dyn ControlStream>, IpAddr)> {
+    socket.set_nodelay(true).map_err(err!())?;
+    let peer_ip = crate::canonical_ip(socket.peer_addr().map_err(err!())?.ip());
+
+    Ok((Box::new(socket), peer_ip))
 }
 
 // Proto-control-socket that can send and receive any packet. After the split, only the packets of
-// the specified types can be exchanged
+// the specified types can be exchanged. Packets are sent in clear until the peers authenticate
+// each other, then they are encrypted.
 pub struct ProtoControlSocket {
-    inner: Framed<TcpStream, Ldc>,
+    inner: ControlFramed,
+    // send, receive
+    ciphers: Option<(ControlCipher, ControlCipher)>,
 }
 
 pub enum PeerType<'a> {
     AnyClient(Vec<IpAddr>),
     Server(&'a TcpListener),
+    // Client on the same machine, identified by its hostname
+    LocalClient(&'a str),
+    #[cfg(unix)]
+    LocalServer(&'a UnixListener),
 }
 
 impl ProtoControlSocket {
+    // Local peers are reported with the loopback IP
     pub async fn connect_to(peer: PeerType<'_>) -> StrResult<(Self, IpAddr)> {
-        let socket = match peer {
+        let (socket, peer_ip): (Box<dyn ControlStream>, _) = match peer {
             PeerType::AnyClient(ips) => {
                 let client_addresses = ips
                     .iter()
                     .map(|&ip| (ip, CONTROL_PORT).into())
                     .collect::<Vec<_>>();
-                TcpStream::connect(client_addresses.as_slice())
+                let socket = TcpStream::connect(client_addresses.as_slice())
                     .await
-                    .map_err(err!())?
+                    .map_err(err!())?;
+
+                tcp_control_stream(socket)?
             }
             PeerType::Server(listener) => {
                 let (socket, _) = listener.accept().await.map_err(err!())?;
-                socket
+
+                tcp_control_stream(socket)?
             }
-        };
+            #[cfg(unix)]
+            PeerType::LocalClient(hostname) => {
+                let socket = UnixStream::connect(crate::local_control_socket_path(hostname))
+                    .await
+                    .map_err(err!())?;
+
+                (Box::new(socket), IpAddr::from([127, 0, 0, 1]))
+            }
+            #[cfg(not(unix))]
+            PeerType::LocalClient(_) => {
+                return fmt_e!("Local IPC is supported only on Unix platforms");
+            }
+            #[cfg(unix)]
+            PeerType::LocalServer(listener) => {
+                let (socket, _) = listener.accept().await.map_err(err!())?;
 
-        socket.set_nodelay(true).map_err(err!())?;
-        let peer_ip = socket.peer_addr().map_err(err!())?.ip();
+                (Box::new(socket), IpAddr::from([127, 0, 0, 1]))
+            }
+        };
         let socket = Framed::new(socket, Ldc::new());
 
-        Ok((Self { inner: socket }, peer_ip))
+        Ok((
+            Self {
+                inner: socket,
+                ciphers: None,
+            },
+            peer_ip,
+        ))
     }
 
     pub async fn send<S: Serialize>(&mut self, packet: &S) -> StrResult {
         let packet_bytes = bincode::serialize(packet).map_err(err!())?;
-        self.inner.send(packet_bytes.into()).await.map_err(err!())
+        let packet_bytes = if let Some((send_cipher, _)) = &mut self.ciphers {
+            send_cipher.seal(packet_bytes)?
+        } else {
+            packet_bytes.into()
+        };
+        self.inner.send(packet_bytes).await.map_err(err!())
     }
 
     pub async fn recv<R: DeserializeOwned>(&mut self) -> StrResult<R> {
@@ -93,23 +202,252 @@ impl ProtoControlSocket {
             .await
             .ok_or_else(enone!())?
             .map_err(err!())?;
+        let packet_bytes = if let Some((_, receive_cipher)) = &mut self.ciphers {
+            receive_cipher.open(packet_bytes)?
+        } else {
+            packet_bytes
+        };
         bincode::deserialize(&packet_bytes).map_err(err!())
     }
 
+    // Called by the server. If the client is not paired yet, a new pairing key is agreed and
+    // `confirm_pairing` is called with the pairing code, which must be shown to the user. The
+    // pairing fails unless the user confirms on both sides. Returns the pairing key and the keys for
+    // the stream socket.
+    pub async fn authenticate_client<F: Future<Output = bool>>(
+        &mut self,
+        pairing_key: Option<PairingKey>,
+        confirm_pairing: impl FnOnce(u32) -> F,
+    ) -> StrResult<(PairingKey, ChannelKeys)> {
+        let (pairing_key, session_keys) = if let Some(pairing_key) = pairing_key {
+            let nonce = crypto::random_nonce()?;
+            self.send(&ServerHandshakePacket::Authenticate {
+                key_id: pairing_key.id(),
+                nonce,
+            })
+            .await?;
+
+            match self.recv::<ClientHandshakePacket>().await? {
+                ClientHandshakePacket::Authenticate {
+                    nonce: client_nonce,
+                } => (
+                    pairing_key,
+                    SessionKeys::derive(&pairing_key, &nonce, &client_nonce, true)?,
+                ),
+                ClientHandshakePacket::UnknownPairingKey => {
+                    return fmt_e!(
+                        "The client does not know the pairing key. Remove the client and trust it again"
+                    );
+                }
+                _ => return fmt_e!("Unexpected handshake packet"),
+            }
+        } else {
+            let (private_key, public_key) = crypto::generate_key_pair()?;
+            self.send(&ServerHandshakePacket::Pair {
+                public_key_hash: crypto::public_key_hash(&public_key),
+            })
+            .await?;
+
+            let client_public_key = match self.recv::<ClientHandshakePacket>().await? {
+                ClientHandshakePacket::Pair { public_key } => public_key,
+                _ => return fmt_e!("Unexpected handshake packet"),
+            };
+
+            self.send(&ServerHandshakePacket::RevealPublicKey {
+                public_key: public_key.clone(),
+            })
+            .await?;
+
+            let pairing_key = crypto::agree_pairing_key(private_key, &client_public_key)?;
+
+            let confirmed = confirm_pairing(pairing_key.pairing_code()).await;
+            self.send(&ServerHandshakePacket::PairingConfirmed(confirmed))
+                .await?;
+            if !confirmed {
+                return fmt_e!("Pairing rejected");
+            }
+            match self.recv::<ClientHandshakePacket>().await? {
+                ClientHandshakePacket::PairingConfirmed(true) => (),
+                ClientHandshakePacket::PairingConfirmed(false) => {
+                    return fmt_e!("Pairing rejected on the client");
+                }
+                _ => return fmt_e!("Unexpected handshake packet"),
+            }
+
+            (
+                pairing_key,
+                SessionKeys::derive(&pairing_key, &public_key, &client_public_key, true)?,
+            )
+        };
+
+        self.ciphers = Some((
+            ControlCipher::new(session_keys.control.send),
+            ControlCipher::new(session_keys.control.receive),
+        ));
+
+        Ok((pairing_key, session_keys.stream))
+    }
+
+    // Called by the client. If the server starts a new pairing, `confirm_pairing` is called like in
+    // authenticate_client(). Returns the pairing key, which should be stored if new, and the keys
+    // for the stream socket.
+    pub async fn authenticate_server<F: Future<Output = bool>>(
+        &mut self,
+        known_pairing_keys: &[PairingKey],
+        confirm_pairing: impl FnOnce(u32) -> F,
+    ) -> StrResult<(PairingKey, ChannelKeys)> {
+        let (pairing_key, session_keys) = match self.recv::<ServerHandshakePacket>().await? {
+            ServerHandshakePacket::Authenticate { key_id, nonce } => {
+                let pairing_key =
+                    if let Some(key) = known_pairing_keys.iter().find(|key| key.id() == key_id) {
+                        *key
+                    } else {
+                        self.send(&ClientHandshakePacket::UnknownPairingKey).await?;
+
+                        return fmt_e!("The streamer uses an unknown pairing key");
+                    };
+
+                let client_nonce = crypto::random_nonce()?;
+                self.send(&ClientHandshakePacket::Authenticate {
+                    nonce: client_nonce,
+                })
+                .await?;
+
+                (
+                    pairing_key,
+                    SessionKeys::derive(&pairing_key, &nonce, &client_nonce, false)?,
+                )
+            }
+            ServerHandshakePacket::Pair { public_key_hash } => {
+                let (private_key, public_key) = crypto::generate_key_pair()?;
+                self.send(&ClientHandshakePacket::Pair {
+                    public_key: public_key.clone(),
+                })
+                .await?;
+
+                let server_public_key = match self.recv::<ServerHandshakePacket>().await? {
+                    ServerHandshakePacket::RevealPublicKey { public_key } => public_key,
+                    _ => return fmt_e!("Unexpected handshake packet"),
+                };
+                if crypto::public_key_hash(&server_public_key) != public_key_hash {
+                    return fmt_e!("The streamer public key does not match its commitment");
+                }
+
+                let pairing_key = crypto::agree_pairing_key(private_key, &server_public_key)?;
+
+                let confirmed = confirm_pairing(pairing_key.pairing_code()).await;
+                self.send(&ClientHandshakePacket::PairingConfirmed(confirmed))
+                    .await?;
+                if !confirmed {
+                    return fmt_e!("Pairing rejected");
+                }
+                match self.recv::<ServerHandshakePacket>().await? {
+                    ServerHandshakePacket::PairingConfirmed(true) => (),
+                    ServerHandshakePacket::PairingConfirmed(false) => {
+                        return fmt_e!("Pairing rejected on the streamer");
+                    }
+                    _ => return fmt_e!("Unexpected handshake packet"),
+                }
+
+                (
+                    pairing_key,
+                    SessionKeys::derive(&pairing_key, &server_public_key, &public_key, false)?,
+                )
+            }
+            _ => return fmt_e!("Unexpected handshake packet"),
+        };
+
+        self.ciphers = Some((
+            ControlCipher::new(session_keys.control.send),
+            ControlCipher::new(session_keys.control.receive),
+        ));
+
+        Ok((pairing_key, session_keys.stream))
+    }
+
     pub fn split<S: Serialize, R: DeserializeOwned>(
         self,
-    ) -> (ControlSocketSender<S>, ControlSocketReceiver<R>) {
+    ) -> StrResult<(ControlSocketSender<S>, ControlSocketReceiver<R>)> {
+        let (send_cipher, receive_cipher) = self
+            .ciphers
+            .ok_or_else(|| "The control socket is not authenticated".to_owned())?;
         let (sender, receiver) = self.inner.split();
 
-        (
+        Ok((
             ControlSocketSender {
                 inner: sender,
+                cipher: send_cipher,
                 _phantom: PhantomData,
             },
             ControlSocketReceiver {
                 inner: receiver,
+                cipher: receive_cipher,
                 _phantom: PhantomData,
             },
-        )
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn socket_pair() -> (ProtoControlSocket, ProtoControlSocket) {
+        let (server_stream, client_stream) = tokio::io::duplex(4096);
+        let socket = |stream: tokio::io::DuplexStream| ProtoControlSocket {
+            inner: Framed::new(Box::new(stream), Ldc::new()),
+            ciphers: None,
+        };
+
+        (socket(server_stream), socket(client_stream))
+    }
+
+    #[tokio::test]
+    async fn test_pairing() {
+        let (mut server_socket, mut client_socket) = socket_pair();
+
+        let server_code = Cell::new(None);
+        let client_code = Cell::new(None);
+        let (server_res, client_res) = tokio::join!(
+            server_socket.authenticate_client(None, |code| {
+                server_code.set(Some(code));
+                async { true }
+            }),
+            client_socket.authenticate_server(&[], |code| {
+                client_code.set(Some(code));
+                async { true }
+            }),
+        );
+        let (server_pairing_key, _) = server_res.unwrap();
+        let (client_pairing_key, _) = client_res.unwrap();
+        assert!(server_pairing_key == client_pairing_key);
+        assert!(server_code.get().is_some());
+        assert_eq!(server_code.get(), client_code.get());
+
+        server_socket.send(&42_u32).await.unwrap();
+        assert_eq!(client_socket.recv::<u32>().await.unwrap(), 42);
+
+        // The known key is used without asking for confirmation
+        let (mut server_socket, mut client_socket) = socket_pair();
+        let known_pairing_keys = [client_pairing_key];
+        let (server_res, client_res) = tokio::join!(
+            server_socket.authenticate_client(Some(server_pairing_key), |_| async { false }),
+            client_socket.authenticate_server(&known_pairing_keys, |_| async { false }),
+        );
+        assert!(server_res.is_ok());
+        assert!(client_res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pairing_rejected() {
+        let (mut server_socket, mut client_socket) = socket_pair();
+
+        let (server_res, client_res) = tokio::join!(
+            server_socket.authenticate_client(None, |_| async { true }),
+            client_socket.authenticate_server(&[], |_| async { false }),
+        );
+        assert!(server_res.is_err());
+        assert!(client_res.is_err());
     }
 }
@@ -0,0 +1,338 @@
+// Pairing and packet encryption.
+// The first time a trusted client connects, the server and the client run an X25519 key exchange
+// and derive a pairing key. Both show a short pairing code derived from the key, and the key is
+// stored only if the user confirms on both sides that the codes match. The server commits to its
+// public key before seeing the client one, so a man in the middle can't pick its keys to obtain
+// the same code on both sides. At every following connection, the peers exchange
+// random nonces and derive from the pairing key a new set of session keys, one for each channel
+// and direction. Control and stream packets are encrypted and authenticated with
+// ChaCha20-Poly1305. A peer that does not know the pairing key cannot produce valid packets.
+
+use alvr_common::prelude::*;
+use bytes::BytesMut;
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305},
+    agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519},
+    digest, hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, sync::Arc};
+
+pub const PAIRING_KEY_SIZE: usize = 32;
+pub const PACKET_KEY_SIZE: usize = 32;
+pub const TAG_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 32;
+
+const PAIRING_SALT: &[u8] = b"ALVR pairing";
+const PAIRING_CODE_LABEL: &[u8] = b"ALVR pairing code";
+
+// Pairing: Pair (server), Pair (client), RevealPublicKey, then PairingConfirmed from both sides
+#[derive(Serialize, Deserialize)]
+pub enum ServerHandshakePacket {
+    Pair {
+        public_key_hash: Vec<u8>,
+    },
+    RevealPublicKey {
+        public_key: Vec<u8>,
+    },
+    Authenticate {
+        key_id: u64,
+        nonce: [u8; NONCE_SIZE],
+    },
+    PairingConfirmed(bool),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ClientHandshakePacket {
+    Pair { public_key: Vec<u8> },
+    Authenticate { nonce: [u8; NONCE_SIZE] },
+    UnknownPairingKey,
+    PairingConfirmed(bool),
+}
+
+struct KeyLength(usize);
+
+impl hkdf::KeyType for KeyLength {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PairingKey([u8; PAIRING_KEY_SIZE]);
+
+impl PairingKey {
+    // Identifies the key without revealing it. Used by the client to pick the right key when
+    // paired with multiple servers.
+    pub fn id(&self) -> u64 {
+        let hash = digest::digest(&digest::SHA256, &self.0);
+
+        let mut id_bytes = [0; 8];
+        id_bytes.copy_from_slice(&hash.as_ref()[..8]);
+
+        u64::from_le_bytes(id_bytes)
+    }
+
+    // Six digits shown to the user on both sides during pairing. They differ if the key exchange
+    // was intercepted.
+    pub fn pairing_code(&self) -> u32 {
+        let hash = digest::digest(&digest::SHA256, &[PAIRING_CODE_LABEL, &self.0].concat());
+
+        let mut code_bytes = [0; 4];
+        code_bytes.copy_from_slice(&hash.as_ref()[..4]);
+
+        u32::from_le_bytes(code_bytes) % 1_000_000
+    }
+
+    pub fn to_hex_string(self) -> String {
+        self.0.iter().fold(String::new(), |mut string, byte| {
+            write!(string, "{byte:02x}").ok();
+            string
+        })
+    }
+
+    pub fn from_hex_string(string: &str) -> StrResult<Self> {
+        if string.len() != PAIRING_KEY_SIZE * 2 || !string.is_ascii() {
+            return fmt_e!("Invalid pairing key");
+        }
+
+        let mut key = [0; PAIRING_KEY_SIZE];
+        for (index, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&string[index * 2..index * 2 + 2], 16).map_err(err!())?;
+        }
+
+        Ok(Self(key))
+    }
+}
+
+pub fn random_nonce() -> StrResult<[u8; NONCE_SIZE]> {
+    let mut nonce = [0; NONCE_SIZE];
+    SystemRandom::new().fill(&mut nonce).map_err(err!())?;
+
+    Ok(nonce)
+}
+
+pub fn generate_key_pair() -> StrResult<(EphemeralPrivateKey, Vec<u8>)> {
+    let private_key =
+        EphemeralPrivateKey::generate(&X25519, &SystemRandom::new()).map_err(err!())?;
+    let public_key = private_key.compute_public_key().map_err(err!())?;
+
+    Ok((private_key, public_key.as_ref().to_vec()))
+}
+
+// Commitment to a public key, sent before the key itself
+pub fn public_key_hash(public_key: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, public_key)
+        .as_ref()
+        .to_vec()
+}
+
+pub fn agree_pairing_key(
+    private_key: EphemeralPrivateKey,
+    peer_public_key: &[u8],
+) -> StrResult<PairingKey> {
+    agreement::agree_ephemeral(
+        private_key,
+        &UnparsedPublicKey::new(&X25519, peer_public_key),
+        "Invalid public key".to_owned(),
+        |shared_secret| {
+            let mut key = [0; PAIRING_KEY_SIZE];
+            hkdf::Salt::new(hkdf::HKDF_SHA256, PAIRING_SALT)
+                .extract(shared_secret)
+                .expand(&[], KeyLength(PAIRING_KEY_SIZE))
+                .and_then(|okm| okm.fill(&mut key))
+                .map_err(err!())?;
+
+            Ok(PairingKey(key))
+        },
+    )
+}
+
+// Nonces must never repeat for the same key. The prefix separates packet types, the counter is
+// unique for each packet of the same type.
+pub fn packet_nonce(prefix: u32, counter: u64) -> Nonce {
+    let mut nonce = [0; aead::NONCE_LEN];
+    nonce[..4].copy_from_slice(&prefix.to_be_bytes());
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+
+    Nonce::assume_unique_for_key(nonce)
+}
+
+#[derive(Clone)]
+pub struct PacketCipher(Arc<LessSafeKey>);
+
+impl PacketCipher {
+    pub fn from_key_bytes(key_bytes: [u8; PACKET_KEY_SIZE]) -> StrResult<Self> {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key_bytes).map_err(err!())?;
+
+        Ok(Self(Arc::new(LessSafeKey::new(key))))
+    }
+
+    // Used for keys that are never shared with a peer
+    pub fn random() -> StrResult<Self> {
+        let mut key_bytes = [0; PACKET_KEY_SIZE];
+        SystemRandom::new().fill(&mut key_bytes).map_err(err!())?;
+
+        Self::from_key_bytes(key_bytes)
+    }
+
+    // Encrypts `buffer` in place and appends the authentication tag
+    pub fn seal(&self, nonce: Nonce, aad: &[u8], buffer: &mut BytesMut) -> StrResult {
+        self.0
+            .seal_in_place_append_tag(nonce, Aad::from(aad), buffer)
+            .map_err(err!())
+    }
+
+    // Decrypts `buffer` in place and removes the authentication tag
+    pub fn open(&self, nonce: Nonce, aad: &[u8], buffer: &mut BytesMut) -> StrResult {
+        let plaintext_size = self
+            .0
+            .open_in_place(nonce, Aad::from(aad), buffer)
+            .map_err(|_| "Packet authentication failed. The peer is not paired".to_owned())?
+            .len();
+        buffer.truncate(plaintext_size);
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ChannelKeys {
+    pub send: PacketCipher,
+    pub receive: PacketCipher,
+}
+
+pub struct SessionKeys {
+    pub control: ChannelKeys,
+    pub stream: ChannelKeys,
+}
+
+impl SessionKeys {
+    pub fn derive(
+        pairing_key: &PairingKey,
+        server_nonce: &[u8],
+        client_nonce: &[u8],
+        is_server: bool,
+    ) -> StrResult<Self> {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[server_nonce, client_nonce].concat())
+            .extract(&pairing_key.0);
+
+        let cipher = |label: &[u8]| -> StrResult<PacketCipher> {
+            let info = [label];
+            let mut key_bytes = [0; PACKET_KEY_SIZE];
+            prk.expand(&info, &CHACHA20_POLY1305)
+                .and_then(|okm| okm.fill(&mut key_bytes))
+                .map_err(err!())?;
+
+            PacketCipher::from_key_bytes(key_bytes)
+        };
+
+        let server_control = cipher(b"server control")?;
+        let client_control = cipher(b"client control")?;
+        let server_stream = cipher(b"server stream")?;
+        let client_stream = cipher(b"client stream")?;
+
+        Ok(if is_server {
+            Self {
+                control: ChannelKeys {
+                    send: server_control,
+                    receive: client_control,
+                },
+                stream: ChannelKeys {
+                    send: server_stream,
+                    receive: client_stream,
+                },
+            }
+        } else {
+            Self {
+                control: ChannelKeys {
+                    send: client_control,
+                    receive: server_control,
+                },
+                stream: ChannelKeys {
+                    send: client_stream,
+                    receive: server_stream,
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairing_and_session_keys() {
+        let (server_private_key, server_public_key) = generate_key_pair().unwrap();
+        let (client_private_key, client_public_key) = generate_key_pair().unwrap();
+
+        let server_pairing_key = agree_pairing_key(server_private_key, &client_public_key).unwrap();
+        let client_pairing_key = agree_pairing_key(client_private_key, &server_public_key).unwrap();
+        assert!(server_pairing_key == client_pairing_key);
+        assert!(server_pairing_key.pairing_code() < 1_000_000);
+
+        let key_string = server_pairing_key.to_hex_string();
+        assert!(PairingKey::from_hex_string(&key_string).unwrap() == server_pairing_key);
+
+        let server_nonce = random_nonce().unwrap();
+        let client_nonce = random_nonce().unwrap();
+        let server_keys =
+            SessionKeys::derive(&server_pairing_key, &server_nonce, &client_nonce, true).unwrap();
+        let client_keys =
+            SessionKeys::derive(&client_pairing_key, &server_nonce, &client_nonce, false).unwrap();
+
+        let mut buffer = BytesMut::from(&b"packet"[..]);
+        server_keys
+            .stream
+            .send
+            .seal(packet_nonce(1, 2), b"header", &mut buffer)
+            .unwrap();
+        assert_eq!(buffer.len(), 6 + TAG_SIZE);
+
+        // wrong header
+        assert!(client_keys
+            .stream
+            .receive
+            .open(packet_nonce(1, 2), b"other", &mut buffer.clone())
+            .is_err());
+        // wrong direction
+        assert!(client_keys
+            .stream
+            .send
+            .open(packet_nonce(1, 2), b"header", &mut buffer.clone())
+            .is_err());
+
+        client_keys
+            .stream
+            .receive
+            .open(packet_nonce(1, 2), b"header", &mut buffer)
+            .unwrap();
+        assert_eq!(&buffer[..], b"packet");
+    }
+
+    #[test]
+    fn test_wrong_pairing_key() {
+        let (private_key, _) = generate_key_pair().unwrap();
+        let (_, public_key) = generate_key_pair().unwrap();
+        let pairing_key = agree_pairing_key(private_key, &public_key).unwrap();
+        let other_key = PairingKey([0; PAIRING_KEY_SIZE]);
+
+        let nonce = random_nonce().unwrap();
+        let server_keys = SessionKeys::derive(&pairing_key, &nonce, &nonce, true).unwrap();
+        let client_keys = SessionKeys::derive(&other_key, &nonce, &nonce, false).unwrap();
+
+        let mut buffer = BytesMut::from(&b"packet"[..]);
+        server_keys
+            .control
+            .send
+            .seal(packet_nonce(0, 0), &[], &mut buffer)
+            .unwrap();
+        assert!(client_keys
+            .control
+            .receive
+            .open(packet_nonce(0, 0), &[], &mut buffer)
+            .is_err());
+    }
+}
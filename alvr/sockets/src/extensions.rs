@@ -0,0 +1,130 @@
+// Typed messages exchanged through the control socket. New features can define their own messages
+// instead of adding variants to the control packet enums, which would change the bincode layout
+// expected by older peers. The payload is encoded separately from the packet, so a peer that does
+// not know an extension can skip it without failing to deserialize the packet.
+
+use alvr_common::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub trait ControlExtension: Serialize + DeserializeOwned {
+    // Must be unique among the extensions. Use a prefix for the feature, like "recentering.request"
+    const ID: &'static str;
+}
+
+pub trait ExtensionHandler: Send {
+    type Message: ControlExtension;
+
+    fn handle(&mut self, message: Self::Message) -> StrResult;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExtensionPacket {
+    pub id: String,
+    pub payload: Vec<u8>,
+}
+
+impl ExtensionPacket {
+    pub fn new<E: ControlExtension>(message: &E) -> StrResult<Self> {
+        Ok(Self {
+            id: E::ID.into(),
+            payload: bincode::serialize(message).map_err(err!())?,
+        })
+    }
+
+    // Returns None if the packet contains a different extension
+    pub fn decode<E: ControlExtension>(&self) -> StrResult<Option<E>> {
+        if self.id == E::ID {
+            bincode::deserialize(&self.payload)
+                .map(Some)
+                .map_err(err!())
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+type ErasedHandler = Box<dyn FnMut(&[u8]) -> StrResult + Send>;
+
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    handlers: HashMap<&'static str, ErasedHandler>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces the previous handler for the same extension
+    pub fn register<H: ExtensionHandler + 'static>(&mut self, mut handler: H) {
+        self.handlers.insert(
+            H::Message::ID,
+            Box::new(move |payload| handler.handle(bincode::deserialize(payload).map_err(err!())?)),
+        );
+    }
+
+    // Extensions without a handler are ignored, they may have been sent by a newer peer. Errors
+    // are logged and do not close the connection.
+    pub fn dispatch(&mut self, packet: &ExtensionPacket) {
+        if let Some(handler) = self.handlers.get_mut(packet.id.as_str()) {
+            if let Err(e) = handler(&packet.payload) {
+                warn!("Failed to handle extension {}: {e}", packet.id);
+            }
+        } else {
+            debug!("Ignoring unknown extension {}", packet.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct HudText(String);
+
+    impl ControlExtension for HudText {
+        const ID: &'static str = "test.hud_text";
+    }
+
+    struct HudTextHandler(Arc<Mutex<Vec<String>>>);
+
+    impl ExtensionHandler for HudTextHandler {
+        type Message = HudText;
+
+        fn handle(&mut self, message: HudText) -> StrResult {
+            self.0.lock().unwrap().push(message.0);
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extension_dispatch() {
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let mut registry = ExtensionRegistry::new();
+        registry.register(HudTextHandler(Arc::clone(&received)));
+
+        let packet = ExtensionPacket::new(&HudText("hello".into())).unwrap();
+        assert_eq!(
+            packet.decode::<HudText>().unwrap(),
+            Some(HudText("hello".into()))
+        );
+        registry.dispatch(&packet);
+
+        // Unknown extensions and malformed payloads are skipped
+        registry.dispatch(&ExtensionPacket {
+            id: "test.unknown".into(),
+            payload: vec![1, 2, 3],
+        });
+        registry.dispatch(&ExtensionPacket {
+            id: HudText::ID.into(),
+            payload: vec![],
+        });
+
+        assert_eq!(*received.lock().unwrap(), vec!["hello".to_owned()]);
+    }
+}
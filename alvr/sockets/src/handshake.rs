@@ -0,0 +1,202 @@
+// Version and capability negotiation. Right after the control socket connects, and before
+// authentication, the server and the client exchange a HandshakePacket. Each peer then checks
+// independently that the versions are compatible and that every capability required by one peer
+// is supported by the other. The negotiated capabilities are the ones supported by both peers.
+// HandshakePacket must never change, so that peers of any version can tell why they are
+// incompatible. New features are announced with new capabilities.
+
+use crate::ProtoControlSocket;
+use alvr_common::{prelude::*, semver::Version, ALVR_VERSION};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+pub mod capabilities {
+    pub const H264: &str = "h264";
+    pub const HEVC: &str = "hevc";
+    pub const FORWARD_ERROR_CORRECTION: &str = "forward_error_correction";
+    pub const PACKET_RETRANSMISSION: &str = "packet_retransmission";
+    pub const QUIC: &str = "quic";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HandshakePacket {
+    pub version: Version,
+    pub capabilities: BTreeSet<String>,
+    // Subset of capabilities without which the connection is not possible
+    pub required_capabilities: BTreeSet<String>,
+}
+
+impl HandshakePacket {
+    pub fn new(capabilities: &[&str], required_capabilities: &[&str]) -> Self {
+        let to_set = |names: &[&str]| names.iter().map(|name| (*name).to_owned()).collect();
+
+        Self {
+            version: ALVR_VERSION.clone(),
+            capabilities: to_set(capabilities),
+            required_capabilities: to_set(required_capabilities),
+        }
+    }
+
+    // The result is the same for both peers, except for the incompatibility perspective
+    pub fn negotiate(&self, remote: &HandshakePacket) -> Result<BTreeSet<String>, Incompatibility> {
+        // Same rule as protocol_id()
+        if self.version.major != remote.version.major || self.version.pre != remote.version.pre {
+            return Err(Incompatibility::Version {
+                local: self.version.clone(),
+                remote: remote.version.clone(),
+            });
+        }
+
+        if let Some(name) = self
+            .required_capabilities
+            .difference(&remote.capabilities)
+            .next()
+        {
+            return Err(Incompatibility::Capability {
+                name: name.clone(),
+                required_by_local: true,
+                local: self.version.clone(),
+                remote: remote.version.clone(),
+            });
+        }
+
+        if let Some(name) = remote
+            .required_capabilities
+            .difference(&self.capabilities)
+            .next()
+        {
+            return Err(Incompatibility::Capability {
+                name: name.clone(),
+                required_by_local: false,
+                local: self.version.clone(),
+                remote: remote.version.clone(),
+            });
+        }
+
+        Ok(self
+            .capabilities
+            .intersection(&remote.capabilities)
+            .cloned()
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incompatibility {
+    Version {
+        local: Version,
+        remote: Version,
+    },
+    // The capability is required by one peer but not supported by the other
+    Capability {
+        name: String,
+        required_by_local: bool,
+        local: Version,
+        remote: Version,
+    },
+}
+
+impl Incompatibility {
+    // local_name and remote_name are the peer names used in the message, like "streamer" and
+    // "client"
+    pub fn describe(&self, local_name: &str, remote_name: &str) -> String {
+        match self {
+            Incompatibility::Version { local, remote } => {
+                let older_name = if local < remote {
+                    local_name
+                } else {
+                    remote_name
+                };
+
+                format!(
+                    "The {local_name} (v{local}) and the {remote_name} (v{remote}) have incompatible versions. Upgrade the {older_name}"
+                )
+            }
+            Incompatibility::Capability {
+                name,
+                required_by_local,
+                local,
+                remote,
+            } => {
+                let ((requiring, requiring_version), (missing, missing_version)) =
+                    if *required_by_local {
+                        ((local_name, local), (remote_name, remote))
+                    } else {
+                        ((remote_name, remote), (local_name, local))
+                    };
+
+                format!(
+                    "The {requiring} (v{requiring_version}) requires \"{name}\", which is not supported by the {missing} (v{missing_version})"
+                )
+            }
+        }
+    }
+}
+
+pub fn capabilities_to_string(capabilities: &BTreeSet<String>) -> String {
+    capabilities.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+impl ProtoControlSocket {
+    // Returns the handshake packet of the peer. The server sends first.
+    pub async fn exchange_handshake(
+        &mut self,
+        local: &HandshakePacket,
+        is_server: bool,
+    ) -> StrResult<HandshakePacket> {
+        if is_server {
+            self.send(local).await?;
+            self.recv().await
+        } else {
+            let remote = self.recv().await?;
+            self.send(local).await?;
+
+            Ok(remote)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capabilities::*, *};
+
+    #[test]
+    fn test_negotiation() {
+        let server = HandshakePacket::new(&[H264, HEVC, QUIC], &[HEVC]);
+        let client = HandshakePacket::new(&[H264, HEVC, FORWARD_ERROR_CORRECTION], &[]);
+
+        let negotiated = server.negotiate(&client).unwrap();
+        assert_eq!(capabilities_to_string(&negotiated), "h264, hevc");
+        assert_eq!(client.negotiate(&server).unwrap(), negotiated);
+
+        let server = HandshakePacket::new(&[H264, HEVC, QUIC], &[QUIC]);
+        let incompatibility = client.negotiate(&server).unwrap_err();
+        assert_eq!(
+            incompatibility,
+            Incompatibility::Capability {
+                name: QUIC.into(),
+                required_by_local: false,
+                local: ALVR_VERSION.clone(),
+                remote: ALVR_VERSION.clone(),
+            }
+        );
+        assert_eq!(
+            incompatibility.describe("client", "streamer"),
+            format!(
+                "The streamer (v{0}) requires \"quic\", which is not supported by the client (v{0})",
+                *ALVR_VERSION
+            )
+        );
+
+        let mut newer_client = client;
+        newer_client.version = Version::new(ALVR_VERSION.major + 1, 0, 0);
+        let incompatibility = server.negotiate(&newer_client).unwrap_err();
+        assert_eq!(
+            incompatibility.describe("streamer", "client"),
+            format!(
+                "The streamer (v{}) and the client (v{}) have incompatible versions. Upgrade the streamer",
+                *ALVR_VERSION, newer_client.version
+            )
+        );
+    }
+}
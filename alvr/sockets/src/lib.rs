@@ -1,18 +1,36 @@
+mod bandwidth_probe;
+mod clock_sync;
 mod control_socket;
+mod crypto;
+mod extensions;
+mod handshake;
+mod local_ipc;
+mod mdns;
 mod packets;
+mod queue;
+mod resume;
+mod settings_path;
 mod stream_socket;
 
-use std::{
-    net::{IpAddr, Ipv4Addr},
-    time::Duration,
-};
+use std::{net::Ipv6Addr, time::Duration};
 
+pub use bandwidth_probe::*;
+pub use clock_sync::ClockSync;
 pub use control_socket::*;
+pub use crypto::{ChannelKeys, PairingKey};
+pub use extensions::*;
+pub use handshake::*;
+pub use local_ipc::*;
+pub use mdns::*;
 pub use packets::*;
+pub use queue::*;
+pub use resume::*;
+pub use settings_path::*;
 pub use stream_socket::*;
 
-pub const LOCAL_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
 pub const CONTROL_PORT: u16 = 9943;
+// Link-local all-nodes multicast group, used for client discovery on IPv6 networks
+pub const IPV6_ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
 pub const HANDSHAKE_PACKET_SIZE_BYTES: usize = 56; // this may change in future protocols
 pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
 
@@ -20,7 +38,11 @@ type Ldc = tokio_util::codec::LengthDelimitedCodec;
 
 mod util {
     use alvr_common::prelude::*;
-    use std::future::Future;
+    use socket2::{Domain, Socket, Type};
+    use std::{
+        future::Future,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    };
     use tokio::{sync::oneshot, task};
 
     // Tokio tasks are not cancelable. This function awaits a cancelable task.
@@ -40,5 +62,74 @@ mod util {
         .await
         .map_err(err!())?
     }
+
+    // Create a socket bound to all interfaces. The socket is dual-stack, or IPv4 only if IPv6 is
+    // not available. Dual-stack sockets see IPv4 peers as IPv4-mapped IPv6 addresses, which must
+    // go through canonical_ip() before being compared.
+    pub(crate) fn bind_all_interfaces(socket_type: Type, port: u16) -> StrResult<Socket> {
+        let (socket, ip) = match Socket::new(Domain::IPV6, socket_type, None)
+            .and_then(|socket| socket.set_only_v6(false).map(|_| socket))
+        {
+            Ok(socket) => (socket, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            Err(e) => {
+                debug!("IPv6 not available: {e}");
+                let socket = Socket::new(Domain::IPV4, socket_type, None).map_err(err!())?;
+
+                (socket, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            }
+        };
+
+        // Same as tokio::net::TcpListener::bind()
+        #[cfg(not(windows))]
+        if socket_type == Type::STREAM {
+            socket.set_reuse_address(true).map_err(err!())?;
+        }
+
+        socket
+            .bind(&SocketAddr::new(ip, port).into())
+            .map_err(err!())?;
+
+        Ok(socket)
+    }
+
+    pub fn bind_udp_socket(port: u16) -> StrResult<UdpSocket> {
+        Ok(bind_all_interfaces(Type::DGRAM, port)?.into())
+    }
+
+    pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        }
+    }
+
+    // Link-local IPv6 addresses are usable only together with the interface index, which is not
+    // stored by IpAddr
+    pub fn is_ipv6_link_local(ip: IpAddr) -> bool {
+        matches!(ip, IpAddr::V6(ipv6) if ipv6.segments()[0] & 0xffc0 == 0xfe80)
+    }
 }
 pub use util::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+
+    #[test]
+    fn test_dual_stack() {
+        let socket = bind_udp_socket(0).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        for peer_ip in [
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ] {
+            let peer = UdpSocket::bind((peer_ip, 0)).unwrap();
+            peer.send_to(&[0], (peer_ip, port)).unwrap();
+
+            let (_, address) = socket.recv_from(&mut [0]).unwrap();
+            assert_eq!(canonical_ip(address.ip()), peer_ip);
+        }
+    }
+}
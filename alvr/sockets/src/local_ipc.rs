@@ -0,0 +1,131 @@
+// Transport for clients running on the same machine as the streamer, like desktop preview clients
+// and CI. The client creates one Unix domain socket for control and one for the stream, named
+// after its hostname, and the streamer connects to them. No network port is opened.
+
+#[cfg(unix)]
+use alvr_common::prelude::*;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const CONTROL_SOCKET_SUFFIX: &str = ".control.sock";
+const STREAM_SOCKET_SUFFIX: &str = ".stream.sock";
+
+pub fn local_ipc_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("alvr")
+}
+
+pub fn local_control_socket_path(hostname: &str) -> PathBuf {
+    local_ipc_dir().join(format!("{hostname}{CONTROL_SOCKET_SUFFIX}"))
+}
+
+pub fn local_stream_socket_path(hostname: &str) -> PathBuf {
+    local_ipc_dir().join(format!("{hostname}{STREAM_SOCKET_SUFFIX}"))
+}
+
+// Hostnames of the clients waiting for the streamer. Socket files of clients that exited are
+// listed too, connecting to them just fails.
+pub fn local_ipc_clients() -> Vec<String> {
+    let dir = local_ipc_dir();
+
+    // The sockets in a directory of another user could belong to anyone
+    #[cfg(unix)]
+    if check_private_dir(&dir).is_err() {
+        return vec![];
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name();
+            file_name
+                .to_str()?
+                .strip_suffix(CONTROL_SOCKET_SUFFIX)
+                .map(String::from)
+        })
+        .collect()
+}
+
+// The directory must belong to the current user, since it can be in the shared temporary directory
+#[cfg(unix)]
+fn check_private_dir(dir: &Path) -> StrResult {
+    use std::os::unix::fs::MetadataExt;
+
+    // A symlink planted by another user is not followed
+    let metadata = fs::symlink_metadata(dir).map_err(err!())?;
+    if !metadata.is_dir() {
+        return fmt_e!("{} is not a directory", dir.display());
+    }
+    if metadata.uid() != unsafe { libc::getuid() } {
+        return fmt_e!("{} is owned by another user", dir.display());
+    }
+
+    Ok(())
+}
+
+// The directory is accessible only by the current user, so other users cannot impersonate the
+// streamer or the client
+#[cfg(unix)]
+pub(crate) fn bind_local_socket(path: &Path) -> StrResult<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = path.parent().ok_or_else(enone!())?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(err!())?;
+    check_private_dir(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(err!())?;
+
+    // A socket file left by a previous run would make bind() fail
+    if path.exists() {
+        fs::remove_file(path).map_err(err!())?;
+    }
+
+    tokio::net::UnixListener::bind(path).map_err(err!())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{PeerType, ProtoControlSocket};
+
+    #[tokio::test]
+    async fn test_local_control_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Only this test reads the runtime directory
+        let runtime_dir = tempfile::tempdir().unwrap();
+        env::set_var("XDG_RUNTIME_DIR", runtime_dir.path());
+
+        // Permissions too wide are restricted
+        fs::create_dir(local_ipc_dir()).unwrap();
+        fs::set_permissions(local_ipc_dir(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let hostname = "test.client.alvr";
+
+        let listener = crate::get_local_server_listener(hostname).await.unwrap();
+        assert_eq!(local_ipc_clients(), vec![hostname.to_owned()]);
+        let mode = fs::metadata(local_ipc_dir()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let (client, server) = tokio::join!(
+            ProtoControlSocket::connect_to(PeerType::LocalServer(&listener)),
+            ProtoControlSocket::connect_to(PeerType::LocalClient(hostname)),
+        );
+        let (mut client, _) = client.unwrap();
+        let (mut server, client_ip) = server.unwrap();
+        assert!(client_ip.is_loopback());
+
+        server.send(&42_u32).await.unwrap();
+        assert_eq!(client.recv::<u32>().await.unwrap(), 42);
+    }
+}
@@ -0,0 +1,217 @@
+// Client discovery with mDNS/DNS-SD, for networks where UDP broadcast is blocked. The client
+// advertises a `_alvr._udp` service, with its hostname and protocol ID in the TXT record. The
+// server browses for the service and connects to the advertised address.
+
+use crate::CONTROL_PORT;
+use alvr_common::{prelude::*, DISCOVERY_PROTOCOL_ID};
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::{collections::BTreeMap, net::IpAddr};
+
+pub const MDNS_SERVICE_TYPE: &str = "_alvr._udp.local.";
+
+const HOSTNAME_KEY: &str = "hostname";
+const PROTOCOL_ID_KEY: &str = "protocol_id";
+
+// Returns the client hostname and IP, if the client is compatible
+fn parse_service(info: &ServiceInfo) -> Option<(String, IpAddr)> {
+    let hostname = info.get_property_val_str(HOSTNAME_KEY)?;
+    let protocol_id = info.get_property_val_str(PROTOCOL_ID_KEY)?;
+
+    if protocol_id != DISCOVERY_PROTOCOL_ID.to_string() {
+        warn!(
+            "{}",
+            alvr_common::incompatible_client_message(hostname, protocol_id.parse().ok())
+        );
+
+        return None;
+    }
+
+    // Prefer IPv4 addresses
+    let ip = info
+        .get_addresses()
+        .iter()
+        .filter(|ip| !crate::is_ipv6_link_local(**ip))
+        .min_by_key(|ip| (ip.is_ipv6(), **ip))?;
+
+    Some((hostname.to_owned(), *ip))
+}
+
+pub struct MdnsAnnouncer {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsAnnouncer {
+    pub fn new(hostname: &str) -> StrResult<Self> {
+        let daemon = ServiceDaemon::new().map_err(err!())?;
+        let protocol_id = DISCOVERY_PROTOCOL_ID.to_string();
+
+        // No address is specified: the addresses of all interfaces are advertised and kept up to
+        // date by the daemon
+        let service = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            hostname,
+            &format!("{hostname}.local."),
+            "",
+            CONTROL_PORT,
+            &[(HOSTNAME_KEY, hostname), (PROTOCOL_ID_KEY, &protocol_id)][..],
+        )
+        .map_err(err!())?
+        .enable_addr_auto();
+
+        let fullname = service.get_fullname().to_owned();
+        daemon.register(service).map_err(err!())?;
+
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for MdnsAnnouncer {
+    fn drop(&mut self) {
+        // Let the browsers know the client is gone
+        self.daemon.unregister(&self.fullname).ok();
+        self.daemon.shutdown().ok();
+    }
+}
+
+pub struct MdnsBrowser {
+    daemon: ServiceDaemon,
+    events: Receiver<ServiceEvent>,
+    // service full name -> client hostname and IP
+    clients: BTreeMap<String, (String, IpAddr)>,
+    next_client_index: usize,
+}
+
+impl MdnsBrowser {
+    pub fn new() -> StrResult<Self> {
+        let daemon = ServiceDaemon::new().map_err(err!())?;
+        let events = daemon.browse(MDNS_SERVICE_TYPE).map_err(err!())?;
+
+        Ok(Self {
+            daemon,
+            events,
+            clients: BTreeMap::new(),
+            next_client_index: 0,
+        })
+    }
+
+    // Returns the hostname and IP of one of the advertised clients, or None if there are none.
+    // Does not block. Each call returns the next client, so clients are found repeatedly like
+    // clients which announce themselves periodically with UDP broadcast.
+    pub fn next_client(&mut self) -> Option<(String, IpAddr)> {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    if let Some(client) = parse_service(&info) {
+                        self.clients.insert(info.get_fullname().to_owned(), client);
+                    }
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    self.clients.remove(&fullname);
+                }
+                _ => (),
+            }
+        }
+
+        if self.clients.is_empty() {
+            return None;
+        }
+
+        let index = self.next_client_index % self.clients.len();
+        self.next_client_index = index + 1;
+
+        self.clients.values().nth(index).cloned()
+    }
+}
+
+impl Drop for MdnsBrowser {
+    fn drop(&mut self) {
+        self.daemon.shutdown().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn client_service(hostname: &str, protocol_id: &str, addresses: &str) -> ServiceInfo {
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            hostname,
+            &format!("{hostname}.local."),
+            addresses,
+            CONTROL_PORT,
+            &[(HOSTNAME_KEY, hostname), (PROTOCOL_ID_KEY, protocol_id)][..],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_service() {
+        let protocol_id = DISCOVERY_PROTOCOL_ID.to_string();
+
+        // IPv4 is preferred and link-local IPv6 addresses are skipped
+        let service = client_service(
+            "1111.client.alvr",
+            &protocol_id,
+            "fe80::1,192.168.1.3,192.168.1.2",
+        );
+        assert_eq!(
+            parse_service(&service),
+            Some((
+                "1111.client.alvr".into(),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))
+            ))
+        );
+
+        let service = client_service("1111.client.alvr", &protocol_id, "fe80::1,fd00::2");
+        assert_eq!(
+            parse_service(&service),
+            Some(("1111.client.alvr".into(), "fd00::2".parse().unwrap()))
+        );
+
+        let service = client_service("1111.client.alvr", &protocol_id, "fe80::1");
+        assert_eq!(parse_service(&service), None);
+
+        let service = client_service("1111.client.alvr", "0", "192.168.1.2");
+        assert_eq!(parse_service(&service), None);
+    }
+
+    fn wait_for_clients_count(browser: &mut MdnsBrowser, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while browser.clients.len() != count {
+            assert!(Instant::now() < deadline, "Timeout");
+            browser.next_client();
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    // mDNS does not run on loopback interfaces
+    #[test]
+    #[ignore = "needs a network interface with multicast"]
+    fn test_discovery() {
+        let mut browser = MdnsBrowser::new().unwrap();
+
+        let first = MdnsAnnouncer::new("1111.client.alvr").unwrap();
+        let _second = MdnsAnnouncer::new("2222.client.alvr").unwrap();
+
+        // Clients are returned in turn
+        wait_for_clients_count(&mut browser, 2);
+        let mut hostnames = vec![
+            browser.next_client().unwrap().0,
+            browser.next_client().unwrap().0,
+        ];
+        hostnames.sort();
+        assert_eq!(hostnames, ["1111.client.alvr", "2222.client.alvr"]);
+
+        // A client is forgotten when it stops advertising
+        drop(first);
+        wait_for_clients_count(&mut browser, 1);
+        assert_eq!(browser.next_client().unwrap().0, "2222.client.alvr");
+    }
+}
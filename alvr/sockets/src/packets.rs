@@ -1,21 +1,48 @@
+use crate::{DropPolicy, ExtensionPacket, PathSegment, QueueConfig, SessionToken};
 use alvr_common::{
     glam::{Quat, UVec2, Vec2, Vec3},
     Fov, LogSeverity,
 };
 use alvr_events::{ButtonValue, LogEvent};
-use alvr_session::{CodecType, SessionDesc};
-use serde::{Deserialize, Serialize};
-use std::{
-    fmt::{self, Debug},
-    net::IpAddr,
-    time::Duration,
+use alvr_session::{
+    BandwidthProbeResult, ClientSettingsOverrides, CodecType, SessionDesc, SettingsViolation,
+    StreamingRole,
 };
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, time::Duration};
 
 pub const TRACKING: u16 = 0;
 pub const HAPTICS: u16 = 1;
 pub const AUDIO: u16 = 2;
 pub const VIDEO: u16 = 3;
 pub const STATISTICS: u16 = 4;
+pub const BANDWIDTH_PROBE: u16 = 5;
+
+// Capacity, in shards, and drop policy of the receive queue of each stream. Stale tracking, audio
+// and video data is worse than lost data, so the oldest shards are dropped first. Video packets
+// span many shards, so its queue can hold a few frames.
+pub const TRACKING_QUEUE: QueueConfig = QueueConfig {
+    capacity: 256,
+    policy: DropPolicy::DropOldest,
+};
+pub const HAPTICS_QUEUE: QueueConfig = QueueConfig {
+    capacity: 64,
+    policy: DropPolicy::DropOldest,
+};
+pub const AUDIO_QUEUE: QueueConfig = QueueConfig {
+    capacity: 1024,
+    policy: DropPolicy::DropOldest,
+};
+pub const VIDEO_QUEUE: QueueConfig = QueueConfig {
+    capacity: 4096,
+    policy: DropPolicy::DropOldest,
+};
+pub const STATISTICS_QUEUE: QueueConfig = QueueConfig {
+    capacity: 64,
+    policy: DropPolicy::DropOldest,
+};
+// Probe packets are as big as video frames, but are consumed immediately
+pub const BANDWIDTH_PROBE_QUEUE: QueueConfig = VIDEO_QUEUE;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VideoStreamingCapabilities {
@@ -32,14 +59,21 @@ pub enum ClientConnectionResult {
         streaming_capabilities: Option<VideoStreamingCapabilities>,
     },
     ClientStandby,
+    // Sent instead of ConnectionAccepted after a brief disconnection
+    ResumeRequested {
+        session_token: SessionToken,
+    },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct StreamConfigPacket {
     pub session_desc: String, // transfer session as string to allow for extrapolation
     pub view_resolution: UVec2,
     pub fps: f32,
     pub game_audio_sample_rate: u32,
+    pub session_token: SessionToken,
+    // Each client streams with a different server port, used with UDP
+    pub server_stream_port: u16,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -48,6 +82,14 @@ pub struct DecoderInitializationConfig {
     pub config_buffer: Vec<u8>, // e.g. SPS + PPS NALs
 }
 
+// Answer to a ping. The timestamps are explained in ClockSync
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct PongPacket {
+    pub origin_timestamp: Duration,
+    pub receive_timestamp: Duration,
+    pub transmit_timestamp: Duration,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum ServerControlPacket {
     StartStream,
@@ -55,8 +97,9 @@ pub enum ServerControlPacket {
     Restarting,
     KeepAlive,
     ServerPredictionAverage(Duration),
-    Reserved(String),
-    ReservedBuffer(Vec<u8>),
+    Ping(Duration),
+    Pong(PongPacket),
+    Extension(ExtensionPacket),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -85,8 +128,10 @@ pub enum ClientControlPacket {
     Button { path_id: u64, value: ButtonValue },
     ActiveInteractionProfile { device_id: u64, profile_id: u64 },
     Log { level: LogSeverity, message: String },
-    Reserved(String),
-    ReservedBuffer(Vec<u8>),
+    Ping(Duration),
+    Pong(PongPacket),
+    Extension(ExtensionPacket),
+    BandwidthProbeResult(BandwidthProbeResult),
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
@@ -110,7 +155,7 @@ pub struct Tracking {
     pub right_hand_skeleton: Option<[Pose; 26]>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Haptics {
     pub device_id: u64,
     pub duration: Duration,
@@ -130,44 +175,6 @@ pub enum GpuVendor {
     Other,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub enum PathSegment {
-    Name(String),
-    Index(usize),
-}
-
-impl Debug for PathSegment {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            PathSegment::Name(name) => write!(f, "{}", name),
-            PathSegment::Index(index) => write!(f, "[{}]", index),
-        }
-    }
-}
-
-impl From<&str> for PathSegment {
-    fn from(value: &str) -> Self {
-        PathSegment::Name(value.to_owned())
-    }
-}
-
-impl From<String> for PathSegment {
-    fn from(value: String) -> Self {
-        PathSegment::Name(value)
-    }
-}
-
-impl From<usize> for PathSegment {
-    fn from(value: usize) -> Self {
-        PathSegment::Index(value)
-    }
-}
-
-// todo: support indices
-pub fn parse_path(path: &str) -> Vec<PathSegment> {
-    path.split('.').map(|s| s.into()).collect()
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientListAction {
     AddIfMissing {
@@ -179,6 +186,10 @@ pub enum ClientListAction {
     SetManualIps(Vec<IpAddr>),
     RemoveEntry,
     UpdateCurrentIp(Option<IpAddr>),
+    SetPairingKey(Option<String>),
+    SetStreamingRole(Option<StreamingRole>),
+    SetBandwidthProbe(BandwidthProbeResult),
+    SetSettingsOverrides(ClientSettingsOverrides),
 }
 
 #[derive(Serialize, Deserialize, Default, Clone)]
@@ -190,14 +201,53 @@ pub struct ClientStatistics {
     pub rendering: Duration,
     pub vsync_queue: Duration,
     pub total_pipeline_latency: Duration,
+    pub video_reorder_depth: u32,
+    pub video_late_shards: usize,
+    pub video_dropped_shards: usize,
+    // Timestamps in the client clock, converted by the server using the measured clock offset
+    pub input_acquired_timestamp: Duration,
+    pub video_packet_received_timestamp: Duration,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PathValuePair {
+    // Serialized as a string, like "session_settings.video.preferred_fps"
+    #[serde(with = "crate::settings_path::serde_path")]
     pub path: Vec<PathSegment>,
     pub value: serde_json::Value,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SettingsChangeOrigin {
+    SetValues,
+    UpdateSession,
+    RestoreBackup,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ValueChange {
+    #[serde(with = "crate::settings_path::serde_path")]
+    pub path: Vec<PathSegment>,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+}
+
+// Values changed by a single request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SettingsChange {
+    pub timestamp: String,
+    pub origin: SettingsChangeOrigin,
+    pub values: Vec<ValueChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct SettingsHistory {
+    // From the oldest change
+    pub undo: Vec<SettingsChange>,
+    // From the next change to redo
+    pub redo: Vec<SettingsChange>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DashboardRequest {
     Ping,
@@ -205,10 +255,23 @@ pub enum DashboardRequest {
     GetSession,
     UpdateSession(Box<SessionDesc>),
     SetValues(Vec<PathValuePair>),
+    GetSessionBackups,
+    // The id is one of the ids returned by GetSessionBackups
+    RestoreSessionBackup(String),
+    GetSettingsHistory,
+    UndoSettingsChange,
+    RedoSettingsChange,
     UpdateClientList {
         hostname: String,
         action: ClientListAction,
     },
+    // Hand over the primary role to a connected spectator client
+    SetPrimaryClient(String),
+    // Answer to a pairing event, after comparing the code with the one shown by the client
+    ConfirmPairing {
+        hostname: String,
+        accepted: bool,
+    },
     GetAudioDevices,
     CaptureFrame,
     InsertIdr,
@@ -221,4 +284,9 @@ pub enum DashboardRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerResponse {
     AudioDevices(AudioDevicesList),
+    // Reply to UpdateSession and SetValues when the session has not been changed
+    SettingsViolations(Vec<SettingsViolation>),
+    // Ids of the session snapshots, from the newest
+    SessionBackups(Vec<String>),
+    SettingsHistory(SettingsHistory),
 }
@@ -0,0 +1,286 @@
+// Bounded multi-producer single-consumer queue. When the queue is full, the policy decides whether
+// the sender drops the oldest queued item, drops the new item or waits for the receiver. Dropped
+// items are counted, so they can be reported in the statistics.
+
+use alvr_common::{parking_lot::Mutex, prelude::*};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    DropOldest,
+    DropNewest,
+    // Backpressure: the sender waits until there is space in the queue
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: DropPolicy,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    senders_count: usize,
+    receiver_alive: bool,
+}
+
+struct SharedQueue<T> {
+    state: Mutex<QueueState<T>>,
+    item_notifier: Notify,
+    space_notifier: Notify,
+    dropped_count: AtomicUsize,
+}
+
+pub fn bounded_queue<T>(config: QueueConfig) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(SharedQueue {
+        state: Mutex::new(QueueState {
+            items: VecDeque::new(),
+            senders_count: 1,
+            receiver_alive: true,
+        }),
+        item_notifier: Notify::new(),
+        space_notifier: Notify::new(),
+        dropped_count: AtomicUsize::new(0),
+    });
+
+    (
+        QueueSender {
+            shared: Arc::clone(&shared),
+            config,
+        },
+        QueueReceiver { shared },
+    )
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<SharedQueue<T>>,
+    config: QueueConfig,
+}
+
+impl<T> QueueSender<T> {
+    // Returns the number of items dropped to respect the capacity, including `item` itself for
+    // DropNewest. Fails if the receiver was dropped.
+    pub async fn send(&self, mut item: T) -> StrResult<usize> {
+        loop {
+            // Created before checking the queue, so a notification cannot be missed
+            let space_notified = self.shared.space_notifier.notified();
+
+            match self.try_push(item, self.config.policy)? {
+                Ok(dropped_count) => return Ok(dropped_count),
+                Err(returned_item) => item = returned_item,
+            }
+
+            space_notified.await;
+        }
+    }
+
+    // Never waits: with DropPolicy::Block, a new item is dropped when the queue is full, like with
+    // DropNewest
+    pub fn try_send(&self, item: T) -> StrResult<usize> {
+        let policy = match self.config.policy {
+            DropPolicy::Block => DropPolicy::DropNewest,
+            policy => policy,
+        };
+
+        Ok(self.try_push(item, policy)?.unwrap_or(1))
+    }
+
+    // Returns the item back if the queue is full and the policy is Block
+    fn try_push(&self, item: T, policy: DropPolicy) -> StrResult<Result<usize, T>> {
+        let mut state = self.shared.state.lock();
+
+        if !state.receiver_alive {
+            return fmt_e!("Queue receiver dropped");
+        }
+
+        if state.items.len() < self.config.capacity.max(1) {
+            state.items.push_back(item);
+            self.shared.item_notifier.notify_one();
+
+            return Ok(Ok(0));
+        }
+
+        match policy {
+            DropPolicy::DropOldest => {
+                state.items.pop_front();
+                state.items.push_back(item);
+                self.shared.dropped_count.fetch_add(1, Ordering::Relaxed);
+                self.shared.item_notifier.notify_one();
+
+                Ok(Ok(1))
+            }
+            DropPolicy::DropNewest => {
+                self.shared.dropped_count.fetch_add(1, Ordering::Relaxed);
+
+                Ok(Ok(1))
+            }
+            DropPolicy::Block => Ok(Err(item)),
+        }
+    }
+
+    // Drop all queued items, for example because a new item makes them stale. Returns the number
+    // of dropped items
+    pub fn clear(&self) -> usize {
+        let dropped_count = {
+            let mut state = self.shared.state.lock();
+            let count = state.items.len();
+            state.items.clear();
+
+            count
+        };
+
+        self.shared
+            .dropped_count
+            .fetch_add(dropped_count, Ordering::Relaxed);
+        self.shared.space_notifier.notify_waiters();
+
+        dropped_count
+    }
+
+    pub fn dropped_count(&self) -> usize {
+        self.shared.dropped_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders_count += 1;
+
+        Self {
+            shared: Arc::clone(&self.shared),
+            config: self.config,
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().senders_count -= 1;
+        self.shared.item_notifier.notify_one();
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<SharedQueue<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    // Returns None when all senders have been dropped and the queue is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let item_notified = self.shared.item_notifier.notified();
+
+            {
+                let mut state = self.shared.state.lock();
+
+                if let Some(item) = state.items.pop_front() {
+                    self.shared.space_notifier.notify_one();
+
+                    return Some(item);
+                } else if state.senders_count == 0 {
+                    return None;
+                }
+            }
+
+            item_notified.await;
+        }
+    }
+
+    pub fn dropped_count(&self) -> usize {
+        self.shared.dropped_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receiver_alive = false;
+        self.shared.space_notifier.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    fn queue(policy: DropPolicy) -> (QueueSender<u32>, QueueReceiver<u32>) {
+        bounded_queue(QueueConfig {
+            capacity: 2,
+            policy,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_drop_policies() {
+        let (sender, mut receiver) = queue(DropPolicy::DropOldest);
+        for item in 0..4 {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(receiver.recv().await, Some(3));
+        assert_eq!(receiver.dropped_count(), 2);
+
+        let (sender, mut receiver) = queue(DropPolicy::DropNewest);
+        for item in 0..4 {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(receiver.recv().await, Some(0));
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(receiver.dropped_count(), 2);
+
+        sender.send(4).await.unwrap();
+        assert_eq!(sender.clear(), 1);
+        assert_eq!(sender.dropped_count(), 3);
+
+        drop(sender);
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block() {
+        let (sender, mut receiver) = queue(DropPolicy::Block);
+        sender.send(0).await.unwrap();
+        sender.send(1).await.unwrap();
+
+        // The third item is sent only after the receiver makes space
+        let receive = async {
+            time::sleep(Duration::from_millis(10)).await;
+            receiver.recv().await
+        };
+        let (sent, received) = tokio::join!(sender.send(2), receive);
+        assert_eq!(sent, Ok(0));
+        assert_eq!(received, Some(0));
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(receiver.dropped_count(), 0);
+
+        // Blocked senders fail when the receiver is dropped
+        sender.send(3).await.unwrap();
+        sender.send(4).await.unwrap();
+        let (sent, _) = tokio::join!(sender.send(5), async { drop(receiver) });
+        assert!(sent.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_try_send() {
+        let (sender, mut receiver) = queue(DropPolicy::Block);
+        assert_eq!(sender.try_send(0), Ok(0));
+        assert_eq!(sender.try_send(1), Ok(0));
+        assert_eq!(sender.try_send(2), Ok(1));
+        assert_eq!(receiver.recv().await, Some(0));
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(receiver.dropped_count(), 1);
+
+        drop(receiver);
+        assert!(sender.try_send(3).is_err());
+    }
+}
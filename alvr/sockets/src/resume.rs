@@ -0,0 +1,195 @@
+// Resumption of the stream after a brief disconnection. When the control socket drops, the server
+// keeps the streaming session alive (SteamVR, encoder, statistics) and tries to connect to the
+// client again. The client presents the session token received with the stream configuration, then
+// both sides re-create the stream socket, without negotiating the stream configuration again. The
+// peers still authenticate each other, to derive new session keys.
+// Time is passed explicitly to ResumeStateMachine, so that it can be tested without waiting.
+
+use crate::crypto::{self, NONCE_SIZE};
+use alvr_common::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+pub const RESUME_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SessionToken([u8; NONCE_SIZE]);
+
+impl SessionToken {
+    pub fn new_random() -> StrResult<Self> {
+        Ok(Self(crypto::random_nonce()?))
+    }
+}
+
+// How a streaming connection ended
+pub enum ConnectionEnd {
+    // On purpose, for example because the server is restarting. The stream cannot be resumed
+    Closed,
+    // Because of a network error. The stream can be resumed
+    Lost,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectionState {
+    Disconnected,
+    Streaming,
+    Resuming {
+        deadline: Instant,
+        next_attempt: Instant,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResumeAction {
+    TryResume,
+    Wait(Duration),
+    GiveUp,
+}
+
+pub struct ResumeStateMachine {
+    state: ConnectionState,
+    timeout: Duration,
+    retry_interval: Duration,
+}
+
+impl ResumeStateMachine {
+    pub fn new(timeout: Duration, retry_interval: Duration) -> Self {
+        Self {
+            state: ConnectionState::Disconnected,
+            timeout,
+            retry_interval,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn connected(&mut self) {
+        self.state = ConnectionState::Streaming;
+    }
+
+    // Only a stream which was established can be resumed
+    pub fn connection_lost(&mut self, now: Instant) {
+        if self.state == ConnectionState::Streaming {
+            self.state = ConnectionState::Resuming {
+                deadline: now + self.timeout,
+                next_attempt: now,
+            };
+        }
+    }
+
+    pub fn attempt_failed(&mut self, now: Instant) {
+        if let ConnectionState::Resuming { next_attempt, .. } = &mut self.state {
+            *next_attempt = now + self.retry_interval;
+        }
+    }
+
+    // The session token is valid only while resuming, until the timeout
+    pub fn validate_token(
+        &self,
+        session_token: SessionToken,
+        requested_token: SessionToken,
+        now: Instant,
+    ) -> StrResult {
+        match self.state {
+            ConnectionState::Resuming { deadline, .. } if now < deadline => (),
+            _ => return fmt_e!("The session token is expired"),
+        }
+
+        if requested_token == session_token {
+            Ok(())
+        } else {
+            fmt_e!("Invalid session token")
+        }
+    }
+
+    // TryResume is returned until either connected() or attempt_failed() is called. Once the
+    // timeout expires, the state machine gives up and goes back to Disconnected.
+    pub fn poll(&mut self, now: Instant) -> ResumeAction {
+        match self.state {
+            ConnectionState::Resuming { deadline, .. } if now >= deadline => {
+                self.state = ConnectionState::Disconnected;
+
+                ResumeAction::GiveUp
+            }
+            ConnectionState::Resuming { next_attempt, .. } if now >= next_attempt => {
+                ResumeAction::TryResume
+            }
+            ConnectionState::Resuming {
+                deadline,
+                next_attempt,
+            } => ResumeAction::Wait(Instant::min(deadline, next_attempt) - now),
+            ConnectionState::Disconnected => ResumeAction::GiveUp,
+            // Nothing to resume yet
+            ConnectionState::Streaming => ResumeAction::Wait(self.retry_interval),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_resume_before_timeout() {
+        let mut machine = ResumeStateMachine::new(1000 * MS, 300 * MS);
+        let start = Instant::now();
+
+        // A connection that was never established is not resumed
+        machine.connection_lost(start);
+        assert_eq!(machine.poll(start), ResumeAction::GiveUp);
+
+        machine.connected();
+        machine.connection_lost(start);
+        assert_eq!(machine.poll(start), ResumeAction::TryResume);
+
+        machine.attempt_failed(start + 100 * MS);
+        assert_eq!(machine.poll(start + 100 * MS), ResumeAction::Wait(300 * MS));
+        assert_eq!(machine.poll(start + 400 * MS), ResumeAction::TryResume);
+
+        machine.connected();
+        assert_eq!(machine.state(), ConnectionState::Streaming);
+    }
+
+    #[test]
+    fn test_validate_token() {
+        let mut machine = ResumeStateMachine::new(1000 * MS, 300 * MS);
+        let start = Instant::now();
+        let token = SessionToken::new_random().unwrap();
+        let other_token = SessionToken::new_random().unwrap();
+
+        machine.connected();
+        assert!(machine.validate_token(token, token, start).is_err());
+
+        machine.connection_lost(start);
+        assert!(machine.validate_token(token, token, start).is_ok());
+        assert!(machine.validate_token(token, other_token, start).is_err());
+        assert!(machine
+            .validate_token(token, token, start + 1000 * MS)
+            .is_err());
+    }
+
+    #[test]
+    fn test_resume_timeout() {
+        let mut machine = ResumeStateMachine::new(1000 * MS, 300 * MS);
+        let start = Instant::now();
+
+        machine.connected();
+        machine.connection_lost(start);
+
+        // The wait never goes past the deadline
+        machine.attempt_failed(start + 800 * MS);
+        assert_eq!(machine.poll(start + 900 * MS), ResumeAction::Wait(100 * MS));
+
+        assert_eq!(machine.poll(start + 1000 * MS), ResumeAction::GiveUp);
+        assert_eq!(machine.state(), ConnectionState::Disconnected);
+
+        // Losing the connection again has no effect until the next connection
+        machine.connection_lost(start + 1100 * MS);
+        assert_eq!(machine.poll(start + 1100 * MS), ResumeAction::GiveUp);
+    }
+}
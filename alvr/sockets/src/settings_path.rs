@@ -0,0 +1,270 @@
+// Paths to values of the session, used to set values from the dashboard and the web API.
+//
+// Grammar: names are separated by dots and indices are written in brackets, like
+// `session_settings.headset.extra_openvr_props.content[2].value`. Names containing dots, brackets
+// or quotes, like client hostnames, are written quoted in brackets, like
+// `client_connections["client.alvr"].trusted`. Inside quotes, `"` and `\` are escaped with `\`.
+
+use alvr_common::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Name(String),
+    Index(usize),
+}
+
+impl Debug for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Name(name) => write!(f, "{}", name),
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+impl From<&str> for PathSegment {
+    fn from(value: &str) -> Self {
+        PathSegment::Name(value.to_owned())
+    }
+}
+
+impl From<String> for PathSegment {
+    fn from(value: String) -> Self {
+        PathSegment::Name(value)
+    }
+}
+
+impl From<usize> for PathSegment {
+    fn from(value: usize) -> Self {
+        PathSegment::Index(value)
+    }
+}
+
+fn is_plain_name_char(c: char) -> bool {
+    !matches!(c, '.' | '[' | ']' | '"' | '\\')
+}
+
+struct PathParser<'a> {
+    path: &'a str,
+    position: usize,
+}
+
+impl PathParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.path[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> StrResult {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => self.error(&format!("expected '{expected}', found '{c}'")),
+            None => self.error(&format!("expected '{expected}', found the end of the path")),
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> StrResult<T> {
+        fmt_e!(
+            "Invalid path \"{}\" at position {}: {message}",
+            self.path,
+            self.position
+        )
+    }
+
+    fn parse_name(&mut self) -> StrResult<PathSegment> {
+        let rest = &self.path[self.position..];
+        let len = rest.find(|c| !is_plain_name_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return self.error("expected a name");
+        }
+        self.position += len;
+
+        Ok(PathSegment::Name(rest[..len].to_owned()))
+    }
+
+    // Parses the content of the brackets, after '['
+    fn parse_bracket(&mut self) -> StrResult<PathSegment> {
+        let segment = if self.peek() == Some('"') {
+            self.position += 1;
+
+            let mut name = String::new();
+            loop {
+                match self.peek() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        self.position += 1;
+                        match self.peek() {
+                            Some(c @ ('"' | '\\')) => {
+                                name.push(c);
+                                self.position += 1;
+                            }
+                            _ => return self.error("expected '\"' or '\\' after '\\'"),
+                        }
+                    }
+                    Some(c) => {
+                        name.push(c);
+                        self.position += c.len_utf8();
+                    }
+                    None => return self.error("unterminated quoted name"),
+                }
+            }
+            self.expect('"')?;
+
+            PathSegment::Name(name)
+        } else {
+            let rest = &self.path[self.position..];
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return self.error("expected an index or a quoted name");
+            }
+            let index = rest[..len].parse().map_err(err!())?;
+            self.position += len;
+
+            PathSegment::Index(index)
+        };
+        self.expect(']')?;
+
+        Ok(segment)
+    }
+}
+
+// An empty string is the path of the whole session
+pub fn parse_path(path: &str) -> StrResult<Vec<PathSegment>> {
+    let mut parser = PathParser { path, position: 0 };
+
+    let mut segments = vec![];
+    while let Some(c) = parser.peek() {
+        let segment = match c {
+            '[' => {
+                parser.position += 1;
+                parser.parse_bracket()?
+            }
+            '.' if !segments.is_empty() => {
+                parser.position += 1;
+                parser.parse_name()?
+            }
+            _ if segments.is_empty() => parser.parse_name()?,
+            c => return parser.error(&format!("expected '.' or '[', found '{c}'")),
+        };
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+pub fn format_path(path: &[PathSegment]) -> String {
+    let mut string = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Name(name) if !name.is_empty() && name.chars().all(is_plain_name_char) => {
+                if !string.is_empty() {
+                    string.push('.');
+                }
+                string.push_str(name);
+            }
+            PathSegment::Name(name) => {
+                let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+                string.push_str(&format!("[\"{escaped}\"]"));
+            }
+            PathSegment::Index(index) => string.push_str(&format!("[{index}]")),
+        }
+    }
+
+    string
+}
+
+// Serializes paths as strings. The list of segments is accepted too.
+pub(crate) mod serde_path {
+    use super::*;
+    use serde::{de, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PathRepr {
+        String(String),
+        Segments(Vec<PathSegment>),
+    }
+
+    pub fn serialize<S: Serializer>(
+        path: &[PathSegment],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_path(path))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PathSegment>, D::Error> {
+        match PathRepr::deserialize(deserializer)? {
+            PathRepr::String(path) => parse_path(&path).map_err(de::Error::custom),
+            PathRepr::Segments(segments) => Ok(segments),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = parse_path(
+            r#"session_settings.headset.extra_openvr_props.content[2].value["a.b\"c"][0]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            path,
+            vec![
+                "session_settings".into(),
+                "headset".into(),
+                "extra_openvr_props".into(),
+                "content".into(),
+                2.into(),
+                "value".into(),
+                r#"a.b"c"#.into(),
+                0.into(),
+            ]
+        );
+        assert_eq!(parse_path(&format_path(&path)).unwrap(), path);
+
+        let path = vec!["client_connections".into(), "client.alvr".into()];
+        assert_eq!(format_path(&path), r#"client_connections["client.alvr"]"#);
+        assert_eq!(parse_path(&format_path(&path)).unwrap(), path);
+
+        assert!(parse_path("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_serde() {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "serde_path")] Vec<PathSegment>);
+
+        let path = vec!["video".into(), "preferred_fps".into()];
+        let json = serde_json::to_string(&Wrapper(path.clone())).unwrap();
+        assert_eq!(json, r#""video.preferred_fps""#);
+        assert_eq!(serde_json::from_str::<Wrapper>(&json).unwrap().0, path);
+
+        let json = r#"[{"Name":"video"},{"Name":"preferred_fps"}]"#;
+        assert_eq!(serde_json::from_str::<Wrapper>(json).unwrap().0, path);
+
+        assert!(serde_json::from_str::<Wrapper>(r#""video..fps""#).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        for path in ["a..b", "a.", ".a", "a[b]", "a[1", "a[\"b]", "a[1]b", "a]"] {
+            assert!(parse_path(path).is_err(), "{path}");
+        }
+
+        assert_eq!(
+            parse_path("video[x]").unwrap_err(),
+            "Invalid path \"video[x]\" at position 6: expected an index or a quoted name"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+// Capture of the shards received by a stream socket, for reproducing user traffic offline.
+// Shards are stored decrypted, so the capture does not contain any session key. Shards that fail
+// authentication are not stored, the receiver would drop them anyway. Retransmission requests are
+// not stored either. The capture is written by a separate thread, so the receive loop is never
+// blocked on disk writes.
+// A capture can be replayed with replay_capture(), which returns a stream socket that delivers the
+// shards with the original timing. The shards are encrypted again with a random key.
+//
+// file layout:
+// header: [ 8B (magic) | 4B (reorder window size) ]
+// record: [ 4B (microseconds since the previous record) | 2B (stream ID) | 4B (size) | shard ]
+
+use super::{
+    enqueue_packet, shard_nonce, StreamReceiveSocket, StreamSendSocket, StreamSocket,
+    NACK_STREAM_ID,
+};
+use crate::{
+    crypto::{ChannelKeys, PacketCipher, TAG_SIZE},
+    QueueSender,
+};
+use alvr_common::prelude::*;
+use bytes::{Buf, BufMut, BytesMut};
+use ring::aead::Nonce;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    sync::Mutex,
+    time::{self, Instant},
+};
+
+const MAGIC: &[u8; 8] = b"ALVRCAP2";
+const RECORD_HEADER_SIZE: usize = 4 + 2 + 4;
+// Shard header without the stream ID
+const SHARD_HEADER_SIZE: usize = 4 + 4 + 4 + 4;
+// Shards are received as single UDP datagrams
+const MAX_SHARD_SIZE: usize = 65507;
+
+// Nonce and additional authenticated data of a shard. `shard` starts after the stream ID
+fn shard_crypto_params(stream_id: u16, shard: &[u8]) -> (Nonce, Vec<u8>) {
+    let mut header = &shard[..SHARD_HEADER_SIZE];
+    let packet_index = header.get_u32();
+    header.advance(4 + 4);
+    let shard_index = header.get_u32();
+
+    (
+        shard_nonce(stream_id, packet_index, shard_index),
+        [&stream_id.to_be_bytes(), &shard[..SHARD_HEADER_SIZE]].concat(),
+    )
+}
+
+fn write_loop(
+    mut file: BufWriter<File>,
+    receive_cipher: PacketCipher,
+    reorder_window_size: usize,
+    start_instant: Instant,
+    record_receiver: mpsc::Receiver<(Instant, u16, BytesMut)>,
+) -> StrResult {
+    file.write_all(MAGIC).map_err(err!())?;
+    file.write_all(&(reorder_window_size as u32).to_be_bytes())
+        .map_err(err!())?;
+
+    let mut last_record_instant = start_instant;
+    for (instant, stream_id, mut shard) in record_receiver {
+        if stream_id == NACK_STREAM_ID || shard.len() < SHARD_HEADER_SIZE + TAG_SIZE {
+            continue;
+        }
+
+        let mut payload = shard.split_off(SHARD_HEADER_SIZE);
+        let (nonce, aad) = shard_crypto_params(stream_id, &shard);
+        if receive_cipher.open(nonce, &aad, &mut payload).is_err() {
+            continue;
+        }
+        shard.unsplit(payload);
+
+        let elapsed_us = (instant - last_record_instant).as_micros();
+        last_record_instant = instant;
+
+        let mut record_header = BytesMut::with_capacity(RECORD_HEADER_SIZE);
+        record_header.put_u32(elapsed_us.min(u32::MAX as _) as _);
+        record_header.put_u16(stream_id);
+        record_header.put_u32(shard.len() as _);
+
+        file.write_all(&record_header).map_err(err!())?;
+        file.write_all(&shard).map_err(err!())?;
+    }
+
+    file.flush().map_err(err!())
+}
+
+pub struct CaptureWriter {
+    // None after the write thread stopped
+    record_sender: Option<mpsc::Sender<(Instant, u16, BytesMut)>>,
+    write_thread: Option<JoinHandle<()>>,
+}
+
+impl CaptureWriter {
+    pub fn new(
+        path: &Path,
+        receive_cipher: &PacketCipher,
+        reorder_window_size: usize,
+    ) -> StrResult<Self> {
+        let file = BufWriter::new(File::create(path).map_err(err!())?);
+
+        let (record_sender, record_receiver) = mpsc::channel();
+        let receive_cipher = receive_cipher.clone();
+        let start_instant = Instant::now();
+        let write_thread = thread::spawn(move || {
+            if let Err(e) = write_loop(
+                file,
+                receive_cipher,
+                reorder_window_size,
+                start_instant,
+                record_receiver,
+            ) {
+                warn!("Stopping stream capture. {e}");
+            }
+        });
+
+        Ok(Self {
+            record_sender: Some(record_sender),
+            write_thread: Some(write_thread),
+        })
+    }
+
+    // Errors stop the capture, without interrupting the stream
+    pub fn write_shard(&mut self, stream_id: u16, shard: &[u8]) {
+        if let Some(sender) = &self.record_sender {
+            if sender
+                .send((Instant::now(), stream_id, BytesMut::from(shard)))
+                .is_err()
+            {
+                self.record_sender = None;
+            }
+        }
+    }
+}
+
+impl Drop for CaptureWriter {
+    // Wait for the queued records to be written, so the capture is complete once the receive loop
+    // is dropped
+    fn drop(&mut self) {
+        self.record_sender = None;
+        if let Some(thread) = self.write_thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+pub struct CaptureReader {
+    file: BufReader<tokio::fs::File>,
+    cipher: PacketCipher,
+}
+
+impl CaptureReader {
+    // Returns None at the end of the capture
+    async fn read_record(&mut self) -> StrResult<Option<(Duration, u16, BytesMut)>> {
+        let mut record_header = [0; RECORD_HEADER_SIZE];
+        match self.file.read_exact(&mut record_header).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return fmt_e!("{e}"),
+        }
+
+        let mut record_header = &record_header[..];
+        let elapsed = Duration::from_micros(record_header.get_u32() as _);
+        let stream_id = record_header.get_u16();
+        let size = record_header.get_u32() as usize;
+
+        if !(SHARD_HEADER_SIZE..=MAX_SHARD_SIZE).contains(&size) {
+            return fmt_e!("Corrupted stream capture");
+        }
+
+        // The capture is truncated if the recording was interrupted while writing
+        let mut shard = BytesMut::zeroed(size);
+        match self.file.read_exact(&mut shard).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                warn!("Stream capture is truncated. Ignoring the last record");
+                return Ok(None);
+            }
+            Err(e) => return fmt_e!("{e}"),
+        }
+
+        let mut payload = shard.split_off(SHARD_HEADER_SIZE);
+        let (nonce, aad) = shard_crypto_params(stream_id, &shard);
+        self.cipher.seal(nonce, &aad, &mut payload)?;
+        shard.unsplit(payload);
+
+        Ok(Some((elapsed, stream_id, shard)))
+    }
+}
+
+// Open a capture for replay. Subscribe to the streams of interest, then run receive_loop(), which
+// returns when all shards have been delivered. After that, receiving from a stream fails once the
+// remaining packets are consumed. Packets sent from this socket are discarded.
+pub fn replay_capture(path: &Path) -> StrResult<StreamSocket> {
+    let mut file = File::open(path).map_err(err!())?;
+
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic).map_err(err!())?;
+    if &magic != MAGIC {
+        return fmt_e!("Not a stream capture: {}", path.display());
+    }
+
+    let mut reorder_window_size = [0; 4];
+    file.read_exact(&mut reorder_window_size).map_err(err!())?;
+
+    let cipher = PacketCipher::random()?;
+
+    Ok(StreamSocket::new(
+        StreamSendSocket::Replay,
+        StreamReceiveSocket::Replay(CaptureReader {
+            file: BufReader::new(tokio::fs::File::from_std(file)),
+            cipher: cipher.clone(),
+        }),
+        0,
+        None,
+        None,
+        u32::from_be_bytes(reorder_window_size) as _,
+        ChannelKeys {
+            // Nothing is sent, any key works
+            send: cipher.clone(),
+            receive: cipher,
+        },
+    ))
+}
+
+async fn replay_records(
+    reader: &mut CaptureReader,
+    packet_enqueuers: &Mutex<HashMap<u16, QueueSender<BytesMut>>>,
+) -> StrResult {
+    let mut deadline = Instant::now();
+    while let Some((elapsed, stream_id, shard)) = reader.read_record().await? {
+        deadline += elapsed;
+        time::sleep_until(deadline).await;
+
+        enqueue_packet(packet_enqueuers, stream_id, shard).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn receive_loop(
+    mut reader: CaptureReader,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+) -> StrResult {
+    let res = replay_records(&mut reader, &packet_enqueuers).await;
+
+    // Close the streams, so the receivers return an error once the queued shards are consumed.
+    // This is done on errors too, otherwise the receivers would wait forever
+    packet_enqueuers.lock().await.clear();
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        loopback_pair, ImpairmentConfig, RandomImpairment, ReceiverBuffer, VIDEO, VIDEO_QUEUE,
+    };
+
+    // The capture is complete once this returns, because the receive loop is dropped
+    async fn write_capture(path: &Path) {
+        let (server_socket, client_socket) = loopback_pair(
+            100,
+            None,
+            None,
+            1,
+            Box::new(RandomImpairment::new(ImpairmentConfig::default())),
+            Box::new(RandomImpairment::new(ImpairmentConfig::default())),
+        )
+        .unwrap();
+        client_socket.capture_to_file(path).await.unwrap();
+
+        let mut sender = server_socket.request_stream::<u32>(VIDEO).await.unwrap();
+        let mut receiver = client_socket
+            .subscribe_to_stream::<u32>(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+
+        // Packets are sent 10ms apart and span multiple shards
+        let stream = async {
+            let mut buffer = ReceiverBuffer::new();
+            for index in 0..3 {
+                sender.send(&index, vec![index as u8; 250]).await.unwrap();
+                receiver.recv_buffer(&mut buffer).await.unwrap();
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::select! {
+            _ = stream => (),
+            res = client_socket.receive_loop() => panic!("{res:?}"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_capture_and_replay() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("capture.bin");
+        write_capture(&path).await;
+
+        let replay_socket = replay_capture(&path).unwrap();
+        let mut receiver = replay_socket
+            .subscribe_to_stream::<u32>(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        let replay = async {
+            let mut buffer = ReceiverBuffer::new();
+            for index in 0..3 {
+                receiver.recv_buffer(&mut buffer).await.unwrap();
+                let (header, data) = buffer.get().unwrap();
+
+                assert_eq!(header, index);
+                assert_eq!(data, vec![index as u8; 250]);
+                assert_eq!(
+                    Instant::now() - start,
+                    Duration::from_millis(10 * index as u64)
+                );
+            }
+
+            // the stream is closed at the end of the capture
+            assert!(receiver.recv_buffer(&mut buffer).await.is_err());
+        };
+        let (_, res) = tokio::join!(replay, replay_socket.receive_loop());
+        res.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_truncated_capture() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("capture.bin");
+        write_capture(&path).await;
+
+        // Cut the last shard
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 10).unwrap();
+
+        let replay_socket = replay_capture(&path).unwrap();
+        let mut receiver = replay_socket
+            .subscribe_to_stream::<u32>(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+
+        let replay = async {
+            let mut buffer = ReceiverBuffer::new();
+            for index in 0..2 {
+                receiver.recv_buffer(&mut buffer).await.unwrap();
+                assert_eq!(buffer.get().unwrap().0, index);
+            }
+
+            // the last packet is incomplete
+            assert!(receiver.recv_buffer(&mut buffer).await.is_err());
+        };
+        let (_, res) = tokio::join!(replay, replay_socket.receive_loop());
+        res.unwrap();
+    }
+}
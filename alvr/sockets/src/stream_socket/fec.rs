@@ -0,0 +1,203 @@
+// Forward error correction based on interleaved XOR parity.
+// Parity shard `p` protects all data shards with index `i` where `i % parity_shards_count == p`.
+// Interleaving spreads a burst of consecutive lost shards over different groups. Each group can
+// recover at most one missing data shard.
+//
+// parity shard layout:
+// [ 2B (XOR of the data shard lengths) | XOR of the zero-padded data shards ]
+
+use bytes::{Buf, BytesMut};
+use std::collections::HashMap;
+
+pub const PARITY_PREFIX_SIZE: usize = 2;
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    for (target, source) in target.iter_mut().zip(source) {
+        *target ^= source;
+    }
+}
+
+fn xor_shard_into(parity: &mut [u8], shard: &[u8]) {
+    xor_into(
+        &mut parity[..PARITY_PREFIX_SIZE],
+        &(shard.len() as u16).to_be_bytes(),
+    );
+    xor_into(&mut parity[PARITY_PREFIX_SIZE..], shard);
+}
+
+pub fn parity_shards_count(data_shards_count: usize, redundancy_ratio: f32) -> usize {
+    if redundancy_ratio > 0.0 {
+        ((data_shards_count as f32 * redundancy_ratio).ceil() as usize).clamp(1, data_shards_count)
+    } else {
+        0
+    }
+}
+
+pub fn encode(data_shards: &[&[u8]], parity_shards_count: usize) -> Vec<Vec<u8>> {
+    (0..parity_shards_count)
+        .map(|parity_index| {
+            let group = data_shards
+                .iter()
+                .skip(parity_index)
+                .step_by(parity_shards_count);
+
+            let max_shard_size = group.clone().map(|shard| shard.len()).max().unwrap_or(0);
+
+            let mut parity = vec![0; PARITY_PREFIX_SIZE + max_shard_size];
+            for shard in group {
+                xor_shard_into(&mut parity, shard);
+            }
+
+            parity
+        })
+        .collect()
+}
+
+// Rebuild the missing data shards where possible. Parity shards are stored right after the data
+// shards, at index data_shards_count + parity_index.
+// Returns true if all data shards are available.
+pub fn recover(
+    shards: &mut HashMap<usize, BytesMut>,
+    data_shards_count: usize,
+    parity_shards_count: usize,
+) -> bool {
+    for parity_index in 0..parity_shards_count {
+        let group = (parity_index..data_shards_count).step_by(parity_shards_count);
+
+        let mut missing_indices = group.clone().filter(|index| !shards.contains_key(index));
+        let missing_index = match (missing_indices.next(), missing_indices.next()) {
+            (Some(index), None) => index,
+            _ => continue,
+        };
+
+        let mut recovered = if let Some(parity) = shards.get(&(data_shards_count + parity_index)) {
+            parity.clone()
+        } else {
+            continue;
+        };
+        if recovered.len() < PARITY_PREFIX_SIZE {
+            continue;
+        }
+
+        for index in group.filter(|index| *index != missing_index) {
+            xor_shard_into(&mut recovered, &shards[&index]);
+        }
+
+        let shard_size = recovered.get_u16() as usize;
+        recovered.truncate(shard_size);
+
+        shards.insert(missing_index, recovered);
+    }
+
+    (0..data_shards_count).all(|index| shards.contains_key(&index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{seq::index, Rng, SeedableRng};
+
+    fn make_shards(rng: &mut impl Rng, count: usize, max_size: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|index| {
+                // the last shard is usually shorter
+                let size = if index == count - 1 {
+                    rng.gen_range(1..=max_size)
+                } else {
+                    max_size
+                };
+                (0..size).map(|_| rng.gen()).collect()
+            })
+            .collect()
+    }
+
+    fn encode_all(data_shards: &[Vec<u8>], parity_shards_count: usize) -> Vec<Vec<u8>> {
+        let data_refs = data_shards.iter().map(|s| s.as_slice()).collect::<Vec<_>>();
+
+        data_shards
+            .iter()
+            .cloned()
+            .chain(encode(&data_refs, parity_shards_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_parity_shards_count() {
+        assert_eq!(parity_shards_count(10, 0.0), 0);
+        assert_eq!(parity_shards_count(1, 0.1), 1);
+        assert_eq!(parity_shards_count(10, 0.25), 3);
+        assert_eq!(parity_shards_count(4, 2.0), 4);
+    }
+
+    #[test]
+    fn test_recover_single_loss_per_group() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let data_shards_count = rng.gen_range(1..50);
+            let parity_shards_count = parity_shards_count(data_shards_count, 0.2);
+            let data_shards = make_shards(&mut rng, data_shards_count, 64);
+            let all_shards = encode_all(&data_shards, parity_shards_count);
+
+            // drop at most one random data shard per parity group
+            let mut shards = all_shards
+                .iter()
+                .map(|s| BytesMut::from(s.as_slice()))
+                .enumerate()
+                .collect::<HashMap<_, _>>();
+            for parity_index in 0..parity_shards_count {
+                let group = (parity_index..data_shards_count)
+                    .step_by(parity_shards_count)
+                    .collect::<Vec<_>>();
+                let index = group[rng.gen_range(0..group.len())];
+                shards.remove(&index);
+            }
+
+            assert!(recover(&mut shards, data_shards_count, parity_shards_count));
+            for (index, shard) in data_shards.iter().enumerate() {
+                assert_eq!(&shards[&index][..], shard.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn test_recover_random_drops() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        for _ in 0..100 {
+            let data_shards_count = rng.gen_range(1..50);
+            let parity_shards_count = parity_shards_count(data_shards_count, 0.3);
+            let data_shards = make_shards(&mut rng, data_shards_count, 64);
+            let all_shards = encode_all(&data_shards, parity_shards_count);
+
+            let drop_count = rng.gen_range(0..=all_shards.len() / 4);
+            let dropped = index::sample(&mut rng, all_shards.len(), drop_count).into_vec();
+
+            let mut shards = all_shards
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !dropped.contains(index))
+                .map(|(index, s)| (index, BytesMut::from(s.as_slice())))
+                .collect::<HashMap<_, _>>();
+
+            let recoverable = (0..parity_shards_count).all(|parity_index| {
+                let lost_data = (parity_index..data_shards_count)
+                    .step_by(parity_shards_count)
+                    .filter(|index| dropped.contains(index))
+                    .count();
+                lost_data == 0
+                    || (lost_data == 1 && !dropped.contains(&(data_shards_count + parity_index)))
+            });
+
+            assert_eq!(
+                recover(&mut shards, data_shards_count, parity_shards_count),
+                recoverable
+            );
+            if recoverable {
+                for (index, shard) in data_shards.iter().enumerate() {
+                    assert_eq!(&shards[&index][..], shard.as_slice());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// Network impairment models, used by the loopback transport and by the network proxy. A model
+// decides if and when each packet is delivered.
+// Impairments are random but deterministic: the same seed produces the same sequence of decisions.
+
+use super::ShardHeader;
+use alvr_common::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+// Number of packets for which the drop decision of a whole frame is remembered. The oldest
+// decisions are forgotten first
+const MAX_FRAME_DECISIONS: usize = 256;
+
+pub trait ImpairmentModel: Send {
+    // Returns when the copies of a packet sent at `now` are delivered. Returns an empty list if
+    // the packet is dropped.
+    fn schedule(&mut self, packet: &[u8], now: Instant) -> Vec<Instant>;
+}
+
+#[derive(Clone, Default)]
+pub struct ImpairmentConfig {
+    // Probability that a loss burst starts at a packet. All rates are in the range [0, 1]
+    pub drop_rate: f32,
+    // Number of consecutive packets dropped by a loss burst. 0 is the same as 1
+    pub drop_burst_length: usize,
+    pub duplication_rate: f32,
+    // A reordered packet is delayed by `reorder_delay`, so the following packets overtake it
+    pub reorder_rate: f32,
+    pub reorder_delay: Duration,
+    pub delay: Duration,
+    // The delay varies uniformly in [delay - jitter, delay + jitter]
+    pub jitter: Duration,
+    // None for unlimited bandwidth
+    pub bandwidth_bytes_per_sec: Option<f32>,
+    // Probabilities of dropping all shards of a stream packet or a single shard
+    pub frame_drop_rate: f32,
+    pub shard_drop_rate: f32,
+    // Streams affected by frame and shard drops. Empty for all streams
+    pub stream_ids: Vec<u16>,
+    pub seed: u64,
+}
+
+pub struct RandomImpairment {
+    config: ImpairmentConfig,
+    random_state: u64,
+    burst_remaining_packets: usize,
+    // time at which the link finishes transmitting the queued packets
+    link_free_instant: Instant,
+    // (stream ID, packet index) -> drop
+    frame_decisions: HashMap<(u16, u32), bool>,
+    // keys of frame_decisions, from the oldest
+    frame_decisions_order: VecDeque<(u16, u32)>,
+}
+
+impl RandomImpairment {
+    pub fn new(config: ImpairmentConfig) -> Self {
+        Self {
+            // xorshift does not work with a zero state
+            random_state: config.seed.max(1),
+            config,
+            burst_remaining_packets: 0,
+            link_free_instant: Instant::now(),
+            frame_decisions: HashMap::new(),
+            frame_decisions_order: VecDeque::new(),
+        }
+    }
+
+    // xorshift64*, uniform in [0, 1)
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.random_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.random_state = x;
+
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1 << 24) as f32
+    }
+
+    // Random numbers are drawn only for the impairments that are enabled, so that enabling one
+    // does not change the decisions of the others for the same seed
+    fn chance(&mut self, rate: f32) -> bool {
+        rate > 0.0 && self.next_random() < rate
+    }
+
+    fn should_drop(&mut self, packet: &[u8]) -> bool {
+        if self.burst_remaining_packets > 0 {
+            self.burst_remaining_packets -= 1;
+            debug!("Dropped packet in loss burst");
+
+            return true;
+        }
+
+        if self.next_random() < self.config.drop_rate {
+            self.burst_remaining_packets = self.config.drop_burst_length.saturating_sub(1);
+            if self.burst_remaining_packets > 0 {
+                info!("Loss burst of {} packets", self.config.drop_burst_length);
+            }
+
+            return true;
+        }
+
+        if self.config.frame_drop_rate <= 0.0 && self.config.shard_drop_rate <= 0.0 {
+            return false;
+        }
+
+        let header = if let Some(header) = ShardHeader::parse(packet) {
+            header
+        } else {
+            return false;
+        };
+
+        if !self.config.stream_ids.is_empty() && !self.config.stream_ids.contains(&header.stream_id)
+        {
+            return false;
+        }
+
+        let key = (header.stream_id, header.packet_index);
+        let drop_frame = if let Some(drop) = self.frame_decisions.get(&key) {
+            *drop
+        } else {
+            let drop = self.chance(self.config.frame_drop_rate);
+            if drop {
+                info!(
+                    "Dropping packet {} of stream {} ({} data shards, {} parity shards)",
+                    header.packet_index,
+                    header.stream_id,
+                    header.data_shards_count,
+                    header.parity_shards_count
+                );
+            }
+
+            if self.frame_decisions_order.len() >= MAX_FRAME_DECISIONS {
+                if let Some(oldest_key) = self.frame_decisions_order.pop_front() {
+                    self.frame_decisions.remove(&oldest_key);
+                }
+            }
+            self.frame_decisions.insert(key, drop);
+            self.frame_decisions_order.push_back(key);
+
+            drop
+        };
+        if drop_frame {
+            return true;
+        }
+
+        if self.chance(self.config.shard_drop_rate) {
+            info!(
+                "Dropped shard {} of packet {} of stream {}",
+                header.shard_index, header.packet_index, header.stream_id
+            );
+
+            return true;
+        }
+
+        false
+    }
+}
+
+impl ImpairmentModel for RandomImpairment {
+    fn schedule(&mut self, packet: &[u8], now: Instant) -> Vec<Instant> {
+        let sent_instant = if let Some(bandwidth) = self.config.bandwidth_bytes_per_sec {
+            let start_instant = Instant::max(now, self.link_free_instant);
+            self.link_free_instant =
+                start_instant + Duration::from_secs_f32(packet.len() as f32 / bandwidth);
+
+            self.link_free_instant
+        } else {
+            now
+        };
+
+        if self.should_drop(packet) {
+            return vec![];
+        }
+
+        let mut delay = self.config.delay;
+        if self.config.jitter > Duration::ZERO {
+            let jitter = self.config.jitter.as_secs_f32() * (self.next_random() * 2.0 - 1.0);
+            delay = Duration::from_secs_f32((delay.as_secs_f32() + jitter).max(0.0));
+        }
+
+        let mut delivery_instant = sent_instant + delay;
+        if self.next_random() < self.config.reorder_rate {
+            delivery_instant += self.config.reorder_delay;
+        }
+
+        if self.next_random() < self.config.duplication_rate {
+            vec![delivery_instant, delivery_instant]
+        } else {
+            vec![delivery_instant]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    fn shard(stream_id: u16, packet_index: u32, shard_index: u32) -> Vec<u8> {
+        let mut packet = vec![];
+        packet.put_u16(stream_id);
+        packet.put_u32(packet_index);
+        packet.put_u32(4);
+        packet.put_u32(0);
+        packet.put_u32(shard_index);
+        packet.put_slice(&[0; 10]);
+
+        packet
+    }
+
+    #[test]
+    fn test_deterministic_impairment() {
+        let config = ImpairmentConfig {
+            drop_rate: 0.5,
+            seed: 7,
+            ..Default::default()
+        };
+        let now = Instant::now();
+
+        let mut first = RandomImpairment::new(config.clone());
+        let mut second = RandomImpairment::new(config);
+        let first_drops = (0..100)
+            .map(|_| first.schedule(&[0; 10], now).is_empty())
+            .collect::<Vec<_>>();
+        let second_drops = (0..100)
+            .map(|_| second.schedule(&[0; 10], now).is_empty())
+            .collect::<Vec<_>>();
+
+        assert_eq!(first_drops, second_drops);
+        assert!(first_drops.iter().any(|dropped| *dropped));
+        assert!(first_drops.iter().any(|dropped| !dropped));
+    }
+
+    #[test]
+    fn test_bandwidth_cap() {
+        let now = Instant::now();
+        let mut impairment = RandomImpairment::new(ImpairmentConfig {
+            bandwidth_bytes_per_sec: Some(1000.0),
+            delay: Duration::from_millis(5),
+            ..Default::default()
+        });
+
+        let first = impairment.schedule(&[0; 10], now)[0];
+        let second = impairment.schedule(&[0; 10], now)[0];
+
+        assert!(first >= now + Duration::from_millis(14));
+        assert!(second >= first + Duration::from_millis(9));
+    }
+
+    #[test]
+    fn test_loss_bursts() {
+        let now = Instant::now();
+        let mut impairment = RandomImpairment::new(ImpairmentConfig {
+            drop_rate: 0.1,
+            drop_burst_length: 3,
+            ..Default::default()
+        });
+
+        let dropped = (0..1000)
+            .map(|_| impairment.schedule(&[0; 100], now).is_empty())
+            .collect::<Vec<_>>();
+        assert!(dropped.contains(&true));
+
+        // Bursts can be back to back, so a run of drops can be longer than a burst
+        let mut run_length = 0;
+        for drop in dropped {
+            if drop {
+                run_length += 1;
+            } else {
+                assert!(run_length == 0 || run_length >= 3);
+                run_length = 0;
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_drops() {
+        let now = Instant::now();
+        let mut impairment = RandomImpairment::new(ImpairmentConfig {
+            frame_drop_rate: 0.5,
+            stream_ids: vec![3],
+            ..Default::default()
+        });
+
+        let mut dropped_packets_count = 0;
+        for packet_index in 0..100 {
+            let dropped = (0..4)
+                .map(|shard_index| {
+                    impairment
+                        .schedule(&shard(3, packet_index, shard_index), now)
+                        .is_empty()
+                })
+                .collect::<Vec<_>>();
+
+            // All shards of a packet share the same decision
+            assert!(dropped.iter().all(|drop| *drop == dropped[0]));
+            if dropped[0] {
+                dropped_packets_count += 1;
+            }
+        }
+        assert!(dropped_packets_count > 0 && dropped_packets_count < 100);
+
+        // Other streams and packets that are not shards are not affected
+        for packet_index in 0..100 {
+            assert!(!impairment
+                .schedule(&shard(4, packet_index, 0), now)
+                .is_empty());
+            assert!(!impairment.schedule(&[0; 4], now).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_frame_decisions_eviction() {
+        let now = Instant::now();
+        let mut impairment = RandomImpairment::new(ImpairmentConfig {
+            frame_drop_rate: 0.5,
+            ..Default::default()
+        });
+
+        let packets_count = MAX_FRAME_DECISIONS as u32 + 10;
+        let decisions = (0..packets_count)
+            .map(|packet_index| {
+                impairment
+                    .schedule(&shard(3, packet_index, 0), now)
+                    .is_empty()
+            })
+            .collect::<Vec<_>>();
+
+        // Only the oldest decisions are forgotten
+        assert_eq!(impairment.frame_decisions.len(), MAX_FRAME_DECISIONS);
+        assert!(!impairment.frame_decisions.contains_key(&(3, 9)));
+        for packet_index in 10..packets_count {
+            assert_eq!(
+                impairment.frame_decisions[&(3, packet_index)],
+                decisions[packet_index as usize]
+            );
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        let now = Instant::now();
+        let mut impairment = RandomImpairment::new(ImpairmentConfig {
+            delay: Duration::from_millis(50),
+            jitter: Duration::from_millis(40),
+            ..Default::default()
+        });
+
+        // The jitter reorders the packets, within the range of the delay
+        let delivery_instants = (0..100)
+            .map(|_| impairment.schedule(&[0; 100], now)[0])
+            .collect::<Vec<_>>();
+        assert!(delivery_instants.windows(2).any(|pair| pair[1] < pair[0]));
+        assert!(delivery_instants.iter().all(|instant| {
+            *instant >= now + Duration::from_millis(10)
+                && *instant <= now + Duration::from_millis(90)
+        }));
+    }
+}
@@ -0,0 +1,484 @@
+// In-memory transport, for tests. Packets are not sent over the network but passed to the other
+// socket through an impairment model, which decides if and when each packet is delivered. This
+// allows testing sharding, reassembly and packet loss handling without real sockets.
+
+use super::{
+    enqueue_packet, CaptureWriter, ImpairmentModel, StreamReceiveSocket, StreamSendSocket,
+    StreamSocket,
+};
+use crate::{
+    crypto::{self, SessionKeys},
+    QueueSender,
+};
+use alvr_common::prelude::*;
+use alvr_session::RetransmissionConfig;
+use bytes::{Buf, Bytes, BytesMut};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::{self, Instant},
+};
+
+struct Link {
+    impairment: Box<dyn ImpairmentModel>,
+    // Packets are delivered in order of delivery instant, then of scheduling
+    in_flight: BTreeMap<(Instant, u64), Bytes>,
+    next_sequence_index: u64,
+}
+
+#[derive(Clone)]
+pub struct LoopbackStreamSendSocket {
+    link: Arc<Mutex<Link>>,
+    notifier: Arc<Notify>,
+}
+
+impl LoopbackStreamSendSocket {
+    pub async fn send(&self, buffer: Bytes) {
+        let mut link = self.link.lock().await;
+
+        for instant in link.impairment.schedule(&buffer, Instant::now()) {
+            let sequence_index = link.next_sequence_index;
+            link.next_sequence_index += 1;
+
+            link.in_flight
+                .insert((instant, sequence_index), buffer.clone());
+        }
+
+        self.notifier.notify_one();
+    }
+}
+
+pub struct LoopbackStreamReceiveSocket {
+    link: Arc<Mutex<Link>>,
+    notifier: Arc<Notify>,
+}
+
+fn link(
+    impairment: Box<dyn ImpairmentModel>,
+) -> (LoopbackStreamSendSocket, LoopbackStreamReceiveSocket) {
+    let link = Arc::new(Mutex::new(Link {
+        impairment,
+        in_flight: BTreeMap::new(),
+        next_sequence_index: 0,
+    }));
+    let notifier = Arc::new(Notify::new());
+
+    (
+        LoopbackStreamSendSocket {
+            link: Arc::clone(&link),
+            notifier: Arc::clone(&notifier),
+        },
+        LoopbackStreamReceiveSocket { link, notifier },
+    )
+}
+
+// Two connected stream sockets. Each impairment model is applied to the packets sent by the
+// corresponding socket.
+pub fn loopback_pair(
+    max_packet_size: usize,
+    fec_redundancy_ratio: Option<f32>,
+    retransmission: Option<RetransmissionConfig>,
+    reorder_window_size: usize,
+    server_impairment: Box<dyn ImpairmentModel>,
+    client_impairment: Box<dyn ImpairmentModel>,
+) -> StrResult<(StreamSocket, StreamSocket)> {
+    let (private_key, _) = crypto::generate_key_pair()?;
+    let (_, public_key) = crypto::generate_key_pair()?;
+    let pairing_key = crypto::agree_pairing_key(private_key, &public_key)?;
+    let nonce = crypto::random_nonce()?;
+
+    let (server_send_socket, client_receive_socket) = link(server_impairment);
+    let (client_send_socket, server_receive_socket) = link(client_impairment);
+
+    let server_socket = StreamSocket::new(
+        StreamSendSocket::Loopback(server_send_socket),
+        StreamReceiveSocket::Loopback(server_receive_socket),
+        max_packet_size,
+        fec_redundancy_ratio,
+        retransmission.clone(),
+        reorder_window_size,
+        SessionKeys::derive(&pairing_key, &nonce, &nonce, true)?.stream,
+    );
+    let client_socket = StreamSocket::new(
+        StreamSendSocket::Loopback(client_send_socket),
+        StreamReceiveSocket::Loopback(client_receive_socket),
+        max_packet_size,
+        fec_redundancy_ratio,
+        retransmission,
+        reorder_window_size,
+        SessionKeys::derive(&pairing_key, &nonce, &nonce, false)?.stream,
+    );
+
+    Ok((server_socket, client_socket))
+}
+
+pub async fn receive_loop(
+    socket: LoopbackStreamReceiveSocket,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+    mut capture_writer: Option<CaptureWriter>,
+) -> StrResult {
+    loop {
+        let next_delivery = socket.link.lock().await.in_flight.keys().next().copied();
+
+        match next_delivery {
+            Some(key @ (instant, _)) if instant <= Instant::now() => {
+                let packet = socket
+                    .link
+                    .lock()
+                    .await
+                    .in_flight
+                    .remove(&key)
+                    .ok_or_else(enone!())?;
+
+                let mut packet = BytesMut::from(&packet[..]);
+                let stream_id = packet.get_u16();
+                if let Some(writer) = &mut capture_writer {
+                    writer.write_shard(stream_id, &packet);
+                }
+                enqueue_packet(&packet_enqueuers, stream_id, packet).await?;
+            }
+            Some((instant, _)) => {
+                // A packet sent in the meantime could be delivered earlier
+                tokio::select! {
+                    _ = time::sleep_until(instant) => (),
+                    _ = socket.notifier.notified() => (),
+                }
+            }
+            None => socket.notifier.notified().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::TAG_SIZE, stream_socket::NACK_STREAM_ID, DropPolicy, ImpairmentConfig, QueueConfig,
+        RandomImpairment, ReceiverBuffer, StreamReceiver, VIDEO, VIDEO_QUEUE,
+    };
+    use std::time::Duration;
+
+    const MAX_PACKET_SIZE: usize = 100;
+
+    struct NoImpairment;
+
+    impl ImpairmentModel for NoImpairment {
+        fn schedule(&mut self, _: &[u8], now: Instant) -> Vec<Instant> {
+            vec![now]
+        }
+    }
+
+    // Drop the first transmission of some shards, identified by packet index and shard index
+    struct DropShards(Vec<(u32, u32)>);
+
+    impl ImpairmentModel for DropShards {
+        fn schedule(&mut self, packet: &[u8], now: Instant) -> Vec<Instant> {
+            let mut header = &packet[2..];
+            let packet_index = header.get_u32();
+            header.advance(4 + 4);
+            let shard_index = header.get_u32();
+
+            if let Some(position) = self
+                .0
+                .iter()
+                .position(|shard| *shard == (packet_index, shard_index))
+            {
+                self.0.remove(position);
+
+                vec![]
+            } else {
+                vec![now]
+            }
+        }
+    }
+
+    fn payload(packet_index: u32) -> Vec<u8> {
+        (0..1000).map(|i| (i as u32 + packet_index) as u8).collect()
+    }
+
+    // Send `packets_count` packets from the server to the client, one every `send_interval`.
+    // Returns the index of the received packets and whether there was packet loss before each.
+    async fn transfer(
+        server_socket: StreamSocket,
+        client_socket: StreamSocket,
+        packets_count: u32,
+        send_interval: Duration,
+        mut receiver: StreamReceiver<u32>,
+    ) -> Vec<(u32, bool)> {
+        let mut sender = server_socket.request_stream::<u32>(VIDEO).await.unwrap();
+        assert!(server_socket.request_stream::<u32>(VIDEO).await.is_err());
+
+        let receive_packets = async {
+            let mut packets = vec![];
+            let mut buffer = ReceiverBuffer::new();
+            loop {
+                receiver.recv_buffer(&mut buffer).await.unwrap();
+                let (packet_index, data) = buffer.get().unwrap();
+                assert_eq!(data, payload(packet_index));
+
+                packets.push((packet_index, buffer.had_packet_loss()));
+
+                if packet_index == packets_count - 1 {
+                    break packets;
+                }
+            }
+        };
+
+        let send_packets = async {
+            for packet_index in 0..packets_count {
+                sender
+                    .send(&packet_index, payload(packet_index))
+                    .await
+                    .unwrap();
+                time::sleep(send_interval).await;
+            }
+        };
+
+        let (packets, _) = tokio::select! {
+            res = async { tokio::join!(receive_packets, send_packets) } => res,
+            res = server_socket.receive_loop() => panic!("{res:?}"),
+            res = client_socket.receive_loop() => panic!("{res:?}"),
+            _ = time::sleep(Duration::from_secs(5)) => panic!("Timeout"),
+        };
+
+        packets
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sharding() {
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            None,
+            1,
+            Box::new(NoImpairment),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 3, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(0, false), (1, false), (2, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reordering_and_duplication() {
+        let impairment = RandomImpairment::new(ImpairmentConfig {
+            duplication_rate: 0.3,
+            reorder_rate: 0.3,
+            reorder_delay: Duration::from_millis(2),
+            seed: 42,
+            ..Default::default()
+        });
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            None,
+            4,
+            Box::new(impairment),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(
+            server_socket,
+            client_socket,
+            10,
+            Duration::from_millis(1),
+            receiver,
+        )
+        .await;
+
+        assert_eq!(
+            packets,
+            (0..10).map(|index| (index, false)).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_packet_loss() {
+        // All shards of packet 1 are lost
+        let lost_shards = (0..20).map(|shard_index| (1, shard_index)).collect();
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            None,
+            1,
+            Box::new(DropShards(lost_shards)),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 3, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(0, false), (2, true)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_incomplete_packet_deadline() {
+        // Packet 0 can't be completed and no other packet follows packet 1
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            None,
+            4,
+            Box::new(DropShards(vec![(0, 2)])),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 2, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(1, true)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fec_recovery() {
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            Some(0.2),
+            None,
+            1,
+            Box::new(DropShards(vec![(0, 3)])),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 2, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(0, false), (1, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retransmission() {
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            Some(RetransmissionConfig {
+                deadline_ms: 1000,
+                history_size: 256,
+            }),
+            4,
+            Box::new(DropShards(vec![(0, 2), (0, 5)])),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream_with_retransmission(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 2, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(0, false), (1, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_malformed_retransmission_requests() {
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            Some(RetransmissionConfig {
+                deadline_ms: 1000,
+                history_size: 256,
+            }),
+            4,
+            Box::new(DropShards(vec![(0, 2)])),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        // Truncated header, then a request that does not authenticate. Both are dropped by the
+        // server and the following valid requests are still served
+        let nack_id = NACK_STREAM_ID.to_be_bytes();
+        for packet in [
+            [&nack_id[..], &[0]].concat(),
+            [&nack_id[..], &[0; 2 + 8 + TAG_SIZE + 4]].concat(),
+        ] {
+            client_socket.send_socket.feed(packet.into()).await;
+        }
+        client_socket.send_socket.flush().await.unwrap();
+
+        let receiver = client_socket
+            .subscribe_to_stream_with_retransmission(VIDEO, VIDEO_QUEUE)
+            .await
+            .unwrap();
+        let packets = transfer(server_socket, client_socket, 2, Duration::ZERO, receiver).await;
+
+        assert_eq!(packets, [(0, false), (1, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_overflow() {
+        let (server_socket, client_socket) = loopback_pair(
+            MAX_PACKET_SIZE,
+            None,
+            None,
+            1,
+            Box::new(NoImpairment),
+            Box::new(NoImpairment),
+        )
+        .unwrap();
+
+        // Each packet is split into 17 shards. The queue has room for one packet and a few shards of
+        // the previous one
+        let mut receiver = client_socket
+            .subscribe_to_stream::<u32>(
+                VIDEO,
+                QueueConfig {
+                    capacity: 20,
+                    policy: DropPolicy::DropOldest,
+                },
+            )
+            .await
+            .unwrap();
+        let mut sender = server_socket.request_stream::<u32>(VIDEO).await.unwrap();
+
+        // All shards arrive before the receiver starts consuming them
+        let send_packets = async {
+            for packet_index in 0..2 {
+                sender
+                    .send(&packet_index, payload(packet_index))
+                    .await
+                    .unwrap();
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        };
+        tokio::select! {
+            _ = send_packets => (),
+            res = client_socket.receive_loop() => panic!("{res:?}"),
+        }
+
+        let mut buffer = ReceiverBuffer::new();
+        receiver.recv_buffer(&mut buffer).await.unwrap();
+        let (packet_index, data) = buffer.get().unwrap();
+
+        assert_eq!(packet_index, 1);
+        assert_eq!(data, payload(1));
+        assert!(buffer.had_packet_loss());
+        assert_eq!(buffer.dropped_shards_count(), 14);
+    }
+}
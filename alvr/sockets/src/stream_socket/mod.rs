@@ -4,26 +4,141 @@
 // StreamSender and StreamReceiver endpoints allow for convenient conversion of the header to/from
 // bytes while still handling the additional byte buffer with zero copies and extra allocations.
 
+mod capture;
+mod fec;
+#[cfg(any(test, feature = "loopback"))]
+mod impairment;
+#[cfg(any(test, feature = "loopback"))]
+mod loopback;
+mod pacing;
+mod quic;
+mod reassembly;
 mod tcp;
 mod udp;
+#[cfg(unix)]
+mod unix;
 
+use crate::{
+    crypto::{self, ChannelKeys, PacketCipher, TAG_SIZE},
+    queue::{bounded_queue, DropPolicy, QueueConfig, QueueReceiver, QueueSender},
+};
 use alvr_common::prelude::*;
-use alvr_session::{SocketBufferSize, SocketProtocol};
-use bytes::{Buf, BufMut, BytesMut};
+use alvr_session::{RetransmissionConfig, SocketBufferSize, SocketProtocol};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use capture::{CaptureReader, CaptureWriter};
 use futures::SinkExt;
+#[cfg(any(test, feature = "loopback"))]
+pub use impairment::{ImpairmentConfig, ImpairmentModel, RandomImpairment};
+#[cfg(any(test, feature = "loopback"))]
+pub use loopback::loopback_pair;
+#[cfg(any(test, feature = "loopback"))]
+use loopback::{LoopbackStreamReceiveSocket, LoopbackStreamSendSocket};
+use pacing::Pacer;
+use quic::{QuicStreamReceiveSocket, QuicStreamSendSocket};
+use reassembly::ReassemblyWindow;
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
+    iter,
     marker::PhantomData,
-    mem,
     net::IpAddr,
     ops::{Deref, DerefMut},
+    path::Path,
     sync::Arc,
+    time::Duration,
 };
 use tcp::{TcpStreamReceiveSocket, TcpStreamSendSocket};
 use tokio::net;
-use tokio::sync::{mpsc, Mutex};
+use tokio::{
+    sync::Mutex,
+    time::{self, Instant},
+};
 use udp::{UdpStreamReceiveSocket, UdpStreamSendSocket};
+#[cfg(unix)]
+use unix::{UnixStreamReceiveSocket, UnixStreamSendSocket};
+
+pub use capture::replay_capture;
+
+// Reserved stream ID used by a StreamReceiver to request the retransmission of lost shards.
+// packet layout:
+// [ 2B (requested stream ID) | 8B (request index) | 4B (packet index) | N * 4B (shard indices) ]
+// If no shard index is specified, all shards of the packet are requested. The part after the
+// request index is encrypted.
+const NACK_STREAM_ID: u16 = u16::MAX;
+const NACK_QUEUE: QueueConfig = QueueConfig {
+    capacity: 64,
+    policy: DropPolicy::DropOldest,
+};
+
+// How long a StreamReceiver waits for the missing shards of a packet once a newer packet is
+// complete, if retransmission is not enabled. Sparse streams could not make progress otherwise.
+const REORDER_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Plaintext header at the start of every shard. The packet layout is described in
+// StreamSender::send
+#[derive(Clone, Copy, Debug)]
+pub struct ShardHeader {
+    pub stream_id: u16,
+    pub packet_index: u32,
+    pub data_shards_count: u32,
+    pub parity_shards_count: u32,
+    pub shard_index: u32,
+}
+
+impl ShardHeader {
+    pub const SIZE: usize = 2 + 4 + 4 + 4 + 4;
+
+    // Returns None if the packet is not a shard, like retransmission requests
+    pub fn parse(mut packet: &[u8]) -> Option<Self> {
+        if packet.len() < Self::SIZE {
+            return None;
+        }
+
+        let stream_id = packet.get_u16();
+        if stream_id == NACK_STREAM_ID {
+            return None;
+        }
+
+        Some(Self {
+            stream_id,
+            packet_index: packet.get_u32(),
+            data_shards_count: packet.get_u32(),
+            parity_shards_count: packet.get_u32(),
+            shard_index: packet.get_u32(),
+        })
+    }
+}
+
+// Number of shards that a paced sender can send back to back
+const PACING_BURST_SHARDS: usize = 4;
+
+// Nonce prefix for retransmission requests. It cannot collide with the prefix of shards, which is
+// the stream ID.
+fn nack_nonce_prefix(stream_id: u16) -> u32 {
+    ((NACK_STREAM_ID as u32) << 16) | stream_id as u32
+}
+
+fn shard_nonce(stream_id: u16, packet_index: u32, shard_index: u32) -> ring::aead::Nonce {
+    crypto::packet_nonce(
+        stream_id as _,
+        ((packet_index as u64) << 32) | shard_index as u64,
+    )
+}
+
+// Push a received packet to the queue of its stream, if subscribed. The map is not locked while
+// waiting for space in the queue, so the other streams can still be subscribed to
+async fn enqueue_packet(
+    packet_enqueuers: &Mutex<HashMap<u16, QueueSender<BytesMut>>>,
+    stream_id: u16,
+    packet: BytesMut,
+) -> StrResult {
+    let maybe_enqueuer = packet_enqueuers.lock().await.get(&stream_id).cloned();
+    if let Some(enqueuer) = maybe_enqueuer {
+        enqueuer.send(packet).await?;
+    }
+
+    Ok(())
+}
 
 pub fn set_socket_buffers(
     socket: &socket2::Socket,
@@ -81,11 +196,110 @@ pub fn set_socket_buffers(
 enum StreamSendSocket {
     Udp(UdpStreamSendSocket),
     Tcp(TcpStreamSendSocket),
+    Quic(QuicStreamSendSocket),
+    #[cfg(unix)]
+    Unix(UnixStreamSendSocket),
+    #[cfg(any(test, feature = "loopback"))]
+    Loopback(LoopbackStreamSendSocket),
+    // Sent packets are discarded
+    Replay,
+}
+
+impl StreamSendSocket {
+    async fn feed(&self, buffer: Bytes) {
+        match self {
+            StreamSendSocket::Udp(socket) => socket
+                .inner
+                .lock()
+                .await
+                .feed((buffer, socket.peer_addr))
+                .await
+                .map_err(err!())
+                .ok(),
+            StreamSendSocket::Tcp(socket) => {
+                socket.lock().await.feed(buffer).await.map_err(err!()).ok()
+            }
+            StreamSendSocket::Quic(socket) => socket.send(buffer).await.ok(),
+            #[cfg(unix)]
+            StreamSendSocket::Unix(socket) => {
+                socket.lock().await.feed(buffer).await.map_err(err!()).ok()
+            }
+            #[cfg(any(test, feature = "loopback"))]
+            StreamSendSocket::Loopback(socket) => {
+                socket.send(buffer).await;
+                Some(())
+            }
+            StreamSendSocket::Replay => None,
+        };
+    }
+
+    async fn flush(&self) -> StrResult {
+        match self {
+            StreamSendSocket::Udp(socket) => {
+                socket.inner.lock().await.flush().await.map_err(err!())
+            }
+            StreamSendSocket::Tcp(socket) => socket.lock().await.flush().await.map_err(err!()),
+            // QUIC packets are sent immediately
+            StreamSendSocket::Quic(_) => Ok(()),
+            #[cfg(unix)]
+            StreamSendSocket::Unix(socket) => socket.lock().await.flush().await.map_err(err!()),
+            #[cfg(any(test, feature = "loopback"))]
+            StreamSendSocket::Loopback(_) => Ok(()),
+            StreamSendSocket::Replay => Ok(()),
+        }
+    }
 }
 
 enum StreamReceiveSocket {
     Udp(UdpStreamReceiveSocket),
     Tcp(TcpStreamReceiveSocket),
+    Quic(QuicStreamReceiveSocket),
+    #[cfg(unix)]
+    Unix(UnixStreamReceiveSocket),
+    #[cfg(any(test, feature = "loopback"))]
+    Loopback(LoopbackStreamReceiveSocket),
+    Replay(CaptureReader),
+}
+
+struct SentShard {
+    packet_index: u32,
+    shard_index: u32,
+    bytes: Bytes,
+}
+
+// Recently sent shards, for each stream ID. Used to serve retransmission requests.
+struct SentShardsHistory {
+    max_size: usize,
+    shards: HashMap<u16, VecDeque<SentShard>>,
+}
+
+impl SentShardsHistory {
+    fn push(&mut self, stream_id: u16, shard: SentShard) {
+        let stream_shards = self.shards.entry(stream_id).or_default();
+
+        if stream_shards.len() >= self.max_size {
+            stream_shards.pop_front();
+        }
+
+        stream_shards.push_back(shard);
+    }
+
+    fn find(&self, stream_id: u16, packet_index: u32, shard_indices: &[u32]) -> Vec<Bytes> {
+        self.shards
+            .get(&stream_id)
+            .map(|stream_shards| {
+                stream_shards
+                    .iter()
+                    .filter(|shard| {
+                        shard.packet_index == packet_index
+                            && (shard_indices.is_empty()
+                                || shard_indices.contains(&shard.shard_index))
+                    })
+                    .map(|shard| shard.bytes.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 pub struct SendBufferLock<'a> {
@@ -113,78 +327,136 @@ impl Drop for SendBufferLock<'_> {
     }
 }
 
-#[derive(Clone)]
+// Not Clone: each packet index must be used once per stream, since it is part of the shard nonces
 pub struct StreamSender<T> {
     stream_id: u16,
     max_packet_size: usize,
+    // 0 means FEC disabled
+    fec_redundancy_ratio: f32,
     socket: StreamSendSocket,
-    // if the packet index overflows the worst that happens is a false positive packet loss
+    cipher: PacketCipher,
+    sent_shards_history: Option<Arc<Mutex<SentShardsHistory>>>,
+    // if the packet index overflows the worst that happens is a false positive packet loss, but the
+    // shard nonces would repeat after 2^32 packets under the same key. The session keys are renewed
+    // at every connection, and a stream is never that long in practice
     next_packet_index: u32,
+    pacer: Option<Pacer>,
+    // time the last shard of the last packet waited in the pacer
+    pacing_queue_delay: Duration,
     _phantom: PhantomData<T>,
 }
 
-impl<T: Serialize> StreamSender<T> {
-    async fn send_buffer(&self, buffer: BytesMut) {
-        match &self.socket {
-            StreamSendSocket::Udp(socket) => socket
-                .inner
-                .lock()
-                .await
-                .feed((buffer.freeze(), socket.peer_addr))
-                .await
-                .map_err(err!())
-                .ok(),
-            StreamSendSocket::Tcp(socket) => socket
-                .lock()
-                .await
-                .feed(buffer.freeze())
-                .await
-                .map_err(err!())
-                .ok(),
-        };
+impl<T> StreamSender<T> {
+    // Spread the shards of each packet over time, at `rate_bytes_per_sec`. A rate of 0 disables
+    // pacing.
+    pub fn set_pacing_rate(&mut self, rate_bytes_per_sec: f32) {
+        self.pacer
+            .get_or_insert_with(|| Pacer::new(PACING_BURST_SHARDS * self.max_packet_size))
+            .set_rate(rate_bytes_per_sec);
     }
 
+    pub fn pacing_rate(&self) -> f32 {
+        self.pacer
+            .as_ref()
+            .map(|pacer| pacer.rate_bytes_per_sec())
+            .unwrap_or(0.0)
+    }
+
+    pub fn pacing_queue_delay(&self) -> Duration {
+        self.pacing_queue_delay
+    }
+}
+
+impl<T: Serialize> StreamSender<T> {
     pub async fn send(&mut self, header: &T, buffer: Vec<u8>) -> StrResult {
         // packet layout:
-        // [ 2B (stream ID) | 4B (packet index) | 4B (packet shard count) | 4B (shard index)]
+        // [ 2B (stream ID) | 4B (packet index) | 4B (data shard count) | 4B (parity shard count) |
+        //   4B (shard index) | encrypted payload | 16B (authentication tag) ]
+        // Shards with index >= data shard count are FEC parity shards, which are bigger than data
+        // shards by fec::PARITY_PREFIX_SIZE.
         // this escluses length delimited coding, which is handled by the TCP backend
-        const OFFSET: usize = 2 + 4 + 4 + 4;
-        let max_payload_size = self.max_packet_size - OFFSET;
+        const OFFSET: usize = ShardHeader::SIZE;
+        let max_payload_size = if self.fec_redundancy_ratio > 0.0 {
+            self.max_packet_size - OFFSET - TAG_SIZE - fec::PARITY_PREFIX_SIZE
+        } else {
+            self.max_packet_size - OFFSET - TAG_SIZE
+        };
 
-        let data_header_size = bincode::serialized_size(header).map_err(err!()).unwrap() as usize;
+        let header_bytes = bincode::serialize(header).map_err(err!()).unwrap();
 
-        let shards = buffer.chunks(max_payload_size);
-        let shards_count = shards.len() + 1;
+        // The first shard contains the serialized header
+        let data_shards = iter::once(header_bytes.as_slice())
+            .chain(buffer.chunks(max_payload_size))
+            .collect::<Vec<_>>();
+        let data_shards_count = data_shards.len();
 
-        let mut shards_buffer =
-            BytesMut::with_capacity(data_header_size + buffer.len() + shards_count * OFFSET);
+        let parity_shards = fec::encode(
+            &data_shards,
+            fec::parity_shards_count(data_shards_count, self.fec_redundancy_ratio),
+        );
+        let parity_shards_count = parity_shards.len();
 
-        {
-            shards_buffer.put_u16(self.stream_id);
-            shards_buffer.put_u32(self.next_packet_index);
-            shards_buffer.put_u32(shards_count as _);
-            shards_buffer.put_u32(0);
-            shards_buffer.put_slice(&bincode::serialize(header).map_err(err!()).unwrap());
-            self.send_buffer(shards_buffer.split()).await;
-        }
+        let send_start_instant = Instant::now();
+        self.pacing_queue_delay = Duration::ZERO;
+
+        let mut shards_buffer = BytesMut::with_capacity(
+            header_bytes.len()
+                + buffer.len()
+                + parity_shards_count * (max_payload_size + fec::PARITY_PREFIX_SIZE)
+                + (data_shards_count + parity_shards_count) * (OFFSET + TAG_SIZE),
+        );
 
-        for (shard_index, shard) in shards.enumerate() {
+        for (shard_index, shard) in data_shards
+            .into_iter()
+            .chain(parity_shards.iter().map(|shard| shard.as_slice()))
+            .enumerate()
+        {
             shards_buffer.put_u16(self.stream_id);
             shards_buffer.put_u32(self.next_packet_index);
-            shards_buffer.put_u32(shards_count as _);
-            shards_buffer.put_u32((shard_index + 1) as _);
+            shards_buffer.put_u32(data_shards_count as _);
+            shards_buffer.put_u32(parity_shards_count as _);
+            shards_buffer.put_u32(shard_index as _);
             shards_buffer.put_slice(shard);
-            self.send_buffer(shards_buffer.split()).await;
-        }
 
-        match &self.socket {
-            StreamSendSocket::Udp(socket) => {
-                socket.inner.lock().await.flush().await.map_err(err!())?;
+            // The header is authenticated but not encrypted
+            let mut payload = shards_buffer.split_off(OFFSET);
+            self.cipher.seal(
+                shard_nonce(self.stream_id, self.next_packet_index, shard_index as _),
+                &shards_buffer,
+                &mut payload,
+            )?;
+            shards_buffer.unsplit(payload);
+
+            let shard_bytes = shards_buffer.split().freeze();
+
+            if let Some(history) = &self.sent_shards_history {
+                history.lock().await.push(
+                    self.stream_id,
+                    SentShard {
+                        packet_index: self.next_packet_index,
+                        shard_index: shard_index as _,
+                        bytes: shard_bytes.clone(),
+                    },
+                );
             }
-            StreamSendSocket::Tcp(socket) => socket.lock().await.flush().await.map_err(err!())?,
+
+            if let Some(pacer) = &mut self.pacer {
+                let wait = pacer.consume(shard_bytes.len(), Instant::now());
+                if wait > Duration::ZERO {
+                    // Send what is already queued before waiting
+                    self.socket.flush().await?;
+                    time::sleep(wait).await;
+
+                    self.pacing_queue_delay = send_start_instant.elapsed();
+                }
+            }
+
+            self.socket.feed(shard_bytes).await;
         }
 
-        self.next_packet_index += 1;
+        self.socket.flush().await?;
+
+        self.next_packet_index = self.next_packet_index.wrapping_add(1);
 
         Ok(())
     }
@@ -194,6 +466,9 @@ impl<T: Serialize> StreamSender<T> {
 pub struct ReceiverBuffer<T> {
     inner: BytesMut,
     had_packet_loss: bool,
+    reorder_depth: u32,
+    late_shards_count: usize,
+    dropped_shards_count: usize,
     _phantom: PhantomData<T>,
 }
 
@@ -202,6 +477,9 @@ impl<T> ReceiverBuffer<T> {
         Self {
             inner: BytesMut::new(),
             had_packet_loss: false,
+            reorder_depth: 0,
+            late_shards_count: 0,
+            dropped_shards_count: 0,
             _phantom: PhantomData,
         }
     }
@@ -209,6 +487,21 @@ impl<T> ReceiverBuffer<T> {
     pub fn had_packet_loss(&self) -> bool {
         self.had_packet_loss
     }
+
+    // Number of newer packets that started arriving before this packet was complete
+    pub fn reorder_depth(&self) -> u32 {
+        self.reorder_depth
+    }
+
+    // Number of shards received after their packet was already delivered or dropped
+    pub fn late_shards_count(&self) -> usize {
+        self.late_shards_count
+    }
+
+    // Number of shards dropped because the stream queue was full, since the previous packet
+    pub fn dropped_shards_count(&self) -> usize {
+        self.dropped_shards_count
+    }
 }
 
 impl<T: DeserializeOwned> ReceiverBuffer<T> {
@@ -220,80 +513,148 @@ impl<T: DeserializeOwned> ReceiverBuffer<T> {
     }
 }
 
+struct RetransmissionRequester {
+    stream_id: u16,
+    socket: StreamSendSocket,
+    cipher: PacketCipher,
+    deadline: Duration,
+    next_request_index: u64,
+}
+
+impl RetransmissionRequester {
+    async fn request(&mut self, packet_index: u32, shard_indices: &[u32]) -> StrResult {
+        const OFFSET: usize = 2 + 2 + 8;
+
+        let mut buffer = BytesMut::with_capacity(OFFSET + 4 + shard_indices.len() * 4 + TAG_SIZE);
+        buffer.put_u16(NACK_STREAM_ID);
+        buffer.put_u16(self.stream_id);
+        buffer.put_u64(self.next_request_index);
+        buffer.put_u32(packet_index);
+        for index in shard_indices {
+            buffer.put_u32(*index);
+        }
+
+        let mut payload = buffer.split_off(OFFSET);
+        self.cipher.seal(
+            crypto::packet_nonce(nack_nonce_prefix(self.stream_id), self.next_request_index),
+            &buffer,
+            &mut payload,
+        )?;
+        buffer.unsplit(payload);
+
+        self.next_request_index += 1;
+
+        self.socket.feed(buffer.freeze()).await;
+        self.socket.flush().await
+    }
+}
+
 pub struct StreamReceiver<T> {
-    receiver: mpsc::UnboundedReceiver<BytesMut>,
-    next_packet_shards: HashMap<usize, BytesMut>,
-    next_packet_shards_count: Option<usize>,
-    next_packet_index: u32,
+    stream_id: u16,
+    receiver: QueueReceiver<BytesMut>,
+    reported_dropped_shards_count: usize,
+    cipher: PacketCipher,
+    retransmission_requester: Option<RetransmissionRequester>,
+    reassembly_window: ReassemblyWindow,
     _phantom: PhantomData<T>,
 }
 
-/// Get next packet reconstructing from shards. It can store at max shards from two packets; if the
-/// reordering entropy is too high, packets will never be successfully reconstructed.
+/// Get next packet reconstructing from shards. Shards from multiple packets are held in a
+/// reassembly window, so packets can be reordered up to the window size. If the window overflows,
+/// the oldest incomplete packets are dropped. Missing shards are rebuilt from the FEC parity shards
+/// when possible. Once a newer packet is complete, an incomplete packet is dropped after a
+/// deadline. If retransmission is enabled for this stream, the missing shards are requested to the
+/// sender before signaling packet loss.
 impl<T: DeserializeOwned> StreamReceiver<T> {
     pub async fn recv_buffer(&mut self, buffer: &mut ReceiverBuffer<T>) -> StrResult {
-        buffer.had_packet_loss = false;
+        // packet index, deadline
+        let mut drop_deadline: Option<(u32, Instant)> = None;
 
         loop {
-            let current_packet_index = self.next_packet_index;
-            self.next_packet_index += 1;
+            let window = &mut self.reassembly_window;
 
-            let mut current_packet_shards =
-                HashMap::with_capacity(self.next_packet_shards.capacity());
-            mem::swap(&mut current_packet_shards, &mut self.next_packet_shards);
+            if let Some(reorder_depth) = window.pop_next(&mut buffer.inner) {
+                buffer.had_packet_loss = window.take_lost_packets_count() > 0;
+                buffer.reorder_depth = reorder_depth;
+                buffer.late_shards_count = window.take_late_shards_count();
 
-            let mut current_packet_shards_count = self.next_packet_shards_count.take();
+                let dropped_shards_count = self.receiver.dropped_count();
+                buffer.dropped_shards_count =
+                    dropped_shards_count - self.reported_dropped_shards_count;
+                self.reported_dropped_shards_count = dropped_shards_count;
 
-            loop {
-                if let Some(shards_count) = current_packet_shards_count {
-                    if current_packet_shards.len() >= shards_count {
-                        buffer.inner.clear();
+                return Ok(());
+            }
 
-                        for i in 0..shards_count {
-                            if let Some(shard) = current_packet_shards.get(&i) {
-                                buffer.inner.put_slice(shard);
-                            } else {
-                                error!("Cannot find shard with given index!");
-                                buffer.had_packet_loss = true;
+            let next_packet_index = window.next_packet_index();
 
-                                self.next_packet_shards.clear();
+            // the deadline is no longer valid if the window moved forward
+            drop_deadline = drop_deadline.filter(|(index, _)| *index == next_packet_index);
 
-                                break;
-                            }
-                        }
+            // A newer packet is complete before the next one: the missing shards are probably lost
+            // rather than reordered
+            if drop_deadline.is_none() && window.is_stalled() {
+                let timeout = if let Some(requester) = &mut self.retransmission_requester {
+                    // If no shard was received for the next packet, the whole packet is requested
+                    requester
+                        .request(
+                            next_packet_index,
+                            &window.missing_data_shards(next_packet_index),
+                        )
+                        .await?;
 
-                        return Ok(());
-                    }
-                }
+                    requester.deadline
+                } else {
+                    REORDER_TIMEOUT
+                };
 
-                let mut shard = self.receiver.recv().await.ok_or_else(enone!())?;
+                drop_deadline = Some((next_packet_index, Instant::now() + timeout));
+            }
 
-                let shard_packet_index = shard.get_u32();
-                let shards_count = shard.get_u32() as usize;
-                let shard_index = shard.get_u32() as usize;
+            let maybe_shard = if let Some((_, deadline)) = drop_deadline {
+                time::timeout_at(deadline, self.receiver.recv()).await.ok()
+            } else {
+                Some(self.receiver.recv().await)
+            };
+            let mut shard = if let Some(maybe_shard) = maybe_shard {
+                maybe_shard.ok_or_else(enone!())?
+            } else {
+                debug!("Deadline expired for incomplete packet. Dropping packet.");
+                window.drop_next();
+                drop_deadline = None;
 
-                if shard_packet_index == current_packet_index {
-                    current_packet_shards.insert(shard_index, shard);
-                    current_packet_shards_count = Some(shards_count);
-                } else if shard_packet_index >= self.next_packet_index {
-                    if shard_packet_index > self.next_packet_index {
-                        self.next_packet_shards.clear();
-                    }
+                continue;
+            };
 
-                    self.next_packet_shards.insert(shard_index, shard);
-                    self.next_packet_shards_count = Some(shards_count);
-                    self.next_packet_index = shard_packet_index;
+            if shard.len() < 4 + 4 + 4 + 4 + TAG_SIZE {
+                debug!("Received truncated shard");
+                continue;
+            }
 
-                    if shard_packet_index > self.next_packet_index
-                        || self.next_packet_shards.len() == shards_count
-                    {
-                        debug!("Skipping to next packet. Signaling packet loss.");
-                        buffer.had_packet_loss = true;
-                        break;
-                    }
-                }
-                // else: ignore old shard
+            let mut shard_header = shard.split_to(4 + 4 + 4 + 4);
+            let aad = [&self.stream_id.to_be_bytes(), &shard_header[..]].concat();
+
+            let shard_packet_index = shard_header.get_u32();
+            let data_shards_count = shard_header.get_u32() as usize;
+            let parity_shards_count = shard_header.get_u32() as usize;
+            let shard_index = shard_header.get_u32() as usize;
+
+            if let Err(e) = self.cipher.open(
+                shard_nonce(self.stream_id, shard_packet_index, shard_index as _),
+                &aad,
+                &mut shard,
+            ) {
+                debug!("Dropping shard. {e}");
+                continue;
             }
+
+            window.insert(
+                shard_packet_index,
+                data_shards_count,
+                parity_shards_count,
+                shard_index,
+                shard,
+            );
         }
     }
 
@@ -308,6 +669,9 @@ impl<T: DeserializeOwned> StreamReceiver<T> {
 pub enum StreamSocketBuilder {
     Tcp(net::TcpListener),
     Udp(net::UdpSocket),
+    Quic(quinn::Endpoint),
+    #[cfg(unix)]
+    Unix(net::UnixListener),
 }
 
 impl StreamSocketBuilder {
@@ -324,14 +688,31 @@ impl StreamSocketBuilder {
             SocketProtocol::Tcp => StreamSocketBuilder::Tcp(
                 tcp::bind(port, send_buffer_bytes, recv_buffer_bytes).await?,
             ),
+            SocketProtocol::Quic => {
+                StreamSocketBuilder::Quic(quic::bind(port, send_buffer_bytes, recv_buffer_bytes)?)
+            }
         })
     }
 
+    // Used in place of listen_for_server() for local IPC
+    pub async fn listen_for_local_server(hostname: &str) -> StrResult<Self> {
+        #[cfg(unix)]
+        return Ok(StreamSocketBuilder::Unix(unix::bind(hostname)?));
+
+        #[cfg(not(unix))]
+        fmt_e!("Local IPC is supported only on Unix platforms: {hostname}")
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn accept_from_server(
         self,
         server_ip: IpAddr,
         port: u16,
         max_packet_size: usize,
+        fec_redundancy_ratio: Option<f32>,
+        retransmission: Option<RetransmissionConfig>,
+        reorder_window_size: usize,
+        keys: ChannelKeys,
     ) -> StrResult<StreamSocket> {
         let (send_socket, receive_socket) = match self {
             StreamSocketBuilder::Udp(socket) => {
@@ -349,27 +730,53 @@ impl StreamSocketBuilder {
                     StreamReceiveSocket::Tcp(receive_socket),
                 )
             }
+            StreamSocketBuilder::Quic(endpoint) => {
+                let (send_socket, receive_socket) =
+                    quic::accept_from_server(endpoint, server_ip, &keys).await?;
+                (
+                    StreamSendSocket::Quic(send_socket),
+                    StreamReceiveSocket::Quic(receive_socket),
+                )
+            }
+            #[cfg(unix)]
+            StreamSocketBuilder::Unix(listener) => {
+                let (send_socket, receive_socket) = unix::accept_from_server(listener).await?;
+                (
+                    StreamSendSocket::Unix(send_socket),
+                    StreamReceiveSocket::Unix(receive_socket),
+                )
+            }
         };
 
-        Ok(StreamSocket {
-            max_packet_size,
+        Ok(StreamSocket::new(
             send_socket,
-            receive_socket: Arc::new(Mutex::new(Some(receive_socket))),
-            packet_queues: Arc::new(Mutex::new(HashMap::new())),
-        })
+            receive_socket,
+            max_packet_size,
+            fec_redundancy_ratio,
+            retransmission,
+            reorder_window_size,
+            keys,
+        ))
     }
 
+    #[allow(clippy::too_many_arguments)]
+    // server_port is used only by UDP. TCP and QUIC connect from a port chosen by the OS
     pub async fn connect_to_client(
         client_ip: IpAddr,
         port: u16,
+        server_port: u16,
         protocol: SocketProtocol,
         send_buffer_bytes: SocketBufferSize,
         recv_buffer_bytes: SocketBufferSize,
         max_packet_size: usize,
+        fec_redundancy_ratio: Option<f32>,
+        retransmission: Option<RetransmissionConfig>,
+        reorder_window_size: usize,
+        keys: ChannelKeys,
     ) -> StrResult<StreamSocket> {
         let (send_socket, receive_socket) = match protocol {
             SocketProtocol::Udp => {
-                let socket = udp::bind(port, send_buffer_bytes, recv_buffer_bytes).await?;
+                let socket = udp::bind(server_port, send_buffer_bytes, recv_buffer_bytes).await?;
                 let (send_socket, receive_socket) = udp::connect(socket, client_ip, port).await?;
                 (
                     StreamSendSocket::Udp(send_socket),
@@ -385,56 +792,296 @@ impl StreamSocketBuilder {
                     StreamReceiveSocket::Tcp(receive_socket),
                 )
             }
+            SocketProtocol::Quic => {
+                let (send_socket, receive_socket) = quic::connect_to_client(
+                    client_ip,
+                    port,
+                    send_buffer_bytes,
+                    recv_buffer_bytes,
+                    &keys,
+                )
+                .await?;
+                (
+                    StreamSendSocket::Quic(send_socket),
+                    StreamReceiveSocket::Quic(receive_socket),
+                )
+            }
         };
 
-        Ok(StreamSocket {
-            max_packet_size,
+        Ok(StreamSocket::new(
             send_socket,
-            receive_socket: Arc::new(Mutex::new(Some(receive_socket))),
-            packet_queues: Arc::new(Mutex::new(HashMap::new())),
-        })
+            receive_socket,
+            max_packet_size,
+            fec_redundancy_ratio,
+            retransmission,
+            reorder_window_size,
+            keys,
+        ))
+    }
+
+    // Used in place of connect_to_client() for local IPC
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub async fn connect_to_local_client(
+        hostname: &str,
+        max_packet_size: usize,
+        fec_redundancy_ratio: Option<f32>,
+        retransmission: Option<RetransmissionConfig>,
+        reorder_window_size: usize,
+        keys: ChannelKeys,
+    ) -> StrResult<StreamSocket> {
+        #[cfg(unix)]
+        {
+            let (send_socket, receive_socket) = unix::connect_to_client(hostname).await?;
+
+            Ok(StreamSocket::new(
+                StreamSendSocket::Unix(send_socket),
+                StreamReceiveSocket::Unix(receive_socket),
+                max_packet_size,
+                fec_redundancy_ratio,
+                retransmission,
+                reorder_window_size,
+                keys,
+            ))
+        }
+
+        #[cfg(not(unix))]
+        fmt_e!("Local IPC is supported only on Unix platforms: {hostname}")
     }
 }
 
 pub struct StreamSocket {
     max_packet_size: usize,
+    fec_redundancy_ratio: f32,
+    reorder_window_size: usize,
+    retransmission_deadline: Option<Duration>,
+    sent_shards_history: Option<Arc<Mutex<SentShardsHistory>>>,
     send_socket: StreamSendSocket,
+    send_cipher: PacketCipher,
+    receive_cipher: PacketCipher,
     receive_socket: Arc<Mutex<Option<StreamReceiveSocket>>>,
-    packet_queues: Arc<Mutex<HashMap<u16, mpsc::UnboundedSender<BytesMut>>>>,
+    capture_writer: Mutex<Option<CaptureWriter>>,
+    packet_queues: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+    // A second sender for the same stream would reuse the packet indices, and so the nonces
+    requested_streams: Mutex<HashSet<u16>>,
 }
 
 impl StreamSocket {
+    fn new(
+        send_socket: StreamSendSocket,
+        receive_socket: StreamReceiveSocket,
+        max_packet_size: usize,
+        fec_redundancy_ratio: Option<f32>,
+        retransmission: Option<RetransmissionConfig>,
+        reorder_window_size: usize,
+        keys: ChannelKeys,
+    ) -> Self {
+        // Bigger shards would not fit in QUIC datagrams and would be sent reliably
+        let max_packet_size = match &send_socket {
+            StreamSendSocket::Quic(socket) => socket
+                .max_datagram_size()
+                .map_or(max_packet_size, |size| max_packet_size.min(size)),
+            _ => max_packet_size,
+        };
+
+        StreamSocket {
+            max_packet_size,
+            fec_redundancy_ratio: fec_redundancy_ratio.unwrap_or(0.0),
+            reorder_window_size,
+            retransmission_deadline: retransmission
+                .as_ref()
+                .map(|config| Duration::from_millis(config.deadline_ms)),
+            sent_shards_history: retransmission.map(|config| {
+                Arc::new(Mutex::new(SentShardsHistory {
+                    max_size: config.history_size as _,
+                    shards: HashMap::new(),
+                }))
+            }),
+            send_socket,
+            send_cipher: keys.send,
+            receive_cipher: keys.receive,
+            receive_socket: Arc::new(Mutex::new(Some(receive_socket))),
+            capture_writer: Mutex::new(None),
+            packet_queues: Arc::new(Mutex::new(HashMap::new())),
+            requested_streams: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Record all received shards into a capture file, which can be replayed with
+    // replay_capture(). Must be called before receive_loop()
+    pub async fn capture_to_file(&self, path: &Path) -> StrResult {
+        *self.capture_writer.lock().await = Some(CaptureWriter::new(
+            path,
+            &self.receive_cipher,
+            self.reorder_window_size,
+        )?);
+
+        Ok(())
+    }
+
+    // Each stream can be requested only once
     pub async fn request_stream<T>(&self, stream_id: u16) -> StrResult<StreamSender<T>> {
+        if !self.requested_streams.lock().await.insert(stream_id) {
+            return fmt_e!("Stream {stream_id} has already been requested");
+        }
+
         Ok(StreamSender {
             stream_id,
             max_packet_size: self.max_packet_size,
+            fec_redundancy_ratio: self.fec_redundancy_ratio,
             socket: self.send_socket.clone(),
+            cipher: self.send_cipher.clone(),
+            sent_shards_history: self.sent_shards_history.clone(),
             next_packet_index: 0,
+            pacer: None,
+            pacing_queue_delay: Duration::ZERO,
             _phantom: PhantomData,
         })
     }
 
-    pub async fn subscribe_to_stream<T>(&self, stream_id: u16) -> StrResult<StreamReceiver<T>> {
-        let (sender, receiver) = mpsc::unbounded_channel();
+    // Received shards are held in a queue until the receiver consumes them. `queue` limits the
+    // number of queued shards and decides which ones to drop when the receiver is too slow.
+    pub async fn subscribe_to_stream<T>(
+        &self,
+        stream_id: u16,
+        queue: QueueConfig,
+    ) -> StrResult<StreamReceiver<T>> {
+        self.subscribe(stream_id, queue, None).await
+    }
+
+    // Lost shards are requested again to the sender, if retransmission is enabled. Use this only
+    // for streams with small packets, where waiting for the retransmission is better than waiting
+    // for the next packet.
+    pub async fn subscribe_to_stream_with_retransmission<T>(
+        &self,
+        stream_id: u16,
+        queue: QueueConfig,
+    ) -> StrResult<StreamReceiver<T>> {
+        let requester = self
+            .retransmission_deadline
+            .map(|deadline| RetransmissionRequester {
+                stream_id,
+                socket: self.send_socket.clone(),
+                cipher: self.send_cipher.clone(),
+                deadline,
+                next_request_index: 0,
+            });
+
+        self.subscribe(stream_id, queue, requester).await
+    }
+
+    async fn subscribe<T>(
+        &self,
+        stream_id: u16,
+        queue: QueueConfig,
+        retransmission_requester: Option<RetransmissionRequester>,
+    ) -> StrResult<StreamReceiver<T>> {
+        let (sender, receiver) = bounded_queue(queue);
         self.packet_queues.lock().await.insert(stream_id, sender);
 
         Ok(StreamReceiver {
+            stream_id,
             receiver,
-            next_packet_shards: HashMap::new(),
-            next_packet_shards_count: None,
-            next_packet_index: 0,
+            reported_dropped_shards_count: 0,
+            cipher: self.receive_cipher.clone(),
+            retransmission_requester,
+            reassembly_window: ReassemblyWindow::new(self.reorder_window_size),
             _phantom: PhantomData,
         })
     }
 
     pub async fn receive_loop(&self) -> StrResult {
-        match self.receive_socket.lock().await.take().unwrap() {
-            StreamReceiveSocket::Udp(socket) => {
-                udp::receive_loop(socket, Arc::clone(&self.packet_queues)).await
+        let socket_receive_loop = async {
+            let capture_writer = self.capture_writer.lock().await.take();
+
+            match self.receive_socket.lock().await.take().unwrap() {
+                StreamReceiveSocket::Udp(socket) => {
+                    udp::receive_loop(socket, Arc::clone(&self.packet_queues), capture_writer).await
+                }
+                StreamReceiveSocket::Tcp(socket) => {
+                    tcp::receive_loop(socket, Arc::clone(&self.packet_queues), capture_writer).await
+                }
+                StreamReceiveSocket::Quic(socket) => {
+                    if capture_writer.is_some() {
+                        warn!("Stream capture is not supported with QUIC");
+                    }
+
+                    quic::receive_loop(socket, Arc::clone(&self.packet_queues)).await
+                }
+                #[cfg(unix)]
+                StreamReceiveSocket::Unix(socket) => {
+                    tcp::receive_loop(socket, Arc::clone(&self.packet_queues), capture_writer).await
+                }
+                #[cfg(any(test, feature = "loopback"))]
+                StreamReceiveSocket::Loopback(socket) => {
+                    loopback::receive_loop(socket, Arc::clone(&self.packet_queues), capture_writer)
+                        .await
+                }
+                StreamReceiveSocket::Replay(reader) => {
+                    capture::receive_loop(reader, Arc::clone(&self.packet_queues)).await
+                }
             }
-            StreamReceiveSocket::Tcp(socket) => {
-                tcp::receive_loop(socket, Arc::clone(&self.packet_queues)).await
+        };
+
+        if let Some(history) = &self.sent_shards_history {
+            let (nack_sender, mut nack_receiver) = bounded_queue(NACK_QUEUE);
+            self.packet_queues
+                .lock()
+                .await
+                .insert(NACK_STREAM_ID, nack_sender);
+
+            let retransmission_loop = async {
+                while let Some(mut packet) = nack_receiver.recv().await {
+                    if packet.len() < 2 + 8 + TAG_SIZE {
+                        continue;
+                    }
+
+                    let mut request_header = packet.split_to(2 + 8);
+                    let aad = [&NACK_STREAM_ID.to_be_bytes(), &request_header[..]].concat();
+
+                    let stream_id = request_header.get_u16();
+                    let request_index = request_header.get_u64();
+
+                    if let Err(e) = self.receive_cipher.open(
+                        crypto::packet_nonce(nack_nonce_prefix(stream_id), request_index),
+                        &aad,
+                        &mut packet,
+                    ) {
+                        debug!("Dropping retransmission request. {e}");
+                        continue;
+                    }
+                    if packet.len() < 4 {
+                        continue;
+                    }
+
+                    let packet_index = packet.get_u32();
+                    let shard_indices =
+                        iter::from_fn(|| (packet.remaining() >= 4).then(|| packet.get_u32()))
+                            .collect::<Vec<_>>();
+
+                    let shards = history
+                        .lock()
+                        .await
+                        .find(stream_id, packet_index, &shard_indices);
+                    if shards.is_empty() {
+                        debug!("Requested shards not found in history. Stream ID: {stream_id}");
+                        continue;
+                    }
+
+                    for shard in shards {
+                        self.send_socket.feed(shard).await;
+                    }
+                    self.send_socket.flush().await?;
+                }
+
+                StrResult::Ok(())
+            };
+
+            tokio::select! {
+                res = socket_receive_loop => res,
+                res = retransmission_loop => res,
             }
+        } else {
+            socket_receive_loop.await
         }
     }
 }
@@ -0,0 +1,122 @@
+// Token bucket pacer for outgoing shards. Tokens are bytes and are refilled at the pacing rate, up
+// to the burst size. A shard can always be sent, but if the bucket does not have enough tokens the
+// bucket goes in debt and the sender must wait until the debt is repaid before sending the next
+// shard. This way the wait does not depend on the timer resolution: if the sender oversleeps,
+// the following shards are sent back to back until the pacer catches up.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Clone)]
+pub struct Pacer {
+    rate_bytes_per_sec: f32,
+    burst_bytes: f32,
+    tokens: f32,
+    last_refill_instant: Instant,
+}
+
+impl Pacer {
+    pub fn new(burst_bytes: usize) -> Self {
+        Self {
+            rate_bytes_per_sec: 0.0,
+            burst_bytes: burst_bytes as f32,
+            tokens: burst_bytes as f32,
+            last_refill_instant: Instant::now(),
+        }
+    }
+
+    pub fn rate_bytes_per_sec(&self) -> f32 {
+        self.rate_bytes_per_sec
+    }
+
+    // A rate of 0 disables pacing. Invalid rates, like NaN or infinity, disable it too
+    pub fn set_rate(&mut self, rate_bytes_per_sec: f32) {
+        self.rate_bytes_per_sec = if rate_bytes_per_sec.is_finite() && rate_bytes_per_sec > 0.0 {
+            rate_bytes_per_sec
+        } else {
+            0.0
+        };
+    }
+
+    // Take `size` bytes from the bucket. Returns how long the caller must wait before sending
+    pub fn consume(&mut self, size: usize, now: Instant) -> Duration {
+        if self.rate_bytes_per_sec <= 0.0 {
+            return Duration::ZERO;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill_instant);
+        self.last_refill_instant = now;
+
+        self.tokens = f32::min(
+            self.tokens + elapsed.as_secs_f32() * self.rate_bytes_per_sec,
+            self.burst_bytes,
+        );
+        self.tokens -= size as f32;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // With a tiny rate the wait can overflow. Don't stall the sender forever in this case
+            let wait_secs = -self.tokens / self.rate_bytes_per_sec;
+            if wait_secs < u64::MAX as f32 {
+                Duration::from_secs_f32(wait_secs)
+            } else {
+                self.tokens = 0.0;
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing() {
+        let mut pacer = Pacer::new(2000);
+        let start = Instant::now();
+
+        // disabled
+        assert_eq!(pacer.consume(1_000_000, start), Duration::ZERO);
+
+        let mut pacer = Pacer::new(2000);
+        pacer.set_rate(1_000_000.0);
+
+        // the burst is sent immediately
+        assert_eq!(pacer.consume(1000, start), Duration::ZERO);
+        assert_eq!(pacer.consume(1000, start), Duration::ZERO);
+
+        // then 1000 bytes every millisecond
+        let wait = pacer.consume(1000, start);
+        assert!((wait.as_secs_f32() - 0.001).abs() < 1e-5);
+
+        // oversleeping by 1ms lets the next shard go immediately
+        assert_eq!(
+            pacer.consume(1000, start + Duration::from_millis(2)),
+            Duration::ZERO
+        );
+
+        // the bucket does not grow past the burst size
+        let later = start + Duration::from_secs(1);
+        assert_eq!(pacer.consume(2000, later), Duration::ZERO);
+        assert!(pacer.consume(1000, later) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        let start = Instant::now();
+
+        for rate in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let mut pacer = Pacer::new(2000);
+            pacer.set_rate(rate);
+            assert_eq!(pacer.rate_bytes_per_sec(), 0.0);
+            assert_eq!(pacer.consume(1_000_000, start), Duration::ZERO);
+        }
+
+        // the wait would overflow
+        let mut pacer = Pacer::new(0);
+        pacer.set_rate(f32::MIN_POSITIVE);
+        assert_eq!(pacer.consume(1000, start), Duration::ZERO);
+    }
+}
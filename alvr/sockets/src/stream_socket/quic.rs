@@ -0,0 +1,447 @@
+// QUIC transport. Shards of streams that need low latency are sent as unreliable datagrams, while
+// streams that must not lose packets use one reliable unidirectional QUIC stream each. The packet
+// layout is the same as for the other protocols, so the receiver does not need to know which
+// channel a packet came from.
+// The client is the QUIC server, mirroring the TCP connection direction. The certificate is
+// self-signed and not verified by TLS. Instead, right after connecting each peer proves that it
+// knows the session keys agreed on the control socket and that it terminates this same QUIC
+// connection: it sends the TLS keying material of the connection, encrypted with its stream key. A
+// man in the middle would have two TLS sessions with different keying material and could not
+// forge the proof.
+
+use super::enqueue_packet;
+use crate::{
+    crypto::{self, ChannelKeys},
+    Ldc, QueueSender, HAPTICS, STATISTICS,
+};
+use alvr_common::prelude::*;
+use alvr_session::SocketBufferSize;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use quinn::{
+    ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig,
+    TokioRuntime,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::sync::Mutex;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+const SERVER_NAME: &str = "alvr";
+
+const CHANNEL_BINDING_LABEL: &[u8] = b"ALVR stream channel binding";
+const CHANNEL_BINDING_SIZE: usize = 32;
+// Does not collide with the nonce prefixes of shards and retransmission requests
+const CHANNEL_BINDING_NONCE_PREFIX: u32 = 0xfffe_0000;
+
+// Streams that are sent over reliable QUIC streams. All other streams use datagrams.
+const RELIABLE_STREAM_IDS: &[u16] = &[HAPTICS, STATISTICS];
+
+#[derive(Clone)]
+pub struct QuicStreamSendSocket {
+    connection: Connection,
+    reliable_streams: Arc<Mutex<HashMap<u16, FramedWrite<SendStream, Ldc>>>>,
+}
+
+impl QuicStreamSendSocket {
+    // Packets bigger than this are not sent as datagrams
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+
+    pub async fn send(&self, buffer: Bytes) -> StrResult {
+        let stream_id = (&buffer[..]).get_u16();
+
+        // Packets bigger than the maximum datagram size are sent reliably instead of being dropped
+        let fits_datagram = self
+            .connection
+            .max_datagram_size()
+            .map(|max_size| buffer.len() <= max_size)
+            .unwrap_or(false);
+
+        if !RELIABLE_STREAM_IDS.contains(&stream_id) && fits_datagram {
+            self.connection.send_datagram(buffer).map_err(err!())
+        } else {
+            let mut reliable_streams = self.reliable_streams.lock().await;
+
+            let stream = if let Some(stream) = reliable_streams.get_mut(&stream_id) {
+                stream
+            } else {
+                let stream = self.connection.open_uni().await.map_err(err!())?;
+                reliable_streams
+                    .entry(stream_id)
+                    .or_insert(FramedWrite::new(stream, Ldc::new()))
+            };
+
+            stream.send(buffer).await.map_err(err!())
+        }
+    }
+}
+
+pub struct QuicStreamReceiveSocket {
+    connection: Connection,
+}
+
+// Accept any certificate. The connection is authenticated by verify_channel_binding() instead
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    // Video frames are split in many datagrams. Avoid dropping them on the receiving side before
+    // they are dequeued.
+    config.datagram_receive_buffer_size(Some(16 * 1024 * 1024));
+    config.keep_alive_interval(Some(crate::KEEPALIVE_INTERVAL));
+
+    Arc::new(config)
+}
+
+fn endpoint(
+    port: u16,
+    send_buffer_bytes: SocketBufferSize,
+    recv_buffer_bytes: SocketBufferSize,
+    server_config: Option<ServerConfig>,
+) -> StrResult<Endpoint> {
+    // Quinn takes care of IPv4-mapped addresses when the socket is dual-stack
+    let socket = crate::bind_all_interfaces(socket2::Type::DGRAM, port)?;
+
+    super::set_socket_buffers(&socket, send_buffer_bytes, recv_buffer_bytes).ok();
+
+    Endpoint::new(
+        EndpointConfig::default(),
+        server_config,
+        socket.into(),
+        Arc::new(TokioRuntime),
+    )
+    .map_err(err!())
+}
+
+pub fn bind(
+    port: u16,
+    send_buffer_bytes: SocketBufferSize,
+    recv_buffer_bytes: SocketBufferSize,
+) -> StrResult<Endpoint> {
+    let certificate = rcgen::generate_simple_self_signed([SERVER_NAME.into()]).map_err(err!())?;
+    let certificate_chain = vec![rustls::Certificate(
+        certificate.serialize_der().map_err(err!())?,
+    )];
+    let private_key = rustls::PrivateKey(certificate.serialize_private_key_der());
+
+    let mut server_config =
+        ServerConfig::with_single_cert(certificate_chain, private_key).map_err(err!())?;
+    server_config.transport_config(transport_config());
+
+    endpoint(
+        port,
+        send_buffer_bytes,
+        recv_buffer_bytes,
+        Some(server_config),
+    )
+}
+
+// Exchanges the channel binding proofs on a bidirectional stream
+async fn verify_channel_binding(
+    connection: &Connection,
+    (mut send_stream, mut recv_stream): (SendStream, RecvStream),
+    keys: &ChannelKeys,
+) -> StrResult {
+    let mut binding = [0; CHANNEL_BINDING_SIZE];
+    connection
+        .export_keying_material(&mut binding, CHANNEL_BINDING_LABEL, &[])
+        .map_err(err_dbg!())?;
+    let nonce = || crypto::packet_nonce(CHANNEL_BINDING_NONCE_PREFIX, 0);
+
+    let mut proof = BytesMut::from(&binding[..]);
+    keys.send.seal(nonce(), &[], &mut proof)?;
+
+    let send_proof = async {
+        send_stream.write_all(&proof).await.map_err(err!())?;
+        send_stream.finish().await.map_err(err!())
+    };
+    let receive_proof = async {
+        recv_stream
+            .read_to_end(CHANNEL_BINDING_SIZE + crypto::TAG_SIZE)
+            .await
+            .map_err(err!())
+    };
+    let (send_res, receive_res) = tokio::join!(send_proof, receive_proof);
+    send_res?;
+
+    let mut peer_proof = BytesMut::from(&receive_res?[..]);
+    keys.receive.open(nonce(), &[], &mut peer_proof)?;
+    if peer_proof[..] != binding[..] {
+        return fmt_e!("QUIC channel binding mismatch. The connection is intercepted");
+    }
+
+    Ok(())
+}
+
+pub async fn accept_from_server(
+    endpoint: Endpoint,
+    server_ip: IpAddr,
+    keys: &ChannelKeys,
+) -> StrResult<(QuicStreamSendSocket, QuicStreamReceiveSocket)> {
+    let connecting = endpoint.accept().await.ok_or_else(enone!())?;
+
+    let server_address = connecting.remote_address();
+    if crate::canonical_ip(server_address.ip()) != server_ip {
+        return fmt_e!("Connected to wrong server: {server_address} != {server_ip}");
+    }
+
+    let connection = connecting.await.map_err(err!())?;
+
+    let streams = connection.accept_bi().await.map_err(err!())?;
+    verify_channel_binding(&connection, streams, keys).await?;
+
+    Ok(split(connection))
+}
+
+pub async fn connect_to_client(
+    client_ip: IpAddr,
+    port: u16,
+    send_buffer_bytes: SocketBufferSize,
+    recv_buffer_bytes: SocketBufferSize,
+    keys: &ChannelKeys,
+) -> StrResult<(QuicStreamSendSocket, QuicStreamReceiveSocket)> {
+    // The local port is chosen by the OS, like for TCP
+    let mut endpoint = endpoint(0, send_buffer_bytes, recv_buffer_bytes, None)?;
+
+    let crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    let mut client_config = ClientConfig::new(Arc::new(crypto));
+    client_config.transport_config(transport_config());
+    endpoint.set_default_client_config(client_config);
+
+    let connection = endpoint
+        .connect(SocketAddr::new(client_ip, port), SERVER_NAME)
+        .map_err(err!())?
+        .await
+        .map_err(err!())?;
+
+    let streams = connection.open_bi().await.map_err(err!())?;
+    verify_channel_binding(&connection, streams, keys).await?;
+
+    Ok(split(connection))
+}
+
+fn split(connection: Connection) -> (QuicStreamSendSocket, QuicStreamReceiveSocket) {
+    (
+        QuicStreamSendSocket {
+            connection: connection.clone(),
+            reliable_streams: Arc::new(Mutex::new(HashMap::new())),
+        },
+        QuicStreamReceiveSocket { connection },
+    )
+}
+
+async fn dispatch_packet(
+    mut packet: BytesMut,
+    packet_enqueuers: &Mutex<HashMap<u16, QueueSender<BytesMut>>>,
+) -> StrResult {
+    // A malformed packet from the peer must not panic the receive loop
+    if packet.len() < 2 {
+        debug!("Dropping packet without stream ID");
+
+        return Ok(());
+    }
+
+    let stream_id = packet.get_u16();
+    enqueue_packet(packet_enqueuers, stream_id, packet).await
+}
+
+async fn reliable_stream_receive_loop(
+    stream: RecvStream,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+) -> StrResult {
+    let mut stream = FramedRead::new(stream, Ldc::new());
+
+    while let Some(maybe_packet) = stream.next().await {
+        dispatch_packet(maybe_packet.map_err(err!())?, &packet_enqueuers).await?;
+    }
+
+    Ok(())
+}
+
+async fn datagrams_receive_loop(
+    connection: &Connection,
+    packet_enqueuers: &Mutex<HashMap<u16, QueueSender<BytesMut>>>,
+) -> StrResult {
+    loop {
+        let datagram = connection.read_datagram().await.map_err(err!())?;
+        dispatch_packet(BytesMut::from(&datagram[..]), packet_enqueuers).await?;
+    }
+}
+
+async fn reliable_streams_accept_loop(
+    connection: &Connection,
+    packet_enqueuers: &Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+) -> StrResult {
+    loop {
+        let stream = connection.accept_uni().await.map_err(err!())?;
+
+        let packet_enqueuers = Arc::clone(packet_enqueuers);
+        tokio::spawn(async move {
+            reliable_stream_receive_loop(stream, packet_enqueuers)
+                .await
+                .map_err(|e| debug!("QUIC stream closed: {e}"))
+                .ok();
+        });
+    }
+}
+
+pub async fn receive_loop(
+    socket: QuicStreamReceiveSocket,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+) -> StrResult {
+    tokio::select! {
+        res = datagrams_receive_loop(&socket.connection, &packet_enqueuers) => res,
+        res = reliable_streams_accept_loop(&socket.connection, &packet_enqueuers) => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bounded_queue, crypto::SessionKeys, HAPTICS_QUEUE, VIDEO, VIDEO_QUEUE};
+    use bytes::BufMut;
+    use std::net::Ipv4Addr;
+
+    // Returns the server and client stream keys. Each call uses a new pairing key
+    fn stream_keys() -> (ChannelKeys, ChannelKeys) {
+        let (private_key, _) = crypto::generate_key_pair().unwrap();
+        let (_, public_key) = crypto::generate_key_pair().unwrap();
+        let pairing_key = crypto::agree_pairing_key(private_key, &public_key).unwrap();
+        let nonce = crypto::random_nonce().unwrap();
+
+        (
+            SessionKeys::derive(&pairing_key, &nonce, &nonce, true)
+                .unwrap()
+                .stream,
+            SessionKeys::derive(&pairing_key, &nonce, &nonce, false)
+                .unwrap()
+                .stream,
+        )
+    }
+
+    async fn connect(
+        server_keys: &ChannelKeys,
+        client_keys: &ChannelKeys,
+    ) -> (
+        StrResult<(QuicStreamSendSocket, QuicStreamReceiveSocket)>,
+        StrResult<(QuicStreamSendSocket, QuicStreamReceiveSocket)>,
+    ) {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let endpoint = bind(0, SocketBufferSize::Default, SocketBufferSize::Default).unwrap();
+        let port = endpoint.local_addr().unwrap().port();
+
+        tokio::join!(
+            accept_from_server(endpoint, localhost, client_keys),
+            connect_to_client(
+                localhost,
+                port,
+                SocketBufferSize::Default,
+                SocketBufferSize::Default,
+                server_keys
+            )
+        )
+    }
+
+    fn make_packet(stream_id: u16, size: usize) -> Bytes {
+        let mut packet = BytesMut::new();
+        packet.put_u16(stream_id);
+        packet.put_slice(&vec![0; size]);
+
+        packet.freeze()
+    }
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let (server_keys, client_keys) = stream_keys();
+        let (client_sockets, server_sockets) = connect(&server_keys, &client_keys).await;
+        let (_, client_receive_socket) = client_sockets.unwrap();
+        let (server_send_socket, _) = server_sockets.unwrap();
+
+        let packet_enqueuers = Arc::new(Mutex::new(HashMap::new()));
+        let (video_sender, mut video_receiver) = bounded_queue(VIDEO_QUEUE);
+        let (haptics_sender, mut haptics_receiver) = bounded_queue(HAPTICS_QUEUE);
+        packet_enqueuers.lock().await.insert(VIDEO, video_sender);
+        packet_enqueuers
+            .lock()
+            .await
+            .insert(HAPTICS, haptics_sender);
+
+        tokio::spawn(receive_loop(
+            client_receive_socket,
+            Arc::clone(&packet_enqueuers),
+        ));
+
+        // datagram, datagram too big (sent reliably), reliable stream
+        for size in [1000, 100_000] {
+            server_send_socket
+                .send(make_packet(VIDEO, size))
+                .await
+                .unwrap();
+        }
+        server_send_socket
+            .send(make_packet(HAPTICS, 20))
+            .await
+            .unwrap();
+
+        let mut video_sizes = [
+            video_receiver.recv().await.unwrap().len(),
+            video_receiver.recv().await.unwrap().len(),
+        ];
+        video_sizes.sort_unstable();
+        assert_eq!(video_sizes, [1000, 100_000]);
+        assert_eq!(haptics_receiver.recv().await.unwrap().len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_truncated_packet() {
+        let packet_enqueuers = Mutex::new(HashMap::new());
+        let (video_sender, mut video_receiver) = bounded_queue(VIDEO_QUEUE);
+        packet_enqueuers.lock().await.insert(VIDEO, video_sender);
+
+        dispatch_packet(BytesMut::from(&[0][..]), &packet_enqueuers)
+            .await
+            .unwrap();
+        dispatch_packet(
+            BytesMut::from(&make_packet(VIDEO, 20)[..]),
+            &packet_enqueuers,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(video_receiver.recv().await.unwrap().len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_channel_binding_with_wrong_keys() {
+        let (server_keys, _) = stream_keys();
+        let (_, client_keys) = stream_keys();
+        let (client_sockets, server_sockets) = connect(&server_keys, &client_keys).await;
+
+        assert!(client_sockets.is_err());
+        assert!(server_sockets.is_err());
+    }
+}
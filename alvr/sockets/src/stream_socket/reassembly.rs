@@ -0,0 +1,274 @@
+// Reassembly of packets from shards, over a window of consecutive packet indices. Packets inside
+// the window can be completed in any order but are delivered in order. A shard of a packet past
+// the end of the window makes the window slide forward, dropping the oldest incomplete packets.
+// Shards of packets before the start of the window (already delivered or dropped) are late.
+// Packet indices can wrap around.
+
+use super::fec;
+use bytes::{BufMut, BytesMut};
+use std::collections::HashMap;
+
+// Returns the distance of `index` ahead of `base`, or None if `index` is behind `base`
+fn distance_ahead(base: u32, index: u32) -> Option<u32> {
+    let distance = index.wrapping_sub(base);
+
+    (distance <= i32::MAX as u32).then_some(distance)
+}
+
+#[derive(Default)]
+struct PartialPacket {
+    shards: HashMap<usize, BytesMut>,
+    // data shards count, parity shards count
+    shards_count: Option<(usize, usize)>,
+}
+
+impl PartialPacket {
+    fn is_complete(&mut self) -> bool {
+        if let Some((data_shards_count, parity_shards_count)) = self.shards_count {
+            self.shards.len() >= data_shards_count
+                && fec::recover(&mut self.shards, data_shards_count, parity_shards_count)
+        } else {
+            false
+        }
+    }
+}
+
+pub struct ReassemblyWindow {
+    packets: HashMap<u32, PartialPacket>,
+    window_size: u32,
+    next_packet_index: u32,
+    highest_packet_index: u32,
+    lost_packets_count: usize,
+    late_shards_count: usize,
+}
+
+impl ReassemblyWindow {
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            packets: HashMap::new(),
+            window_size: window_size.clamp(1, i32::MAX as usize) as u32,
+            next_packet_index: 0,
+            highest_packet_index: 0,
+            lost_packets_count: 0,
+            late_shards_count: 0,
+        }
+    }
+
+    // Index of the packet that will be delivered next
+    pub fn next_packet_index(&self) -> u32 {
+        self.next_packet_index
+    }
+
+    pub fn insert(
+        &mut self,
+        packet_index: u32,
+        data_shards_count: usize,
+        parity_shards_count: usize,
+        shard_index: usize,
+        shard: BytesMut,
+    ) {
+        let distance = if let Some(distance) = distance_ahead(self.next_packet_index, packet_index)
+        {
+            distance
+        } else {
+            self.late_shards_count += 1;
+            return;
+        };
+
+        if distance >= self.window_size {
+            self.skip_to(packet_index.wrapping_sub(self.window_size - 1));
+        }
+
+        if distance_ahead(self.highest_packet_index, packet_index).is_some() {
+            self.highest_packet_index = packet_index;
+        }
+
+        let packet = self.packets.entry(packet_index).or_default();
+        packet.shards.insert(shard_index, shard);
+        packet.shards_count = Some((data_shards_count, parity_shards_count));
+    }
+
+    pub fn is_complete(&mut self, packet_index: u32) -> bool {
+        self.packets
+            .get_mut(&packet_index)
+            .map(|packet| packet.is_complete())
+            .unwrap_or(false)
+    }
+
+    // The next packet is incomplete while a newer packet is complete. Either the next packet is
+    // lost or its shards are reordered, waiting for more shards would not unblock the window.
+    pub fn is_stalled(&mut self) -> bool {
+        let next_packet_index = self.next_packet_index;
+        if self.is_complete(next_packet_index) {
+            return false;
+        }
+
+        self.packets
+            .iter_mut()
+            .any(|(index, packet)| *index != next_packet_index && packet.is_complete())
+    }
+
+    // Indices of the missing data shards of a packet. If no shard of the packet was received, the
+    // shards count is unknown and an empty list is returned.
+    pub fn missing_data_shards(&self, packet_index: u32) -> Vec<u32> {
+        self.packets
+            .get(&packet_index)
+            .and_then(|packet| {
+                packet.shards_count.map(|(data_shards_count, _)| {
+                    (0..data_shards_count)
+                        .filter(|index| !packet.shards.contains_key(index))
+                        .map(|index| index as u32)
+                        .collect()
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    // Write the next packet to `output` if it is complete. Returns the reorder depth, that is how
+    // many newer packets started arriving before this packet could be delivered.
+    pub fn pop_next(&mut self, output: &mut BytesMut) -> Option<u32> {
+        let packet_index = self.next_packet_index;
+        let mut packet = self.packets.remove(&packet_index)?;
+
+        if !packet.is_complete() {
+            self.packets.insert(packet_index, packet);
+
+            return None;
+        }
+
+        let (data_shards_count, _) = packet.shards_count?;
+        output.clear();
+        for index in 0..data_shards_count {
+            output.put_slice(&packet.shards[&index]);
+        }
+
+        self.next_packet_index = packet_index.wrapping_add(1);
+
+        Some(distance_ahead(packet_index, self.highest_packet_index).unwrap_or(0))
+    }
+
+    // Give up on the next packet
+    pub fn drop_next(&mut self) {
+        self.skip_to(self.next_packet_index.wrapping_add(1));
+    }
+
+    // Number of packets dropped since the last call
+    pub fn take_lost_packets_count(&mut self) -> usize {
+        std::mem::take(&mut self.lost_packets_count)
+    }
+
+    // Number of late shards received since the last call
+    pub fn take_late_shards_count(&mut self) -> usize {
+        std::mem::take(&mut self.late_shards_count)
+    }
+
+    fn skip_to(&mut self, packet_index: u32) {
+        self.lost_packets_count += packet_index.wrapping_sub(self.next_packet_index) as usize;
+        self.next_packet_index = packet_index;
+
+        self.packets
+            .retain(|index, _| distance_ahead(packet_index, *index).is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_packet(window: &mut ReassemblyWindow, packet_index: u32, shards: &[&[u8]]) {
+        for (shard_index, shard) in shards.iter().enumerate() {
+            window.insert(
+                packet_index,
+                shards.len(),
+                0,
+                shard_index,
+                BytesMut::from(*shard),
+            );
+        }
+    }
+
+    #[test]
+    fn test_reordered_packets() {
+        let mut window = ReassemblyWindow::new(4);
+        let mut output = BytesMut::new();
+
+        insert_packet(&mut window, 2, &[b"c"]);
+        insert_packet(&mut window, 1, &[b"b"]);
+        assert_eq!(window.pop_next(&mut output), None);
+        assert!(window.is_stalled());
+
+        insert_packet(&mut window, 0, &[b"a0", b"a1"]);
+        assert_eq!(window.pop_next(&mut output), Some(2));
+        assert_eq!(&output[..], b"a0a1");
+        assert_eq!(window.pop_next(&mut output), Some(1));
+        assert_eq!(&output[..], b"b");
+        assert_eq!(window.pop_next(&mut output), Some(0));
+        assert_eq!(&output[..], b"c");
+        assert!(!window.is_stalled());
+
+        assert_eq!(window.take_lost_packets_count(), 0);
+        assert_eq!(window.take_late_shards_count(), 0);
+    }
+
+    #[test]
+    fn test_window_overflow() {
+        let mut window = ReassemblyWindow::new(3);
+        let mut output = BytesMut::new();
+
+        window.insert(0, 2, 0, 0, BytesMut::from(&b"a"[..]));
+        insert_packet(&mut window, 1, &[b"b"]);
+        insert_packet(&mut window, 3, &[b"d"]);
+
+        // packet 0 is dropped to make room for packet 3
+        assert_eq!(window.next_packet_index(), 1);
+        assert_eq!(window.take_lost_packets_count(), 1);
+        assert_eq!(window.pop_next(&mut output), Some(2));
+        assert_eq!(&output[..], b"b");
+        assert_eq!(window.pop_next(&mut output), None);
+
+        // the missing shard of packet 0 is now late
+        window.insert(0, 2, 0, 1, BytesMut::from(&b"a"[..]));
+        assert_eq!(window.take_late_shards_count(), 1);
+
+        window.drop_next();
+        assert_eq!(window.take_lost_packets_count(), 1);
+        assert_eq!(window.pop_next(&mut output), Some(0));
+        assert_eq!(&output[..], b"d");
+    }
+
+    #[test]
+    fn test_packet_index_wraparound() {
+        let mut window = ReassemblyWindow::new(4);
+        window.next_packet_index = u32::MAX - 1;
+        window.highest_packet_index = u32::MAX - 1;
+        let mut output = BytesMut::new();
+
+        insert_packet(&mut window, u32::MAX - 1, &[b"a"]);
+        insert_packet(&mut window, 0, &[b"c"]);
+        insert_packet(&mut window, u32::MAX, &[b"b"]);
+        assert_eq!(window.take_lost_packets_count(), 0);
+
+        assert_eq!(window.pop_next(&mut output), Some(2));
+        assert_eq!(&output[..], b"a");
+        assert_eq!(window.pop_next(&mut output), Some(1));
+        assert_eq!(&output[..], b"b");
+        assert_eq!(window.pop_next(&mut output), Some(0));
+        assert_eq!(&output[..], b"c");
+        assert_eq!(window.next_packet_index(), 1);
+
+        insert_packet(&mut window, u32::MAX, &[b"b"]);
+        assert_eq!(window.take_late_shards_count(), 1);
+    }
+
+    #[test]
+    fn test_missing_data_shards() {
+        let mut window = ReassemblyWindow::new(2);
+
+        assert!(window.missing_data_shards(0).is_empty());
+
+        window.insert(0, 4, 0, 1, BytesMut::from(&b"b"[..]));
+        window.insert(0, 4, 0, 2, BytesMut::from(&b"c"[..]));
+        assert_eq!(window.missing_data_shards(0), vec![0, 3]);
+        assert!(!window.is_complete(0));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::{Ldc, LOCAL_IP};
+use super::{enqueue_packet, CaptureWriter};
+use crate::{Ldc, QueueSender};
 use alvr_common::prelude::*;
 use alvr_session::SocketBufferSize;
 use bytes::{Buf, Bytes, BytesMut};
@@ -8,8 +9,9 @@ use futures::{
 };
 use std::{collections::HashMap, net::IpAddr, sync::Arc};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
-    sync::{mpsc, Mutex},
+    sync::Mutex,
 };
 use tokio_util::codec::Framed;
 
@@ -21,11 +23,12 @@ pub async fn bind(
     send_buffer_bytes: SocketBufferSize,
     recv_buffer_bytes: SocketBufferSize,
 ) -> StrResult<TcpListener> {
-    let socket = TcpListener::bind((LOCAL_IP, port)).await.map_err(err!())?;
-    let socket = socket2::Socket::from(socket.into_std().map_err(err!())?);
+    let socket = crate::bind_all_interfaces(socket2::Type::STREAM, port)?;
 
     super::set_socket_buffers(&socket, send_buffer_bytes, recv_buffer_bytes).ok();
 
+    socket.listen(1024).map_err(err!())?;
+    socket.set_nonblocking(true).map_err(err!())?;
     TcpListener::from_std(socket.into()).map_err(err!())
 }
 
@@ -35,7 +38,7 @@ pub async fn accept_from_server(
 ) -> StrResult<(TcpStreamSendSocket, TcpStreamReceiveSocket)> {
     let (socket, server_address) = listener.accept().await.map_err(err!())?;
 
-    if server_address.ip() != server_ip {
+    if crate::canonical_ip(server_address.ip()) != server_ip {
         return fmt_e!("Connected to wrong client: {server_address} != {server_ip}");
     }
 
@@ -67,17 +70,20 @@ pub async fn connect_to_client(
     Ok((Arc::new(Mutex::new(send_socket)), receive_socket))
 }
 
-pub async fn receive_loop(
-    mut socket: TcpStreamReceiveSocket,
-    packet_enqueuers: Arc<Mutex<HashMap<u16, mpsc::UnboundedSender<BytesMut>>>>,
+// Also used by the Unix domain socket transport, which has the same framing
+pub async fn receive_loop<S: AsyncRead + AsyncWrite>(
+    mut socket: SplitStream<Framed<S, Ldc>>,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+    mut capture_writer: Option<CaptureWriter>,
 ) -> StrResult {
     while let Some(maybe_packet) = socket.next().await {
         let mut packet = maybe_packet.map_err(err!())?;
 
         let stream_id = packet.get_u16();
-        if let Some(enqueuer) = packet_enqueuers.lock().await.get_mut(&stream_id) {
-            enqueuer.send(packet).map_err(err!())?;
+        if let Some(writer) = &mut capture_writer {
+            writer.write_shard(stream_id, &packet);
         }
+        enqueue_packet(&packet_enqueuers, stream_id, packet).await?;
     }
 
     Ok(())
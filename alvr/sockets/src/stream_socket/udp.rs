@@ -1,4 +1,5 @@
-use crate::{Ldc, LOCAL_IP};
+use super::{enqueue_packet, CaptureWriter};
+use crate::{Ldc, QueueSender};
 use alvr_common::prelude::*;
 use alvr_session::SocketBufferSize;
 use bytes::{Buf, Bytes, BytesMut};
@@ -11,10 +12,7 @@ use std::{
     net::{IpAddr, SocketAddr},
     sync::Arc,
 };
-use tokio::{
-    net::UdpSocket,
-    sync::{mpsc, Mutex},
-};
+use tokio::{net::UdpSocket, sync::Mutex};
 use tokio_util::udp::UdpFramed;
 
 #[allow(clippy::type_complexity)]
@@ -31,18 +29,16 @@ pub struct UdpStreamReceiveSocket {
     pub inner: SplitStream<UdpFramed<Ldc>>,
 }
 
-// Create tokio socket, convert to socket2, apply settings, convert back to tokio. This is done to
-// let tokio set all the internal parameters it needs from the start.
 pub async fn bind(
     port: u16,
     send_buffer_bytes: SocketBufferSize,
     recv_buffer_bytes: SocketBufferSize,
 ) -> StrResult<UdpSocket> {
-    let socket = UdpSocket::bind((LOCAL_IP, port)).await.map_err(err!())?;
-    let socket = socket2::Socket::from(socket.into_std().map_err(err!())?);
+    let socket = crate::bind_all_interfaces(socket2::Type::DGRAM, port)?;
 
     super::set_socket_buffers(&socket, send_buffer_bytes, recv_buffer_bytes).ok();
 
+    socket.set_nonblocking(true).map_err(err!())?;
     UdpSocket::from_std(socket.into()).map_err(err!())
 }
 
@@ -51,6 +47,13 @@ pub async fn connect(
     peer_ip: IpAddr,
     port: u16,
 ) -> StrResult<(UdpStreamSendSocket, UdpStreamReceiveSocket)> {
+    // Dual-stack sockets send to and receive from IPv4 peers using IPv4-mapped addresses
+    let peer_ip = match peer_ip {
+        IpAddr::V4(ipv4) if socket.local_addr().map_err(err!())?.is_ipv6() => {
+            IpAddr::V6(ipv4.to_ipv6_mapped())
+        }
+        ip => ip,
+    };
     let peer_addr = (peer_ip, port).into();
     let socket = UdpFramed::new(socket, Ldc::new());
     let (send_socket, receive_socket) = socket.split();
@@ -69,7 +72,8 @@ pub async fn connect(
 
 pub async fn receive_loop(
     mut socket: UdpStreamReceiveSocket,
-    packet_enqueuers: Arc<Mutex<HashMap<u16, mpsc::UnboundedSender<BytesMut>>>>,
+    packet_enqueuers: Arc<Mutex<HashMap<u16, QueueSender<BytesMut>>>>,
+    mut capture_writer: Option<CaptureWriter>,
 ) -> StrResult {
     while let Some(maybe_packet) = socket.inner.next().await {
         let (mut packet_bytes, address) = maybe_packet.map_err(err!())?;
@@ -79,9 +83,10 @@ pub async fn receive_loop(
         }
 
         let stream_id = packet_bytes.get_u16();
-        if let Some(enqueuer) = packet_enqueuers.lock().await.get_mut(&stream_id) {
-            enqueuer.send(packet_bytes).map_err(err!())?;
+        if let Some(writer) = &mut capture_writer {
+            writer.write_shard(stream_id, &packet_bytes);
         }
+        enqueue_packet(&packet_enqueuers, stream_id, packet_bytes).await?;
     }
 
     Ok(())
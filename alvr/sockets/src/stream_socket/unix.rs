@@ -0,0 +1,45 @@
+// Same framing as TCP, over a Unix domain socket. Used for local IPC.
+
+use crate::Ldc;
+use alvr_common::prelude::*;
+use bytes::Bytes;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    StreamExt,
+};
+use std::sync::Arc;
+use tokio::{
+    net::{UnixListener, UnixStream},
+    sync::Mutex,
+};
+use tokio_util::codec::Framed;
+
+pub type UnixStreamSendSocket = Arc<Mutex<SplitSink<Framed<UnixStream, Ldc>, Bytes>>>;
+pub type UnixStreamReceiveSocket = SplitStream<Framed<UnixStream, Ldc>>;
+
+pub fn bind(hostname: &str) -> StrResult<UnixListener> {
+    crate::bind_local_socket(&crate::local_stream_socket_path(hostname))
+}
+
+// No peer check is needed, only the current user can access the socket file
+pub async fn accept_from_server(
+    listener: UnixListener,
+) -> StrResult<(UnixStreamSendSocket, UnixStreamReceiveSocket)> {
+    let (socket, _) = listener.accept().await.map_err(err!())?;
+    let socket = Framed::new(socket, Ldc::new());
+    let (send_socket, receive_socket) = socket.split();
+
+    Ok((Arc::new(Mutex::new(send_socket)), receive_socket))
+}
+
+pub async fn connect_to_client(
+    hostname: &str,
+) -> StrResult<(UnixStreamSendSocket, UnixStreamReceiveSocket)> {
+    let socket = UnixStream::connect(crate::local_stream_socket_path(hostname))
+        .await
+        .map_err(err!())?;
+    let socket = Framed::new(socket, Ldc::new());
+    let (send_socket, receive_socket) = socket.split();
+
+    Ok((Arc::new(Mutex::new(send_socket)), receive_socket))
+}
@@ -0,0 +1,164 @@
+// Replay a stream capture recorded with the "capture stream" setting. Shards are fed to the stream
+// receivers with the original timing, so packet loss, reordering and the arrival time of video and
+// audio packets are the same as in the original session.
+
+use alvr_common::prelude::*;
+use alvr_sockets::{
+    ReceiverBuffer, StreamReceiver, AUDIO, AUDIO_QUEUE, HAPTICS, HAPTICS_QUEUE, STATISTICS,
+    STATISTICS_QUEUE, TRACKING, TRACKING_QUEUE, VIDEO, VIDEO_QUEUE,
+};
+use pico_args::Arguments;
+use serde::de::DeserializeOwned;
+use std::{fs::File, io::Write, path::PathBuf, time::Duration};
+use tokio::time::Instant;
+
+const HELP_STR: &str = r#"
+alvr_stream_replay
+Replay a stream capture with the original timing and print statistics for each stream.
+
+USAGE:
+    alvr_stream_replay <CAPTURE> [FLAG] [ARGS]
+
+FLAGS:
+    --help                  Print this text
+
+ARGS:
+    --video-output <PATH>   Write the received video NAL units to a file
+    --audio-output <PATH>   Write the received audio samples to a file, as raw 16 bit PCM
+"#;
+
+#[derive(Default)]
+struct StreamSummary {
+    packets_count: usize,
+    bytes_count: usize,
+    packet_loss_count: usize,
+    max_reorder_depth: u32,
+    late_shards_count: usize,
+    dropped_shards_count: usize,
+    // Maximum time between consecutive packets
+    max_interval: Duration,
+}
+
+// Returns when the stream is closed at the end of the capture
+async fn replay_stream<T: DeserializeOwned>(
+    mut receiver: StreamReceiver<T>,
+    name: &str,
+    mut output: Option<File>,
+) -> StrResult<StreamSummary> {
+    let mut summary = StreamSummary::default();
+    let mut buffer = ReceiverBuffer::new();
+    let mut last_packet_instant = None;
+    while receiver.recv_buffer(&mut buffer).await.is_ok() {
+        let (_, data) = buffer.get()?;
+
+        let now = Instant::now();
+        if let Some(last_instant) = last_packet_instant {
+            summary.max_interval = Duration::max(summary.max_interval, now - last_instant);
+        }
+        last_packet_instant = Some(now);
+
+        summary.packets_count += 1;
+        summary.bytes_count += data.len();
+        summary.max_reorder_depth = u32::max(summary.max_reorder_depth, buffer.reorder_depth());
+        summary.late_shards_count += buffer.late_shards_count();
+        summary.dropped_shards_count += buffer.dropped_shards_count();
+        if buffer.had_packet_loss() {
+            summary.packet_loss_count += 1;
+            info!(
+                "[{name}] Packet loss before packet {}",
+                summary.packets_count
+            );
+        }
+
+        if let Some(file) = &mut output {
+            file.write_all(data).map_err(err!())?;
+        }
+    }
+
+    Ok(summary)
+}
+
+fn print_summary(name: &str, summary: StrResult<StreamSummary>) {
+    match summary {
+        Ok(summary) if summary.packets_count > 0 => println!(
+            "{name}: {} packets, {} bytes, {} losses, max reorder depth {}, {} late shards, \
+            {} dropped shards, max packet interval {:.1}ms",
+            summary.packets_count,
+            summary.bytes_count,
+            summary.packet_loss_count,
+            summary.max_reorder_depth,
+            summary.late_shards_count,
+            summary.dropped_shards_count,
+            summary.max_interval.as_secs_f32() * 1000.0
+        ),
+        Ok(_) => (),
+        Err(e) => println!("{name}: {e}"),
+    }
+}
+
+async fn run(mut args: Arguments) -> StrResult {
+    let create_output = |path: Option<PathBuf>| -> StrResult<Option<File>> {
+        path.map(|path| File::create(path).map_err(err!()))
+            .transpose()
+    };
+    let video_output = create_output(args.opt_value_from_str("--video-output").map_err(err!())?)?;
+    let audio_output = create_output(args.opt_value_from_str("--audio-output").map_err(err!())?)?;
+    let capture_path: PathBuf = args.free_from_str().map_err(err!())?;
+
+    let socket = alvr_sockets::replay_capture(&capture_path)?;
+
+    // The header type does not matter for streams which are only counted
+    let tracking = socket
+        .subscribe_to_stream::<()>(TRACKING, TRACKING_QUEUE)
+        .await?;
+    let haptics = socket
+        .subscribe_to_stream::<()>(HAPTICS, HAPTICS_QUEUE)
+        .await?;
+    let audio = socket.subscribe_to_stream::<()>(AUDIO, AUDIO_QUEUE).await?;
+    let video = socket
+        .subscribe_to_stream::<Duration>(VIDEO, VIDEO_QUEUE)
+        .await?;
+    let statistics = socket
+        .subscribe_to_stream::<()>(STATISTICS, STATISTICS_QUEUE)
+        .await?;
+
+    let start_instant = Instant::now();
+
+    let (tracking, haptics, audio, video, statistics, res) = tokio::join!(
+        replay_stream(tracking, "tracking", None),
+        replay_stream(haptics, "haptics", None),
+        replay_stream(audio, "audio", audio_output),
+        replay_stream(video, "video", video_output),
+        replay_stream(statistics, "statistics", None),
+        socket.receive_loop(),
+    );
+    res?;
+
+    println!(
+        "Replayed {:.1}s of capture",
+        start_instant.elapsed().as_secs_f32()
+    );
+    print_summary("Tracking", tracking);
+    print_summary("Haptics", haptics);
+    print_summary("Audio", audio);
+    print_summary("Video", video);
+    print_summary("Statistics", statistics);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let mut args = Arguments::from_env();
+    if args.contains("--help") {
+        println!("{HELP_STR}");
+        return;
+    }
+
+    if let Err(e) = run(args).await {
+        error!("{e}");
+        println!("{HELP_STR}");
+    }
+}